@@ -0,0 +1,107 @@
+use std::io;
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::collapse::bpftrace::{Folder, Options};
+use inferno::collapse::demangle::Demangle;
+use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use lazy_static::lazy_static;
+use structopt::StructOpt;
+
+mod common;
+
+use common::demangle::DemangleOpt;
+
+lazy_static! {
+    static ref NTHREADS: String = format!("{}", *DEFAULT_NTHREADS);
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-collapse-bpftrace",
+    about,
+    after_help = "\
+[1] This processes bpftrace map dumps with stack keys, such as the output of:
+        bpftrace -e 'profile:hz:99 { @[kstack, ustack, comm] = count(); }'
+    as well as the folded output of BCC tools, such as:
+        profile -df -F 99 30
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Include raw addresses where symbols can't be found
+    #[structopt(long = "addrs")]
+    addrs: bool,
+
+    /// Annotate kernel functions with a _[k]
+    #[structopt(long = "kernel")]
+    kernel: bool,
+
+    #[structopt(flatten)]
+    demangle: DemangleOpt,
+
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Number of threads to use
+    #[structopt(
+        short = "n",
+        long = "nthreads",
+        default_value = &NTHREADS,
+        value_name = "UINT"
+    )]
+    nthreads: usize,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    #[structopt(value_name = "PATH")]
+    /// bpftrace or BCC output file, or STDIN if not specified
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.annotate_kernel = self.kernel;
+        options.include_addrs = self.addrs;
+        options.nthreads = self.nthreads;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let demangle = opt.demangle.options();
+    let (infile, options) = opt.into_parts();
+    let mut folder = Folder::from(options);
+    match demangle {
+        Some(demangle) => {
+            Demangle::new(folder, demangle).collapse_file(infile.as_ref(), io::stdout().lock())
+        }
+        None => folder.collapse_file(infile.as_ref(), io::stdout().lock()),
+    }
+}
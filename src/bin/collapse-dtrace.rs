@@ -2,11 +2,16 @@ use std::io;
 use std::path::PathBuf;
 
 use env_logger::Env;
+use inferno::collapse::demangle::Demangle;
 use inferno::collapse::dtrace::{Folder, Options};
 use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
 use lazy_static::lazy_static;
 use structopt::StructOpt;
 
+mod common;
+
+use common::demangle::DemangleOpt;
+
 lazy_static! {
     static ref NTHREADS: String = format!("{}", *DEFAULT_NTHREADS);
 }
@@ -30,6 +35,13 @@ struct Opt {
     #[structopt(long = "includeoffset")]
     includeoffset: bool,
 
+    /// Add the source file and line that follow frames, as in "app`compute+0x1a (compute.c:12)"
+    #[structopt(long = "lines")]
+    lines: bool,
+
+    #[structopt(flatten)]
+    demangle: DemangleOpt,
+
     /// Silence all log output
     #[structopt(short = "q", long = "quiet")]
     quiet: bool,
@@ -62,6 +74,7 @@ impl Opt {
     fn into_parts(self) -> (Option<PathBuf>, Options) {
         let mut options = Options::default();
         options.includeoffset = self.includeoffset;
+        options.include_lines = self.lines;
         options.nthreads = self.nthreads;
         (self.infile, options)
     }
@@ -82,6 +95,14 @@ fn main() -> io::Result<()> {
         .init();
     }
 
-    let (infile, options) = opt.into_parts();
-    Folder::from(options).collapse_file(infile.as_ref(), io::stdout().lock())
+    let demangle = opt.demangle.options();
+    let (infile, mut options) = opt.into_parts();
+    options.keep_cpp_signatures = demangle.is_some();
+    let mut folder = Folder::from(options);
+    match demangle {
+        Some(demangle) => {
+            Demangle::new(folder, demangle).collapse_file(infile.as_ref(), io::stdout().lock())
+        }
+        None => folder.collapse_file(infile.as_ref(), io::stdout().lock()),
+    }
 }
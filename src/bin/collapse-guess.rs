@@ -2,11 +2,16 @@ use std::io;
 use std::path::PathBuf;
 
 use env_logger::Env;
+use inferno::collapse::demangle::Demangle;
 use inferno::collapse::guess::{Folder, Options};
 use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
 use lazy_static::lazy_static;
 use structopt::StructOpt;
 
+mod common;
+
+use common::demangle::DemangleOpt;
+
 lazy_static! {
     static ref NTHREADS: String = format!("{}", *DEFAULT_NTHREADS);
 }
@@ -23,6 +28,9 @@ struct Opt {
     // ************* //
     // *** FLAGS *** //
     // ************* //
+    #[structopt(flatten)]
+    demangle: DemangleOpt,
+
     /// Silence all log output
     #[structopt(short = "q", long = "quiet")]
     quiet: bool,
@@ -74,6 +82,13 @@ fn main() -> io::Result<()> {
         .init();
     }
 
+    let demangle = opt.demangle.options();
     let (infile, options) = opt.into_parts();
-    Folder::from(options).collapse_file(infile.as_ref(), io::stdout().lock())
+    let mut folder = Folder::from(options);
+    match demangle {
+        Some(demangle) => {
+            Demangle::new(folder, demangle).collapse_file(infile.as_ref(), io::stdout().lock())
+        }
+        None => folder.collapse_file(infile.as_ref(), io::stdout().lock()),
+    }
 }
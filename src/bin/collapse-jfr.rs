@@ -0,0 +1,92 @@
+use std::io;
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::collapse::demangle::Demangle;
+use inferno::collapse::jfr::{Folder, Options, Weight};
+use inferno::collapse::Collapse;
+use structopt::StructOpt;
+
+mod common;
+
+use common::demangle::DemangleOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-collapse-jfr",
+    about,
+    after_help = "\
+[1] This processes JDK Flight Recorder recordings, created with:
+        java -XX:StartFlightRecording=filename=recording.jfr,settings=profile App
+    or with async-profiler:
+        asprof -e cpu,alloc -f recording.jfr <pid>
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    #[structopt(flatten)]
+    demangle: DemangleOpt,
+
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Weight stacks by CPU samples or by allocated bytes
+    #[structopt(
+        long = "weight",
+        value_name = "WEIGHT",
+        default_value = "cpu",
+        possible_values = &["cpu", "alloc"]
+    )]
+    weight: Weight,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// JFR recording, or STDIN if not specified
+    #[structopt(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.weight = self.weight;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let demangle = opt.demangle.options();
+    let (infile, options) = opt.into_parts();
+    let mut folder = Folder::from(options);
+    match demangle {
+        Some(demangle) => {
+            Demangle::new(folder, demangle).collapse_file(infile.as_ref(), io::stdout().lock())
+        }
+        None => folder.collapse_file(infile.as_ref(), io::stdout().lock()),
+    }
+}
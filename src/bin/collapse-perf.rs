@@ -2,11 +2,19 @@ use std::io;
 use std::path::PathBuf;
 
 use env_logger::Env;
-use inferno::collapse::perf::{Folder, Options};
+use inferno::collapse::demangle::{self, Demangle};
+use inferno::collapse::perf::{self, Folder, GroupBy, Options};
+use inferno::collapse::symbolize;
 use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use inferno::folded::FoldedStacks;
 use lazy_static::lazy_static;
+use regex::Regex;
 use structopt::StructOpt;
 
+mod common;
+
+use common::demangle::DemangleOpt;
+
 lazy_static! {
     static ref NTHREADS: String = format!("{}", *DEFAULT_NTHREADS);
 }
@@ -20,7 +28,10 @@ lazy_static! {
         perf script -f comm,pid,tid,cpu,time,event,ip,sym,dso,trace
     for Linux >= 4.1:
         perf script -F comm,pid,tid,cpu,time,event,ip,sym,dso,trace
-    If you save this output add --header on Linux >= 3.14 to include perf info."
+    If you save this output add --header on Linux >= 3.14 to include perf info.
+
+[2] With --input-format=data, symbols are read from the ELF files on this machine
+    (or under --symfs), so the binaries must match the ones that were profiled."
 )]
 struct Opt {
     // ************* //
@@ -34,6 +45,15 @@ struct Opt {
     #[structopt(long = "all")]
     all: bool,
 
+    /// Collapse the samples of every event type, under a root frame named after the event,
+    /// like cycles or instructions
+    #[structopt(long = "all-events", conflicts_with = "event-filter")]
+    all_events: bool,
+
+    /// Add the functions inlined at the addresses resolved with --binary or --maps, marked with _[i]
+    #[structopt(long = "inlines")]
+    inlines: bool,
+
     /// Annotate jit functions with a _[j]
     #[structopt(long = "jit")]
     jit: bool,
@@ -42,6 +62,15 @@ struct Opt {
     #[structopt(long = "kernel")]
     kernel: bool,
 
+    /// Add the source file and line of each frame, from the srcline field of perf script
+    /// (perf script -F+srcline) or for the addresses resolved with --binary or --maps
+    #[structopt(long = "lines")]
+    lines: bool,
+
+    /// Count each sample as its period, the number of events it stands for, rather than as one
+    #[structopt(long = "periods")]
+    periods: bool,
+
     /// Include PID with process names
     #[structopt(long = "pid")]
     pid: bool,
@@ -50,6 +79,9 @@ struct Opt {
     #[structopt(long = "tid")]
     tid: bool,
 
+    #[structopt(flatten)]
+    demangle: DemangleOpt,
+
     /// Silence all log output
     #[structopt(short = "q", long = "quiet")]
     quiet: bool,
@@ -61,10 +93,78 @@ struct Opt {
     // *************** //
     // *** OPTIONS *** //
     // *************** //
+    /// Resolve the addresses of unknown frames from this binary, which isn't relocated (such as
+    /// an executable that isn't position independent) [may be repeated]
+    #[structopt(long = "binary", value_name = "PATH", number_of_values = 1)]
+    binaries: Vec<PathBuf>,
+
+    /// Group the stacks by process, thread, CPU or thread name, under a root frame named after
+    /// the group, like pid-4242, tid-4243, cpu-002 or the thread name
+    #[structopt(
+        long = "group-by",
+        possible_values = &["process", "thread", "cpu", "thread-name"],
+        value_name = "GROUP"
+    )]
+    group_by: Option<String>,
+
+    /// Group the threads whose names match this regular expression as one with --group-by
+    /// thread-name, named after its first capture group or the expression itself [may be repeated]
+    #[structopt(
+        long = "group-pattern",
+        value_name = "REGEX",
+        number_of_values = 1,
+        requires = "group-by"
+    )]
+    group_patterns: Vec<Regex>,
+
+    /// Write the stacks of each event of --all-events, or else of each group of --group-by, to a
+    /// file of its own in this directory, named after the event or group (as in cycles.folded or
+    /// tid-4243.folded), instead of to STDOUT
+    #[structopt(long = "output-dir", value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+
+    /// Write differential folded stacks that compare the periods of two events, for
+    /// inferno-flamegraph to color red where their ratio is lower than overall and blue where it
+    /// is higher, as with --ratio instructions cycles for the instructions per cycle. Implies
+    /// --all-events and --periods
+    #[structopt(
+        long = "ratio",
+        number_of_values = 2,
+        value_names = &["NUMERATOR", "DENOMINATOR"],
+        conflicts_with_all = &["event-filter", "output-dir"]
+    )]
+    ratio: Vec<String>,
+
     /// Event filter [default: first encountered event]
     #[structopt(long = "event-filter", value_name = "STRING")]
     event_filter: Option<String>,
 
+    /// Input format: perf script output, or a perf.data file [2]
+    #[structopt(
+        long = "input-format",
+        default_value = "script",
+        possible_values = &["script", "data"],
+        value_name = "FORMAT"
+    )]
+    input_format: String,
+
+    /// File to read kernel symbols from with --input-format=data [default: /proc/kallsyms]
+    #[structopt(long = "kallsyms", value_name = "PATH")]
+    kallsyms: Option<PathBuf>,
+
+    /// Resolve the addresses of unknown frames from the files listed in this file, saved from
+    /// /proc/<pid>/maps while the profiled process was running
+    #[structopt(long = "maps", value_name = "PATH")]
+    maps: Option<PathBuf>,
+
+    /// Only resolve the addresses of unknown frames of this process, which --maps was saved from
+    #[structopt(long = "maps-pid", value_name = "PID")]
+    maps_pid: Option<u32>,
+
+    /// Look for files referenced by a perf.data file or by --maps relative to this directory
+    #[structopt(long = "symfs", value_name = "DIR")]
+    symfs: Option<PathBuf>,
+
     /// Number of threads to use
     #[structopt(
         short = "n",
@@ -78,7 +178,7 @@ struct Opt {
     // *** ARGS *** //
     // ************ //
     #[structopt(value_name = "PATH")]
-    /// Perf script output file, or STDIN if not specified
+    /// Perf script output (or perf.data) file, or STDIN if not specified
     infile: Option<PathBuf>,
 }
 
@@ -88,10 +188,33 @@ impl Opt {
         options.include_pid = self.pid;
         options.include_tid = self.tid;
         options.include_addrs = self.addrs;
+        options.include_lines = self.lines;
         options.annotate_jit = self.jit || self.all;
         options.annotate_kernel = self.kernel || self.all;
         options.event_filter = self.event_filter;
+        options.all_events = self.all_events || !self.ratio.is_empty();
+        options.count_periods = self.periods || !self.ratio.is_empty();
+        options.group_by = match self.group_by.as_deref() {
+            Some("process") => Some(GroupBy::Process),
+            Some("thread") => Some(GroupBy::Thread),
+            Some("cpu") => Some(GroupBy::Cpu),
+            Some("thread-name") => Some(GroupBy::ThreadName(self.group_patterns)),
+            _ => None,
+        };
         options.nthreads = self.nthreads;
+        if !self.binaries.is_empty() || self.maps.is_some() {
+            let mut symbolize = symbolize::Options::default();
+            symbolize.binaries = self.binaries;
+            symbolize.maps = self.maps;
+            symbolize.symfs = self.symfs.clone();
+            symbolize.lines = self.lines;
+            symbolize.inlines = self.inlines;
+            symbolize.pid = self.maps_pid;
+            symbolize.demangle = self.demangle.options().unwrap_or_default();
+            options.symbolize = Some(symbolize);
+        }
+        options.symfs = self.symfs;
+        options.kallsyms = self.kallsyms;
         (self.infile, options)
     }
 }
@@ -111,6 +234,51 @@ fn main() -> io::Result<()> {
         .init();
     }
 
+    let from_data = opt.input_format == "data";
+    let demangle = opt.demangle.options();
+    let output_dir = opt.output_dir.clone();
+    let ratio = opt.ratio.clone();
+    if output_dir.is_some() && !opt.all_events && opt.group_by.is_none() {
+        eprintln!("error: '--output-dir <DIR>' requires --all-events or --group-by");
+        std::process::exit(1);
+    }
+    if !opt.group_patterns.is_empty() && opt.group_by.as_deref() != Some("thread-name") {
+        eprintln!("error: '--group-pattern <REGEX>' requires --group-by thread-name");
+        std::process::exit(1);
+    }
     let (infile, options) = opt.into_parts();
-    Folder::from(options).collapse_file(infile.as_ref(), io::stdout().lock())
+    if let Some(dir) = output_dir {
+        let mut folded = Vec::new();
+        collapse(from_data, demangle, options, infile, &mut folded)?;
+        let stacks = FoldedStacks::from_reader(&folded[..])?;
+        perf::write_groups(&stacks, dir)?;
+        Ok(())
+    } else if let [numerator, denominator] = &ratio[..] {
+        let mut folded = Vec::new();
+        collapse(from_data, demangle, options, infile, &mut folded)?;
+        let stacks = FoldedStacks::from_reader(&folded[..])?;
+        let stdout = io::stdout();
+        perf::write_ratio(&stacks, numerator, denominator, stdout.lock())
+    } else {
+        let stdout = io::stdout();
+        collapse(from_data, demangle, options, infile, stdout.lock())
+    }
+}
+
+fn collapse<W: io::Write>(
+    from_data: bool,
+    demangle: Option<demangle::Options>,
+    options: Options,
+    infile: Option<PathBuf>,
+    writer: W,
+) -> io::Result<()> {
+    match (from_data, demangle) {
+        (true, Some(demangle)) => Demangle::new(perf::data::Folder::from(options), demangle)
+            .collapse_file(infile.as_ref(), writer),
+        (true, None) => perf::data::Folder::from(options).collapse_file(infile.as_ref(), writer),
+        (false, Some(demangle)) => {
+            Demangle::new(Folder::new(options)?, demangle).collapse_file(infile.as_ref(), writer)
+        }
+        (false, None) => Folder::new(options)?.collapse_file(infile.as_ref(), writer),
+    }
 }
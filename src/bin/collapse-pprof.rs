@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::collapse::demangle::Demangle;
+use inferno::collapse::pprof::{self, ExportOptions, Folder, Options};
+use inferno::collapse::Collapse;
+use structopt::StructOpt;
+
+mod common;
+
+use common::demangle::DemangleOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-collapse-pprof",
+    about,
+    after_help = "\
+[1] This processes pprof profiles (profile.proto, gzipped or not), such as those written by Go's
+    runtime/pprof package or fetched with:
+        curl -o cpu.pb.gz http://localhost:6060/debug/pprof/profile?seconds=30
+
+[2] With --export, folded stacks are read instead, and converted into a gzipped profile.proto
+    that can be loaded with `go tool pprof`.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Convert folded stacks into a pprof profile instead [2]
+    #[structopt(long = "export", conflicts_with = "demangle")]
+    export: bool,
+
+    #[structopt(flatten)]
+    demangle: DemangleOpt,
+
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Index of the sample type to use as the count [default: the profile's default, or the last]
+    #[structopt(long = "sample-index", value_name = "INDEX")]
+    sample_index: Option<usize>,
+
+    /// What the exported counts measure
+    #[structopt(long = "sample-type", value_name = "STRING", default_value = "samples")]
+    sample_type: String,
+
+    /// Unit of the exported counts
+    #[structopt(long = "unit", value_name = "STRING", default_value = "count")]
+    unit: String,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// pprof profile (or folded stacks with --export), or STDIN if not specified
+    #[structopt(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options, Option<ExportOptions>) {
+        let mut options = Options::default();
+        options.sample_index = self.sample_index;
+
+        let export_options = if self.export {
+            let mut export_options = ExportOptions::default();
+            export_options.sample_type = self.sample_type;
+            export_options.unit = self.unit;
+            Some(export_options)
+        } else {
+            None
+        };
+
+        (self.infile, options, export_options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let demangle = opt.demangle.options();
+    let (infile, options, export_options) = opt.into_parts();
+    match export_options {
+        Some(export_options) => {
+            let stdout = io::stdout();
+            match infile {
+                Some(path) => {
+                    let reader = BufReader::new(File::open(path)?);
+                    pprof::export(&export_options, reader, stdout.lock())
+                }
+                None => {
+                    let stdin = io::stdin();
+                    pprof::export(&export_options, stdin.lock(), stdout.lock())
+                }
+            }
+        }
+        None => match demangle {
+            Some(demangle) => Demangle::new(Folder::from(options), demangle)
+                .collapse_file(infile.as_ref(), io::stdout().lock()),
+            None => Folder::from(options).collapse_file(infile.as_ref(), io::stdout().lock()),
+        },
+    }
+}
@@ -2,10 +2,15 @@ use std::io;
 use std::path::PathBuf;
 
 use env_logger::Env;
+use inferno::collapse::demangle::Demangle;
 use inferno::collapse::vtune::{Folder, Options};
 use inferno::collapse::Collapse;
 use structopt::StructOpt;
 
+mod common;
+
+use common::demangle::DemangleOpt;
+
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "inferno-collapse-vtune",
@@ -24,6 +29,9 @@ struct Opt {
     #[structopt(long = "no-modules")]
     no_modules: bool,
 
+    #[structopt(flatten)]
+    demangle: DemangleOpt,
+
     /// Silence all log output
     #[structopt(short = "q", long = "quiet")]
     quiet: bool,
@@ -63,6 +71,13 @@ fn main() -> io::Result<()> {
         .init();
     }
 
+    let demangle = opt.demangle.options();
     let (infile, options) = opt.into_parts();
-    Folder::from(options).collapse_file(infile.as_ref(), io::stdout().lock())
+    let mut folder = Folder::from(options);
+    match demangle {
+        Some(demangle) => {
+            Demangle::new(folder, demangle).collapse_file(infile.as_ref(), io::stdout().lock())
+        }
+        None => folder.collapse_file(infile.as_ref(), io::stdout().lock()),
+    }
 }
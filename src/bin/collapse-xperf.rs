@@ -0,0 +1,112 @@
+use std::io;
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::collapse::demangle::Demangle;
+use inferno::collapse::xperf::{Folder, Options};
+use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use lazy_static::lazy_static;
+use structopt::StructOpt;
+
+mod common;
+
+use common::demangle::DemangleOpt;
+
+lazy_static! {
+    static ref NTHREADS: String = format!("{}", *DEFAULT_NTHREADS);
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-collapse-xperf",
+    about,
+    after_help = "\
+[1] This processes stacks exported as CSV, with one row per stack, such as the output of:
+        wpaexporter trace.etl -profile ExportStacks.wpaProfile
+    The CSV needs a Stack column and a Weight (in ms) or Count column. Weights are written in
+    microseconds. Process and Thread ID columns are used when present.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Include PID with process names [1]
+    #[structopt(long = "pid")]
+    pid: bool,
+
+    /// Include TID and PID with process names [1]
+    #[structopt(long = "tid")]
+    tid: bool,
+
+    /// Don't include modules with function names
+    #[structopt(long = "no-modules")]
+    no_modules: bool,
+
+    #[structopt(flatten)]
+    demangle: DemangleOpt,
+
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Number of threads to use
+    #[structopt(
+        short = "n",
+        long = "nthreads",
+        default_value = &NTHREADS,
+        value_name = "UINT"
+    )]
+    nthreads: usize,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    #[structopt(value_name = "PATH")]
+    /// xperf or WPA CSV file, or STDIN if not specified
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.include_pid = self.pid;
+        options.include_tid = self.tid;
+        options.no_modules = self.no_modules;
+        options.nthreads = self.nthreads;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let demangle = opt.demangle.options();
+    let (infile, options) = opt.into_parts();
+    let mut folder = Folder::from(options);
+    match demangle {
+        Some(demangle) => {
+            Demangle::new(folder, demangle).collapse_file(infile.as_ref(), io::stdout().lock())
+        }
+        None => folder.collapse_file(infile.as_ref(), io::stdout().lock()),
+    }
+}
@@ -0,0 +1,32 @@
+use inferno::collapse::demangle::Options;
+use structopt::StructOpt;
+
+/// The flags that turn on and configure demangling, shared by the collapse binaries through
+/// `#[structopt(flatten)]`.
+#[derive(Debug, StructOpt)]
+pub struct DemangleOpt {
+    /// Demangle Rust and C++ symbols
+    #[structopt(long = "demangle")]
+    demangle: bool,
+
+    /// Keep the hashes of Rust symbols when demangling
+    #[structopt(long = "keep-rust-hash", requires = "demangle")]
+    keep_rust_hash: bool,
+
+    /// Strip C++ template arguments and Rust generics when demangling
+    #[structopt(long = "simplify-templates", requires = "demangle")]
+    simplify_templates: bool,
+}
+
+impl DemangleOpt {
+    /// The demangling options, or `None` if `--demangle` wasn't given.
+    pub fn options(&self) -> Option<Options> {
+        if !self.demangle {
+            return None;
+        }
+        let mut options = Options::default();
+        options.keep_rust_hash = self.keep_rust_hash;
+        options.simplify_templates = self.simplify_templates;
+        Some(options)
+    }
+}
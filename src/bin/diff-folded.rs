@@ -2,7 +2,8 @@ use std::io;
 use std::path::PathBuf;
 
 use env_logger::Env;
-use inferno::differential::{self, Options};
+use inferno::differential::{self, AlignOptions, Options, Score};
+use regex::Regex;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -24,20 +25,46 @@ and pass the --negate flag to inferno-flamegraph like this:
 
   $ inferno-diff-folded folded2 folded1 | inferno-flamegraph --negate > diff1.svg
 
+With several runs of each profile, pass them with --before and --after
+instead. The mean of the runs is used for each stack, along with a score of
+how significant its change is, so that only those changes are colored:
+
+  $ inferno-diff-folded -b old1 -b old2 -b old3 -a new1 -a new2 -a new3 \\
+      | inferno-flamegraph --significance 2 > diff.svg
+
+When functions were renamed or wrapped between the profiles, --strip-generics,
+--strip-rust-hash, --rename and --drop-frame rewrite the frames of both
+profiles before their stacks are lined up. --functions then lists how the
+self and total samples of each function changed instead:
+
+  $ inferno-diff-folded --strip-rust-hash --drop-frame '^with_retry$' --functions folded1 folded2
+
 You can use the inferno-collapse-* tools to generate the folded files."
 )]
 struct Opt {
     // ************* //
     // *** FLAGS *** //
     // ************* //
+    /// List the change in the self and total samples of each function instead of the stacks
+    #[structopt(long = "functions", conflicts_with_all = &["before", "after"])]
+    functions: bool,
+
     /// Normalize sample counts
     #[structopt(short = "n", long = "normalize")]
     normalize: bool,
 
+    /// Strip generic and template parameters from frames before lining up stacks
+    #[structopt(long = "strip-generics", conflicts_with_all = &["before", "after"])]
+    strip_generics: bool,
+
     /// Strip hex numbers (addresses)
     #[structopt(short = "s", long = "strip-hex")]
     strip_hex: bool,
 
+    /// Strip the hashes off of Rust symbols before lining up stacks
+    #[structopt(long = "strip-rust-hash", conflicts_with_all = &["before", "after"])]
+    strip_rust_hash: bool,
+
     /// Silence all log output
     #[structopt(short = "q", long = "quiet")]
     quiet: bool,
@@ -46,28 +73,118 @@ struct Opt {
     #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     verbose: usize,
 
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Path to a run of folded stack profile 2 [may be repeated]
+    #[structopt(
+        short = "a",
+        long = "after",
+        value_name = "PATH",
+        number_of_values = 1,
+        requires = "before"
+    )]
+    after: Vec<PathBuf>,
+
+    /// Path to a run of folded stack profile 1 [may be repeated]
+    #[structopt(
+        short = "b",
+        long = "before",
+        value_name = "PATH",
+        number_of_values = 1,
+        requires = "after"
+    )]
+    before: Vec<PathBuf>,
+
+    /// Drop frames matching this regular expression before lining up stacks [may be repeated]
+    #[structopt(
+        long = "drop-frame",
+        value_name = "REGEX",
+        number_of_values = 1,
+        conflicts_with_all = &["before", "after"]
+    )]
+    drop_frame: Vec<Regex>,
+
+    /// Replace matches of a regular expression in frames before lining up stacks, where the
+    /// replacement can refer to capture groups as $1 [may be repeated]
+    #[structopt(
+        long = "rename",
+        number_of_values = 2,
+        value_names = &["REGEX", "REPLACEMENT"],
+        conflicts_with_all = &["before", "after"]
+    )]
+    rename: Vec<String>,
+
+    /// Statistic that scores the changes between the runs given with --before and --after
+    #[structopt(
+        long = "score",
+        default_value = "welch",
+        possible_values = &["welch", "poisson"],
+        value_name = "STRING"
+    )]
+    score: Score,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
     /// Path to folded stack profile 1
-    #[structopt(value_name = "PATH1")]
-    path1: PathBuf,
+    #[structopt(
+        value_name = "PATH1",
+        required_unless = "before",
+        conflicts_with_all = &["before", "after"]
+    )]
+    path1: Option<PathBuf>,
 
     /// Path to folded stack profile 2
-    #[structopt(value_name = "PATH2")]
-    path2: PathBuf,
+    #[structopt(value_name = "PATH2", required_unless = "before")]
+    path2: Option<PathBuf>,
+}
+
+// How the profiles are compared.
+enum Mode {
+    Stacks,
+    Runs(Score),
+    Aligned(AlignOptions),
+    Functions(AlignOptions),
 }
 
 impl Opt {
-    fn into_parts(self) -> (PathBuf, PathBuf, Options) {
-        (
-            self.path1,
-            self.path2,
+    fn into_parts(self) -> Result<(Vec<PathBuf>, Vec<PathBuf>, Options, Mode), regex::Error> {
+        let mut align = AlignOptions::default();
+        align.strip_rust_hashes = self.strip_rust_hash;
+        align.strip_generics = self.strip_generics;
+        for pair in self.rename.chunks(2) {
+            align.rename.push((Regex::new(&pair[0])?, pair[1].clone()));
+        }
+        align.drop = self.drop_frame;
+
+        let mode = if !self.before.is_empty() {
+            Mode::Runs(self.score)
+        } else if self.functions {
+            Mode::Functions(align)
+        } else if align.strip_rust_hashes
+            || align.strip_generics
+            || !align.rename.is_empty()
+            || !align.drop.is_empty()
+        {
+            Mode::Aligned(align)
+        } else {
+            Mode::Stacks
+        };
+
+        let (before, after) = match (self.path1, self.path2) {
+            (Some(path1), Some(path2)) => (vec![path1], vec![path2]),
+            _ => (self.before, self.after),
+        };
+        Ok((
+            before,
+            after,
             Options {
                 normalize: self.normalize,
                 strip_hex: self.strip_hex,
             },
-        )
+            mode,
+        ))
     }
 }
 
@@ -86,6 +203,30 @@ fn main() -> io::Result<()> {
         .init();
     }
 
-    let (folded1, folded2, options) = opt.into_parts();
-    differential::from_files(options, folded1, folded2, io::stdout().lock())
+    let (before, after, options, mode) = match opt.into_parts() {
+        Ok(parts) => parts,
+        Err(e) => {
+            eprintln!(
+                "error: Invalid value for '--rename <REGEX> <REPLACEMENT>': {}",
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+    let stdout = io::stdout();
+    let writer = stdout.lock();
+    match mode {
+        Mode::Stacks => differential::from_files(options, &before[0], &after[0], writer),
+        Mode::Runs(score) => {
+            differential::from_multiple_files(options, score, &before, &after, writer)
+        }
+        Mode::Aligned(align) => {
+            differential::from_files_aligned(options, &align, &before[0], &after[0], writer)
+        }
+        Mode::Functions(align) => {
+            let functions =
+                differential::function_deltas_from_files(options, &align, &before[0], &after[0])?;
+            differential::write_function_deltas(&functions, writer)
+        }
+    }
 }
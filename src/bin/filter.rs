@@ -0,0 +1,123 @@
+use std::io;
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::filter::{self, Options};
+use regex::Regex;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-filter",
+    about,
+    after_help = "\
+Filters and rewrites folded stacks on their way from inferno-collapse-* to
+inferno-flamegraph. Frames are renamed first, then recursion is folded, then
+stacks are kept or dropped, focused and pruned, and finally cut to the
+maximum depth.
+
+  $ inferno-filter --focus '^handle_request$' folded.txt | inferno-flamegraph > handler.svg
+  $ inferno-filter --rename '::h[0-9a-f]{16}$' '' --fold-recursion folded.txt"
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Collapse frames that directly call themselves into a single frame
+    #[structopt(long = "fold-recursion")]
+    fold_recursion: bool,
+
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Drop stacks with a frame matching this regular expression [may be repeated]
+    #[structopt(short = "x", long = "exclude", value_name = "REGEX")]
+    exclude: Vec<Regex>,
+
+    /// Drop the callers of the first frame matching this regular expression, and the stacks
+    /// without one
+    #[structopt(long = "focus", value_name = "REGEX")]
+    focus: Option<Regex>,
+
+    /// Only keep stacks with a frame matching this regular expression [may be repeated]
+    #[structopt(short = "g", long = "grep", value_name = "REGEX")]
+    grep: Vec<Regex>,
+
+    /// Only keep this many frames from the root of each stack
+    #[structopt(long = "max-depth", value_name = "UINT")]
+    max_depth: Option<usize>,
+
+    /// Drop the callees of the first frame matching this regular expression
+    #[structopt(long = "prune", value_name = "REGEX")]
+    prune: Option<Regex>,
+
+    /// Replace matches of a regular expression in frame names, where the replacement can refer
+    /// to capture groups as $1 [may be repeated]
+    #[structopt(
+        long = "rename",
+        number_of_values = 2,
+        value_names = &["REGEX", "REPLACEMENT"]
+    )]
+    rename: Vec<String>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Folded stack files. With no PATH, or PATH is -, read STDIN.
+    #[structopt(name = "PATH", parse(from_os_str))]
+    infiles: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> Result<(Vec<PathBuf>, Options), regex::Error> {
+        let mut options = Options::default();
+        for pair in self.rename.chunks(2) {
+            options
+                .rename
+                .push((Regex::new(&pair[0])?, pair[1].clone()));
+        }
+        options.fold_recursion = self.fold_recursion;
+        options.grep = self.grep;
+        options.exclude = self.exclude;
+        options.focus = self.focus;
+        options.prune = self.prune;
+        options.max_depth = self.max_depth;
+        Ok((self.infiles, options))
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infiles, options) = match opt.into_parts() {
+        Ok(parts) => parts,
+        Err(e) => {
+            eprintln!(
+                "error: Invalid value for '--rename <REGEX> <REPLACEMENT>': {}",
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+    filter::from_files(&options, &infiles, io::stdout().lock())
+}
@@ -1,15 +1,24 @@
+use std::env;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use env_logger::Env;
-use inferno::flamegraph::color::{BackgroundColor, PaletteMap, SearchColor};
-use inferno::flamegraph::{self, defaults, Direction, Options, Palette, TextTruncateDirection};
+use inferno::flamegraph::color::{BackgroundColor, BasicPalette, PaletteMap, SearchColor};
+use inferno::flamegraph::{
+    self, defaults, Direction, Options, OutputFormat, Palette, TextTruncateDirection, Unit,
+};
 
 #[cfg(feature = "nameattr")]
 use inferno::flamegraph::FuncFrameAttrsMap;
 
 use structopt::StructOpt;
 
+#[cfg(not(feature = "raster"))]
+const FORMATS: &[&str] = &["svg", "html", "json", "speedscope", "text"];
+#[cfg(feature = "raster")]
+const FORMATS: &[&str] = &["svg", "html", "json", "speedscope", "text", "png"];
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "inferno-flamegraph", about)]
 struct Opt {
@@ -77,15 +86,14 @@ struct Opt {
     #[structopt(long = "bgcolors", value_name = "STRING")]
     bgcolors: Option<BackgroundColor>,
 
-    /// Set color palette
+    /// Set color palette [default: hot, or mem with --unit bytes]
     #[structopt(
         short = "c",
         long = "colors",
-        default_value = defaults::COLORS,
         possible_values = &["aqua","blue","green","hot","io","java","js","mem","orange","perl","purple","red","wakeup","yellow"],
         value_name = "STRING"
     )]
-    colors: Palette,
+    colors: Option<Palette>,
 
     /// Color frames based on their width, highlighting expensive codepaths
     #[structopt(long = "colordiffusion", conflicts_with = "colors")]
@@ -107,6 +115,17 @@ struct Opt {
     )]
     factor: f64,
 
+    /// Output format: an SVG image, an HTML page that draws the flame graph on a canvas and lists
+    /// the functions with the most samples, a d3-flame-graph JSON tree, a speedscope profile, or
+    /// colored text for the terminal, or a PNG image (with the `raster` feature)
+    #[structopt(
+        long = "format",
+        default_value = "svg",
+        possible_values = FORMATS,
+        value_name = "STRING"
+    )]
+    format: OutputFormat,
+
     /// Font size
     #[structopt(
         long = "fontsize",
@@ -142,6 +161,7 @@ struct Opt {
     /// Omit functions smaller than <FLOAT> pixels
     #[structopt(
         long = "minwidth",
+        alias = "min-width",
         default_value = &defaults::str::MIN_WIDTH,
         value_name = "FLOAT"
     )]
@@ -154,6 +174,12 @@ struct Opt {
     #[structopt(long = "nameattr", value_name = "PATH")]
     nameattr: Option<PathBuf>,
 
+    /// Link the frames that have a source location to this URL, with {file} and {line}
+    /// replaced by the location, as in https://github.com/user/repo/blob/main/{file}#L{line}
+    #[cfg(feature = "nameattr")]
+    #[structopt(long = "source-url", value_name = "TEMPLATE")]
+    source_url: Option<String>,
+
     /// Name type label
     #[structopt(
         long = "nametype",
@@ -174,6 +200,11 @@ struct Opt {
     )]
     search_color: SearchColor,
 
+    /// Only color the differences of stacks whose significance score, as written by
+    /// inferno-diff-folded --before/--after, is at least this large
+    #[structopt(long = "significance", value_name = "FLOAT")]
+    significance: Option<f64>,
+
     /// Second level title (optional)
     #[structopt(long = "subtitle", value_name = "STRING")]
     subtitle: Option<String>,
@@ -186,7 +217,17 @@ struct Opt {
     )]
     title: String,
 
-    /// Width of image
+    /// Unit of the sample counts, which are shown scaled in tooltips, search matches and the
+    /// title: bytes (B to TiB), ns, us or ms (ns to s), or percent for only the share of the total
+    #[structopt(
+        long = "unit",
+        default_value = "samples",
+        possible_values = &["samples", "bytes", "ns", "us", "ms", "percent"],
+        value_name = "STRING"
+    )]
+    unit: Unit,
+
+    /// Width of image, or number of columns with `--format text` (defaults to $COLUMNS, or 80)
     #[structopt(long = "width", value_name = "UINT")]
     width: Option<usize>,
 
@@ -210,7 +251,12 @@ impl<'a> Opt {
     fn into_parts(self) -> (Vec<PathBuf>, Options<'a>) {
         let mut options = Options::default();
         options.title = self.title.clone();
-        options.colors = self.colors;
+        options.colors = match self.colors {
+            Some(colors) => colors,
+            None if self.unit == Unit::Bytes => Palette::Basic(BasicPalette::Mem),
+            None => Palette::from_str(defaults::COLORS).unwrap(),
+        };
+        options.unit = self.unit;
         options.bgcolors = self.bgcolors;
         options.hash = self.hash;
         options.deterministic = self.deterministic;
@@ -234,6 +280,7 @@ impl<'a> Opt {
         options.color_diffusion = self.color_diffusion;
         options.reverse_stack_order = self.reverse;
         options.flame_chart = self.flame_chart;
+        options.format = self.format;
 
         if self.flame_chart && self.title == defaults::TITLE {
             options.title = defaults::CHART_TITLE.to_owned();
@@ -242,6 +289,9 @@ impl<'a> Opt {
         // set style options
         options.subtitle = self.subtitle;
         options.image_width = self.width;
+        if self.format == OutputFormat::Text && self.width.is_none() {
+            options.image_width = env::var("COLUMNS").ok().and_then(|c| c.parse().ok());
+        }
         options.frame_height = self.height;
         options.min_width = self.minwidth;
         options.font_type = self.fonttype;
@@ -253,6 +303,7 @@ impl<'a> Opt {
             options.notes = notes;
         }
         options.negate_differentials = self.negate;
+        options.significance_threshold = self.significance;
         options.factor = self.factor;
         options.search_color = self.search_color;
         (self.infiles, options)
@@ -268,6 +319,9 @@ impl<'a> Opt {
                 Err(e) => panic!("Error reading {}: {:?}", file.display(), e),
             }
         };
+        if let Some(source_url) = &self.source_url {
+            options.func_frameattrs.set_source_url(source_url.clone());
+        }
     }
 
     #[cfg(not(feature = "nameattr"))]
@@ -332,7 +386,9 @@ fn save_consistent_palette_if_needed(
 #[cfg(test)]
 mod tests {
     use super::Opt;
-    use inferno::flamegraph::{color, Direction, Options, Palette, TextTruncateDirection};
+    use inferno::flamegraph::{
+        color, Direction, Options, OutputFormat, Palette, TextTruncateDirection, Unit,
+    };
     use pretty_assertions::assert_eq;
     use std::path::PathBuf;
     use std::str::FromStr;
@@ -346,6 +402,27 @@ mod tests {
         assert_eq!(options, Options::default());
     }
 
+    #[test]
+    fn unit_bytes_defaults_to_mem_palette() {
+        let args = vec!["inferno-flamegraph", "--unit", "bytes", "test_infile"];
+        let opt = Opt::from_iter_safe(args).unwrap();
+        let (_infiles, options) = opt.into_parts();
+        assert_eq!(options.unit, Unit::Bytes);
+        assert_eq!(options.colors, Palette::from_str("mem").unwrap());
+
+        let args = vec![
+            "inferno-flamegraph",
+            "--unit",
+            "bytes",
+            "--colors",
+            "red",
+            "test_infile",
+        ];
+        let opt = Opt::from_iter_safe(args).unwrap();
+        let (_infiles, options) = opt.into_parts();
+        assert_eq!(options.colors, Palette::from_str("red").unwrap());
+    }
+
     #[test]
     fn options() {
         let args = vec![
@@ -383,11 +460,15 @@ mod tests {
             "--notes",
             "Test notes",
             "--negate",
+            "--significance",
+            "2.5",
             "--factor",
             "0.1",
             "--pretty-xml",
             "--reverse",
             "--no-javascript",
+            "--format",
+            "html",
             "test_infile1",
             "test_infile2",
         ];
@@ -413,11 +494,13 @@ mod tests {
         expected_options.hash = true;
         expected_options.direction = Direction::Inverted;
         expected_options.negate_differentials = true;
+        expected_options.significance_threshold = Some(2.5);
         expected_options.pretty_xml = true;
         expected_options.no_sort = false;
         expected_options.reverse_stack_order = true;
         expected_options.no_javascript = true;
         expected_options.color_diffusion = false;
+        expected_options.format = OutputFormat::Html;
 
         assert_eq!(options, expected_options);
         assert_eq!(infiles.len(), 2, "expected 2 input files");
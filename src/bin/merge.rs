@@ -0,0 +1,121 @@
+use std::io;
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::merge::{self, Input, Normalize, Options};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-merge",
+    about,
+    after_help = "\
+Sums the sample counts of each stack across folded stack files, such as the
+profiles of many hosts, and writes them out as sorted folded stacks.
+
+  $ inferno-merge --normalize --prefix-root web1.folded web2.folded | inferno-flamegraph > web.svg
+  $ inferno-merge --weight 1 --weight 0.5 today.folded yesterday.folded"
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Add the name of each file, without its extension, as the root frame of its stacks
+    #[structopt(long = "prefix-root")]
+    prefix_root: bool,
+
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Scale each file to TOTAL samples (as in --normalize=1000), or to the samples of the
+    /// largest file when no TOTAL is given, before applying its weight
+    #[structopt(long = "normalize", value_name = "TOTAL", require_equals = true)]
+    normalize: Option<Option<usize>>,
+
+    /// Multiply the sample counts of the files by these weights, given once for each file in the
+    /// same order [default: 1]
+    #[structopt(
+        short = "w",
+        long = "weight",
+        value_name = "FLOAT",
+        number_of_values = 1
+    )]
+    weights: Vec<f64>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Folded stack files to merge. A PATH of - reads STDIN.
+    #[structopt(name = "PATH", parse(from_os_str), required = true)]
+    infiles: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> Result<(Vec<(PathBuf, Input)>, Options), String> {
+        if !self.weights.is_empty() && self.weights.len() != self.infiles.len() {
+            return Err(format!(
+                "{} weights were given for {} files",
+                self.weights.len(),
+                self.infiles.len()
+            ));
+        }
+
+        let mut inputs = Vec::with_capacity(self.infiles.len());
+        for (i, path) in self.infiles.into_iter().enumerate() {
+            let mut input = Input::default();
+            if let Some(&weight) = self.weights.get(i) {
+                if !weight.is_finite() || weight < 0.0 {
+                    return Err(format!("invalid weight: {}", weight));
+                }
+                input.weight = weight;
+            }
+            if self.prefix_root {
+                input.root = Some(match path.file_stem() {
+                    Some(stem) if path.to_str() != Some("-") => stem.to_string_lossy().into(),
+                    _ => "stdin".to_string(),
+                });
+            }
+            inputs.push((path, input));
+        }
+
+        let mut options = Options::default();
+        options.normalize = self.normalize.map(|total| match total {
+            Some(total) => Normalize::Total(total),
+            None => Normalize::Largest,
+        });
+        Ok((inputs, options))
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (inputs, options) = match opt.into_parts() {
+        Ok(parts) => parts,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    merge::from_files(&options, &inputs, io::stdout().lock())
+}
@@ -0,0 +1,106 @@
+use std::io;
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::report::{self, Options, ReportFormat, SortBy};
+use regex::Regex;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-report",
+    about,
+    after_help = "\
+Lists the functions with the most samples in folded stack profiles, with their
+self and total samples, percentages, and the number of distinct stacks they
+appear in.
+
+  $ inferno-report --top 10 folded.txt
+  $ inferno-report --group '^([^:]+)::' --format csv folded.txt > crates.csv
+
+You can use the inferno-collapse-* tools to generate the folded files."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Output format
+    #[structopt(
+        long = "format",
+        default_value = "text",
+        possible_values = &["text", "csv", "json"],
+        value_name = "STRING"
+    )]
+    format: ReportFormat,
+
+    /// Report functions matching this regular expression as one, named after its first capture
+    /// group or the expression itself [may be repeated]
+    #[structopt(short = "g", long = "group", value_name = "REGEX")]
+    groups: Vec<Regex>,
+
+    /// Only look at this many frames from the root of each stack
+    #[structopt(long = "max-depth", value_name = "UINT")]
+    max_depth: Option<usize>,
+
+    /// Column to sort by
+    #[structopt(
+        long = "sort",
+        default_value = "self",
+        possible_values = &["self", "total", "stacks"],
+        value_name = "STRING"
+    )]
+    sort_by: SortBy,
+
+    /// Number of functions to list, or 0 to list all of them
+    #[structopt(short = "n", long = "top", default_value = "20", value_name = "UINT")]
+    top: usize,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Folded stack files. With no PATH, or PATH is -, read STDIN.
+    #[structopt(name = "PATH", parse(from_os_str))]
+    infiles: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Vec<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.top = if self.top == 0 { None } else { Some(self.top) };
+        options.sort_by = self.sort_by;
+        options.format = self.format;
+        options.max_depth = self.max_depth;
+        options.groups = self.groups;
+        (self.infiles, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infiles, options) = opt.into_parts();
+    report::from_files(&options, &infiles, io::stdout().lock())
+}
@@ -0,0 +1,117 @@
+use std::io;
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::collapse::demangle;
+use inferno::collapse::symbolize::{self, Options, Symbolizer};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-symbolize",
+    about,
+    after_help = "\
+Resolves the raw addresses in folded stacks, such as 0x401036 or the
+[app <401036>] frames of inferno-collapse-perf --addrs, to function names
+using the symbol tables and DWARF debug info of the binaries on disk.
+
+  $ cat /proc/$(pidof app)/maps > app.maps
+  $ inferno-symbolize --maps app.maps --lines --inlines folded.txt | inferno-flamegraph > app.svg
+  $ inferno-symbolize --binary ./target/release/app folded.txt"
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Add the functions inlined at each address, marked with _[i]
+    #[structopt(long = "inlines")]
+    inlines: bool,
+
+    /// Add the source file and line of each address
+    #[structopt(long = "lines")]
+    lines: bool,
+
+    /// Keep the hashes of Rust symbols
+    #[structopt(long = "keep-rust-hash")]
+    keep_rust_hash: bool,
+
+    /// Strip C++ template arguments and Rust generics
+    #[structopt(long = "simplify-templates")]
+    simplify_templates: bool,
+
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// A binary whose addresses aren't relocated, such as an executable that isn't position
+    /// independent [may be repeated]
+    #[structopt(
+        short = "b",
+        long = "binary",
+        value_name = "PATH",
+        number_of_values = 1,
+        required_unless = "maps"
+    )]
+    binaries: Vec<PathBuf>,
+
+    /// A file saved from /proc/<pid>/maps while the profiled process was running, which says
+    /// where its binaries were loaded
+    #[structopt(short = "m", long = "maps", value_name = "PATH")]
+    maps: Option<PathBuf>,
+
+    /// Look for the files listed in --maps relative to this directory
+    #[structopt(long = "symfs", value_name = "DIR")]
+    symfs: Option<PathBuf>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Folded stack file, or STDIN if not specified
+    #[structopt(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.binaries = self.binaries;
+        options.maps = self.maps;
+        options.symfs = self.symfs;
+        options.lines = self.lines;
+        options.inlines = self.inlines;
+        options.demangle = {
+            let mut demangle = demangle::Options::default();
+            demangle.keep_rust_hash = self.keep_rust_hash;
+            demangle.simplify_templates = self.simplify_templates;
+            demangle
+        };
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    let mut symbolizer = Symbolizer::new(options)?;
+    symbolize::from_file(&mut symbolizer, infile.as_ref(), io::stdout().lock())
+}
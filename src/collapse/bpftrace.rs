@@ -0,0 +1,606 @@
+use std::borrow::Cow;
+use std::io::{self, prelude::*};
+
+use crate::collapse::common::{self, CollapsePrivate, Occurrences};
+
+/// `bpftrace` folder configuration options.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Options {
+    /// Annotate kernel functions with a `_[k]` suffix.
+    ///
+    /// For bpftrace map dumps, the first of two stacks in a map key (as in
+    /// `@[kstack, ustack, comm]`) is taken to be the kernel stack. For BCC output, the frames after
+    /// the `-` delimiter (`profile -d`) are kernel frames.
+    ///
+    /// Default is `false`.
+    pub annotate_kernel: bool,
+
+    /// Include raw addresses (e.g., `0x7f1c2a3b4c5d`) where symbols can't be found.
+    ///
+    /// Default is `false`.
+    pub include_addrs: bool,
+
+    /// The number of threads to use.
+    ///
+    /// Default is the number of logical cores on your machine.
+    pub nthreads: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            annotate_kernel: false,
+            include_addrs: false,
+            nthreads: *common::DEFAULT_NTHREADS,
+        }
+    }
+}
+
+/// A component of a bpftrace map key.
+enum KeyPart {
+    /// A scalar such as `comm` or `pid`.
+    Scalar(String),
+    /// A stack from `kstack` or `ustack`, leaf frame first.
+    Stack(Vec<String>),
+}
+
+/// A stack collapser for bpftrace map dumps and for the folded output of BCC tools.
+///
+/// bpftrace maps keyed by stacks, such as the one produced by
+///
+/// ```text
+/// bpftrace -e 'profile:hz:99 { @[kstack, ustack, comm] = count(); }'
+/// ```
+///
+/// are turned into one folded line per map entry. Scalar keys (like `comm`) become the root
+/// frames, in the order they appear in the key, followed by the stacks in reverse order, so that
+/// the user stack ends up below the kernel stack.
+///
+/// BCC tools that produce folded output (like `profile -f` or `offcputime -f`) are also
+/// supported, in which case counts of identical stacks are summed and the `-` delimiter between
+/// user and kernel frames is removed.
+///
+/// To construct one, either use `bpftrace::Folder::default()` or create an [`Options`] and use
+/// `bpftrace::Folder::from(options)`.
+pub struct Folder {
+    /// The parts of the map key that we have seen so far, if we are inside a key.
+    key: Option<Vec<KeyPart>>,
+
+    /// The stack we are currently reading frames into, if any.
+    stack: Option<Vec<String>>,
+
+    /// The number of stacks per job to send to the threadpool.
+    nstacks_per_job: usize,
+
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(mut opt: Options) -> Self {
+        if opt.nthreads == 0 {
+            opt.nthreads = 1;
+        }
+        Self {
+            key: None,
+            stack: None,
+            nstacks_per_job: common::DEFAULT_NSTACKS_PER_JOB,
+            opt,
+        }
+    }
+}
+
+impl Default for Folder {
+    fn default() -> Self {
+        Options::default().into()
+    }
+}
+
+impl CollapsePrivate for Folder {
+    fn pre_process<R>(&mut self, _: &mut R, _: &mut Occurrences) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        // Lines such as "Attaching 1 probe..." are skipped as we go.
+        Ok(())
+    }
+
+    fn collapse_single_threaded<R>(
+        &mut self,
+        mut reader: R,
+        occurrences: &mut Occurrences,
+    ) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                break;
+            }
+            let s = String::from_utf8_lossy(&line);
+            let line = s.trim_end();
+            if self.key.is_some() {
+                self.on_key_line(line, occurrences);
+            } else if line.starts_with('@') {
+                self.on_map_line(line, occurrences);
+            } else if line.is_empty() || line.starts_with("Attaching ") || line == "^C" {
+                continue;
+            } else {
+                self.on_folded_line(line, occurrences);
+            }
+        }
+
+        if self.key.is_some() {
+            self.key = None;
+            self.stack = None;
+            return invalid_data_error!("Input data ends in the middle of a map entry.");
+        }
+        Ok(())
+    }
+
+    // A stack ends with a line whose last word is a count, like `]: 42` for bpftrace or
+    // `comm;func 42` for BCC. Frame lines never have whitespace before a trailing number.
+    fn would_end_stack(&mut self, line: &[u8]) -> bool {
+        let line = match line.iter().rposition(|b| !b.is_ascii_whitespace()) {
+            Some(end) => &line[..=end],
+            None => return false,
+        };
+        let start = match line.iter().rposition(|b| !b.is_ascii_digit()) {
+            Some(i) if i + 1 < line.len() => i,
+            _ => return false,
+        };
+        line[start].is_ascii_whitespace() && line[..start].iter().any(|b| !b.is_ascii_whitespace())
+    }
+
+    fn clone_and_reset_stack_context(&self) -> Self {
+        Self {
+            key: None,
+            stack: None,
+            nstacks_per_job: self.nstacks_per_job,
+            opt: self.opt.clone(),
+        }
+    }
+
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        let mut framed = false;
+        let mut found_marker = false;
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            } else if line.starts_with("Attaching ") || line == "^C" {
+                framed = true;
+            } else if line.starts_with('@') {
+                return Some(line.contains('['));
+            } else {
+                // BCC folded output has at least the process name and one frame.
+                let is_folded = match line.rsplitn(2, ' ').collect::<Vec<_>>()[..] {
+                    [count, stack] => stack.contains(';') && count.parse::<usize>().is_ok(),
+                    _ => false,
+                };
+                if !is_folded {
+                    return Some(false);
+                }
+                // Plain folded stacks look just the same, so only claim the input once it has
+                // something that only BCC writes.
+                found_marker |= framed
+                    || line.contains(";-;")
+                    || line.contains("[Missed User Stack]")
+                    || line.contains("[Missed Kernel Stack]");
+            }
+        }
+
+        if found_marker {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    fn nstacks_per_job(&self) -> usize {
+        self.nstacks_per_job
+    }
+
+    fn set_nstacks_per_job(&mut self, n: usize) {
+        self.nstacks_per_job = n;
+    }
+
+    fn nthreads(&self) -> usize {
+        self.opt.nthreads
+    }
+
+    fn set_nthreads(&mut self, n: usize) {
+        self.opt.nthreads = n;
+    }
+}
+
+impl Folder {
+    // The first line of a map entry, like `@[` or `@samples[bash, ` or `@[bash]: 3`.
+    fn on_map_line(&mut self, line: &str, occurrences: &mut Occurrences) {
+        let open = match line.find('[') {
+            Some(open) => open,
+            // A map without keys, like `@total: 42`, has no stacks.
+            None => return,
+        };
+        self.key = Some(Vec::new());
+        self.on_key_text(&line[open + 1..], occurrences);
+    }
+
+    // Any line after the first one of a map entry. Frames are indented, while the other lines
+    // separate the key's components.
+    fn on_key_line(&mut self, line: &str, occurrences: &mut Occurrences) {
+        let trimmed = line.trim_start();
+        if trimmed.len() != line.len() && !trimmed.starts_with(',') && !trimmed.starts_with(']') {
+            if !trimmed.is_empty() {
+                let frame = self.frame(trimmed);
+                self.stack.get_or_insert_with(Vec::new).push(frame);
+            }
+        } else {
+            self.on_key_text(trimmed, occurrences);
+        }
+    }
+
+    // Handles the text of a map key outside of stacks, e.g. `, comm]: 5`.
+    fn on_key_text(&mut self, text: &str, occurrences: &mut Occurrences) {
+        let key = self
+            .key
+            .as_mut()
+            .expect("on_key_text is only called inside a key");
+        if let Some(stack) = self.stack.take() {
+            key.push(KeyPart::Stack(stack));
+        }
+
+        // A leading comma separates this text from the stack before it.
+        let text = text.trim_start_matches(',');
+        let (scalars, count) = match text.rfind("]:") {
+            Some(close) => (&text[..close], Some(text[close + 2..].trim())),
+            None => (text, None),
+        };
+        for scalar in scalars.split(',') {
+            let scalar = scalar.trim();
+            if !scalar.is_empty() {
+                key.push(KeyPart::Scalar(scalar.replace(';', ":")));
+            }
+        }
+
+        match count {
+            Some(count) => {
+                let key = self.key.take().unwrap();
+                match count.parse::<usize>() {
+                    Ok(count) => self.on_key_end(key, count, occurrences),
+                    Err(_) => logging::weird_count(count),
+                }
+            }
+            // The line ended inside the key, so a stack follows.
+            None => self.stack = Some(Vec::new()),
+        }
+    }
+
+    fn on_key_end(&self, key: Vec<KeyPart>, count: usize, occurrences: &mut Occurrences) {
+        let nstacks = key
+            .iter()
+            .filter(|part| matches!(part, KeyPart::Stack(_)))
+            .count();
+
+        let mut stack_str = String::new();
+        for part in &key {
+            if let KeyPart::Scalar(scalar) = part {
+                push_frame(&mut stack_str, scalar, false);
+            }
+        }
+        for (i, part) in key.iter().enumerate().rev() {
+            if let KeyPart::Stack(frames) = part {
+                // With both a kernel and a user stack, the kernel stack comes first.
+                let kernel = self.opt.annotate_kernel && nstacks == 2 && i == first_stack(&key);
+                for frame in frames.iter().rev() {
+                    push_frame(&mut stack_str, frame, kernel);
+                }
+            }
+        }
+
+        if stack_str.is_empty() {
+            return;
+        }
+        occurrences.insert_or_add(stack_str, count);
+    }
+
+    // BCC folded output, like `comm;user_func;-;kernel_func 42`.
+    fn on_folded_line(&self, line: &str, occurrences: &mut Occurrences) {
+        let mut parts = line.rsplitn(2, ' ');
+        let (count, stack) = match (parts.next(), parts.next()) {
+            (Some(count), Some(stack)) => (count, stack.trim_end()),
+            _ => return logging::weird_line(line),
+        };
+        let count = match count.parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => return logging::weird_line(line),
+        };
+
+        let mut stack_str = String::with_capacity(stack.len() + 16);
+        let mut kernel = false;
+        for frame in stack.split(';') {
+            if frame == "-" || frame == "--" {
+                kernel = true;
+                continue;
+            }
+            let frame = common::fix_partially_demangled_rust_symbol(frame);
+            push_frame(
+                &mut stack_str,
+                &frame,
+                kernel && self.opt.annotate_kernel && !frame.ends_with("_[k]"),
+            );
+        }
+        occurrences.insert_or_add(stack_str, count);
+    }
+
+    // Cleans up a frame line of a bpftrace stack. Depending on the stack mode, these look like
+    //
+    //     do_syscall_64+91
+    //     7f2a3b4c5d6e __libc_read+14 (/usr/lib/libc-2.31.so)
+    //     0x7f2a3b4c5d6e
+    //
+    fn frame(&self, line: &str) -> String {
+        let mut func = line;
+
+        // `perf` mode prefixes the address
+        if let Some(space) = func.find(' ') {
+            if func[..space].bytes().all(|b| b.is_ascii_hexdigit()) {
+                func = func[space + 1..].trim_start();
+            }
+        }
+
+        // `perf` mode also includes the module
+        let mut module = None;
+        if func.ends_with(')') {
+            if let Some(open) = func.rfind(" (") {
+                module = Some(&func[open + 2..func.len() - 1]);
+                func = &func[..open];
+            }
+        }
+
+        if let Some(plus) = func.rfind('+') {
+            let offset = &func[plus + 1..];
+            let offset = offset.trim_start_matches("0x");
+            if plus > 0 && !offset.is_empty() && offset.bytes().all(|b| b.is_ascii_hexdigit()) {
+                func = &func[..plus];
+            }
+        }
+
+        let is_addr = func.starts_with("0x") && func[2..].bytes().all(|b| b.is_ascii_hexdigit());
+        if is_addr && !self.opt.include_addrs {
+            func = "[unknown]";
+        }
+
+        if func == "[unknown]" {
+            if let Some(module) = module.filter(|m| *m != "[unknown]") {
+                let module = &module[module.rfind('/').map(|i| i + 1).unwrap_or(0)..];
+                return format!("[{}]", module);
+            }
+        }
+
+        match common::fix_partially_demangled_rust_symbol(func) {
+            Cow::Borrowed(func) => func.replace(';', ":"),
+            Cow::Owned(func) => func.replace(';', ":"),
+        }
+    }
+}
+
+fn first_stack(key: &[KeyPart]) -> usize {
+    key.iter()
+        .position(|part| matches!(part, KeyPart::Stack(_)))
+        .unwrap_or(0)
+}
+
+fn push_frame(stack_str: &mut String, frame: &str, kernel: bool) {
+    if !stack_str.is_empty() {
+        stack_str.push(';');
+    }
+    stack_str.push_str(frame);
+    if kernel {
+        stack_str.push_str("_[k]");
+    }
+}
+
+mod logging {
+    use log::warn;
+
+    pub(super) fn weird_line(line: &str) {
+        warn!("Weird folded stack line: {}", line);
+    }
+
+    pub(super) fn weird_count(count: &str) {
+        warn!("Weird map entry count: {}", count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use lazy_static::lazy_static;
+    use pretty_assertions::assert_eq;
+    use rand::prelude::*;
+
+    use super::*;
+    use crate::collapse::common;
+    use crate::collapse::Collapse;
+
+    lazy_static! {
+        static ref INPUT: Vec<PathBuf> = {
+            [
+                "./tests/data/collapse-bpftrace/bcc-profile.txt",
+                "./tests/data/collapse-bpftrace/kstack-ustack-comm.txt",
+                "./tests/data/collapse-bpftrace/kstack.txt",
+                "./tests/data/collapse-bpftrace/perf-mode.txt",
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        };
+    }
+
+    #[test]
+    fn test_would_end_stack() {
+        let mut folder = Folder::default();
+        assert!(folder.would_end_stack(b"]: 42\n"));
+        assert!(folder.would_end_stack(b", bash]: 3"));
+        assert!(folder.would_end_stack(b"bash;main;foo 17\n"));
+        assert!(!folder.would_end_stack(b"        do_syscall_64+91\n"));
+        assert!(!folder.would_end_stack(b"        401234\n"));
+        assert!(!folder.would_end_stack(b"@[\n"));
+        assert!(!folder.would_end_stack(b"\n"));
+    }
+
+    #[test]
+    fn test_is_applicable() {
+        let mut folder = Folder::default();
+        assert_eq!(
+            Collapse::is_applicable(&mut folder, "Attaching 1 probe...\n@[\n"),
+            Some(true)
+        );
+        assert_eq!(
+            Collapse::is_applicable(&mut folder, "@total: 42\n"),
+            Some(false)
+        );
+        assert_eq!(
+            Collapse::is_applicable(&mut folder, "main;foo 17\nmain;bar 3\n"),
+            None
+        );
+        assert_eq!(
+            Collapse::is_applicable(
+                &mut folder,
+                "main;foo 17\nbash;[Missed User Stack];-;vfs_write 3\n"
+            ),
+            Some(true)
+        );
+        assert_eq!(
+            Collapse::is_applicable(&mut folder, "Attaching 1 probe...\nmain;foo 17\n"),
+            Some(true)
+        );
+        assert_eq!(
+            Collapse::is_applicable(&mut folder, "main;foo 17\nnot a stack\n"),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_frame() {
+        let folder = Folder::default();
+        assert_eq!(folder.frame("do_syscall_64+91"), "do_syscall_64");
+        assert_eq!(folder.frame("operator+"), "operator+");
+        assert_eq!(
+            folder.frame("7f2a3b4c5d6e __libc_read+14 (/usr/lib/libc-2.31.so)"),
+            "__libc_read"
+        );
+        assert_eq!(
+            folder.frame("7f2a3b4c5d6e [unknown] (/usr/lib/libc-2.31.so)"),
+            "[libc-2.31.so]"
+        );
+        assert_eq!(folder.frame("0x7f2a3b4c5d6e"), "[unknown]");
+
+        let folder = Folder::from(Options {
+            include_addrs: true,
+            ..Default::default()
+        });
+        assert_eq!(folder.frame("0x7f2a3b4c5d6e"), "0x7f2a3b4c5d6e");
+    }
+
+    #[test]
+    fn test_collapse_multi_bpftrace() -> io::Result<()> {
+        let mut folder = Folder::default();
+        common::testing::test_collapse_multi(&mut folder, &INPUT)
+    }
+
+    #[test]
+    fn test_collapse_multi_bpftrace_simple() -> io::Result<()> {
+        let path = "./tests/data/collapse-bpftrace/kstack-ustack-comm.txt";
+        let mut file = fs::File::open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let mut folder = Folder::default();
+        <Folder as Collapse>::collapse(&mut folder, &bytes[..], io::sink())
+    }
+
+    /// Varies the nstacks_per_job parameter and outputs the 10 fastests configurations by file.
+    ///
+    /// Command: `cargo test bench_nstacks_bpftrace --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_nstacks_bpftrace() -> io::Result<()> {
+        let mut folder = Folder::default();
+        common::testing::bench_nstacks(&mut folder, &INPUT)
+    }
+
+    #[test]
+    #[ignore]
+    /// Fuzz test the multithreaded collapser.
+    ///
+    /// Command: `cargo test fuzz_collapse_bpftrace --release -- --ignored --nocapture`
+    fn fuzz_collapse_bpftrace() -> io::Result<()> {
+        let seed = thread_rng().gen::<u64>();
+        println!("Random seed: {}", seed);
+        let mut rng = SmallRng::seed_from_u64(seed);
+
+        let mut buf_actual = Vec::new();
+        let mut buf_expected = Vec::new();
+        let mut count = 0;
+
+        let inputs = common::testing::read_inputs(&INPUT)?;
+
+        loop {
+            let nstacks_per_job = rng.gen_range(1, 500 + 1);
+            let options = Options {
+                annotate_kernel: rng.gen(),
+                include_addrs: rng.gen(),
+                nthreads: rng.gen_range(2, 32 + 1),
+            };
+
+            for (path, input) in inputs.iter() {
+                buf_actual.clear();
+                buf_expected.clear();
+
+                let mut folder = {
+                    let mut options = options.clone();
+                    options.nthreads = 1;
+                    Folder::from(options)
+                };
+                folder.nstacks_per_job = nstacks_per_job;
+                <Folder as Collapse>::collapse(&mut folder, &input[..], &mut buf_expected)?;
+                let expected = std::str::from_utf8(&buf_expected[..]).unwrap();
+
+                let mut folder = Folder::from(options.clone());
+                folder.nstacks_per_job = nstacks_per_job;
+                <Folder as Collapse>::collapse(&mut folder, &input[..], &mut buf_actual)?;
+                let actual = std::str::from_utf8(&buf_actual[..]).unwrap();
+
+                if actual != expected {
+                    eprintln!(
+                        "Failed on file: {}\noptions: {:#?}\n",
+                        path.display(),
+                        options
+                    );
+                    assert_eq!(actual, expected);
+                }
+            }
+
+            count += 1;
+            if count % 10 == 0 {
+                println!("Successfully ran {} fuzz tests.", count);
+            }
+        }
+    }
+}
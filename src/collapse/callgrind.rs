@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::FoldedStacks;
+
+/// `callgrind` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// The event (such as `Ir`, `Dr` or `Cycles`) whose cost becomes the sample count.
+    ///
+    /// Default is `None`, which uses the first event listed on the profile's `events:` line.
+    pub event: Option<String>,
+}
+
+/// A function seen in the profile, along with its costs for the selected event.
+#[derive(Clone, Debug, Default)]
+struct Function {
+    name: String,
+
+    /// Cost incurred in the function itself.
+    self_cost: u64,
+
+    /// Inclusive cost of the calls made by this function, by callee.
+    calls: Vec<(usize, u64)>,
+
+    /// Inclusive cost of the calls made to this function.
+    incoming: u64,
+}
+
+impl Function {
+    fn inclusive(&self) -> u64 {
+        self.self_cost + self.calls.iter().map(|&(_, cost)| cost).sum::<u64>()
+    }
+}
+
+/// A stack collapser for the output of Valgrind's `callgrind` and `cachegrind` tools.
+///
+/// Callgrind does not record individual stacks; it records the cost of each function along with
+/// the inclusive cost of every call between two functions. Stacks are rebuilt by walking the call
+/// graph down from the functions that were not (entirely) called by others, splitting each
+/// function's cost between its callers in proportion to the cost of their calls. This is exact
+/// for functions that are called from a single place, and an approximation otherwise. Calls into
+/// a function that is already on the stack (recursion) are not followed.
+///
+/// Cachegrind profiles have no calls, so each function ends up as a stack of its own.
+///
+/// To construct one, either use `callgrind::Folder::default()` or create an [`Options`] and use
+/// `callgrind::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    /// Functions seen so far, in order of appearance.
+    functions: Vec<Function>,
+
+    /// Index into `functions` by name.
+    function_indices: HashMap<String, usize>,
+
+    /// Function names by compressed id, as in `fn=(12) name`.
+    compressed_names: HashMap<String, String>,
+
+    /// The function that cost lines are currently attributed to.
+    current_fn: Option<usize>,
+
+    /// The function called by the next `calls=` line.
+    current_cfn: Option<usize>,
+
+    /// Set by a `calls=` line; the following cost line is the inclusive cost of that call.
+    pending_call: Option<usize>,
+
+    /// Number of position columns at the start of each cost line.
+    npositions: usize,
+
+    /// Column of the selected event among the costs.
+    event_index: Option<usize>,
+
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        self.collapse_to_occurrences(reader)?
+            .write_and_clear(writer)
+    }
+
+    fn collapse_into<R>(&mut self, reader: R, stacks: &mut FoldedStacks) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        self.collapse_to_occurrences(reader)?
+            .add_to_and_clear(stacks);
+        Ok(())
+    }
+
+    /// Check for a header made of `key: value` lines that includes an `events:` line.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            } else if line.starts_with("events:") {
+                return Some(true);
+            }
+            match line.find(':') {
+                Some(i) if is_header_key(&line[..i]) => continue,
+                _ => return Some(false),
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder {
+            opt,
+            ..Default::default()
+        }
+    }
+}
+
+impl Folder {
+    fn collapse_to_occurrences<R>(&mut self, mut reader: R) -> io::Result<Occurrences>
+    where
+        R: io::BufRead,
+    {
+        self.npositions = 1;
+
+        // Process the data...
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let line = l.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Err(e) = self.on_line(line) {
+                self.reset();
+                return Err(e);
+            }
+        }
+
+        if self.event_index.is_none() {
+            warn!("File ended before events line");
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        self.write_stacks(&mut occurrences);
+
+        // Reset the state...
+        self.reset();
+        Ok(occurrences)
+    }
+
+    fn on_line(&mut self, line: &str) -> io::Result<()> {
+        let first = line.as_bytes()[0];
+        if first.is_ascii_digit() || first == b'+' || first == b'-' || first == b'*' {
+            return self.on_cost_line(line);
+        }
+
+        // Specification lines are `key=value`, and header lines are `key: value`, where the value
+        // may contain either separator.
+        let separator = line.find(&['=', ':'][..]);
+        if let Some(eq) = separator.filter(|&i| line.as_bytes()[i] == b'=') {
+            let (key, value) = (&line[..eq], line[eq + 1..].trim());
+            match key {
+                "fn" => {
+                    let name = self.resolve_name(value, line)?;
+                    self.current_fn = Some(self.function_index(name));
+                }
+                "cfn" => {
+                    let name = self.resolve_name(value, line)?;
+                    self.current_cfn = Some(self.function_index(name));
+                }
+                "calls" => match self.current_cfn {
+                    Some(callee) => self.pending_call = Some(callee),
+                    None => {
+                        return invalid_data_error!("Call without a called function:\n{}", line)
+                    }
+                },
+                // Objects, files and jumps don't change which function costs belong to.
+                "ob" | "fl" | "fi" | "fe" | "cob" | "cfi" | "cfl" | "jump" | "jcnd" => {}
+                _ => warn!("Unknown specification line:\n{}", line),
+            }
+            return Ok(());
+        }
+
+        if let Some(colon) = separator {
+            let (key, value) = (&line[..colon], line[colon + 1..].trim());
+            match key {
+                "events" => self.on_events(value)?,
+                "positions" => self.npositions = value.split_whitespace().count(),
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        invalid_data_error!("Unable to parse line:\n{}", line)
+    }
+
+    fn on_events(&mut self, events: &str) -> io::Result<()> {
+        let mut events = events.split_whitespace();
+        self.event_index = match self.opt.event {
+            Some(ref event) => match events.position(|e| e == event) {
+                Some(index) => Some(index),
+                None => return invalid_data_error!("Event {} not found in profile", event),
+            },
+            None => Some(0),
+        };
+        Ok(())
+    }
+
+    // Cost lines are made of position columns (like the line number) followed by one cost for
+    // each event. Trailing costs that are zero may be left out.
+    fn on_cost_line(&mut self, line: &str) -> io::Result<()> {
+        let event_index = match self.event_index {
+            Some(index) => index,
+            None => return invalid_data_error!("Cost line before events line:\n{}", line),
+        };
+        let cost = match line
+            .split_whitespace()
+            .skip(self.npositions)
+            .nth(event_index)
+        {
+            Some(cost) => match cost.parse::<u64>() {
+                Ok(cost) => cost,
+                Err(_) => return invalid_data_error!("Invalid cost in line:\n{}", line),
+            },
+            None => 0,
+        };
+
+        let caller = match self.current_fn {
+            Some(caller) => caller,
+            None => return invalid_data_error!("Cost line outside of a function:\n{}", line),
+        };
+
+        match self.pending_call.take() {
+            // Recursive calls are already accounted for in the cost of the outermost call.
+            Some(callee) if callee == caller => {}
+            Some(callee) => {
+                let calls = &mut self.functions[caller].calls;
+                match calls.iter_mut().find(|(c, _)| *c == callee) {
+                    Some((_, total)) => *total += cost,
+                    None => calls.push((callee, cost)),
+                }
+                self.functions[callee].incoming += cost;
+            }
+            None => self.functions[caller].self_cost += cost,
+        }
+        Ok(())
+    }
+
+    // Names may be compressed: the first time a name is seen, it is given as `(id) name`, and
+    // later uses refer to it as just `(id)`.
+    fn resolve_name(&mut self, value: &str, line: &str) -> io::Result<String> {
+        if !value.starts_with('(') {
+            return Ok(value.replace(';', ":"));
+        }
+        let close = match value.find(')') {
+            Some(close) => close,
+            None => return invalid_data_error!("Invalid compressed name:\n{}", line),
+        };
+        let id = &value[1..close];
+        let name = value[close + 1..].trim();
+        if name.is_empty() {
+            match self.compressed_names.get(id) {
+                Some(name) => Ok(name.clone()),
+                None => invalid_data_error!("Unknown compressed name {}:\n{}", id, line),
+            }
+        } else {
+            let name = name.replace(';', ":");
+            self.compressed_names.insert(id.to_string(), name.clone());
+            Ok(name)
+        }
+    }
+
+    fn function_index(&mut self, name: String) -> usize {
+        if let Some(&index) = self.function_indices.get(&name) {
+            return index;
+        }
+        let index = self.functions.len();
+        self.functions.push(Function {
+            name: name.clone(),
+            ..Default::default()
+        });
+        self.function_indices.insert(name, index);
+        index
+    }
+
+    fn write_stacks(&self, occurrences: &mut Occurrences) {
+        // (function, depth, cost flowing into the function along this path)
+        let mut pending: Vec<(usize, usize, f64)> = Vec::new();
+        for (index, function) in self.functions.iter().enumerate().rev() {
+            let inclusive = function.inclusive();
+            if inclusive > function.incoming {
+                pending.push((index, 0, (inclusive - function.incoming) as f64));
+            }
+        }
+
+        let mut path: Vec<usize> = Vec::new();
+        while let Some((index, depth, flow)) = pending.pop() {
+            path.truncate(depth);
+            path.push(index);
+
+            let function = &self.functions[index];
+            let inclusive = function.inclusive();
+            if inclusive == 0 {
+                continue;
+            }
+            let share = (flow / inclusive as f64).min(1.0);
+
+            let count = (function.self_cost as f64 * share).round();
+            if count >= 1.0 {
+                let stack = path
+                    .iter()
+                    .map(|&i| self.functions[i].name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(";");
+                occurrences.insert_or_add(stack, count as usize);
+            }
+
+            for &(callee, cost) in function.calls.iter().rev() {
+                let flow = cost as f64 * share;
+                // Branches this small don't add up to a sample, and skipping callees that are
+                // already on the stack keeps cycles from being followed forever.
+                if flow >= 0.5 && !path.contains(&callee) {
+                    pending.push((callee, depth + 1, flow));
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.functions.clear();
+        self.function_indices.clear();
+        self.compressed_names.clear();
+        self.current_fn = None;
+        self.current_cfn = None;
+        self.pending_call = None;
+        self.npositions = 1;
+        self.event_index = None;
+    }
+}
+
+// Keys that appear in the header of callgrind and cachegrind profiles.
+fn is_header_key(key: &str) -> bool {
+    matches!(
+        key,
+        "version"
+            | "creator"
+            | "pid"
+            | "cmd"
+            | "part"
+            | "desc"
+            | "thread"
+            | "positions"
+            | "event"
+            | "totals"
+            | "summary"
+    )
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::io;
+
+use log::warn;
+use serde::Deserialize;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::FoldedStacks;
+
+// The name V8 gives the node at the base of every stack.
+static ROOT: &str = "(root)";
+
+/// `chrome` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Emit the samples in the order they were taken, merging consecutive samples with the same
+    /// stack, instead of summing all samples of each stack.
+    ///
+    /// Use this to produce input for a flame chart (see
+    /// [`flamegraph::Options::flame_chart`](crate::flamegraph::Options::flame_chart)).
+    ///
+    /// Default is `false`.
+    pub flame_chart: bool,
+}
+
+/// A stack collapser for the `.cpuprofile` files written by Chrome DevTools, Node.js
+/// (`--cpu-prof`) and other tools built on V8's CPU profiler.
+///
+/// Frames are named after their function, followed by the script's `url:line` when it has one.
+/// The `(root)` node at the base of every stack is left out.
+///
+/// To construct one, either use `chrome::Folder::default()` or create an [`Options`] and use
+/// `chrome::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Profile {
+    nodes: Vec<Node>,
+    #[serde(default)]
+    samples: Vec<u64>,
+    #[serde(default)]
+    time_deltas: Vec<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Node {
+    id: u64,
+    call_frame: CallFrame,
+    #[serde(default)]
+    hit_count: usize,
+    #[serde(default)]
+    children: Vec<u64>,
+    #[serde(default)]
+    parent: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallFrame {
+    function_name: String,
+    #[serde(default)]
+    url: String,
+    /// Zero-based, or -1 if unknown.
+    #[serde(default)]
+    line_number: i64,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let (mut occurrences, runs) = self.collapse_profile(reader)?;
+        occurrences.write_and_clear(&mut writer)?;
+        for (stack, count) in runs {
+            writeln!(writer, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+
+    fn collapse_into<R>(&mut self, reader: R, stacks: &mut FoldedStacks) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        let (mut occurrences, runs) = self.collapse_profile(reader)?;
+        occurrences.add_to_and_clear(stacks);
+        for (stack, count) in runs {
+            stacks.add(&stack, count);
+        }
+        Ok(())
+    }
+
+    /// Check for a JSON object with `nodes` that have a `callFrame`.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if input.is_empty() {
+            None
+        } else if !input.starts_with('{') {
+            Some(false)
+        } else if input.contains("\"nodes\"") && input.contains("\"callFrame\"") {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    // Collapses the samples of a profile by their stack or, for a flame chart, into the runs of
+    // consecutive samples with the same stack, in the order they were taken.
+    fn collapse_profile<R>(
+        &mut self,
+        mut reader: R,
+    ) -> io::Result<(Occurrences, Vec<(String, usize)>)>
+    where
+        R: io::BufRead,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.iter().all(u8::is_ascii_whitespace) {
+            warn!("File is empty");
+            return Ok((Occurrences::new(1), Vec::new()));
+        }
+        let profile: Profile = match serde_json::from_slice(&data) {
+            Ok(profile) => profile,
+            Err(e) => return invalid_data_error!("Invalid cpuprofile: {}", e),
+        };
+
+        let stacks = Stacks::new(&profile);
+
+        if profile.samples.is_empty() {
+            // Without samples, all we have to go by is the hit count of each node.
+            let mut occurrences = Occurrences::new(1);
+            for node in &profile.nodes {
+                if node.hit_count > 0 {
+                    match stacks.get(node.id) {
+                        Some("") | None => {}
+                        Some(stack) => occurrences.insert_or_add(stack.to_string(), node.hit_count),
+                    }
+                }
+            }
+            return Ok((occurrences, Vec::new()));
+        }
+
+        if !self.opt.flame_chart {
+            let mut occurrences = Occurrences::new(1);
+            for &id in &profile.samples {
+                match stacks.get(id) {
+                    Some("") => {}
+                    Some(stack) => occurrences.insert_or_add(stack.to_string(), 1),
+                    None => warn!("Sample refers to unknown node {}", id),
+                }
+            }
+            return Ok((occurrences, Vec::new()));
+        }
+
+        // Samples are mostly in time order already, but the deltas between them can be negative.
+        let mut timestamps = Vec::with_capacity(profile.samples.len());
+        let mut timestamp = 0;
+        for (i, &id) in profile.samples.iter().enumerate() {
+            timestamp += profile.time_deltas.get(i).copied().unwrap_or(0);
+            timestamps.push((timestamp, id));
+        }
+        timestamps.sort_by_key(|&(timestamp, _)| timestamp);
+
+        let mut runs = Vec::new();
+        let mut run: Option<(&str, usize)> = None;
+        for (_, id) in timestamps {
+            let stack = match stacks.get(id) {
+                Some("") => continue,
+                Some(stack) => stack,
+                None => {
+                    warn!("Sample refers to unknown node {}", id);
+                    continue;
+                }
+            };
+            match run {
+                Some((last, ref mut count)) if last == stack => *count += 1,
+                _ => {
+                    if let Some((last, count)) = run.take() {
+                        runs.push((last.to_string(), count));
+                    }
+                    run = Some((stack, 1));
+                }
+            }
+        }
+        if let Some((last, count)) = run {
+            runs.push((last.to_string(), count));
+        }
+        Ok((Occurrences::new(1), runs))
+    }
+}
+
+/// The stack ending in each node of a profile.
+struct Stacks {
+    stacks: HashMap<u64, String>,
+}
+
+impl Stacks {
+    fn new(profile: &Profile) -> Self {
+        let mut parents = HashMap::new();
+        for node in &profile.nodes {
+            if let Some(parent) = node.parent {
+                parents.insert(node.id, parent);
+            }
+            for &child in &node.children {
+                parents.insert(child, node.id);
+            }
+        }
+        let frames: HashMap<u64, String> = profile
+            .nodes
+            .iter()
+            .map(|node| (node.id, frame(&node.call_frame)))
+            .collect();
+
+        let mut stacks = HashMap::with_capacity(profile.nodes.len());
+        let mut stack = Vec::new();
+        for node in &profile.nodes {
+            stack.clear();
+            let mut next = Some(node.id);
+            while let Some(id) = next {
+                let frame = match frames.get(&id) {
+                    Some(frame) => frame,
+                    None => break,
+                };
+                next = parents.get(&id).copied();
+                if next.is_some() || frame != ROOT {
+                    stack.push(frame.as_str());
+                }
+                // Guard against cycles in malformed profiles.
+                if stack.len() > frames.len() {
+                    break;
+                }
+            }
+            stack.reverse();
+            stacks.insert(node.id, stack.join(";"));
+        }
+        Stacks { stacks }
+    }
+
+    // Returns the stack ending in the given node, or `None` if there is no such node. The stack is
+    // empty for the root node.
+    fn get(&self, id: u64) -> Option<&str> {
+        self.stacks.get(&id).map(String::as_str)
+    }
+}
+
+fn frame(call_frame: &CallFrame) -> String {
+    let name = if call_frame.function_name.is_empty() {
+        "(anonymous)"
+    } else {
+        &call_frame.function_name
+    };
+    let frame = if call_frame.url.is_empty() {
+        name.to_string()
+    } else if call_frame.line_number < 0 {
+        format!("{} {}", name, call_frame.url)
+    } else {
+        format!("{} {}:{}", name, call_frame.url, call_frame.line_number + 1)
+    };
+    frame.replace(';', ":")
+}
@@ -10,6 +10,8 @@ use ahash::AHashMap;
 use dashmap::DashMap;
 use lazy_static::lazy_static;
 
+use crate::folded::FoldedStacks;
+
 macro_rules! invalid_data_error {
     ($($arg:tt)*) => {{
         Err(io::Error::new(
@@ -36,7 +38,7 @@ pub(crate) const DEFAULT_NSTACKS_PER_JOB: usize = 100;
 #[cfg(feature = "multithreaded")]
 const NBYTES_PER_STACK_GUESS: usize = 1024;
 
-const RUST_HASH_LENGTH: usize = 17;
+pub(crate) const RUST_HASH_LENGTH: usize = 17;
 
 #[cfg(feature = "multithreaded")]
 lazy_static! {
@@ -151,10 +153,20 @@ pub trait CollapsePrivate: Send + Sized {
     // ******************** PROVIDED METHODS ********************* //
     // *********************************************************** //
 
-    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
     where
         R: io::BufRead,
         W: io::Write,
+    {
+        let mut occurrences = self.collapse_to_occurrences(reader)?;
+
+        // Write results.
+        occurrences.write_and_clear(writer)
+    }
+
+    fn collapse_to_occurrences<R>(&mut self, mut reader: R) -> io::Result<Occurrences>
+    where
+        R: io::BufRead,
     {
         let mut occurrences = Occurrences::new(self.nthreads());
 
@@ -169,8 +181,7 @@ pub trait CollapsePrivate: Send + Sized {
             self.collapse_single_threaded(reader, &mut occurrences)?;
         }
 
-        // Write results.
-        occurrences.write_and_clear(writer)
+        Ok(occurrences)
     }
 
     #[cfg(not(feature = "multithreaded"))]
@@ -354,6 +365,29 @@ pub trait CollapsePrivate: Send + Sized {
     }
 }
 
+/// Private trait for internal library authors.
+///
+/// If you implement this trait, your type can be used with the public-facing
+/// [`Stream`](crate::collapse::stream::Stream), which feeds it input one line
+/// at a time as the input arrives, rather than handing it a reader that ends.
+pub trait CollapseLines {
+    /// Process a single line of input, including any trailing newline.
+    ///
+    /// Lines arrive in order, starting with any header lines; so, unlike
+    /// `CollapsePrivate`, implementors keep track of whether they are still in
+    /// the header themselves. Stacks should be added to `occurrences` as soon as
+    /// they are complete.
+    fn collapse_line(&mut self, line: &[u8], occurrences: &mut Occurrences) -> io::Result<()>;
+
+    /// Signal that there is no more input.
+    ///
+    /// Implementors should add any stack that is still in progress to
+    /// `occurrences` (or return an error if the format doesn't allow the input
+    /// to end there), and then reset themselves so that they are ready for new
+    /// input.
+    fn end_of_input(&mut self, occurrences: &mut Occurrences) -> io::Result<()>;
+}
+
 /// Occurrences is a HashMap, which uses:
 /// * AHashMap if single-threaded
 /// * DashMap if multi-threaded
@@ -427,19 +461,11 @@ impl Occurrences {
         }
     }
 
-    pub(crate) fn write_and_clear<W>(&mut self, mut writer: W) -> io::Result<()>
-    where
-        W: io::Write,
-    {
+    /// Removes all key-count pairs from the map, returning them in no particular order.
+    pub(crate) fn drain(&mut self) -> Vec<(String, usize)> {
         use self::Occurrences::*;
         match self {
-            SingleThreaded(ref mut map) => {
-                let mut contents: Vec<_> = map.drain().collect();
-                contents.sort();
-                for (key, value) in contents {
-                    writeln!(writer, "{} {}", key, value)?;
-                }
-            }
+            SingleThreaded(ref mut map) => map.drain().collect(),
             #[cfg(feature = "multithreaded")]
             MultiThreaded(ref mut arc) => {
                 let map = match Arc::get_mut(arc) {
@@ -457,14 +483,27 @@ impl Occurrences {
                         ahash::RandomState::default(),
                     ),
                 );
-                let contents = map.iter().collect::<Vec<_>>();
-                let mut pairs = contents.iter().map(|pair| pair.pair()).collect::<Vec<_>>();
-                pairs.sort();
-                for (key, value) in pairs {
-                    writeln!(writer, "{} {}", key, value)?;
-                }
+                map.into_iter().collect()
             }
         }
+    }
+
+    /// Moves all key-count pairs into `stacks`.
+    pub(crate) fn add_to_and_clear(&mut self, stacks: &mut FoldedStacks) {
+        for (stack, count) in self.drain() {
+            stacks.add(&stack, count);
+        }
+    }
+
+    pub(crate) fn write_and_clear<W>(&mut self, mut writer: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        let mut contents = self.drain();
+        contents.sort();
+        for (key, value) in contents {
+            writeln!(writer, "{} {}", key, value)?;
+        }
         Ok(())
     }
 }
@@ -0,0 +1,376 @@
+use std::borrow::Cow;
+use std::io::{self, prelude::*};
+
+use super::common::{fix_partially_demangled_rust_symbol, RUST_HASH_LENGTH};
+use super::Collapse;
+use crate::folded::FoldedStacks;
+
+/// Configure how frames are demangled.
+///
+/// All options default to off, so that Rust hashes are stripped and C++ templates are kept.
+#[derive(Debug, Clone, Copy, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Keep the hash that rustc adds to the end of legacy Rust symbols, as in
+    /// `core::ptr::drop_in_place::h0123456789abcdef`, and the crate disambiguators of v0 symbols,
+    /// as in `mycrate[3c1c0]::foo`.
+    ///
+    /// Without the hashes, the frames of different builds of a program line up with each other.
+    pub keep_rust_hash: bool,
+
+    /// Strip the arguments of C++ templates and Rust generics from frames, as in
+    /// `std::vector::push_back(int const&)` for
+    /// `std::vector<int, std::allocator<int> >::push_back(int const&)`.
+    ///
+    /// This applies to every frame, including those that the profiler already demangled.
+    pub simplify_templates: bool,
+}
+
+/// A collapser that demangles the frames written by another collapser.
+///
+/// Frames with legacy (`_ZN...E`) or v0 (`_R...`) Rust symbols and Itanium C++ (`_Z...`) symbols
+/// are demangled, as are Rust symbols that the profiler only partially demangled, as in
+/// `_$LT$alloc..vec..Vec$LT$T$GT$$GT$::push::h0123456789abcdef`. Module prefixes (as in
+/// ``libfoo.so`_ZN3foo3barEv``) and the annotations of `perf` (as in `_[k]`) are kept as they
+/// are.
+///
+/// Stacks that end up with the same frames, such as ones that only differed in their Rust
+/// hashes, are merged, and the output is sorted like the output of the wrapped collapser.
+///
+/// ```no_run
+/// use std::io;
+///
+/// use inferno::collapse::demangle::{self, Demangle};
+/// use inferno::collapse::{perf, Collapse};
+///
+/// let mut folder = Demangle::new(perf::Folder::default(), demangle::Options::default());
+/// folder.collapse_file(Some("perf.txt"), io::stdout().lock())?;
+/// # Ok::<(), io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Demangle<C> {
+    collapser: C,
+    opt: Options,
+}
+
+impl<C> Demangle<C> {
+    /// Demangle the output of `collapser`.
+    pub fn new(collapser: C, opt: Options) -> Self {
+        Demangle { collapser, opt }
+    }
+
+    /// The wrapped collapser.
+    pub fn into_inner(self) -> C {
+        self.collapser
+    }
+}
+
+impl<C> Collapse for Demangle<C>
+where
+    C: Collapse,
+{
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        let mut stacks = FoldedStacks::new();
+        self.collapse_into(reader, &mut stacks)?;
+        stacks.write_to(writer)
+    }
+
+    fn collapse_into<R>(&mut self, reader: R, stacks: &mut FoldedStacks) -> io::Result<()>
+    where
+        R: BufRead,
+    {
+        let mut collapsed = FoldedStacks::new();
+        self.collapser.collapse_into(reader, &mut collapsed)?;
+        demangle_stacks(&self.opt, &collapsed, stacks);
+        Ok(())
+    }
+
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        self.collapser.is_applicable(input)
+    }
+}
+
+/// Demangle the frames of the folded stack lines of `reader`, such as the output of a collapser
+/// that was run without demangling.
+///
+/// The stacks are merged and written out sorted; see [`Demangle`] for details.
+pub fn from_reader<R, W>(opt: &Options, reader: R, writer: W) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    let collapsed = FoldedStacks::from_reader(reader)?;
+    let mut stacks = FoldedStacks::new();
+    demangle_stacks(opt, &collapsed, &mut stacks);
+    stacks.write_to(writer)
+}
+
+// Adds the stacks of `collapsed` to `stacks` with their frames demangled.
+fn demangle_stacks(opt: &Options, collapsed: &FoldedStacks, stacks: &mut FoldedStacks) {
+    for (stack, count) in collapsed {
+        stacks.add_frames(
+            stack.frames().map(|frame| demangle_frame(opt, frame)),
+            count,
+        );
+    }
+}
+
+/// Demangle a single frame.
+///
+/// Frames that aren't mangled are returned unchanged, apart from stripping their Rust hashes and
+/// template arguments as configured by `opt`.
+pub fn demangle_frame<'a>(opt: &Options, frame: &'a str) -> Cow<'a, str> {
+    // Keep the module of dtrace and sample frames and the annotations of perf frames.
+    let start = frame.rfind('`').map_or(0, |i| i + 1);
+    let end = match frame.rfind("_[") {
+        Some(i) if frame.ends_with(']') && frame.len() - i <= 5 => i,
+        _ => frame.len(),
+    };
+    if start > end {
+        return Cow::Borrowed(frame);
+    }
+
+    let symbol = &frame[start..end];
+    let mut demangled = demangle_symbol(opt, symbol);
+    if opt.simplify_templates {
+        if let Cow::Owned(simplified) = strip_generics(&demangled) {
+            demangled = Cow::Owned(simplified);
+        }
+    }
+
+    match demangled {
+        Cow::Borrowed(demangled) if start == 0 && end == frame.len() => Cow::Borrowed(demangled),
+        Cow::Borrowed(demangled) if demangled.len() == symbol.len() => Cow::Borrowed(frame),
+        demangled => Cow::Owned(format!("{}{}{}", &frame[..start], demangled, &frame[end..])),
+    }
+}
+
+// Demangles a symbol without a module or annotation.
+fn demangle_symbol<'a>(opt: &Options, symbol: &'a str) -> Cow<'a, str> {
+    let mangled = symbol.trim_start_matches('_');
+    let underscores = symbol.len() - mangled.len();
+    let is_rust = mangled.starts_with("ZN") || mangled.starts_with('R');
+    let is_cpp = mangled.starts_with('Z');
+
+    if (underscores == 1 || underscores == 2) && is_rust {
+        if let Ok(demangled) = rustc_demangle::try_demangle(symbol) {
+            return Cow::Owned(if opt.keep_rust_hash {
+                format!("{}", demangled)
+            } else {
+                format!("{:#}", demangled)
+            });
+        }
+    }
+
+    if (underscores == 1 || underscores == 2) && is_cpp {
+        if let Ok(demangled) = cpp_demangle::Symbol::new(symbol)
+            .map_err(|_| ())
+            .and_then(|s| s.demangle(&Default::default()).map_err(|_| ()))
+        {
+            return Cow::Owned(demangled);
+        }
+    }
+
+    if opt.keep_rust_hash {
+        return Cow::Borrowed(symbol);
+    }
+    match fix_partially_demangled_rust_symbol(symbol) {
+        Cow::Borrowed(symbol) => Cow::Borrowed(strip_rust_hash(symbol)),
+        fixed => fixed,
+    }
+}
+
+/// Strips the hash that rustc appends to legacy mangled symbols, as in "::h0123456789abcdef".
+pub(crate) fn strip_rust_hash(frame: &str) -> &str {
+    if frame.len() > RUST_HASH_LENGTH + 2 {
+        let (name, hash) = frame.split_at(frame.len() - RUST_HASH_LENGTH);
+        if name.ends_with("::")
+            && hash.starts_with('h')
+            && hash[1..].chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return &name[..name.len() - 2];
+        }
+    }
+    frame
+}
+
+/// Strips generic and template parameters, as in "Vec<T>::push" or "std::vector<int>::at".
+///
+/// Brackets that start a path segment, as in "<Vec<T> as Drop>::drop", are qualified paths rather
+/// than parameters, so they are kept along with the trait name. The "->" of function types inside
+/// the parameters doesn't close them, and the brackets of operators, as in "operator<<", are
+/// left alone.
+pub(crate) fn strip_generics(frame: &str) -> Cow<'_, str> {
+    if !frame.contains('<') {
+        return Cow::Borrowed(frame);
+    }
+
+    let bytes = frame.as_bytes();
+    let mut stripped = String::with_capacity(frame.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let starts_segment = i == 0 || bytes[i - 1] == b':' || bytes[i - 1] == b'<';
+        if bytes[i] != b'<' || starts_segment {
+            let next = frame[i..].chars().next().unwrap();
+            stripped.push(next);
+            i += next.len_utf8();
+            continue;
+        }
+        if stripped.ends_with("operator") {
+            while i < bytes.len() && (bytes[i] == b'<' || bytes[i] == b'=') {
+                stripped.push(bytes[i] as char);
+                i += 1;
+            }
+            continue;
+        }
+
+        let mut depth = 0;
+        let mut end = None;
+        for (j, &b) in bytes.iter().enumerate().skip(i) {
+            match b {
+                b'<' => depth += 1,
+                b'>' if bytes[j - 1] != b'-' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = Some(j);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        match end {
+            Some(end) => i = end + 1,
+            None => {
+                stripped.push_str(&frame[i..]);
+                break;
+            }
+        }
+    }
+    Cow::Owned(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn demangle(opt: &Options, frame: &str) -> String {
+        demangle_frame(opt, frame).into_owned()
+    }
+
+    #[test]
+    fn demangles_rust_and_cpp_symbols() {
+        let opt = Options::default();
+        assert_eq!(
+            demangle(&opt, "_ZN4core3ptr13drop_in_place17h0123456789abcdefE"),
+            "core::ptr::drop_in_place"
+        );
+        assert_eq!(demangle(&opt, "_RNvCs1234_7mycrate3foo"), "mycrate::foo");
+        assert_eq!(
+            demangle(&opt, "_ZNSt6vectorIiSaIiEE9push_backERKi"),
+            "std::vector<int, std::allocator<int> >::push_back(int const&)"
+        );
+        assert_eq!(demangle(&opt, "__ZN3foo3barEv"), "foo::bar()");
+        assert_eq!(demangle(&opt, "main"), "main");
+        assert_eq!(demangle(&opt, "_Zombie"), "_Zombie");
+    }
+
+    #[test]
+    fn keeps_modules_and_annotations() {
+        let opt = Options::default();
+        assert_eq!(
+            demangle(&opt, "libfoo.so`_ZN3foo3barEv"),
+            "libfoo.so`foo::bar()"
+        );
+        assert_eq!(
+            demangle(&opt, "_ZN4core3ptr13drop_in_place17h0123456789abcdefE_[i]"),
+            "core::ptr::drop_in_place_[i]"
+        );
+        assert_eq!(
+            demangle(&opt, "app::run::h0123456789abcdef_[i]"),
+            "app::run_[i]"
+        );
+        assert_eq!(demangle(&opt, "do_syscall_64_[k]"), "do_syscall_64_[k]");
+    }
+
+    #[test]
+    fn rust_hashes() {
+        let mut opt = Options::default();
+        assert_eq!(demangle(&opt, "app::run::h0123456789abcdef"), "app::run");
+        assert_eq!(
+            demangle(
+                &opt,
+                "_$LT$alloc..vec..Vec$LT$T$GT$$GT$::push::h0123456789abcdef"
+            ),
+            "<alloc::vec::Vec<T>>::push"
+        );
+
+        opt.keep_rust_hash = true;
+        assert_eq!(
+            demangle(&opt, "app::run::h0123456789abcdef"),
+            "app::run::h0123456789abcdef"
+        );
+        assert_eq!(
+            demangle(&opt, "_ZN4core3ptr13drop_in_place17h0123456789abcdefE"),
+            "core::ptr::drop_in_place::h0123456789abcdef"
+        );
+    }
+
+    #[test]
+    fn simplify_templates() {
+        let opt = Options {
+            simplify_templates: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            demangle(&opt, "_ZNSt6vectorIiSaIiEE9push_backERKi"),
+            "std::vector::push_back(int const&)"
+        );
+        assert_eq!(
+            demangle(&opt, "Foo::operator<<(std::ostream&, Bar<int> const&)"),
+            "Foo::operator<<(std::ostream&, Bar const&)"
+        );
+    }
+
+    #[test]
+    fn strip_rust_hash_only_strips_hashes() {
+        assert_eq!(strip_rust_hash("app::run::h0123456789abcdef"), "app::run");
+        assert_eq!(
+            strip_rust_hash("app::run::hash_table"),
+            "app::run::hash_table"
+        );
+        assert_eq!(
+            strip_rust_hash("app::run::h0123456789abcdeg"),
+            "app::run::h0123456789abcdeg"
+        );
+        assert_eq!(strip_rust_hash("main"), "main");
+    }
+
+    #[test]
+    fn strip_generics_keeps_qualified_paths() {
+        assert_eq!(strip_generics("main"), "main");
+        assert_eq!(strip_generics("Vec<u8>::push"), "Vec::push");
+        assert_eq!(
+            strip_generics("std::vector<std::pair<int, int>>::at"),
+            "std::vector::at"
+        );
+        assert_eq!(
+            strip_generics("<alloc::vec::Vec<T> as core::ops::Drop>::drop"),
+            "<alloc::vec::Vec as core::ops::Drop>::drop"
+        );
+        assert_eq!(strip_generics("Box<dyn Fn() -> u32>::call"), "Box::call");
+        assert_eq!(strip_generics("Foo::operator<"), "Foo::operator<");
+    }
+
+    #[test]
+    fn stacks_are_merged() {
+        let mut result = Vec::new();
+        let input = "main;app::run::h0123456789abcdef 1\nmain;app::run::hfedcba9876543210 2\n";
+        from_reader(&Options::default(), input.as_bytes(), &mut result).unwrap();
+        assert_eq!(String::from_utf8(result).unwrap(), "main;app::run 3\n");
+    }
+}
@@ -4,7 +4,8 @@ use std::io::{self, prelude::*};
 
 use log::warn;
 
-use crate::collapse::common::{self, CollapsePrivate, Occurrences};
+use crate::collapse::common::{self, CollapseLines, CollapsePrivate, Occurrences};
+use crate::folded::Location;
 
 /// `dtrace` folder configuration options.
 #[derive(Clone, Debug)]
@@ -15,6 +16,23 @@ pub struct Options {
     /// Default is `false`.
     pub includeoffset: bool,
 
+    /// Turn the source locations that follow frames, as in `app`compute+0x1a (compute.c:12)`
+    /// from a symbolicator like `atos`, into [`Location`] annotations. Each of the `->`
+    /// separated inlined functions of a frame can have its own location.
+    ///
+    /// Default is `false`.
+    pub include_lines: bool,
+
+    /// Keep the arguments and template parameters of C++ frames, as in
+    /// `Foo::get<int>(char const*)`, which are otherwise cut off (to `Foo::get`).
+    ///
+    /// Set this when the output is demangled with [`Demangle`](super::demangle::Demangle), so
+    /// that C++ frames are handled as with the other collapsers, and template parameters are only
+    /// stripped with [`simplify_templates`](super::demangle::Options::simplify_templates).
+    ///
+    /// Default is `false`.
+    pub keep_cpp_signatures: bool,
+
     /// The number of threads to use.
     ///
     /// Default is the number of logical cores on your machine.
@@ -25,6 +43,8 @@ impl Default for Options {
     fn default() -> Self {
         Self {
             includeoffset: false,
+            include_lines: false,
+            keep_cpp_signatures: false,
             nthreads: *common::DEFAULT_NTHREADS,
         }
     }
@@ -38,6 +58,9 @@ pub struct Folder {
     /// Vector for processing java stuff
     cache_inlines: Vec<String>,
 
+    /// Whether the header has been skipped yet, when streaming.
+    header_done: bool,
+
     /// The number of stacks per job to send to the threadpool.
     nstacks_per_job: usize,
 
@@ -57,6 +80,7 @@ impl From<Options> for Folder {
         }
         Self {
             cache_inlines: Vec::new(),
+            header_done: false,
             nstacks_per_job: common::DEFAULT_NSTACKS_PER_JOB,
             stack: VecDeque::default(),
             stack_str_size: 0,
@@ -105,28 +129,9 @@ impl CollapsePrivate for Folder {
             if reader.read_until(0x0A, &mut line)? == 0 {
                 break;
             }
-            let s = String::from_utf8_lossy(&line);
-            let line = s.trim();
-            if line.is_empty() {
-                continue;
-            } else if let Ok(count) = line.parse::<usize>() {
-                self.on_stack_end(count, occurrences);
-            } else {
-                self.on_stack_line(line);
-            }
+            self.on_line(&String::from_utf8_lossy(&line), occurrences);
         }
-        // If we reach this point in the code and there's still something in our
-        // state (`self.stack` and `self.stack_str_size`), it means the input
-        // did not terminate at the end of a stack; rather, it terminated in
-        // the middle of a stack. In this case, we consider the input data
-        // invalid and return an io::Error to the user.
-        if !self.stack.is_empty() || self.stack_str_size != 0 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Input data ends in the middle of a stack.",
-            ));
-        }
-        Ok(())
+        self.check_stack_ended()
     }
 
     fn is_applicable(&mut self, input: &str) -> Option<bool> {
@@ -232,6 +237,7 @@ impl CollapsePrivate for Folder {
     fn clone_and_reset_stack_context(&self) -> Self {
         Self {
             cache_inlines: self.cache_inlines.clone(),
+            header_done: false,
             nstacks_per_job: self.nstacks_per_job,
             stack: VecDeque::default(),
             stack_str_size: 0,
@@ -256,7 +262,56 @@ impl CollapsePrivate for Folder {
     }
 }
 
+impl CollapseLines for Folder {
+    fn collapse_line(&mut self, line: &[u8], occurrences: &mut Occurrences) -> io::Result<()> {
+        let line = String::from_utf8_lossy(line);
+        if self.header_done {
+            self.on_line(&line, occurrences);
+        } else if line.trim().is_empty() {
+            self.header_done = true;
+        }
+        Ok(())
+    }
+
+    fn end_of_input(&mut self, _: &mut Occurrences) -> io::Result<()> {
+        if !self.header_done {
+            warn!("File ended while skipping headers");
+        }
+        self.header_done = false;
+        let result = self.check_stack_ended();
+        self.stack_str_size = 0;
+        self.stack.clear();
+        result
+    }
+}
+
 impl Folder {
+    fn on_line(&mut self, line: &str, occurrences: &mut Occurrences) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        if let Ok(count) = line.parse::<usize>() {
+            self.on_stack_end(count, occurrences);
+        } else {
+            self.on_stack_line(line);
+        }
+    }
+
+    // If there's still something in our state (`self.stack` and `self.stack_str_size`) at the end
+    // of the input, it means the input did not terminate at the end of a stack; rather, it
+    // terminated in the middle of a stack. In this case, we consider the input data invalid and
+    // return an io::Error to the user.
+    fn check_stack_ended(&self) -> io::Result<()> {
+        if !self.stack.is_empty() || self.stack_str_size != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Input data ends in the middle of a stack.",
+            ));
+        }
+        Ok(())
+    }
+
     // This function approximates the Perl regex s/(::.*)[(<].*/$1/
     // from https://github.com/brendangregg/FlameGraph/blob/1b1c6deede9c33c5134c920bdb7a44cc5528e9a7/stackcollapse.pl#L88
     fn uncpp(probe: &str) -> &str {
@@ -330,13 +385,20 @@ impl Folder {
     //     unix`sys_syscall+0x10e
     //       1
     fn on_stack_line(&mut self, line: &str) {
+        let mut locations = Vec::new();
+        let line = if self.opt.include_lines {
+            Self::split_locations(line, &mut locations)
+        } else {
+            Cow::Borrowed(line)
+        };
+
         let (has_inlines, could_be_cpp, has_semicolon, mut frame) = if self.opt.includeoffset {
-            (true, true, true, line)
+            (true, true, true, &*line)
         } else {
-            Self::remove_offset(line)
+            Self::remove_offset(&line)
         };
 
-        if could_be_cpp {
+        if could_be_cpp && !self.opt.keep_cpp_signatures {
             frame = Self::uncpp(frame);
         }
 
@@ -369,6 +431,54 @@ impl Folder {
         } else {
             self.stack.push_front(frame.to_string())
         }
+
+        // The frames of the line are at the front of the stack, outermost first, just like their
+        // locations.
+        for (func, location) in self.stack.iter_mut().zip(locations) {
+            if let Some(location) = location {
+                let len = func.len();
+                location.annotate(func);
+                self.stack_str_size += func.len() - len;
+            }
+        }
+    }
+
+    // Splits the source locations off of the `->` separated functions of a frame, like:
+    //
+    //     app`compute+0x1a (compute.c:12)
+    //     libjvm.so`foo (foo.cpp:40)->bar (bar.hpp:7)
+    //
+    // There is one location for each function, if it has one.
+    fn split_locations<'a>(
+        line: &'a str,
+        locations: &mut Vec<Option<Location<'a>>>,
+    ) -> Cow<'a, str> {
+        let mut frame = String::with_capacity(line.len());
+        for (i, func) in line.split("->").enumerate() {
+            let mut location = None;
+            let mut func = func;
+            if func.ends_with(')') {
+                if let Some(open) = func.rfind(" (") {
+                    let inner = &func[open + 2..func.len() - 1];
+                    // Unknown locations, like `??:0`, are dropped too.
+                    if inner.ends_with(|c: char| c.is_ascii_digit()) && inner.contains(':') {
+                        location = Location::parse(inner);
+                        func = &func[..open];
+                    }
+                }
+            }
+            if i != 0 {
+                frame.push_str("->");
+            }
+            frame.push_str(func);
+            locations.push(location);
+        }
+
+        if frame.len() != line.len() {
+            Cow::Owned(frame)
+        } else {
+            Cow::Borrowed(line)
+        }
     }
 
     fn on_stack_end(&mut self, count: usize, occurrences: &mut Occurrences) {
@@ -386,7 +496,11 @@ impl Folder {
             }
             //trim leaf offset if these were retained:
             if self.opt.includeoffset && i == last {
-                stack_str.push_str(Self::remove_offset(&e).3);
+                let (func, location) = Location::split(&e);
+                stack_str.push_str(Self::remove_offset(func).3);
+                if let Some(location) = location {
+                    location.annotate(&mut stack_str);
+                }
             } else {
                 stack_str.push_str(&e);
             }
@@ -516,6 +630,8 @@ mod tests {
             let nstacks_per_job = rng.gen_range(1, 500 + 1);
             let options = Options {
                 includeoffset: rng.gen(),
+                include_lines: rng.gen(),
+                keep_cpp_signatures: rng.gen(),
                 nthreads: rng.gen_range(2, 32 + 1),
             };
 
@@ -3,7 +3,10 @@ use std::io::{self, Cursor};
 
 use log::{error, info};
 
-use crate::collapse::{self, dtrace, perf, sample, vtune, Collapse};
+use crate::collapse::{
+    self, bpftrace, chrome, dtrace, perf, sample, vtune, xdebug, xperf, Collapse,
+};
+use crate::folded::FoldedStacks;
 
 const LINES_PER_ITERATION: usize = 10;
 
@@ -48,7 +51,34 @@ impl Default for Folder {
 }
 
 impl Collapse for Folder {
-    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        self.guess(reader, Output::Writer(writer))
+    }
+
+    fn collapse_into<R>(&mut self, reader: R, stacks: &mut FoldedStacks) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        self.guess::<_, io::Sink>(reader, Output::Stacks(stacks))
+    }
+
+    fn is_applicable(&mut self, _line: &str) -> Option<bool> {
+        unreachable!()
+    }
+}
+
+// Where the stacks of the collapser that is picked go.
+enum Output<'a, W> {
+    Writer(W),
+    Stacks(&'a mut FoldedStacks),
+}
+
+impl Folder {
+    fn guess<R, W>(&mut self, mut reader: R, output: Output<'_, W>) -> io::Result<()>
     where
         R: io::BufRead,
         W: io::Write,
@@ -65,10 +95,22 @@ impl Collapse for Folder {
         };
         let mut sample = sample::Folder::default();
         let mut vtune = vtune::Folder::default();
+        let mut xdebug = xdebug::Folder::default();
+        let mut chrome = chrome::Folder::default();
+        let mut bpftrace = {
+            let mut options = bpftrace::Options::default();
+            options.nthreads = self.opt.nthreads;
+            bpftrace::Folder::from(options)
+        };
+        let mut xperf = {
+            let mut options = xperf::Options::default();
+            options.nthreads = self.opt.nthreads;
+            xperf::Folder::from(options)
+        };
 
         // Each Collapse impl gets its own flag in this array.
         // It gets set to true when the impl has been ruled out.
-        let mut not_applicable = [false; 4];
+        let mut not_applicable = [false; 8];
 
         let mut buffer = String::new();
         loop {
@@ -91,7 +133,12 @@ impl Collapse for Folder {
                                 // We found a collapser that works! Let's use it.
                                 info!("Using {} collapser", stringify!($collapse));
                                 let cursor = Cursor::new(buffer).chain(reader);
-                                return $collapse.collapse(cursor, writer);
+                                return match output {
+                                    Output::Writer(writer) => $collapse.collapse(cursor, writer),
+                                    Output::Stacks(stacks) => {
+                                        $collapse.collapse_into(cursor, stacks)
+                                    }
+                                };
                             }
                             None => (), // We're not yet sure if this collapser is appropriate
                         }
@@ -102,6 +149,10 @@ impl Collapse for Folder {
             try_collapse_impl!(dtrace, 1);
             try_collapse_impl!(sample, 2);
             try_collapse_impl!(vtune, 3);
+            try_collapse_impl!(bpftrace, 4);
+            try_collapse_impl!(xdebug, 5);
+            try_collapse_impl!(chrome, 6);
+            try_collapse_impl!(xperf, 7);
 
             if eof {
                 break;
@@ -112,8 +163,4 @@ impl Collapse for Folder {
 
         Ok(())
     }
-
-    fn is_applicable(&mut self, _line: &str) -> Option<bool> {
-        unreachable!()
-    }
 }
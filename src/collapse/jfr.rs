@@ -0,0 +1,858 @@
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::FoldedStacks;
+
+// Every chunk of a recording starts with these bytes.
+const MAGIC: &[u8] = b"FLR\0";
+
+// The chunk header is followed by the first event.
+const HEADER_SIZE: usize = 68;
+
+// Set in the header's features when integers are LEB128-encoded.
+const COMPRESSED_INTS: u32 = 1;
+
+// Event type ids reserved for metadata and constant pools.
+const METADATA_TYPE: u64 = 0;
+const CONSTANT_POOL_TYPE: u64 = 1;
+
+// How deeply metadata elements and values may nest, so that malformed input can't recurse
+// without end.
+const MAX_DEPTH: usize = 32;
+
+/// What the counts of the folded stacks measure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Weight {
+    /// Count `jdk.ExecutionSample` events, one per sample.
+    #[default]
+    Cpu,
+    /// Sum the bytes of `jdk.ObjectAllocationSample`, `jdk.ObjectAllocationInNewTLAB` and
+    /// `jdk.ObjectAllocationOutsideTLAB` events. The class of the allocated object becomes the
+    /// leaf frame, annotated with `_[i]` so that it stands apart from the methods.
+    Allocation,
+}
+
+impl FromStr for Weight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cpu" => Ok(Weight::Cpu),
+            "alloc" => Ok(Weight::Allocation),
+            unknown => Err(format!("unknown weight: {}", unknown)),
+        }
+    }
+}
+
+/// `jfr` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Whether to weight stacks by CPU samples or by allocated bytes.
+    ///
+    /// Default is `Weight::Cpu`.
+    pub weight: Weight,
+}
+
+/// A stack collapser for JDK Flight Recorder (`.jfr`) recordings, as written by the JDK itself
+/// (`-XX:StartFlightRecording`, `jcmd JFR.start`) and by async-profiler.
+///
+/// Frames are named `package.Class.method` and carry the annotations understood by the Java
+/// palette: `_[j]` for interpreted and compiled Java code, `_[i]` for inlined code and `_[k]` for
+/// the kernel. Native frames are left unannotated.
+///
+/// To construct one, either use `jfr::Folder::default()` or create an [`Options`] and use
+/// `jfr::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        self.collapse_to_occurrences(reader)?
+            .write_and_clear(writer)
+    }
+
+    fn collapse_into<R>(&mut self, reader: R, stacks: &mut FoldedStacks) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        self.collapse_to_occurrences(reader)?
+            .add_to_and_clear(stacks);
+        Ok(())
+    }
+
+    /// Check for the magic bytes at the start of a recording.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        if input.len() < MAGIC.len() {
+            None
+        } else {
+            Some(input.as_bytes().starts_with(MAGIC))
+        }
+    }
+}
+
+impl Folder {
+    fn collapse_to_occurrences<R>(&mut self, mut reader: R) -> io::Result<Occurrences>
+    where
+        R: io::BufRead,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.is_empty() {
+            warn!("File is empty");
+            return Ok(Occurrences::new(1));
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        let mut offset = 0;
+        while offset < data.len() {
+            let chunk = Chunk::parse(&data[offset..])?;
+            chunk.collapse(self.opt.weight, &mut occurrences);
+            offset += chunk.size;
+        }
+        Ok(occurrences)
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+/// A type described by the metadata of a chunk.
+#[derive(Debug, Default)]
+struct Class {
+    name: String,
+    fields: Vec<Field>,
+}
+
+#[derive(Debug)]
+struct Field {
+    name: String,
+    type_id: u64,
+    constant_pool: bool,
+    array: bool,
+}
+
+/// A decoded value of some type.
+#[derive(Debug)]
+enum Value {
+    Null,
+    Int(i64),
+    String(String),
+    /// A reference to an entry of a constant pool, by type id and key.
+    Ref(u64, i64),
+    Array(Vec<Value>),
+    /// The values of the fields of an object, by type id.
+    Object(u64, Vec<Value>),
+}
+
+/// An event whose stack will be collapsed.
+struct Sample {
+    /// The type id and key of the stack trace.
+    stack_trace: (u64, i64),
+    weight: usize,
+    /// The allocated class, for allocation events.
+    class: Option<Value>,
+}
+
+/// A parsed chunk of a recording.
+struct Chunk {
+    size: usize,
+    classes: HashMap<u64, Class>,
+    pools: HashMap<(u64, i64), Value>,
+    /// The fields of the events that have a stack trace, by type id.
+    events: Vec<(u64, Vec<Value>)>,
+}
+
+impl Chunk {
+    fn parse(data: &[u8]) -> io::Result<Self> {
+        if !data.starts_with(MAGIC) {
+            return invalid_data_error!("Expected a JFR chunk, but found no magic bytes");
+        }
+        let mut header = Input::new(data, false);
+        header.pos = MAGIC.len();
+        let major = header.u16()?;
+        let _minor = header.u16()?;
+        let size = header.fixed_u64()? as usize;
+        let _constant_pool_offset = header.fixed_u64()?;
+        let metadata_offset = header.fixed_u64()? as usize;
+        header.pos = HEADER_SIZE - 4;
+        let features = header.fixed_u32()?;
+        if major < 2 {
+            return invalid_data_error!("Unsupported JFR version {}", major);
+        }
+        if size < HEADER_SIZE || size > data.len() {
+            return invalid_data_error!("JFR chunk is truncated");
+        }
+
+        let data = &data[..size];
+        let compressed = features & COMPRESSED_INTS != 0;
+        let mut chunk = Chunk {
+            size,
+            classes: HashMap::new(),
+            pools: HashMap::new(),
+            events: Vec::new(),
+        };
+
+        // Everything else depends on the types described by the metadata.
+        let mut input = Input::new(data, compressed);
+        input.pos = metadata_offset;
+        chunk.parse_metadata(&mut input)?;
+
+        let mut pos = HEADER_SIZE;
+        while pos < size {
+            input.pos = pos;
+            let event_size = input.int()?;
+            let end = if event_size > 0 {
+                pos.checked_add(event_size as usize)
+                    .filter(|&end| end <= size)
+            } else {
+                None
+            };
+            let end = match end {
+                Some(end) => end,
+                None => return invalid_data_error!("Invalid event size in JFR chunk"),
+            };
+            match input.long()? as u64 {
+                METADATA_TYPE => {}
+                CONSTANT_POOL_TYPE => chunk.parse_constant_pool(&mut input)?,
+                type_id => chunk.parse_event(type_id, &mut input)?,
+            }
+            pos = end;
+        }
+        Ok(chunk)
+    }
+
+    fn parse_metadata(&mut self, input: &mut Input) -> io::Result<()> {
+        input.int()?; // size
+        if input.long()? as u64 != METADATA_TYPE {
+            return invalid_data_error!(
+                "Expected metadata at the metadata offset of the JFR chunk"
+            );
+        }
+        input.long()?; // start time
+        input.long()?; // duration
+        input.long()?; // metadata id
+
+        let nstrings = input.int()? as usize;
+        let mut strings = Vec::with_capacity(nstrings.min(input.remaining()));
+        for _ in 0..nstrings {
+            strings.push(input.string()?.unwrap_or_default());
+        }
+
+        let root = Element::parse(input, &strings, 0)?;
+        for metadata in root.children.iter().filter(|e| e.name == "metadata") {
+            for class in metadata.children.iter().filter(|e| e.name == "class") {
+                let id = match class.attribute("id").and_then(|id| id.parse().ok()) {
+                    Some(id) => id,
+                    None => return invalid_data_error!("JFR class without an id"),
+                };
+                let fields = class
+                    .children
+                    .iter()
+                    .filter(|e| e.name == "field")
+                    .map(|field| Field {
+                        name: field.attribute("name").unwrap_or_default().to_string(),
+                        type_id: field
+                            .attribute("class")
+                            .and_then(|id| id.parse().ok())
+                            .unwrap_or_default(),
+                        constant_pool: field.attribute("constantPool") == Some("true"),
+                        array: field.attribute("dimension") == Some("1"),
+                    })
+                    .collect();
+                let name = class.attribute("name").unwrap_or_default().to_string();
+                self.classes.insert(id, Class { name, fields });
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_constant_pool(&mut self, input: &mut Input) -> io::Result<()> {
+        input.long()?; // start time
+        input.long()?; // duration
+        input.long()?; // offset to the previous constant pool
+        input.u8()?; // flush
+        let npools = input.int()?;
+        for _ in 0..npools {
+            let type_id = input.long()? as u64;
+            let count = input.int()?;
+            for _ in 0..count {
+                let key = input.long()?;
+                let value = self.read_value(type_id, input, 0)?;
+                self.pools.insert((type_id, key), value);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_event(&mut self, type_id: u64, input: &mut Input) -> io::Result<()> {
+        match self.classes.get(&type_id) {
+            Some(class) => match class.name.as_str() {
+                "jdk.ExecutionSample"
+                | "jdk.ObjectAllocationSample"
+                | "jdk.ObjectAllocationInNewTLAB"
+                | "jdk.ObjectAllocationOutsideTLAB" => {}
+                _ => return Ok(()),
+            },
+            None => return invalid_data_error!("Unknown JFR event type {}", type_id),
+        }
+        if let Value::Object(_, fields) = self.read_value(type_id, input, 0)? {
+            self.events.push((type_id, fields));
+        }
+        Ok(())
+    }
+
+    fn read_value(&self, type_id: u64, input: &mut Input, depth: usize) -> io::Result<Value> {
+        if depth > MAX_DEPTH {
+            return invalid_data_error!("JFR value is nested too deeply");
+        }
+        let class = match self.classes.get(&type_id) {
+            Some(class) => class,
+            None => return invalid_data_error!("Unknown JFR type {}", type_id),
+        };
+        Ok(match class.name.as_str() {
+            "boolean" | "byte" => Value::Int(i64::from(input.u8()?)),
+            "char" | "short" => Value::Int(input.short()?),
+            "int" => Value::Int(input.int()?),
+            "long" => Value::Int(input.long()?),
+            // Floating point values aren't needed for stacks.
+            "float" => {
+                input.take(4)?;
+                Value::Null
+            }
+            "double" => {
+                input.take(8)?;
+                Value::Null
+            }
+            "java.lang.String" => match input.string_or_ref()? {
+                StringValue::Null => Value::Null,
+                StringValue::String(s) => Value::String(s),
+                StringValue::Ref(key) => Value::Ref(type_id, key),
+            },
+            _ => {
+                let mut fields = Vec::with_capacity(class.fields.len());
+                for field in &class.fields {
+                    fields.push(if field.array {
+                        // Every element takes at least a byte, so an array can't be longer
+                        // than what's left of the chunk.
+                        let len = input.int()?;
+                        if len < 0 || len as u64 > input.remaining() as u64 {
+                            return invalid_data_error!("Invalid JFR array length {}", len);
+                        }
+                        let mut values = Vec::with_capacity(len as usize);
+                        for _ in 0..len {
+                            let remaining = input.remaining();
+                            values.push(self.read_field(field, input, depth + 1)?);
+                            if input.remaining() == remaining {
+                                return invalid_data_error!("Empty element in JFR array");
+                            }
+                        }
+                        Value::Array(values)
+                    } else {
+                        self.read_field(field, input, depth + 1)?
+                    });
+                }
+                Value::Object(type_id, fields)
+            }
+        })
+    }
+
+    fn read_field(&self, field: &Field, input: &mut Input, depth: usize) -> io::Result<Value> {
+        if field.constant_pool {
+            Ok(Value::Ref(field.type_id, input.long()?))
+        } else {
+            self.read_value(field.type_id, input, depth)
+        }
+    }
+
+    // Adds the stacks of this chunk's events to `occurrences`.
+    fn collapse(&self, weight: Weight, occurrences: &mut Occurrences) {
+        for (type_id, fields) in &self.events {
+            let sample = match self.sample(weight, *type_id, fields) {
+                Some(sample) => sample,
+                None => continue,
+            };
+
+            let mut stack = Vec::new();
+            if let Some(Value::Object(type_id, trace)) = self.pools.get(&sample.stack_trace) {
+                if let Some(Value::Array(frames)) = self.field(*type_id, trace, "frames") {
+                    // Frames are listed leaf first.
+                    for frame in frames.iter().rev() {
+                        if let Some(frame) = self.frame(frame) {
+                            stack.push(frame);
+                        }
+                    }
+                }
+            }
+            if let Some(class) = &sample.class {
+                if let Some(name) = self.class_name(class) {
+                    stack.push(format!("{}_[i]", name));
+                }
+            }
+            if stack.is_empty() {
+                continue;
+            }
+            occurrences.insert_or_add(stack.join(";"), sample.weight);
+        }
+    }
+
+    fn sample(&self, weight: Weight, type_id: u64, fields: &[Value]) -> Option<Sample> {
+        let weight_field = match (weight, self.classes.get(&type_id)?.name.as_str()) {
+            (Weight::Cpu, "jdk.ExecutionSample") => None,
+            (Weight::Allocation, "jdk.ObjectAllocationSample") => Some("weight"),
+            (Weight::Allocation, "jdk.ObjectAllocationInNewTLAB") => Some("tlabSize"),
+            (Weight::Allocation, "jdk.ObjectAllocationOutsideTLAB") => Some("allocationSize"),
+            _ => return None,
+        };
+
+        let stack_trace = match self.field(type_id, fields, "stackTrace")? {
+            Value::Ref(trace_type, key) => (*trace_type, *key),
+            _ => return None,
+        };
+        let (weight, class) = match weight_field {
+            None => (1, None),
+            Some(field) => {
+                let weight = match self.field(type_id, fields, field)? {
+                    Value::Int(weight) if *weight > 0 => *weight as usize,
+                    _ => return None,
+                };
+                let class = match self.field(type_id, fields, "objectClass") {
+                    Some(Value::Ref(class_type, key)) => Some(Value::Ref(*class_type, *key)),
+                    _ => None,
+                };
+                (weight, class)
+            }
+        };
+        Some(Sample {
+            stack_trace,
+            weight,
+            class,
+        })
+    }
+
+    fn frame(&self, frame: &Value) -> Option<String> {
+        let (type_id, fields) = match frame {
+            Value::Object(type_id, fields) => (*type_id, fields),
+            _ => return None,
+        };
+        let method = self.deref(self.field(type_id, fields, "method")?);
+        let (method_type, method_fields) = match method {
+            Value::Object(type_id, fields) => (*type_id, fields),
+            _ => return None,
+        };
+        let name = self.string(self.field(method_type, method_fields, "name")?)?;
+        let class = self
+            .field(method_type, method_fields, "type")
+            .and_then(|class| self.class_name(class))
+            .unwrap_or_default();
+
+        let suffix = match self
+            .field(type_id, fields, "type")
+            .and_then(|frame_type| match self.deref(frame_type) {
+                Value::Object(t, f) => self.string(self.field(*t, f, "description")?),
+                _ => None,
+            })
+            .as_deref()
+        {
+            Some("Interpreted") | Some("JIT compiled") | Some("C1 compiled") => "_[j]",
+            Some("Inlined") => "_[i]",
+            Some("Kernel") => "_[k]",
+            _ => "",
+        };
+
+        let frame = if class.is_empty() {
+            format!("{}{}", name, suffix)
+        } else {
+            format!("{}.{}{}", class, name, suffix)
+        };
+        Some(frame.replace(';', ":"))
+    }
+
+    // Returns the dotted name of a `java.lang.Class`.
+    fn class_name(&self, class: &Value) -> Option<String> {
+        match self.deref(class) {
+            Value::Object(type_id, fields) => {
+                let name = self.string(self.field(*type_id, fields, "name")?)?;
+                Some(java_class_name(&name))
+            }
+            _ => None,
+        }
+    }
+
+    fn field<'a>(&self, type_id: u64, fields: &'a [Value], name: &str) -> Option<&'a Value> {
+        let index = self
+            .classes
+            .get(&type_id)?
+            .fields
+            .iter()
+            .position(|field| field.name == name)?;
+        fields.get(index)
+    }
+
+    // Follows constant pool references.
+    fn deref<'a>(&'a self, value: &'a Value) -> &'a Value {
+        let mut value = value;
+        // References may be chained, but never deeply.
+        for _ in 0..8 {
+            match value {
+                Value::Ref(type_id, key) => match self.pools.get(&(*type_id, *key)) {
+                    Some(resolved) => value = resolved,
+                    None => return &Value::Null,
+                },
+                _ => break,
+            }
+        }
+        value
+    }
+
+    // Resolves strings, including `jdk.types.Symbol`s, which wrap a single string.
+    fn string(&self, value: &Value) -> Option<String> {
+        match self.deref(value) {
+            Value::String(s) => Some(s.clone()),
+            Value::Object(_, fields) if fields.len() == 1 => self.string(&fields[0]),
+            _ => None,
+        }
+    }
+}
+
+/// An element of the metadata tree.
+struct Element<'s> {
+    name: &'s str,
+    attributes: Vec<(&'s str, &'s str)>,
+    children: Vec<Element<'s>>,
+}
+
+impl<'s> Element<'s> {
+    fn parse(input: &mut Input, strings: &'s [String], depth: usize) -> io::Result<Self> {
+        if depth > MAX_DEPTH {
+            return invalid_data_error!("JFR metadata is nested too deeply");
+        }
+        let name = string_at(strings, input.int()?)?;
+        let nattributes = input.int()?;
+        let mut attributes = Vec::new();
+        for _ in 0..nattributes {
+            let key = string_at(strings, input.int()?)?;
+            let value = string_at(strings, input.int()?)?;
+            attributes.push((key, value));
+        }
+        let nchildren = input.int()?;
+        let mut children = Vec::new();
+        for _ in 0..nchildren {
+            children.push(Element::parse(input, strings, depth + 1)?);
+        }
+        Ok(Element {
+            name,
+            attributes,
+            children,
+        })
+    }
+
+    fn attribute(&self, key: &str) -> Option<&'s str> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| *v)
+    }
+}
+
+// Turns internal class names like `java/lang/String` and `[[B` into `java.lang.String` and
+// `byte[][]`.
+fn java_class_name(name: &str) -> String {
+    let element = name.trim_start_matches('[');
+    let dimensions = name.len() - element.len();
+    if dimensions == 0 {
+        return name.replace('/', ".");
+    }
+    let mut class = match element {
+        "Z" => "boolean".to_string(),
+        "B" => "byte".to_string(),
+        "C" => "char".to_string(),
+        "S" => "short".to_string(),
+        "I" => "int".to_string(),
+        "J" => "long".to_string(),
+        "F" => "float".to_string(),
+        "D" => "double".to_string(),
+        _ => element
+            .trim_start_matches('L')
+            .trim_end_matches(';')
+            .replace('/', "."),
+    };
+    for _ in 0..dimensions {
+        class.push_str("[]");
+    }
+    class
+}
+
+fn string_at(strings: &[String], index: i64) -> io::Result<&str> {
+    match strings.get(index as usize) {
+        Some(s) => Ok(s),
+        None => invalid_data_error!("Invalid string index {} in JFR metadata", index),
+    }
+}
+
+enum StringValue {
+    Null,
+    String(String),
+    Ref(i64),
+}
+
+/// A big-endian cursor over the bytes of a chunk.
+struct Input<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    compressed: bool,
+}
+
+impl<'a> Input<'a> {
+    fn new(buf: &'a [u8], compressed: bool) -> Self {
+        Input {
+            buf,
+            pos: 0,
+            compressed,
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len().saturating_sub(self.pos)
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if n > self.remaining() {
+            return invalid_data_error!("JFR chunk is truncated");
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn fixed_u32(&mut self) -> io::Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn fixed_u64(&mut self) -> io::Result<u64> {
+        let b = self.take(8)?;
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(b);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    fn short(&mut self) -> io::Result<i64> {
+        if self.compressed {
+            self.long()
+        } else {
+            Ok(i64::from(self.u16()? as i16))
+        }
+    }
+
+    fn int(&mut self) -> io::Result<i64> {
+        if self.compressed {
+            self.long()
+        } else {
+            Ok(i64::from(self.fixed_u32()? as i32))
+        }
+    }
+
+    // Compressed integers use 7 bits of each of their first 8 bytes, and all 8 bits of the ninth.
+    fn long(&mut self) -> io::Result<i64> {
+        if !self.compressed {
+            return Ok(self.fixed_u64()? as i64);
+        }
+        let mut value = 0u64;
+        for i in 0..8 {
+            let byte = self.u8()?;
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value as i64);
+            }
+        }
+        value |= u64::from(self.u8()?) << 56;
+        Ok(value as i64)
+    }
+
+    fn string(&mut self) -> io::Result<Option<String>> {
+        match self.string_or_ref()? {
+            StringValue::Null => Ok(None),
+            StringValue::String(s) => Ok(Some(s)),
+            StringValue::Ref(_) => {
+                invalid_data_error!("Unexpected string reference in JFR metadata")
+            }
+        }
+    }
+
+    fn string_or_ref(&mut self) -> io::Result<StringValue> {
+        Ok(match self.u8()? {
+            0 => StringValue::Null,
+            1 => StringValue::String(String::new()),
+            2 => StringValue::Ref(self.long()?),
+            3 => {
+                let len = self.int()? as usize;
+                StringValue::String(String::from_utf8_lossy(self.take(len)?).into_owned())
+            }
+            4 => {
+                let len = self.int()? as usize;
+                let mut s = String::with_capacity(len.min(self.remaining()));
+                for _ in 0..len {
+                    s.push(std::char::from_u32(self.int()? as u32).unwrap_or('?'));
+                }
+                StringValue::String(s)
+            }
+            5 => {
+                let len = self.int()? as usize;
+                StringValue::String(self.take(len)?.iter().map(|&b| b as char).collect())
+            }
+            encoding => return invalid_data_error!("Unknown JFR string encoding {}", encoding),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_java_class_name() {
+        assert_eq!(java_class_name("java/lang/String"), "java.lang.String");
+        assert_eq!(java_class_name("[B"), "byte[]");
+        assert_eq!(java_class_name("[[I"), "int[][]");
+        assert_eq!(java_class_name("[Ljava/lang/Object;"), "java.lang.Object[]");
+    }
+
+    #[test]
+    fn test_compressed_long() {
+        let mut input = Input::new(&[0x96, 0x01], true);
+        assert_eq!(input.long().unwrap(), 150);
+
+        // The ninth byte contributes all of its 8 bits.
+        let bytes = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        let mut input = Input::new(&bytes, true);
+        assert_eq!(input.long().unwrap(), -1);
+
+        let mut input = Input::new(&[0x80, 0x80], true);
+        assert!(input.long().is_err());
+    }
+
+    #[test]
+    fn test_negative_event_size() {
+        // A chunk with uncompressed integers, whose metadata has a single empty root element.
+        let mut metadata = Vec::new();
+        metadata.extend_from_slice(&0u64.to_be_bytes()); // type
+        metadata.extend_from_slice(&[0; 24]); // start time, duration and id
+        metadata.extend_from_slice(&1u32.to_be_bytes()); // one string
+        metadata.push(3);
+        metadata.extend_from_slice(&4u32.to_be_bytes());
+        metadata.extend_from_slice(b"root");
+        metadata.extend_from_slice(&[0; 12]); // name, attributes and children of the root
+        let metadata_size = 4 + metadata.len();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&2u16.to_be_bytes());
+        data.extend_from_slice(&0u16.to_be_bytes());
+        let size = HEADER_SIZE + metadata_size + 12;
+        data.extend_from_slice(&(size as u64).to_be_bytes());
+        data.extend_from_slice(&0u64.to_be_bytes());
+        data.extend_from_slice(&(HEADER_SIZE as u64).to_be_bytes());
+        data.resize(HEADER_SIZE, 0);
+        data.extend_from_slice(&(metadata_size as u32).to_be_bytes());
+        data.extend_from_slice(&metadata);
+        // An event that claims to be -8 bytes long.
+        data.extend_from_slice(&(-8i32).to_be_bytes());
+        data.extend_from_slice(&2u64.to_be_bytes());
+
+        let error = Chunk::parse(&data).err().unwrap();
+        assert_eq!(error.to_string(), "Invalid event size in JFR chunk");
+    }
+
+    #[test]
+    fn test_read_value_depth() {
+        // A type whose only field is of the type itself never ends.
+        let mut classes = HashMap::new();
+        classes.insert(
+            100,
+            Class {
+                name: "Node".to_string(),
+                fields: vec![Field {
+                    name: "next".to_string(),
+                    type_id: 100,
+                    constant_pool: false,
+                    array: false,
+                }],
+            },
+        );
+        let chunk = Chunk {
+            size: 0,
+            classes,
+            pools: HashMap::new(),
+            events: Vec::new(),
+        };
+        let mut input = Input::new(&[], false);
+        assert!(chunk.read_value(100, &mut input, 0).is_err());
+    }
+
+    #[test]
+    fn test_read_value_array_length() {
+        // An array of objects without fields, which take no input to read.
+        let mut classes = HashMap::new();
+        classes.insert(
+            100,
+            Class {
+                name: "Empty".to_string(),
+                fields: Vec::new(),
+            },
+        );
+        classes.insert(
+            101,
+            Class {
+                name: "Holder".to_string(),
+                fields: vec![Field {
+                    name: "items".to_string(),
+                    type_id: 100,
+                    constant_pool: false,
+                    array: true,
+                }],
+            },
+        );
+        let chunk = Chunk {
+            size: 0,
+            classes,
+            pools: HashMap::new(),
+            events: Vec::new(),
+        };
+        let inputs: [&[u8]; 3] = [
+            // A length far beyond the input.
+            &[0x7f, 0xff, 0xff, 0xff],
+            // A negative length.
+            &[0xff, 0xff, 0xff, 0xff],
+            // A length that fits, but elements that don't use any of the input.
+            &[0, 0, 0, 1, 0],
+        ];
+        for bytes in &inputs {
+            let mut input = Input::new(bytes, false);
+            assert!(chunk.read_value(101, &mut input, 0).is_err());
+        }
+    }
+}
@@ -1,6 +1,30 @@
 #[macro_use]
 pub(crate) mod common;
 
+/// Stack collapsing for the output of [`bpftrace`](https://github.com/iovisor/bpftrace) and the
+/// folded output of [BCC](https://github.com/iovisor/bcc) tools like `profile`.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod bpftrace;
+
+/// Stack collapsing for the output of Valgrind's [`callgrind`](https://valgrind.org/docs/manual/cl-manual.html)
+/// and [`cachegrind`](https://valgrind.org/docs/manual/cg-manual.html) tools.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod callgrind;
+
+/// Stack collapsing for the `.cpuprofile` files written by [Chrome DevTools](https://developer.chrome.com/docs/devtools/)
+/// and Node.js.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod chrome;
+
 /// Stack collapsing for the output of [`dtrace`](https://www.joyent.com/dtrace).
 ///
 /// See the [crate-level documentation] for details.
@@ -8,9 +32,20 @@ pub(crate) mod common;
 ///   [crate-level documentation]: ../../index.html
 pub mod dtrace;
 
+/// Demangling of the Rust and C++ symbols in the frames written by any of the collapsers.
+pub mod demangle;
+
 /// Attempts to use whichever Collapse implementation is appropriate for a given input
 pub mod guess;
 
+/// Stack collapsing for [JDK Flight Recorder](https://docs.oracle.com/en/java/java-components/jdk-mission-control/8/user-guide/using-jdk-flight-recorder.html)
+/// recordings.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod jfr;
+
 /// Stack collapsing for the output of [`perf script`](https://linux.die.net/man/1/perf-script).
 ///
 /// See the [crate-level documentation] for details.
@@ -18,6 +53,14 @@ pub mod guess;
 ///   [crate-level documentation]: ../../index.html
 pub mod perf;
 
+/// Stack collapsing for [`pprof`](https://github.com/google/pprof) profiles (`profile.proto`), and
+/// conversion of folded stacks back into them.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod pprof;
+
 /// Internal string match helper functions for perf
 pub(crate) mod matcher;
 
@@ -28,6 +71,13 @@ pub(crate) mod matcher;
 ///   [crate-level documentation]: ../../index.html
 pub mod sample;
 
+/// Incremental stack collapsing, for input that arrives over time.
+pub mod stream;
+
+/// Offline symbolization of the raw addresses in collapsed frames, using the symbol tables and
+/// DWARF debug info of the binaries on disk.
+pub mod symbolize;
+
 /// Stack collapsing for the output of [`VTune`](https://software.intel.com/en-us/vtune-amplifier-help-command-line-interface).
 ///
 /// See the [crate-level documentation] for details.
@@ -35,6 +85,21 @@ pub mod sample;
 ///   [crate-level documentation]: ../../index.html
 pub mod vtune;
 
+/// Stack collapsing for function traces from [`Xdebug`](https://xdebug.org/docs/trace).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod xdebug;
+
+/// Stack collapsing for the CSV stacks exported by [`xperf`](https://docs.microsoft.com/en-us/windows-hardware/test/wpt/xperf-command-line-reference)
+/// and Windows Performance Analyzer.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod xperf;
+
 // DEFAULT_NTHREADS is public because we use it in the help text of the binaries,
 // but it doesn't need to be exposed to library users, hence #[doc(hidden)].
 #[doc(hidden)]
@@ -45,6 +110,7 @@ use std::io;
 use std::path::Path;
 
 use self::common::{CollapsePrivate, CAPACITY_READER};
+use crate::folded::FoldedStacks;
 
 /// The abstract behavior of stack collapsing.
 ///
@@ -87,6 +153,23 @@ pub trait Collapse {
         }
     }
 
+    /// Collapses the contents of the provided `reader` and adds the resulting stacks to
+    /// `stacks`, rather than writing them out as text.
+    ///
+    /// The default implementation collapses to text and parses the result back. Every
+    /// collapser in this crate overrides it to add its stacks directly.
+    fn collapse_into<R>(&mut self, reader: R, stacks: &mut FoldedStacks) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        let mut folded = Vec::new();
+        self.collapse(reader, &mut folded)?;
+        for line in String::from_utf8_lossy(&folded).lines() {
+            stacks.add_line(line);
+        }
+        Ok(())
+    }
+
     /// Returns whether this implementation is appropriate for the given input.
     ///
     /// - `None` means "not sure -- need more input"
@@ -107,6 +190,15 @@ where
         <Self as CollapsePrivate>::collapse(self, reader, writer)
     }
 
+    fn collapse_into<R>(&mut self, reader: R, stacks: &mut FoldedStacks) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        let mut occurrences = <Self as CollapsePrivate>::collapse_to_occurrences(self, reader)?;
+        occurrences.add_to_and_clear(stacks);
+        Ok(())
+    }
+
     fn is_applicable(&mut self, input: &str) -> Option<bool> {
         <Self as CollapsePrivate>::is_applicable(self, input)
     }
@@ -0,0 +1,831 @@
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ahash::AHashMap;
+use log::{debug, warn};
+use object::{Object, ObjectSegment, ObjectSymbol, SymbolKind};
+
+use super::Options;
+use crate::collapse::common::Occurrences;
+use crate::collapse::symbolize::Symbols;
+use crate::collapse::Collapse;
+use crate::folded::FoldedStacks;
+
+// perf.data file magic ("PERFILE2" read as a little-endian u64).
+const MAGIC: &[u8] = b"PERFILE2";
+
+// Size of `struct perf_file_header`.
+const FILE_HEADER_SIZE: u64 = 104;
+
+// Size of `struct perf_event_header` that precedes every record.
+const RECORD_HEADER_SIZE: usize = 8;
+
+// Record types we care about (see `enum perf_event_type` in linux/perf_event.h).
+const PERF_RECORD_MMAP: u32 = 1;
+const PERF_RECORD_COMM: u32 = 3;
+const PERF_RECORD_FORK: u32 = 7;
+const PERF_RECORD_SAMPLE: u32 = 9;
+const PERF_RECORD_MMAP2: u32 = 10;
+
+// Bits of `perf_event_attr::sample_type`.
+const PERF_SAMPLE_IP: u64 = 1 << 0;
+const PERF_SAMPLE_TID: u64 = 1 << 1;
+const PERF_SAMPLE_TIME: u64 = 1 << 2;
+const PERF_SAMPLE_ADDR: u64 = 1 << 3;
+const PERF_SAMPLE_READ: u64 = 1 << 4;
+const PERF_SAMPLE_CALLCHAIN: u64 = 1 << 5;
+const PERF_SAMPLE_ID: u64 = 1 << 6;
+const PERF_SAMPLE_CPU: u64 = 1 << 7;
+const PERF_SAMPLE_PERIOD: u64 = 1 << 8;
+const PERF_SAMPLE_STREAM_ID: u64 = 1 << 9;
+const PERF_SAMPLE_IDENTIFIER: u64 = 1 << 16;
+
+// Bits of `perf_event_attr::read_format`.
+const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+const PERF_FORMAT_ID: u64 = 1 << 2;
+const PERF_FORMAT_GROUP: u64 = 1 << 3;
+const PERF_FORMAT_LOST: u64 = 1 << 4;
+
+// CPU mode stored in the low bits of `perf_event_header::misc`.
+const PERF_RECORD_MISC_CPUMODE_MASK: u16 = 7;
+const PERF_RECORD_MISC_KERNEL: u16 = 1;
+const PERF_RECORD_MISC_GUEST_KERNEL: u16 = 4;
+
+// Context markers that perf interleaves with the addresses of a callchain.
+const PERF_CONTEXT_HV: u64 = -32i64 as u64;
+const PERF_CONTEXT_KERNEL: u64 = -128i64 as u64;
+const PERF_CONTEXT_GUEST_KERNEL: u64 = -2176i64 as u64;
+const PERF_CONTEXT_MAX: u64 = -4095i64 as u64;
+
+// Index of the `HEADER_EVENT_DESC` feature section, which holds the event names.
+const HEADER_EVENT_DESC: usize = 12;
+
+// Kernel mappings are recorded with a pid of -1.
+const KERNEL_PID: u32 = u32::MAX;
+
+const KERNEL_MODULE: &str = "[kernel.kallsyms]";
+const UNKNOWN: &str = "[unknown]";
+
+/// A stack collapser for the binary `perf.data` files written by `perf record`.
+///
+/// This produces the same folded output as running `perf script` on the file and passing the
+/// result to [`super::Folder`], except that symbols are resolved by reading the symbol tables of
+/// the ELF files on the local machine. The kernel is symbolized using `/proc/kallsyms` (or
+/// [`Options::kallsyms`]).
+///
+/// Only callchains recorded by the kernel are used (`perf record -g` or `--call-graph fp`); stacks
+/// recorded with `--call-graph dwarf` need to be unwound by `perf script`. Symbols are not
+/// demangled; the resulting frame names are exactly the names in the ELF symbol tables.
+///
+/// To construct one, either use `perf::data::Folder::default()` or create an [`Options`] and use
+/// `perf::data::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Self { opt }
+    }
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        self.collapse_to_occurrences(reader)?
+            .write_and_clear(writer)
+    }
+
+    fn collapse_into<R>(&mut self, reader: R, stacks: &mut FoldedStacks) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        self.collapse_to_occurrences(reader)?
+            .add_to_and_clear(stacks);
+        Ok(())
+    }
+
+    /// Check for the `perf.data` file magic.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        if input.len() < MAGIC.len() {
+            None
+        } else {
+            Some(input.as_bytes().starts_with(MAGIC))
+        }
+    }
+}
+
+impl Folder {
+    fn collapse_to_occurrences<R>(&mut self, mut reader: R) -> io::Result<Occurrences>
+    where
+        R: io::BufRead,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        let mut occurrences = Occurrences::new(1);
+        Session::new(&self.opt, &data)?.run(&mut occurrences)?;
+        Ok(occurrences)
+    }
+}
+
+/// A little-endian cursor over the bytes of a `perf.data` file.
+struct Bytes<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len().saturating_sub(self.pos)
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.remaining() < n {
+            return invalid_data_error!("Unexpected end of perf.data record");
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> io::Result<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let mut b = [0; 2];
+        b.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(b))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    /// Reads a `perf_file_section` (offset and size), checking that it lies within `file`.
+    fn section(&mut self, file: &'a [u8]) -> io::Result<&'a [u8]> {
+        let offset = self.u64()? as usize;
+        let size = self.u64()? as usize;
+        match offset.checked_add(size) {
+            Some(end) if end <= file.len() => Ok(&file[offset..end]),
+            _ => invalid_data_error!("perf.data section is out of bounds"),
+        }
+    }
+
+    /// Reads a NUL-terminated (and possibly NUL-padded) string spanning the rest of the buffer.
+    fn c_str(&mut self) -> &'a str {
+        let rest = &self.buf[self.pos..];
+        self.pos = self.buf.len();
+        c_str(rest)
+    }
+}
+
+fn c_str(bytes: &[u8]) -> &str {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..end]).unwrap_or(UNKNOWN)
+}
+
+/// The parts of a `perf_event_attr` needed to parse samples.
+struct Attr {
+    sample_type: u64,
+    read_format: u64,
+    name: String,
+}
+
+impl Attr {
+    fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let mut r = Bytes::new(bytes);
+        let ty = r.u32()?;
+        let _size = r.u32()?;
+        let config = r.u64()?;
+        let _sample_period = r.u64()?;
+        let sample_type = r.u64()?;
+        let read_format = r.u64()?;
+        Ok(Self {
+            sample_type,
+            read_format,
+            name: default_event_name(ty, config),
+        })
+    }
+}
+
+// Event names for the generic hardware and software events, used when the file does not carry a
+// `HEADER_EVENT_DESC` section.
+fn default_event_name(ty: u32, config: u64) -> String {
+    const HARDWARE: &[&str] = &[
+        "cycles",
+        "instructions",
+        "cache-references",
+        "cache-misses",
+        "branch-instructions",
+        "branch-misses",
+        "bus-cycles",
+        "stalled-cycles-frontend",
+        "stalled-cycles-backend",
+        "ref-cycles",
+    ];
+    const SOFTWARE: &[&str] = &[
+        "cpu-clock",
+        "task-clock",
+        "page-faults",
+        "context-switches",
+        "cpu-migrations",
+        "minor-faults",
+        "major-faults",
+        "alignment-faults",
+        "emulation-faults",
+        "dummy",
+        "bpf-output",
+    ];
+    let names = match ty {
+        0 => HARDWARE,
+        1 => SOFTWARE,
+        _ => &[],
+    };
+    match names.get(config as usize) {
+        Some(name) => name.to_string(),
+        None => format!("raw-{}-{:#x}", ty, config),
+    }
+}
+
+// The name perf script prints for an event is everything up to the first modifier, which is also
+// what the text collapser uses as the event type.
+fn event_type(name: &str) -> &str {
+    name.split(':').next().unwrap_or(name)
+}
+
+/// A memory mapping recorded by a `PERF_RECORD_MMAP` or `PERF_RECORD_MMAP2` record.
+#[derive(Clone)]
+struct Mapping {
+    start: u64,
+    end: u64,
+    pgoff: u64,
+    filename: String,
+}
+
+/// The symbols of a file that has been mapped into a process.
+enum Dso {
+    /// An ELF file, whose file offsets have to be translated into virtual addresses through its
+    /// loadable segments.
+    Elf {
+        segments: Vec<(u64, u64, u64)>,
+        symbols: Symbols,
+    },
+    /// A `/tmp/perf-<pid>.map` file written by a JIT, which contains absolute addresses.
+    PerfMap(Symbols),
+}
+
+impl Dso {
+    fn load(path: &Path, filename: &str) -> Option<Self> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                debug!("Unable to read {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        if filename.starts_with("/tmp/perf-") && filename.ends_with(".map") {
+            return Some(Dso::PerfMap(parse_perf_map(&data)));
+        }
+
+        let file = match object::File::parse(&data) {
+            Ok(file) => file,
+            Err(e) => {
+                debug!("Unable to parse {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        let segments = file
+            .segments()
+            .map(|segment| {
+                let (offset, size) = segment.file_range();
+                (offset, size, segment.address())
+            })
+            .collect();
+        let symbols = file
+            .symbols()
+            .chain(file.dynamic_symbols())
+            .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.address() != 0)
+            .filter_map(|symbol| {
+                let name = symbol.name().ok()?;
+                Some((symbol.address(), symbol.size(), name.to_string()))
+            })
+            .collect();
+        Some(Dso::Elf {
+            segments,
+            symbols: Symbols::from_unsorted(symbols),
+        })
+    }
+
+    fn lookup(&self, mapping: &Mapping, ip: u64) -> Option<&str> {
+        match self {
+            Dso::Elf { segments, symbols } => {
+                let offset = (ip - mapping.start).checked_add(mapping.pgoff)?;
+                let vaddr = segments
+                    .iter()
+                    .find(|&&(start, size, _)| offset >= start && offset - start < size)
+                    .and_then(|&(start, _, vaddr)| vaddr.checked_add(offset - start))?;
+                symbols.lookup(vaddr)
+            }
+            Dso::PerfMap(symbols) => symbols.lookup(ip),
+        }
+    }
+}
+
+// Each line of a perf map file is `START SIZE symbolname`, with START and SIZE in hex.
+fn parse_perf_map(data: &[u8]) -> Symbols {
+    let symbols = String::from_utf8_lossy(data)
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.trim().splitn(3, ' ');
+            let start = u64::from_str_radix(parts.next()?.trim_start_matches("0x"), 16).ok()?;
+            let size = u64::from_str_radix(parts.next()?.trim_start_matches("0x"), 16).ok()?;
+            Some((start, size, parts.next()?.to_string()))
+        })
+        .collect();
+    Symbols::from_unsorted(symbols)
+}
+
+// Each line of kallsyms is `ADDRESS TYPE NAME [MODULE]`. Without sufficient privileges all
+// addresses are zero, in which case the file is useless.
+fn parse_kallsyms(data: &str) -> Symbols {
+    let symbols = data
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let addr = u64::from_str_radix(parts.next()?, 16).ok()?;
+            let _ty = parts.next()?;
+            let name = parts.next()?;
+            if addr == 0 {
+                None
+            } else {
+                Some((addr, 0, name.to_string()))
+            }
+        })
+        .collect();
+    Symbols::from_unsorted(symbols)
+}
+
+/// The state needed to collapse a single `perf.data` file.
+struct Session<'a> {
+    records: &'a [u8],
+    attrs: Vec<Attr>,
+    attrs_by_id: AHashMap<u64, usize>,
+
+    comms: AHashMap<u32, String>,
+    maps: AHashMap<u32, Vec<Mapping>>,
+    dsos: AHashMap<String, Option<Dso>>,
+    kallsyms: Option<Symbols>,
+
+    folder: super::Folder,
+    symfs: Option<PathBuf>,
+    kallsyms_path: PathBuf,
+}
+
+impl<'a> Session<'a> {
+    fn new(opt: &Options, data: &'a [u8]) -> io::Result<Self> {
+        if data.len() < FILE_HEADER_SIZE as usize || !data.starts_with(MAGIC) {
+            if data.len() >= MAGIC.len() && data[..MAGIC.len()].iter().rev().eq(MAGIC.iter()) {
+                return invalid_data_error!("Big-endian perf.data files are not supported");
+            }
+            return invalid_data_error!("Input is not a perf.data file");
+        }
+
+        let mut header = Bytes::new(data);
+        header.skip(MAGIC.len())?;
+        if header.u64()? != FILE_HEADER_SIZE {
+            return invalid_data_error!(
+                "perf.data files written in pipe mode (perf record -o -) are not supported"
+            );
+        }
+        let attr_size = header.u64()? as usize;
+        let attrs_section = header.section(data)?;
+        let records_pos = header.pos;
+        let records = header.section(data)?;
+        let _event_types = header.section(data)?;
+        let mut features = [0u64; 4];
+        for word in features.iter_mut() {
+            *word = header.u64()?;
+        }
+
+        // Each entry of the attrs section is a `perf_event_attr` followed by a section holding
+        // the sample ids that belong to it.
+        if attr_size < 16 + 40 {
+            return invalid_data_error!("Invalid perf.data attr size: {}", attr_size);
+        }
+        let mut attrs = Vec::new();
+        let mut attrs_by_id = AHashMap::default();
+        for entry in attrs_section.chunks_exact(attr_size) {
+            attrs.push(Attr::parse(&entry[..attr_size - 16])?);
+            let mut ids = Bytes::new(Bytes::new(&entry[attr_size - 16..]).section(data)?);
+            while let Ok(id) = ids.u64() {
+                attrs_by_id.insert(id, attrs.len() - 1);
+            }
+        }
+        if attrs.is_empty() {
+            return invalid_data_error!("perf.data file does not contain any events");
+        }
+
+        let mut session = Self {
+            records,
+            attrs,
+            attrs_by_id,
+            comms: AHashMap::default(),
+            maps: AHashMap::default(),
+            dsos: AHashMap::default(),
+            kallsyms: None,
+            folder: super::Folder::new({
+                let mut opt = opt.clone();
+                opt.nthreads = 1;
+                opt
+            })?,
+            symfs: opt.symfs.clone(),
+            kallsyms_path: opt
+                .kallsyms
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("/proc/kallsyms")),
+        };
+
+        // The feature sections follow the data section, one for each bit set in the header.
+        let mut sections = Bytes::new(data);
+        sections.pos = records_pos;
+        let feature_offset = match sections.u64()?.checked_add(sections.u64()?) {
+            Some(offset) => offset,
+            None => return invalid_data_error!("perf.data data section is out of bounds"),
+        };
+        sections.pos = feature_offset as usize;
+        for feature in 0..256 {
+            if features[feature / 64] & (1 << (feature % 64)) == 0 {
+                continue;
+            }
+            let section = sections.section(data)?;
+            if feature == HEADER_EVENT_DESC {
+                session.parse_event_desc(section)?;
+            }
+        }
+
+        Ok(session)
+    }
+
+    // Assigns the event names from the `HEADER_EVENT_DESC` feature section to the attrs.
+    fn parse_event_desc(&mut self, section: &[u8]) -> io::Result<()> {
+        let mut r = Bytes::new(section);
+        let nr = r.u32()?;
+        let attr_size = r.u32()? as usize;
+        for i in 0..nr as usize {
+            r.skip(attr_size)?;
+            let nr_ids = r.u32()?;
+            let len = r.u32()? as usize;
+            let name = c_str(r.take(len)?).to_string();
+            let mut index = None;
+            for _ in 0..nr_ids {
+                index = self.attrs_by_id.get(&r.u64()?).copied().or(index);
+            }
+            // Fall back to the position if the ids don't tell us which attr this is.
+            let index = index.unwrap_or(i);
+            if let Some(attr) = self.attrs.get_mut(index) {
+                attr.name = name;
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, occurrences: &mut Occurrences) -> io::Result<()> {
+        let mut records = Bytes::new(self.records);
+        while records.pos < records.buf.len() {
+            let ty = records.u32()?;
+            let misc = records.u16()?;
+            let size = records.u16()? as usize;
+            if size < RECORD_HEADER_SIZE {
+                return invalid_data_error!("Invalid perf.data record size: {}", size);
+            }
+            let body = records.take(size - RECORD_HEADER_SIZE)?;
+            match ty {
+                PERF_RECORD_MMAP | PERF_RECORD_MMAP2 => self.on_mmap(ty, body)?,
+                PERF_RECORD_COMM => self.on_comm(body)?,
+                PERF_RECORD_FORK => self.on_fork(body)?,
+                PERF_RECORD_SAMPLE => self.on_sample(misc, body, occurrences)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn on_mmap(&mut self, ty: u32, body: &[u8]) -> io::Result<()> {
+        let mut r = Bytes::new(body);
+        let pid = r.u32()?;
+        let _tid = r.u32()?;
+        let start = r.u64()?;
+        let len = r.u64()?;
+        let pgoff = r.u64()?;
+        if ty == PERF_RECORD_MMAP2 {
+            // maj, min, ino, ino_generation, prot and flags (or the build id that replaces some
+            // of them).
+            r.skip(4 + 4 + 8 + 8 + 4 + 4)?;
+        }
+        let filename = r.c_str().to_string();
+        self.maps.entry(pid).or_default().push(Mapping {
+            start,
+            end: start.saturating_add(len),
+            pgoff,
+            filename,
+        });
+        Ok(())
+    }
+
+    fn on_comm(&mut self, body: &[u8]) -> io::Result<()> {
+        let mut r = Bytes::new(body);
+        let _pid = r.u32()?;
+        let tid = r.u32()?;
+        let comm = r.c_str().to_string();
+        self.comms.insert(tid, comm);
+        Ok(())
+    }
+
+    fn on_fork(&mut self, body: &[u8]) -> io::Result<()> {
+        let mut r = Bytes::new(body);
+        let pid = r.u32()?;
+        let ppid = r.u32()?;
+        let tid = r.u32()?;
+        let ptid = r.u32()?;
+
+        // A new thread inherits the name of its parent, and a new process inherits its parent's
+        // address space.
+        if let Some(comm) = self.comms.get(&ptid).cloned() {
+            self.comms.entry(tid).or_insert(comm);
+        }
+        if pid != ppid {
+            if let Some(maps) = self.maps.get(&ppid).cloned() {
+                self.maps.entry(pid).or_insert(maps);
+            }
+        }
+        Ok(())
+    }
+
+    fn on_sample(
+        &mut self,
+        misc: u16,
+        body: &[u8],
+        occurrences: &mut Occurrences,
+    ) -> io::Result<()> {
+        let attr = if self.attrs.len() == 1 {
+            0
+        } else {
+            match sample_id(self.attrs[0].sample_type, body)
+                .and_then(|id| self.attrs_by_id.get(&id))
+            {
+                Some(&attr) => attr,
+                None => {
+                    warn!("Skipping sample that doesn't belong to any known event");
+                    return Ok(());
+                }
+            }
+        };
+        let sample_type = self.attrs[attr].sample_type;
+        let read_format = self.attrs[attr].read_format;
+
+        let mut r = Bytes::new(body);
+        if sample_type & PERF_SAMPLE_IDENTIFIER != 0 {
+            r.u64()?;
+        }
+        let ip = if sample_type & PERF_SAMPLE_IP != 0 {
+            Some(r.u64()?)
+        } else {
+            None
+        };
+        let (pid, tid) = if sample_type & PERF_SAMPLE_TID != 0 {
+            (r.u32()?, r.u32()?)
+        } else {
+            (0, 0)
+        };
+        for &(bit, size) in &[
+            (PERF_SAMPLE_TIME, 8),
+            (PERF_SAMPLE_ADDR, 8),
+            (PERF_SAMPLE_ID, 8),
+            (PERF_SAMPLE_STREAM_ID, 8),
+        ] {
+            if sample_type & bit != 0 {
+                r.skip(size)?;
+            }
+        }
+        // Padded like the CPU that `perf script` prints.
+        let cpu = if sample_type & PERF_SAMPLE_CPU != 0 {
+            let cpu = r.u32()?;
+            r.skip(4)?;
+            Some(format!("{:03}", cpu))
+        } else {
+            None
+        };
+        let period = if sample_type & PERF_SAMPLE_PERIOD != 0 {
+            Some(r.u64()? as usize)
+        } else {
+            None
+        };
+        if sample_type & PERF_SAMPLE_READ != 0 {
+            skip_read_values(&mut r, read_format)?;
+        }
+        let callchain = if sample_type & PERF_SAMPLE_CALLCHAIN != 0 {
+            let nr = r.u64()? as usize;
+            // The length comes from the file, so don't trust it any further than the record goes.
+            let mut ips = Vec::with_capacity(nr.min(r.remaining() / 8));
+            for _ in 0..nr {
+                ips.push(r.u64()?);
+            }
+            ips
+        } else {
+            ip.into_iter().collect()
+        };
+
+        let comm = match self.comms.get(&tid) {
+            Some(comm) => Cow::Borrowed(comm.as_str()),
+            None => Cow::Owned(format!(":{}", tid)),
+        };
+        let event = event_type(&self.attrs[attr].name);
+        if self.folder.on_event(
+            &comm,
+            &pid.to_string(),
+            &tid.to_string(),
+            cpu.as_deref(),
+            Some(event),
+            period,
+        ) {
+            let mut kernel = matches!(
+                misc & PERF_RECORD_MISC_CPUMODE_MASK,
+                PERF_RECORD_MISC_KERNEL | PERF_RECORD_MISC_GUEST_KERNEL
+            );
+            for ip in callchain {
+                if ip >= PERF_CONTEXT_MAX {
+                    kernel = matches!(
+                        ip,
+                        PERF_CONTEXT_HV | PERF_CONTEXT_KERNEL | PERF_CONTEXT_GUEST_KERNEL
+                    );
+                    continue;
+                }
+                let (func, module) = self.symbolize(pid, ip, kernel);
+                let pc = format!("{:x}", ip);
+                self.folder.on_frame(&pc, &func, &module);
+            }
+        }
+        self.folder.after_event(occurrences);
+        Ok(())
+    }
+
+    /// Resolves an address to the function and module names that `perf script` would show.
+    fn symbolize(&mut self, pid: u32, ip: u64, kernel: bool) -> (String, String) {
+        let pid = if kernel { KERNEL_PID } else { pid };
+        let mapping = self.maps.get(&pid).and_then(|maps| {
+            maps.iter()
+                .rev()
+                .find(|mapping| ip >= mapping.start && ip < mapping.end)
+        });
+
+        if kernel {
+            let module = match mapping {
+                Some(mapping) if !mapping.filename.starts_with(KERNEL_MODULE) => {
+                    mapping.filename.clone()
+                }
+                _ => KERNEL_MODULE.to_string(),
+            };
+            let kallsyms_path = &self.kallsyms_path;
+            let kallsyms =
+                self.kallsyms
+                    .get_or_insert_with(|| match fs::read_to_string(kallsyms_path) {
+                        Ok(data) => parse_kallsyms(&data),
+                        Err(e) => {
+                            warn!("Unable to read {}: {}", kallsyms_path.display(), e);
+                            Symbols::default()
+                        }
+                    });
+            let func = kallsyms.lookup(ip).unwrap_or(UNKNOWN).to_string();
+            return (func, module);
+        }
+
+        let mapping = match mapping {
+            Some(mapping) => mapping.clone(),
+            None => return (UNKNOWN.to_string(), UNKNOWN.to_string()),
+        };
+        // Anonymous memory, the heap, the stack, the vdso, etc.
+        if mapping.filename.starts_with('[') || mapping.filename.starts_with("//") {
+            return (UNKNOWN.to_string(), mapping.filename);
+        }
+
+        let symfs = &self.symfs;
+        let dso = self
+            .dsos
+            .entry(mapping.filename.clone())
+            .or_insert_with(|| {
+                let path = match symfs {
+                    Some(symfs) => symfs.join(mapping.filename.trim_start_matches('/')),
+                    None => PathBuf::from(&mapping.filename),
+                };
+                Dso::load(&path, &mapping.filename)
+            });
+        let func = dso
+            .as_ref()
+            .and_then(|dso| dso.lookup(&mapping, ip))
+            .unwrap_or(UNKNOWN)
+            .to_string();
+        (func, mapping.filename)
+    }
+}
+
+// Finds the sample id in a sample record without knowing which event the sample belongs to. perf
+// requires all events in a file to agree on where the id is, so the first event's sample type
+// can be used.
+fn sample_id(sample_type: u64, body: &[u8]) -> Option<u64> {
+    let index = if sample_type & PERF_SAMPLE_IDENTIFIER != 0 {
+        0
+    } else if sample_type & PERF_SAMPLE_ID != 0 {
+        [
+            PERF_SAMPLE_IP,
+            PERF_SAMPLE_TID,
+            PERF_SAMPLE_TIME,
+            PERF_SAMPLE_ADDR,
+        ]
+        .iter()
+        .filter(|&&bit| sample_type & bit != 0)
+        .count()
+    } else {
+        return None;
+    };
+    let mut r = Bytes::new(body);
+    r.skip(index * 8).ok()?;
+    r.u64().ok()
+}
+
+fn skip_read_values(r: &mut Bytes<'_>, read_format: u64) -> io::Result<()> {
+    let per_value = 1
+        + (read_format & PERF_FORMAT_ID != 0) as usize
+        + (read_format & PERF_FORMAT_LOST != 0) as usize;
+    let times = (read_format & PERF_FORMAT_TOTAL_TIME_ENABLED != 0) as usize
+        + (read_format & PERF_FORMAT_TOTAL_TIME_RUNNING != 0) as usize;
+    if read_format & PERF_FORMAT_GROUP != 0 {
+        let nr = r.u64()? as usize;
+        let size = nr
+            .checked_mul(per_value)
+            .and_then(|n| n.checked_add(times))
+            .and_then(|n| n.checked_mul(8));
+        match size {
+            Some(size) => r.skip(size),
+            None => invalid_data_error!("Unexpected end of perf.data record"),
+        }
+    } else {
+        r.skip(8 * (times + per_value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skip_read_values_overflow() {
+        let body = [0xff; 8];
+        let mut r = Bytes::new(&body);
+        assert!(skip_read_values(&mut r, PERF_FORMAT_GROUP | PERF_FORMAT_ID).is_err());
+    }
+
+    #[test]
+    fn test_event_names() {
+        assert_eq!(default_event_name(0, 0), "cycles");
+        assert_eq!(default_event_name(1, 0), "cpu-clock");
+        assert_eq!(default_event_name(4, 0x1c2), "raw-4-0x1c2");
+        assert_eq!(event_type("cycles:uppp"), "cycles");
+        assert_eq!(event_type("instructions"), "instructions");
+    }
+
+    #[test]
+    fn test_sample_id() {
+        let body: Vec<u8> = [7u64, 8, 9, 10]
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+        assert_eq!(sample_id(PERF_SAMPLE_IDENTIFIER, &body), Some(7));
+        assert_eq!(
+            sample_id(PERF_SAMPLE_IP | PERF_SAMPLE_TID | PERF_SAMPLE_ID, &body),
+            Some(9)
+        );
+        assert_eq!(sample_id(PERF_SAMPLE_IP, &body), None);
+    }
+}
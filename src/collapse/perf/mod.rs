@@ -1,8 +1,20 @@
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, File};
 use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
-use crate::collapse::common::{self, CollapsePrivate, Occurrences};
+use regex::Regex;
+
+use crate::collapse::common::{self, CollapseLines, CollapsePrivate, Occurrences};
 use crate::collapse::matcher::is_kernel;
+use crate::collapse::symbolize::{self, Symbolizer};
+use crate::folded::{FoldedStacks, Location};
+
+/// Stack collapsing for the binary `perf.data` files written by `perf record`.
+///
+/// This lets you skip `perf script` entirely; see [`data::Folder`].
+pub mod data;
 
 const TIDY_GENERIC: bool = true;
 const TIDY_JAVA: bool = true;
@@ -21,6 +33,10 @@ mod logging {
     pub(super) fn weird_stack_line(line: &str) {
         warn!("Weird stack line: {}", line);
     }
+
+    pub(super) fn unable_to_symbolize(e: &std::io::Error) {
+        warn!("Unable to symbolize addresses: {}", e);
+    }
 }
 
 /// `perf` folder configuration options.
@@ -59,10 +75,56 @@ pub struct Options {
     /// Default is `false`.
     pub include_tid: bool,
 
+    /// Group the stacks by process, thread, CPU or thread name, with a root frame named after
+    /// the group of each stack, above the process name. The groups can then be written to a
+    /// file each with [`write_groups`].
+    ///
+    /// Default is `None`.
+    pub group_by: Option<GroupBy>,
+
+    /// Collapse the samples of every event type in a single pass, such as those of
+    /// `perf record -e cycles,instructions`, with a root frame named after the event of each
+    /// stack, above the group and the process name. `event_filter` is ignored. The events can
+    /// then be written to a file each with [`write_groups`], or compared with [`write_ratio`].
+    ///
+    /// Default is `false`.
+    pub all_events: bool,
+
+    /// Count each sample as its period, the number of events it stands for, rather than as a
+    /// single sample. `perf script` prints the period before the event name, unless it was left
+    /// out of its fields; samples without one count as 1.
+    ///
+    /// Default is `false`.
+    pub count_periods: bool,
+
+    /// Add the source file and line of each frame, as a [`Location`], from the `srcline` field
+    /// of `perf script` (`perf script -F+srcline`). Without it, `srcline` lines are skipped.
+    ///
+    /// Default is `false`.
+    pub include_lines: bool,
+
     /// The number of threads to use.
     ///
     /// Default is the number of logical cores on your machine.
     pub nthreads: usize,
+
+    /// Look for the files referenced by a `perf.data` file relative to this directory, like
+    /// `perf report --symfs`. Only used when reading `perf.data` files.
+    ///
+    /// Default is `None`.
+    pub symfs: Option<PathBuf>,
+
+    /// The file to read kernel symbols from when reading `perf.data` files.
+    ///
+    /// Default is `None`, which means `/proc/kallsyms`.
+    pub kallsyms: Option<PathBuf>,
+
+    /// Resolve the addresses of the frames that `perf` couldn't symbolize (`[unknown]`) from the
+    /// binaries on disk. See [`symbolize::Options`], whose [`pid`](symbolize::Options::pid) limits
+    /// this to the stacks of one process.
+    ///
+    /// Default is `None`.
+    pub symbolize: Option<symbolize::Options>,
 }
 
 impl Default for Options {
@@ -74,21 +136,63 @@ impl Default for Options {
             include_addrs: false,
             include_pid: false,
             include_tid: false,
+            group_by: None,
+            all_events: false,
+            count_periods: false,
+            include_lines: false,
             nthreads: *common::DEFAULT_NTHREADS,
+            symfs: None,
+            kallsyms: None,
+            symbolize: None,
         }
     }
 }
 
+/// How the stacks of `perf script` output are grouped with [`Options::group_by`].
+///
+/// Each group is named deterministically after what the stacks in it have in common, which is
+/// the name of the root frame the stacks get and of the file [`write_groups`] writes them to.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum GroupBy {
+    /// One group per process, named after its PID, as in `pid-4242`.
+    Process,
+
+    /// One group per thread, named after its TID, as in `tid-4243`.
+    Thread,
+
+    /// One group per CPU the samples were taken on, as in `cpu-002`. `perf script` must print
+    /// the CPU of each sample (`perf script -F+cpu`).
+    Cpu,
+
+    /// One group per thread name (the `comm` of `perf script`), with the threads whose name
+    /// matches one of these expressions grouped as one, such as all the threads of a worker pool.
+    ///
+    /// Like the groups of [`report::Options::groups`], such a group is named after the first
+    /// capture group of the first expression that matches, or after the expression itself when
+    /// it has no capture groups. For example, `^(worker)-[0-9]+$` groups `worker-1` and
+    /// `worker-2` as `worker`. Spaces and semicolons in group names are replaced with
+    /// underscores.
+    ///
+    ///   [`report::Options::groups`]: crate::report::Options::groups
+    ThreadName(Vec<Regex>),
+}
+
 /// A stack collapser for the output of `perf script`.
 ///
 /// To construct one, either use `perf::Folder::default()` or create an [`Options`] and use
-/// `perf::Folder::from(options)`.
+/// `perf::Folder::from(options)`. If the symbolizer of [`Options::symbolize`] can't be created,
+/// such as when its maps file doesn't exist, `from` logs a warning and leaves the addresses
+/// unresolved; [`Folder::new`] returns the error instead.
 pub struct Folder {
     // State...
     /// General String cache that can be used while processing lines. Currently only used to keep
     /// track of functions for Java inlining.
     cache_line: Vec<String>,
 
+    /// The event of the current stack, if all event types are collapsed.
+    event: String,
+
     /// Similar to, but different from, the `event_filter` field on `Options`
     ///
     /// * Field on `Options` represents user's provided configuration and will never change.
@@ -99,12 +203,25 @@ pub struct Folder {
     ///   different event types, such as instructions and cycles, would produce misleading results.
     event_filter: Option<String>,
 
+    /// The number of frames added by the last stack line, which the `srcline` line that may
+    /// follow it belongs to.
+    frames_in_line: usize,
+
+    /// The group of the current event, if the stacks are grouped.
+    group: String,
+
     /// All lines until the next empty line are stack lines.
     in_event: bool,
 
     /// The number of stacks per job to send to the threadpool.
     nstacks_per_job: usize,
 
+    /// The number of samples the current stack counts as.
+    period: usize,
+
+    /// The PID of the current event, if it's known.
+    pid: Option<u32>,
+
     /// Current comm name.
     ///
     /// Called pname after original stackcollapse-perf source.
@@ -116,24 +233,60 @@ pub struct Folder {
     /// Function entries on the stack in this entry thus far.
     stack: VecDeque<String>,
 
+    /// Resolves the addresses of unknown frames, shared by the threads that collapse the input.
+    symbolizer: Option<Arc<Mutex<Symbolizer>>>,
+
     // Options...
     opt: Options,
 }
 
 impl From<Options> for Folder {
-    fn from(mut opt: Options) -> Self {
+    fn from(opt: Options) -> Self {
+        let symbolizer = Self::symbolizer(&opt).unwrap_or_else(|e| {
+            logging::unable_to_symbolize(&e);
+            None
+        });
+        Self::with_symbolizer(opt, symbolizer)
+    }
+}
+
+impl Folder {
+    /// Create a folder from `opt`, like `Folder::from(opt)`, but return an error if the
+    /// symbolizer of [`Options::symbolize`] can't be created, as [`Symbolizer::new`] does.
+    pub fn new(opt: Options) -> io::Result<Self> {
+        let symbolizer = Self::symbolizer(&opt)?;
+        Ok(Self::with_symbolizer(opt, symbolizer))
+    }
+
+    fn symbolizer(opt: &Options) -> io::Result<Option<Arc<Mutex<Symbolizer>>>> {
+        match opt.symbolize {
+            Some(ref symbolize) => {
+                let symbolizer = Symbolizer::new(symbolize.clone())?;
+                Ok(Some(Arc::new(Mutex::new(symbolizer))))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn with_symbolizer(mut opt: Options, symbolizer: Option<Arc<Mutex<Symbolizer>>>) -> Self {
         if opt.nthreads == 0 {
             opt.nthreads = 1;
         }
         opt.include_pid = opt.include_pid || opt.include_tid;
         Self {
             cache_line: Vec::default(),
+            event: String::default(),
             event_filter: opt.event_filter.clone(),
+            frames_in_line: 0,
+            group: String::default(),
             in_event: false,
             nstacks_per_job: common::DEFAULT_NSTACKS_PER_JOB,
+            period: 1,
+            pid: None,
             pname: String::default(),
             skip_stack: false,
             stack: VecDeque::default(),
+            symbolizer,
             opt,
         }
     }
@@ -150,8 +303,8 @@ impl CollapsePrivate for Folder {
     where
         R: io::BufRead,
     {
-        // If user has provided an event filter, do nothing...
-        if self.event_filter.is_some() {
+        // If user has provided an event filter, or wants all events, do nothing...
+        if self.event_filter.is_some() || self.opt.all_events {
             return Ok(());
         }
 
@@ -242,12 +395,18 @@ impl CollapsePrivate for Folder {
     fn clone_and_reset_stack_context(&self) -> Self {
         Self {
             cache_line: self.cache_line.clone(),
+            event: String::new(),
             event_filter: self.event_filter.clone(),
+            frames_in_line: 0,
+            group: String::new(),
             in_event: false,
             nstacks_per_job: self.nstacks_per_job,
+            period: 1,
+            pid: None,
             pname: String::new(),
             skip_stack: false,
             stack: VecDeque::default(),
+            symbolizer: self.symbolizer.clone(),
             opt: self.opt.clone(),
         }
     }
@@ -269,6 +428,22 @@ impl CollapsePrivate for Folder {
     }
 }
 
+impl CollapseLines for Folder {
+    fn collapse_line(&mut self, line: &[u8], occurrences: &mut Occurrences) -> io::Result<()> {
+        self.on_line(line, occurrences);
+        Ok(())
+    }
+
+    fn end_of_input(&mut self, occurrences: &mut Occurrences) -> io::Result<()> {
+        if !self.stack.is_empty() {
+            self.after_event(occurrences);
+        }
+        self.in_event = false;
+        self.skip_stack = false;
+        Ok(())
+    }
+}
+
 impl Folder {
     /// Processes a stack. On success, returns `true` if at end of data; `false` otherwise.
     fn process_single_stack<R>(
@@ -288,25 +463,33 @@ impl Folder {
                 }
                 return Ok(true);
             }
-            let line = String::from_utf8_lossy(line_buffer);
-            if line.starts_with('#') {
-                continue;
+            if self.on_line(line_buffer, occurrences) {
+                return Ok(false);
             }
-            let line = line.trim_end();
-            if line.is_empty() {
+        }
+    }
+
+    /// Processes a line of input. Returns `true` if the line ended a stack.
+    fn on_line(&mut self, line: &[u8], occurrences: &mut Occurrences) -> bool {
+        let line = String::from_utf8_lossy(line);
+        if line.starts_with('#') {
+            return false;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            self.after_event(occurrences);
+            return true;
+        } else if self.in_event {
+            self.on_stack_line(line);
+        } else {
+            assert!(self.stack.is_empty());
+            self.on_event_line(line);
+            if !self.stack.is_empty() {
+                // we must have hit a combined event/stack line
                 self.after_event(occurrences);
-                return Ok(false);
-            } else if self.in_event {
-                self.on_stack_line(line);
-            } else {
-                assert!(self.stack.is_empty());
-                self.on_event_line(line);
-                if !self.stack.is_empty() {
-                    // we must have hit a combined event/stack line
-                    self.after_event(occurrences);
-                }
             }
         }
+        false
     }
 
     fn event_line_parts(line: &str) -> Option<(&str, &str, &str, usize)> {
@@ -361,27 +544,20 @@ impl Folder {
 
         if let Some((comm, pid, tid, end)) = Self::event_line_parts(line) {
             let mut by_colons = line[end..].splitn(3, ':').skip(1);
-            let event = by_colons
-                .next()
-                .and_then(|has_event| has_event.rsplit(' ').next());
-            if let Some(event) = event {
-                if let Some(ref event_filter) = self.event_filter {
-                    if event != event_filter {
-                        self.skip_stack = true;
-                        return;
-                    }
-                } else {
-                    // By default only show events of the first encountered event type.
-                    // Merging together different types, such as instructions and cycles,
-                    // produces misleading results.
-                    logging::filtering_for_events_of_type(event);
-                    self.event_filter = Some(event.to_string());
-                }
+            let has_event = by_colons.next();
+            let event = has_event.and_then(|has_event| has_event.rsplit(' ').next());
+            // The period, if perf script printed it, is the number just before the event.
+            let period = has_event
+                .and_then(|has_event| has_event.split_whitespace().rev().nth(1))
+                .and_then(|period| period.parse().ok());
+            let cpu = event_line_cpu(&line[end..]);
+            if !self.on_event(comm, pid, tid, cpu, event, period) {
+                return;
             }
 
             // some event lines _include_ a stack line if the stack only has one frame.
             // in that case, the event will be followed by the stack.
-            let single_stack = if let Some(post_event) = by_colons.next() {
+            if let Some(post_event) = by_colons.next() {
                 // we need to deal with a couple of cases here:
                 //
                 //     vote   913    72.176760:     257597 cycles:uppp:
@@ -398,29 +574,9 @@ impl Folder {
                 let post_event = post_event[post_event_start..].trim();
                 if !post_event.is_empty() {
                     // we have a stack!
-                    Some(post_event)
-                } else {
-                    None
+                    self.on_stack_line(post_event);
+                    self.in_event = false;
                 }
-            } else {
-                None
-            };
-
-            // XXX: re-use existing memory in pname if possible
-            self.pname = comm.replace(' ', "_");
-            if self.opt.include_tid {
-                self.pname.push_str("-");
-                self.pname.push_str(pid);
-                self.pname.push_str("/");
-                self.pname.push_str(tid);
-            } else if self.opt.include_pid {
-                self.pname.push_str("-");
-                self.pname.push_str(pid);
-            }
-
-            if let Some(stack_line) = single_stack {
-                self.on_stack_line(stack_line);
-                self.in_event = false;
             }
         } else {
             logging::weird_event_line(line);
@@ -428,6 +584,92 @@ impl Folder {
         }
     }
 
+    /// Starts a new event for the given thread, on the given CPU and with the given period if
+    /// they're known.
+    ///
+    /// Returns `false` if the event does not pass the event filter, in which case all frames
+    /// until the next call to `after_event` are skipped.
+    pub(super) fn on_event(
+        &mut self,
+        comm: &str,
+        pid: &str,
+        tid: &str,
+        cpu: Option<&str>,
+        event: Option<&str>,
+        period: Option<usize>,
+    ) -> bool {
+        self.in_event = true;
+
+        if self.opt.all_events {
+            self.event.clear();
+            self.event.push_str(event.unwrap_or("unknown"));
+        } else if let Some(event) = event {
+            if let Some(ref event_filter) = self.event_filter {
+                if event != event_filter {
+                    self.skip_stack = true;
+                    return false;
+                }
+            } else {
+                // By default only show events of the first encountered event type.
+                // Merging together different types, such as instructions and cycles,
+                // produces misleading results.
+                logging::filtering_for_events_of_type(event);
+                self.event_filter = Some(event.to_string());
+            }
+        }
+
+        self.pid = pid.parse().ok();
+        self.period = match period {
+            Some(period) if self.opt.count_periods => period,
+            _ => 1,
+        };
+
+        // XXX: re-use existing memory in pname if possible
+        self.pname = comm.replace(' ', "_");
+        if self.opt.include_tid {
+            self.pname.push_str("-");
+            self.pname.push_str(pid);
+            self.pname.push_str("/");
+            self.pname.push_str(tid);
+        } else if self.opt.include_pid {
+            self.pname.push_str("-");
+            self.pname.push_str(pid);
+        }
+
+        if let Some(ref group_by) = self.opt.group_by {
+            self.group.clear();
+            match group_by {
+                GroupBy::Process => {
+                    self.group.push_str("pid-");
+                    self.group
+                        .push_str(if pid == "?" { "unknown" } else { pid });
+                }
+                GroupBy::Thread => {
+                    self.group.push_str("tid-");
+                    self.group.push_str(tid);
+                }
+                GroupBy::Cpu => {
+                    self.group.push_str("cpu-");
+                    self.group.push_str(cpu.unwrap_or("unknown"));
+                }
+                GroupBy::ThreadName(patterns) => {
+                    let name = patterns.iter().find_map(|re| {
+                        let captures = re.captures(comm)?;
+                        Some(match captures.get(1) {
+                            Some(name) => name.as_str(),
+                            None => re.as_str(),
+                        })
+                    });
+                    // Frames are separated by semicolons, and the count by a space.
+                    self.group
+                        .push_str(&name.unwrap_or(comm).replace(&[' ', ';'][..], "_"));
+                }
+            }
+        }
+
+        true
+    }
+
     fn stack_line_parts(line: &str) -> Option<(&str, &str, &str)> {
         let mut line = line.trim_start().splitn(2, ' ');
         let pc = line.next()?.trim_end();
@@ -479,71 +721,135 @@ impl Folder {
                 }
             }
 
-            // skip process names?
-            // see https://github.com/brendangregg/FlameGraph/blob/f857ebc94bfe2a9bfdc4f1536ebacfb7466f69ba/stackcollapse-perf.pl#L269
-            if rawfunc.starts_with('(') {
-                return;
+            self.on_frame(pc, rawfunc, module);
+        } else if is_srcline(line) {
+            self.on_srcline(line.trim());
+        } else {
+            logging::weird_stack_line(line);
+        }
+    }
+
+    // we have the srcline of the preceding stack line, like:
+    //
+    //       /usr/src/debug/glibc-2.28/stdlib/random.c:299
+    //       ??:0
+    fn on_srcline(&mut self, line: &str) {
+        if self.skip_stack || !self.opt.include_lines || self.frames_in_line == 0 {
+            return;
+        }
+        if let Some(location) = Location::parse(line) {
+            // The location is that of the innermost of the frames the stack line added.
+            let func = &mut self.stack[self.frames_in_line - 1];
+            if Location::split(func).1.is_none() {
+                location.annotate(func);
             }
+        }
+        self.frames_in_line = 0;
+    }
 
-            // perf mostly demangles Rust symbols,
-            // but this will fix the things it gets wrong
-            let rawfunc = common::fix_partially_demangled_rust_symbol(rawfunc);
-
-            // Support Java inlining by splitting on "->". After the first func, the
-            // rest are annotated with "_[i]" to mark them as inlined.
-            // See https://github.com/brendangregg/FlameGraph/pull/89.
-            for func in rawfunc.split("->") {
-                let mut func = with_module_fallback(module, func, pc, self.opt.include_addrs);
-                if TIDY_GENERIC {
-                    func = tidy_generic(func);
-                }
+    /// Adds a frame to the current event's stack.
+    ///
+    /// Frames are expected in the order `perf script` prints them: leaf first, root last.
+    pub(super) fn on_frame(&mut self, pc: &str, rawfunc: &str, module: &str) {
+        self.frames_in_line = 0;
+        if self.skip_stack {
+            return;
+        }
 
-                if TIDY_JAVA && self.pname == "java" {
-                    func = tidy_java(func);
-                }
+        // skip process names?
+        // see https://github.com/brendangregg/FlameGraph/blob/f857ebc94bfe2a9bfdc4f1536ebacfb7466f69ba/stackcollapse-perf.pl#L269
+        if rawfunc.starts_with('(') {
+            return;
+        }
 
-                // Annotations
-                //
-                // detect inlined when self.cache_line has funcs
-                // detect kernel from the module name; eg, frames to parse include:
-                //
-                //     ffffffff8103ce3b native_safe_halt ([kernel.kallsyms])
-                //     8c3453 tcp_sendmsg (/lib/modules/4.3.0-rc1-virtual/build/vmlinux)
-                //     7d8 ipv4_conntrack_local+0x7f8f80b8 ([nf_conntrack_ipv4])
-                //
-                // detect jit from the module name; eg:
-                //
-                //     7f722d142778 Ljava/io/PrintStream;::print (/tmp/perf-19982.map)
-                if !self.cache_line.is_empty() {
-                    func.push_str("_[i]"); // inlined
-                } else if self.opt.annotate_kernel && is_kernel(module) {
-                    func.push_str("_[k]"); // kernel
-                } else if self.opt.annotate_jit
-                    && module.starts_with("/tmp/perf-")
-                    && module.ends_with(".map")
-                {
-                    func.push_str("_[j]"); // jitted
+        if rawfunc == "[unknown]" {
+            if let Some(frames) = self.symbolize(pc) {
+                self.frames_in_line = frames.len();
+                for func in frames.into_iter().rev() {
+                    self.stack.push_front(func);
                 }
+                return;
+            }
+        }
 
-                self.cache_line.push(func);
+        // perf mostly demangles Rust symbols,
+        // but this will fix the things it gets wrong
+        let rawfunc = common::fix_partially_demangled_rust_symbol(rawfunc);
+
+        // Support Java inlining by splitting on "->". After the first func, the
+        // rest are annotated with "_[i]" to mark them as inlined.
+        // See https://github.com/brendangregg/FlameGraph/pull/89.
+        for func in rawfunc.split("->") {
+            let mut func = with_module_fallback(module, func, pc, self.opt.include_addrs);
+            if TIDY_GENERIC {
+                func = tidy_generic(func);
             }
 
-            while let Some(func) = self.cache_line.pop() {
-                self.stack.push_front(func);
+            if TIDY_JAVA && self.pname == "java" {
+                func = tidy_java(func);
             }
-        } else {
-            logging::weird_stack_line(line);
+
+            // Annotations
+            //
+            // detect inlined when self.cache_line has funcs
+            // detect kernel from the module name; eg, frames to parse include:
+            //
+            //     ffffffff8103ce3b native_safe_halt ([kernel.kallsyms])
+            //     8c3453 tcp_sendmsg (/lib/modules/4.3.0-rc1-virtual/build/vmlinux)
+            //     7d8 ipv4_conntrack_local+0x7f8f80b8 ([nf_conntrack_ipv4])
+            //
+            // detect jit from the module name; eg:
+            //
+            //     7f722d142778 Ljava/io/PrintStream;::print (/tmp/perf-19982.map)
+            if !self.cache_line.is_empty() {
+                func.push_str("_[i]"); // inlined
+            } else if self.opt.annotate_kernel && is_kernel(module) {
+                func.push_str("_[k]"); // kernel
+            } else if self.opt.annotate_jit
+                && module.starts_with("/tmp/perf-")
+                && module.ends_with(".map")
+            {
+                func.push_str("_[j]"); // jitted
+            }
+
+            self.cache_line.push(func);
+        }
+
+        self.frames_in_line = self.cache_line.len();
+        while let Some(func) = self.cache_line.pop() {
+            self.stack.push_front(func);
         }
     }
 
-    fn after_event(&mut self, occurrences: &mut Occurrences) {
+    // Resolves the address of a frame that perf couldn't symbolize, if there's a symbolizer.
+    fn symbolize(&self, pc: &str) -> Option<Vec<String>> {
+        let addr = u64::from_str_radix(pc, 16).ok()?;
+        let mut symbolizer = self.symbolizer.as_ref()?.lock().ok()?;
+        symbolizer.symbolize_in(self.pid, addr)
+    }
+
+    pub(super) fn after_event(&mut self, occurrences: &mut Occurrences) {
         // end of stack, so emit stack entry
         if !self.skip_stack {
             // allocate a string that is long enough to hold the entire stack string
             let mut stack_str = String::with_capacity(
-                self.pname.len() + self.stack.iter().fold(0, |a, s| a + s.len() + 1),
+                self.event.len()
+                    + 1
+                    + self.group.len()
+                    + 1
+                    + self.pname.len()
+                    + self.stack.iter().fold(0, |a, s| a + s.len() + 1),
             );
 
+            // add the event and the group
+            if self.opt.all_events {
+                stack_str.push_str(&self.event);
+                stack_str.push(';');
+            }
+            if self.opt.group_by.is_some() {
+                stack_str.push_str(&self.group);
+                stack_str.push(';');
+            }
             // add the comm name
             stack_str.push_str(&self.pname);
             // add the other stack entries (if any)
@@ -553,7 +859,7 @@ impl Folder {
             }
 
             // count it!
-            occurrences.insert_or_add(stack_str, 1);
+            occurrences.insert_or_add(stack_str, self.period);
         }
 
         // reset for the next event
@@ -563,6 +869,120 @@ impl Folder {
     }
 }
 
+/// Write the stacks of each group added by [`Options::group_by`] to a folded file of its own in
+/// `dir`, without the root frame of the group. `dir` is created if it doesn't exist.
+///
+/// With [`Options::all_events`], the root frames are the events instead, so each event gets a
+/// file of its own, with the groups of its stacks kept as their root frames.
+///
+/// The files are named after their group, with `.folded` added and any character other than
+/// an ASCII letter, a digit, `-`, `_` or `.` replaced by `_`, as in `tid-4243.folded` or
+/// `cycles.folded`. Groups whose names end up the same, such as `kworker/0:1` and `kworker/0_1`,
+/// or that only differ in case, get a number added to all but the first of them, as in
+/// `kworker_0_1-2.folded`. Returns the paths of the files in the order of the group names.
+pub fn write_groups<P>(stacks: &FoldedStacks, dir: P) -> io::Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let mut paths = Vec::new();
+    let mut names = HashSet::new();
+    for (group, stacks) in stacks.split_roots() {
+        let stem = group_file_stem(&group);
+        let mut name = format!("{}.folded", stem);
+        let mut n = 1;
+        while !names.insert(name.to_lowercase()) {
+            n += 1;
+            name = format!("{}-{}.folded", stem, n);
+        }
+        let path = dir.join(name);
+        let mut file = io::BufWriter::new(File::create(&path)?);
+        stacks.write_to(&mut file)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Write the stacks of two of the events collapsed with [`Options::all_events`] as differential
+/// folded stack lines, which show where the ratio of their counts, such as the instructions per
+/// cycle, is above or below its ratio over the whole profile.
+///
+/// Each line has the count of the `numerator` event, scaled so that both events have the same
+/// total, followed by the count of the `denominator` event. Drawn by [`flamegraph`], the frames
+/// are as wide as the count of the `denominator`, and are red where the ratio is lower than
+/// overall and blue where it is higher. Stacks without samples of the `denominator` are left
+/// out. The counts are best made with [`Options::count_periods`], since events are usually
+/// sampled at different rates.
+///
+/// Returns an error of kind [`io::ErrorKind::InvalidInput`] if either event has no stacks, such
+/// as when its name is misspelled.
+///
+///   [`flamegraph`]: crate::flamegraph
+pub fn write_ratio<W>(
+    stacks: &FoldedStacks,
+    numerator: &str,
+    denominator: &str,
+    mut writer: W,
+) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let mut events = stacks.split_roots();
+    let mut stacks_of = |event: &str| match events.remove(event) {
+        Some(stacks) if stacks.total() != 0 => Ok(stacks),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no stacks of the event {}", event),
+        )),
+    };
+    let numerators = stacks_of(numerator)?;
+    let denominators = stacks_of(denominator)?;
+    let scale = denominators.total() as f64 / numerators.total() as f64;
+
+    for (stack, count) in denominators.sorted() {
+        let stack = stack.to_string();
+        let scaled = numerators.get(&stack).unwrap_or(0) as f64 * scale;
+        writeln!(writer, "{} {} {}", stack, scaled.round() as usize, count)?;
+    }
+    Ok(())
+}
+
+fn group_file_stem(group: &str) -> String {
+    group
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+// The CPU that follows the PID and TID of an event line, like `[002]`, if perf script printed it.
+fn event_line_cpu(rest: &str) -> Option<&str> {
+    let rest = rest.trim_start();
+    if !rest.starts_with('[') {
+        return None;
+    }
+    let cpu = &rest[1..rest.find(']')?];
+    if !cpu.is_empty() && cpu.bytes().all(|b| b.is_ascii_digit()) {
+        Some(cpu)
+    } else {
+        None
+    }
+}
+
+// Whether a stack line is the srcline of the preceding one, which ends in a line number.
+fn is_srcline(line: &str) -> bool {
+    let line = line.trim();
+    match line.rfind(':') {
+        Some(colon) => {
+            colon + 1 < line.len() && line[colon + 1..].bytes().all(|b| b.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
 // massage function name to be nicer
 // NOTE: ignoring https://github.com/jvm-profiling-tools/perf-map-agent/pull/35
 fn with_module_fallback(module: &str, func: &str, pc: &str, include_addrs: bool) -> String {
@@ -680,6 +1100,33 @@ mod tests {
     use crate::collapse::common;
     use crate::collapse::Collapse;
 
+    #[test]
+    fn test_write_groups_with_colliding_names() {
+        let stacks: FoldedStacks = vec![
+            ("kworker/0:1;worker_thread", 1),
+            ("kworker/0_1;worker_thread", 2),
+            ("Kworker_0_1;worker_thread", 3),
+        ]
+        .into_iter()
+        .collect();
+        let dir = std::env::temp_dir().join(format!("groups-{}", rand::random::<u64>()));
+        let paths = write_groups(&stacks, &dir).unwrap();
+        let names: Vec<_> = paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "Kworker_0_1.folded",
+                "kworker_0_1-2.folded",
+                "kworker_0_1-3.folded"
+            ]
+        );
+        assert_eq!(fs::read_to_string(&paths[1]).unwrap(), "worker_thread 1\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     // Test some interesting edge cased for tidy_generic
     #[test]
     fn test_tidy_generic() {
@@ -792,7 +1239,14 @@ mod tests {
                 include_addrs: rng.gen(),
                 include_pid: rng.gen(),
                 include_tid: rng.gen(),
+                group_by: None,
+                all_events: rng.gen(),
+                count_periods: rng.gen(),
+                include_lines: rng.gen(),
                 nthreads: rng.gen_range(2, 32 + 1),
+                symfs: None,
+                kallsyms: None,
+                symbolize: None,
             };
 
             for (path, input) in inputs.iter() {
@@ -0,0 +1,554 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+use libflate::gzip;
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::FoldedStacks;
+
+// Gzip streams start with these two bytes.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// `pprof` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// The index of the sample type (such as `samples`, `cpu`, `alloc_space` or `inuse_objects`)
+    /// whose values become the sample count.
+    ///
+    /// Default is `None`, which uses the profile's default sample type if it names one, and the
+    /// last sample type otherwise, like `go tool pprof` does.
+    pub sample_index: Option<usize>,
+}
+
+/// A stack collapser for [`profile.proto`] files, as written by Go's `runtime/pprof` and
+/// `net/http/pprof` packages and by other tools that speak the `pprof` format.
+///
+/// Both gzipped and uncompressed profiles are accepted. Functions that were inlined into a
+/// location become frames of their own, and locations that were not symbolized are shown as their
+/// address.
+///
+/// To construct one, either use `pprof::Folder::default()` or create an [`Options`] and use
+/// `pprof::Folder::from(options)`.
+///
+///   [`profile.proto`]: https://github.com/google/pprof/blob/master/proto/profile.proto
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        self.collapse_to_occurrences(reader)?
+            .write_and_clear(writer)
+    }
+
+    fn collapse_into<R>(&mut self, reader: R, stacks: &mut FoldedStacks) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        self.collapse_to_occurrences(reader)?
+            .add_to_and_clear(stacks);
+        Ok(())
+    }
+
+    /// `profile.proto` files are binary (and usually gzipped), so they are never recognized from
+    /// text input.
+    fn is_applicable(&mut self, _input: &str) -> Option<bool> {
+        Some(false)
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    fn collapse_to_occurrences<R>(&mut self, mut reader: R) -> io::Result<Occurrences>
+    where
+        R: io::BufRead,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.is_empty() {
+            warn!("File is empty");
+            return Ok(Occurrences::new(1));
+        }
+        if data.starts_with(GZIP_MAGIC) {
+            let mut decompressed = Vec::new();
+            gzip::Decoder::new(&data[..])?.read_to_end(&mut decompressed)?;
+            data = decompressed;
+        }
+
+        let profile = Profile::decode(&data)?;
+        let index = self.sample_index(&profile)?;
+
+        let mut occurrences = Occurrences::new(1);
+        let mut stack = Vec::new();
+        for sample in &profile.samples {
+            let count = match sample.values.get(index) {
+                Some(&value) if value > 0 => value as usize,
+                _ => continue,
+            };
+
+            // Locations are listed leaf first, and so are the functions inlined into them.
+            stack.clear();
+            for id in sample.location_ids.iter().rev() {
+                let location = match profile.locations.get(id) {
+                    Some(location) => location,
+                    None => return invalid_data_error!("Sample refers to unknown location {}", id),
+                };
+                if location.function_ids.is_empty() {
+                    stack.push(format!("{:#x}", location.address));
+                    continue;
+                }
+                for function_id in location.function_ids.iter().rev() {
+                    stack.push(profile.function_name(*function_id, location.address));
+                }
+            }
+            occurrences.insert_or_add(stack.join(";"), count);
+        }
+
+        Ok(occurrences)
+    }
+    fn sample_index(&self, profile: &Profile) -> io::Result<usize> {
+        let ntypes = profile.sample_types.len();
+        match self.opt.sample_index {
+            Some(index) if index < ntypes => Ok(index),
+            Some(index) => invalid_data_error!(
+                "Sample index {} is out of range for a profile with {} sample types",
+                index,
+                ntypes
+            ),
+            None => Ok(profile
+                .sample_types
+                .iter()
+                .position(|&t| profile.default_sample_type != 0 && t == profile.default_sample_type)
+                .unwrap_or_else(|| ntypes.saturating_sub(1))),
+        }
+    }
+}
+
+/// `pprof` export configuration options.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ExportOptions {
+    /// What the counts of the folded stacks measure, as shown by `go tool pprof`.
+    ///
+    /// Default is `"samples"`.
+    pub sample_type: String,
+
+    /// The unit of the counts of the folded stacks.
+    ///
+    /// Default is `"count"`.
+    pub unit: String,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            sample_type: "samples".to_string(),
+            unit: "count".to_string(),
+        }
+    }
+}
+
+/// Converts folded stack lines read from `reader` into a gzipped `profile.proto`, and writes it to
+/// `writer`.
+///
+/// Every distinct frame name becomes a function with a location of its own, so the profile can be
+/// loaded into `go tool pprof` and other tools that read the `pprof` format. Lines whose count
+/// cannot be parsed are skipped with a warning.
+pub fn export<R, W>(opt: &ExportOptions, reader: R, writer: W) -> io::Result<()>
+where
+    R: io::BufRead,
+    W: io::Write,
+{
+    let mut strings = StringTable::default();
+    let sample_type = strings.intern(&opt.sample_type);
+    let unit = strings.intern(&opt.unit);
+
+    let mut profile = Encoder::default();
+    let mut value_type = Encoder::default();
+    value_type.varint_field(1, sample_type as u64);
+    value_type.varint_field(2, unit as u64);
+    profile.bytes_field(1, &value_type.buf);
+
+    // Frame names and the function/location id they were given.
+    let mut frame_ids: HashMap<String, u64> = HashMap::new();
+    let mut location_ids = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (stack, count) = match line
+            .rfind(' ')
+            .and_then(|i| Some((&line[..i], line[i + 1..].parse::<u64>().ok()?)))
+        {
+            Some(parsed) => parsed,
+            None => {
+                warn!("Ignored invalid line: {}", line);
+                continue;
+            }
+        };
+
+        location_ids.clear();
+        for frame in stack.trim_end().split(';') {
+            let next_id = frame_ids.len() as u64 + 1;
+            let id = *frame_ids.entry(frame.to_string()).or_insert(next_id);
+            if id == next_id {
+                let name = strings.intern(frame);
+
+                let mut function = Encoder::default();
+                function.varint_field(1, id);
+                function.varint_field(2, name as u64);
+                function.varint_field(3, name as u64);
+                profile.bytes_field(5, &function.buf);
+
+                let mut location_line = Encoder::default();
+                location_line.varint_field(1, id);
+                let mut location = Encoder::default();
+                location.varint_field(1, id);
+                location.bytes_field(4, &location_line.buf);
+                profile.bytes_field(4, &location.buf);
+            }
+            location_ids.push(id);
+        }
+
+        let mut sample = Encoder::default();
+        let mut packed = Encoder::default();
+        for &id in location_ids.iter().rev() {
+            packed.varint(id);
+        }
+        sample.bytes_field(1, &packed.buf);
+        let mut value = Encoder::default();
+        value.varint(count);
+        sample.bytes_field(2, &value.buf);
+        profile.bytes_field(2, &sample.buf);
+    }
+
+    for string in &strings.strings {
+        profile.bytes_field(6, string.as_bytes());
+    }
+    profile.bytes_field(11, &value_type.buf);
+    profile.varint_field(12, 1);
+
+    let mut encoder = gzip::Encoder::new(writer)?;
+    io::Write::write_all(&mut encoder, &profile.buf)?;
+    encoder.finish().into_result()?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct StringTable {
+    strings: Vec<String>,
+    indices: HashMap<String, usize>,
+}
+
+impl StringTable {
+    fn intern(&mut self, s: &str) -> usize {
+        if self.strings.is_empty() {
+            // The first string of the table must be empty.
+            self.strings.push(String::new());
+            self.indices.insert(String::new(), 0);
+        }
+        if let Some(&index) = self.indices.get(s) {
+            return index;
+        }
+        self.strings.push(s.to_string());
+        self.indices.insert(s.to_string(), self.strings.len() - 1);
+        self.strings.len() - 1
+    }
+}
+
+/// The parts of a decoded `profile.proto` that make up the stacks.
+#[derive(Default)]
+struct Profile {
+    /// The type of each sample value, as an index into `strings`.
+    sample_types: Vec<i64>,
+    samples: Vec<Sample>,
+    locations: HashMap<u64, Location>,
+    /// Function names, as indices into `strings`, by id.
+    functions: HashMap<u64, i64>,
+    strings: Vec<String>,
+    default_sample_type: i64,
+}
+
+struct Sample {
+    location_ids: Vec<u64>,
+    values: Vec<i64>,
+}
+
+struct Location {
+    address: u64,
+    /// The functions at this location, with the ones inlined into it first.
+    function_ids: Vec<u64>,
+}
+
+impl Profile {
+    fn decode(buf: &[u8]) -> io::Result<Self> {
+        let mut profile = Profile::default();
+        let mut message = Decoder::new(buf);
+        while let Some((field, value)) = message.field()? {
+            match (field, value) {
+                (1, Value::Bytes(b)) => profile.sample_types.push(Self::decode_value_type(b)?),
+                (2, Value::Bytes(b)) => profile.samples.push(Self::decode_sample(b)?),
+                (4, Value::Bytes(b)) => {
+                    let (id, location) = Self::decode_location(b)?;
+                    profile.locations.insert(id, location);
+                }
+                (5, Value::Bytes(b)) => {
+                    let mut function = Decoder::new(b);
+                    let (mut id, mut name) = (0, 0);
+                    while let Some((field, value)) = function.field()? {
+                        match (field, value) {
+                            (1, Value::Varint(v)) => id = v,
+                            (2, Value::Varint(v)) => name = v as i64,
+                            _ => {}
+                        }
+                    }
+                    profile.functions.insert(id, name);
+                }
+                (6, Value::Bytes(b)) => profile
+                    .strings
+                    .push(String::from_utf8_lossy(b).into_owned()),
+                (14, Value::Varint(v)) => profile.default_sample_type = v as i64,
+                _ => {}
+            }
+        }
+        Ok(profile)
+    }
+
+    fn decode_value_type(buf: &[u8]) -> io::Result<i64> {
+        let mut type_index = 0;
+        let mut message = Decoder::new(buf);
+        while let Some((field, value)) = message.field()? {
+            if let (1, Value::Varint(v)) = (field, value) {
+                type_index = v as i64;
+            }
+        }
+        Ok(type_index)
+    }
+
+    fn decode_sample(buf: &[u8]) -> io::Result<Sample> {
+        let mut sample = Sample {
+            location_ids: Vec::new(),
+            values: Vec::new(),
+        };
+        let mut message = Decoder::new(buf);
+        while let Some((field, value)) = message.field()? {
+            match field {
+                1 => value.repeated_varints(|v| sample.location_ids.push(v))?,
+                2 => value.repeated_varints(|v| sample.values.push(v as i64))?,
+                _ => {}
+            }
+        }
+        Ok(sample)
+    }
+
+    fn decode_location(buf: &[u8]) -> io::Result<(u64, Location)> {
+        let mut id = 0;
+        let mut location = Location {
+            address: 0,
+            function_ids: Vec::new(),
+        };
+        let mut message = Decoder::new(buf);
+        while let Some((field, value)) = message.field()? {
+            match (field, value) {
+                (1, Value::Varint(v)) => id = v,
+                (3, Value::Varint(v)) => location.address = v,
+                (4, Value::Bytes(b)) => {
+                    let mut line = Decoder::new(b);
+                    while let Some((field, value)) = line.field()? {
+                        if let (1, Value::Varint(v)) = (field, value) {
+                            location.function_ids.push(v);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok((id, location))
+    }
+
+    fn function_name(&self, id: u64, address: u64) -> String {
+        match self
+            .functions
+            .get(&id)
+            .and_then(|&name| self.strings.get(name as usize))
+        {
+            Some(name) if !name.is_empty() => name.replace(';', ":"),
+            _ => format!("{:#x}", address),
+        }
+    }
+}
+
+/// A protobuf field value.
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+impl<'a> Value<'a> {
+    // Repeated scalar fields may be written either packed, as a single length-delimited field,
+    // or as one field per value.
+    fn repeated_varints<F>(self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(u64),
+    {
+        match self {
+            Value::Varint(v) => f(v),
+            Value::Bytes(b) => {
+                let mut packed = Decoder::new(b);
+                while !packed.is_empty() {
+                    f(packed.varint()?);
+                }
+            }
+            Value::Fixed => {}
+        }
+        Ok(())
+    }
+}
+
+/// A reader of protobuf-encoded messages.
+struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Decoder { buf }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for (i, &byte) in self.buf.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.buf = &self.buf[i + 1..];
+                return Ok(value);
+            }
+        }
+        invalid_data_error!("Invalid varint in profile")
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if n > self.buf.len() {
+            return invalid_data_error!("Profile is truncated");
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    // Returns the next field number and its value, or `None` at the end of the message.
+    fn field(&mut self) -> io::Result<Option<(u64, Value<'a>)>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => {
+                self.take(4)?;
+                Value::Fixed
+            }
+            wire_type => {
+                return invalid_data_error!("Unsupported wire type {} in profile", wire_type)
+            }
+        };
+        Ok(Some((key >> 3, value)))
+    }
+}
+
+/// A writer of protobuf-encoded messages.
+#[derive(Default)]
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn varint_field(&mut self, field: u64, value: u64) {
+        self.varint(field << 3);
+        self.varint(value);
+    }
+
+    fn bytes_field(&mut self, field: u64, bytes: &[u8]) {
+        self.varint(field << 3 | 2);
+        self.varint(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint_roundtrip() {
+        for &value in &[0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX] {
+            let mut encoder = Encoder::default();
+            encoder.varint(value);
+            let mut decoder = Decoder::new(&encoder.buf);
+            assert_eq!(decoder.varint().unwrap(), value);
+            assert!(decoder.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_truncated_message() {
+        let mut encoder = Encoder::default();
+        encoder.bytes_field(6, b"main");
+        let truncated = &encoder.buf[..encoder.buf.len() - 1];
+        let error = Profile::decode(truncated).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_export_roundtrip() {
+        let folded = "main;compute;helper 3\nmain;helper 2\nmain 1\n";
+        let mut profile = Vec::new();
+        export(&ExportOptions::default(), folded.as_bytes(), &mut profile).unwrap();
+        assert!(profile.starts_with(GZIP_MAGIC));
+
+        let mut collapsed = Vec::new();
+        Folder::default()
+            .collapse(&profile[..], &mut collapsed)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(collapsed).unwrap(),
+            "main 1\nmain;compute;helper 3\nmain;helper 2\n"
+        );
+    }
+}
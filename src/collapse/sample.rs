@@ -2,8 +2,9 @@ use std::io::{self, BufRead};
 
 use log::warn;
 
-use crate::collapse::common::{self, Occurrences};
+use crate::collapse::common::{self, CollapseLines, Occurrences};
 use crate::collapse::Collapse;
+use crate::folded::FoldedStacks;
 
 // The set of symbols to ignore for 'waiting' threads, for ease of use.
 // This will hide waiting threads from the view, making it easier to
@@ -44,6 +45,14 @@ impl Default for Options {
     }
 }
 
+/// The part of the input that is being read, when streaming.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Section {
+    Header,
+    CallGraph,
+    AfterCallGraph,
+}
+
 /// A stack collapser for the output of `sample` on macOS.
 ///
 /// To construct one, either use `sample::Folder::default()` or create an [`Options`] and use
@@ -56,6 +65,12 @@ pub struct Folder {
     /// Function on the stack in this entry thus far.
     stack: Vec<String>,
 
+    section: Section,
+
+    /// Stacks of the call graph being streamed. They are only handed over once the call graph
+    /// ends, as a stack may be seen more than once and only its last count is kept.
+    call_graph: Option<Occurrences>,
+
     opt: Options,
 }
 
@@ -64,58 +79,29 @@ impl Default for Folder {
         Self {
             current_samples: 0,
             stack: Vec::default(),
+            section: Section::Header,
+            call_graph: None,
             opt: Options::default(),
         }
     }
 }
 
 impl Collapse for Folder {
-    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
     where
         R: io::BufRead,
         W: io::Write,
     {
-        // Consume the header...
-        let mut line = Vec::new();
-        loop {
-            line.clear();
-            if reader.read_until(0x0A, &mut line)? == 0 {
-                warn!("File ended before start of call graph");
-                return Ok(());
-            };
-            let l = String::from_utf8_lossy(&line);
-            if l.starts_with(START_LINE) {
-                break;
-            }
-        }
-
-        // Process the data...
-        let mut occurrences = Occurrences::new(1);
-        loop {
-            line.clear();
-            if reader.read_until(0x0A, &mut line)? == 0 {
-                return invalid_data_error!("File ended before end of call graph");
-            }
-            let l = String::from_utf8_lossy(&line);
-            let line = l.trim_end();
-            if line.is_empty() {
-                continue;
-            } else if line.starts_with("    ") {
-                self.on_line(line, &mut occurrences)?;
-            } else if line.starts_with(END_LINE) {
-                self.write_stack(&mut occurrences);
-                break;
-            } else {
-                return invalid_data_error!("Stack line doesn't start with 4 spaces:\n{}", line);
-            }
-        }
-
-        // Write the results...
-        occurrences.write_and_clear(writer)?;
+        self.collapse_to_occurrences(reader)?
+            .write_and_clear(writer)
+    }
 
-        // Reset the state...
-        self.current_samples = 0;
-        self.stack.clear();
+    fn collapse_into<R>(&mut self, reader: R, stacks: &mut FoldedStacks) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        self.collapse_to_occurrences(reader)?
+            .add_to_and_clear(stacks);
         Ok(())
     }
 
@@ -145,6 +131,51 @@ impl Collapse for Folder {
     }
 }
 
+impl CollapseLines for Folder {
+    fn collapse_line(&mut self, line: &[u8], occurrences: &mut Occurrences) -> io::Result<()> {
+        let line = String::from_utf8_lossy(line);
+        if self.section != Section::CallGraph {
+            if line.starts_with(START_LINE) {
+                self.section = Section::CallGraph;
+            }
+            return Ok(());
+        }
+
+        let mut call_graph = self
+            .call_graph
+            .take()
+            .unwrap_or_else(|| Occurrences::new(1));
+        let result = self.on_call_graph_line(&line, &mut call_graph);
+        if let Ok(true) = result {
+            for (stack, count) in call_graph.drain() {
+                occurrences.insert_or_add(stack, count);
+            }
+            // Another call graph may follow, if the output of several runs is concatenated.
+            self.section = Section::AfterCallGraph;
+            self.current_samples = 0;
+            self.stack.clear();
+        }
+        self.call_graph = Some(call_graph);
+        result.map(|_| ())
+    }
+
+    fn end_of_input(&mut self, _: &mut Occurrences) -> io::Result<()> {
+        let section = self.section;
+        self.section = Section::Header;
+        self.current_samples = 0;
+        self.stack.clear();
+        self.call_graph = None;
+        match section {
+            Section::Header => {
+                warn!("File ended before start of call graph");
+                Ok(())
+            }
+            Section::CallGraph => invalid_data_error!("File ended before end of call graph"),
+            Section::AfterCallGraph => Ok(()),
+        }
+    }
+}
+
 impl From<Options> for Folder {
     fn from(opt: Options) -> Self {
         Folder {
@@ -155,6 +186,63 @@ impl From<Options> for Folder {
 }
 
 impl Folder {
+    fn collapse_to_occurrences<R>(&mut self, mut reader: R) -> io::Result<Occurrences>
+    where
+        R: io::BufRead,
+    {
+        // Consume the header...
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                warn!("File ended before start of call graph");
+                return Ok(Occurrences::new(1));
+            };
+            let l = String::from_utf8_lossy(&line);
+            if l.starts_with(START_LINE) {
+                break;
+            }
+        }
+
+        // Process the data...
+        let mut occurrences = Occurrences::new(1);
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                return invalid_data_error!("File ended before end of call graph");
+            }
+            let l = String::from_utf8_lossy(&line);
+            if self.on_call_graph_line(&l, &mut occurrences)? {
+                break;
+            }
+        }
+
+        // Reset the state...
+        self.current_samples = 0;
+        self.stack.clear();
+        Ok(occurrences)
+    }
+
+    /// Processes a line of the call graph. Returns `true` if the line ended the call graph.
+    fn on_call_graph_line(
+        &mut self,
+        line: &str,
+        occurrences: &mut Occurrences,
+    ) -> io::Result<bool> {
+        let line = line.trim_end();
+        if line.is_empty() {
+            Ok(false)
+        } else if line.starts_with("    ") {
+            self.on_line(line, occurrences)?;
+            Ok(false)
+        } else if line.starts_with(END_LINE) {
+            self.write_stack(occurrences);
+            Ok(true)
+        } else {
+            invalid_data_error!("Stack line doesn't start with 4 spaces:\n{}", line)
+        }
+    }
+
     fn line_parts<'a>(&self, line: &'a str) -> Option<(&'a str, &'a str, &'a str)> {
         let mut line = line.trim_start().splitn(2, ' ');
         let time = line.next()?.trim_end();
@@ -0,0 +1,313 @@
+use std::io;
+
+use ahash::AHashMap;
+
+use crate::collapse::common::{CollapseLines, Occurrences};
+
+/// An incremental stack collapser, for input that arrives a bit at a time or never ends (such
+/// as `perf script` reading from a pipe).
+///
+/// Input is given to the stream in chunks of bytes of any size with [`feed`](Stream::feed). The
+/// stacks found so far can be taken at any time, either as the counts added since they were last
+/// taken ([`take_delta`](Stream::take_delta)), or as the totals for all the input so far
+/// ([`snapshot`](Stream::snapshot)). Input is only ever read once.
+///
+/// Stacks show up as soon as the input has reached their end, except with `sample`, whose
+/// stacks show up once the whole call graph has been read.
+///
+/// The `perf`, `dtrace`, `sample` and `vtune` collapsers can be streamed:
+///
+/// ```
+/// use inferno::collapse::{perf, stream::Stream};
+///
+/// let mut stream = Stream::new(perf::Folder::default());
+/// stream.feed(b"java 12688 [002] 6544038.708352: cpu-clock:\n")?;
+/// stream.feed(b"\tffffffff8103ce3b native_safe_halt ([kernel.kallsyms])\n")?;
+/// stream.feed(b"\tffffffff8101c6a3 default_id")?;
+/// // The stack has not ended yet.
+/// assert!(stream.take_delta().is_empty());
+///
+/// stream.feed(b"le ([kernel.kallsyms])\n\n")?;
+/// assert_eq!(
+///     stream.take_delta(),
+///     vec![("java;default_idle;native_safe_halt".to_string(), 1)]
+/// );
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// Call [`finish`](Stream::finish) once the input ends to flush the last stack.
+pub struct Stream<F> {
+    folder: F,
+
+    /// The start of a line whose end hasn't been fed yet.
+    partial_line: Vec<u8>,
+
+    /// Stacks found by the folder, before they're added to `delta` and `totals`.
+    occurrences: Occurrences,
+
+    /// Counts added since the last call to `take_delta`.
+    delta: AHashMap<String, usize>,
+
+    /// Counts for all the input so far.
+    totals: AHashMap<String, usize>,
+}
+
+impl<F> Stream<F>
+where
+    F: CollapseLines,
+{
+    /// Creates a stream that collapses its input with the given folder.
+    pub fn new(folder: F) -> Self {
+        Self {
+            folder,
+            partial_line: Vec::new(),
+            occurrences: Occurrences::new(1),
+            delta: AHashMap::default(),
+            totals: AHashMap::default(),
+        }
+    }
+
+    /// Collapses the next chunk of input.
+    ///
+    /// Chunks don't need to end at the end of a line or stack; whatever is left over is kept
+    /// until the next call.
+    ///
+    /// If the input turns out to be invalid, an error is returned, and the stacks found before
+    /// the error are kept.
+    pub fn feed(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        let result = loop {
+            let newline = match bytes.iter().position(|&b| b == b'\n') {
+                Some(newline) => newline,
+                None => {
+                    self.partial_line.extend_from_slice(bytes);
+                    break Ok(());
+                }
+            };
+            let (line, rest) = bytes.split_at(newline + 1);
+            bytes = rest;
+
+            let result = if self.partial_line.is_empty() {
+                self.folder.collapse_line(line, &mut self.occurrences)
+            } else {
+                self.partial_line.extend_from_slice(line);
+                let result = self
+                    .folder
+                    .collapse_line(&self.partial_line, &mut self.occurrences);
+                self.partial_line.clear();
+                result
+            };
+            if result.is_err() {
+                break result;
+            }
+        };
+        self.add_occurrences();
+        result
+    }
+
+    /// Signals that the input has ended, which completes the last stack (if any).
+    ///
+    /// The stream can be fed new input afterwards, which is treated as if it were a new file.
+    pub fn finish(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        if !self.partial_line.is_empty() {
+            result = self
+                .folder
+                .collapse_line(&self.partial_line, &mut self.occurrences);
+            self.partial_line.clear();
+        }
+        if result.is_ok() {
+            result = self.folder.end_of_input(&mut self.occurrences);
+        }
+        self.add_occurrences();
+        result
+    }
+
+    /// Returns the stacks found since the last call to `take_delta` along with their counts,
+    /// sorted by stack.
+    pub fn take_delta(&mut self) -> Vec<(String, usize)> {
+        let mut delta: Vec<_> = self.delta.drain().collect();
+        delta.sort();
+        delta
+    }
+
+    /// Returns all the stacks found so far along with their total counts, sorted by stack.
+    pub fn snapshot(&self) -> Vec<(&str, usize)> {
+        let mut snapshot: Vec<_> = self
+            .totals
+            .iter()
+            .map(|(stack, &count)| (stack.as_str(), count))
+            .collect();
+        snapshot.sort();
+        snapshot
+    }
+
+    /// Writes the stacks found since the last call to `take_delta` as folded stack lines.
+    pub fn write_delta<W>(&mut self, mut writer: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        for (stack, count) in self.take_delta() {
+            writeln!(writer, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+
+    /// Writes all the stacks found so far as folded stack lines, like
+    /// [`Collapse::collapse`](crate::collapse::Collapse::collapse) would for all the input so
+    /// far.
+    pub fn write_snapshot<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        for (stack, count) in self.snapshot() {
+            writeln!(writer, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the folder, dropping any input that hasn't been collapsed yet.
+    pub fn into_inner(self) -> F {
+        self.folder
+    }
+
+    fn add_occurrences(&mut self) {
+        for (stack, count) in self.occurrences.drain() {
+            *self.totals.entry(stack.clone()).or_insert(0) += count;
+            *self.delta.entry(stack).or_insert(0) += count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use pretty_assertions::assert_eq;
+    use rand::prelude::*;
+
+    use super::*;
+    use crate::collapse::{dtrace, perf, sample, vtune, Collapse};
+
+    // Streams the file in chunks of random sizes, checking that the snapshot and the sum of the
+    // deltas both match what the folder produces when given the whole file at once.
+    fn test_stream<F>(mut folder: F, path: &str) -> io::Result<()>
+    where
+        F: Collapse + CollapseLines,
+    {
+        let input = fs::read(path)?;
+        let mut expected = Vec::new();
+        folder.collapse(&input[..], &mut expected)?;
+        let expected = String::from_utf8(expected).unwrap();
+
+        let mut rng = SmallRng::seed_from_u64(input.len() as u64);
+        let mut stream = Stream::new(folder);
+        let mut deltas: AHashMap<String, usize> = AHashMap::default();
+        let mut rest = &input[..];
+        while !rest.is_empty() {
+            let (chunk, remaining) = rest.split_at(rng.gen_range(1, 64).min(rest.len()));
+            rest = remaining;
+            stream.feed(chunk)?;
+            for (stack, count) in stream.take_delta() {
+                *deltas.entry(stack).or_insert(0) += count;
+            }
+        }
+        stream.finish()?;
+        for (stack, count) in stream.take_delta() {
+            *deltas.entry(stack).or_insert(0) += count;
+        }
+
+        let mut snapshot = Vec::new();
+        stream.write_snapshot(&mut snapshot)?;
+        assert_eq!(String::from_utf8(snapshot).unwrap(), expected);
+
+        let mut deltas: Vec<_> = deltas.into_iter().collect();
+        deltas.sort();
+        let deltas: String = deltas
+            .into_iter()
+            .map(|(stack, count)| format!("{} {}\n", stack, count))
+            .collect();
+        assert_eq!(deltas, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_perf() -> io::Result<()> {
+        for path in &[
+            "./tests/data/collapse-perf/java-inline.txt",
+            "./tests/data/collapse-perf/go-stacks.txt",
+            "./tests/data/collapse-perf/single-line-stacks.txt",
+            "./tests/data/collapse-perf/empty-line.txt",
+        ] {
+            let options = perf::Options {
+                nthreads: 1,
+                ..Default::default()
+            };
+            test_stream(perf::Folder::from(options), path)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_dtrace() -> io::Result<()> {
+        for path in &[
+            "./tests/data/collapse-dtrace/java.txt",
+            "./tests/data/collapse-dtrace/rust-names.txt",
+            "./tests/data/collapse-dtrace/only-header-lines.txt",
+        ] {
+            let options = dtrace::Options {
+                nthreads: 1,
+                ..Default::default()
+            };
+            test_stream(dtrace::Folder::from(options), path)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_sample() -> io::Result<()> {
+        test_stream(
+            sample::Folder::default(),
+            "./tests/data/collapse-sample/sample.txt",
+        )
+    }
+
+    #[test]
+    fn test_stream_vtune() -> io::Result<()> {
+        test_stream(
+            vtune::Folder::default(),
+            "./tests/data/collapse-vtune/vtune.csv",
+        )
+    }
+
+    #[test]
+    fn test_stream_keeps_stacks_before_error() {
+        let mut stream = Stream::new(sample::Folder::default());
+        stream
+            .feed(b"Call graph:\n    10 Thread_1\n    + 10 start\n    5 Thread_2\n")
+            .unwrap();
+        stream.feed(b"Total number in stack\n").unwrap();
+        assert_eq!(
+            stream.snapshot(),
+            vec![("Thread_1;start", 10), ("Thread_2", 5)]
+        );
+
+        stream.feed(b"Call graph:\n    3 Thread_3\n").unwrap();
+        let error = stream.feed(b"    +    3 odd\n").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            stream.snapshot(),
+            vec![("Thread_1;start", 10), ("Thread_2", 5)]
+        );
+    }
+
+    #[test]
+    fn test_stream_finish_without_newline() -> io::Result<()> {
+        let mut stream = Stream::new(vtune::Folder::default());
+        stream.feed(b"Function Stack,CPU Time:Self,Module\n")?;
+        stream.feed(b"main,1.5,app")?;
+        assert!(stream.snapshot().is_empty());
+        stream.finish()?;
+        assert_eq!(stream.take_delta(), vec![("app`main".to_string(), 1500)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,550 @@
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use addr2line::Context;
+use ahash::AHashMap;
+use gimli::{EndianArcSlice, RunTimeEndian};
+use log::{debug, warn};
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol, SymbolKind};
+
+use super::demangle;
+use crate::folded::{FoldedStacks, Location};
+
+/// Configure how addresses are symbolized.
+///
+/// All options default to off, so that addresses are only resolved to the names of the functions
+/// in the symbol tables of the binaries.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Binaries whose addresses are used as they are, such as executables that aren't position
+    /// independent.
+    ///
+    /// These are only consulted for addresses that aren't covered by [`maps`](Options::maps).
+    pub binaries: Vec<PathBuf>,
+
+    /// A file in the format of `/proc/<pid>/maps`, saved while the profiled process was running,
+    /// that says where each of its files was mapped into memory.
+    ///
+    /// This is needed to symbolize the addresses of shared libraries and position independent
+    /// executables, which are loaded at a different address every time.
+    pub maps: Option<PathBuf>,
+
+    /// Look for the files named in [`maps`](Options::maps) relative to this directory, like
+    /// `perf report --symfs`.
+    pub symfs: Option<PathBuf>,
+
+    /// Add the source file and line of each address from the DWARF debug info of the binaries, as
+    /// in `main [src/main.rs:12]`.
+    pub lines: bool,
+
+    /// Add the functions that were inlined at each address from the DWARF debug info of the
+    /// binaries, as callees of the function they were inlined into. They are marked with `_[i]`,
+    /// like the inlined frames of `perf`.
+    pub inlines: bool,
+
+    /// The process that [`maps`](Options::maps) was saved from.
+    ///
+    /// Collapsers that know the process of each stack, such as [`perf`](super::perf), only
+    /// resolve the addresses of the stacks of this process, since those of other processes would
+    /// resolve to the wrong functions. Without it, all stacks are assumed to be of one process,
+    /// and a warning is logged if they aren't.
+    pub pid: Option<u32>,
+
+    /// How the names of the functions are demangled. They are always demangled, as the
+    /// collapsers would with these options.
+    pub demangle: demangle::Options,
+}
+
+/// Resolves the raw addresses of unsymbolized frames to the names of their functions, using the
+/// symbol tables and DWARF debug info of the binaries on disk.
+///
+/// Binaries are only read once they are needed, and the frames of each address are cached.
+pub struct Symbolizer {
+    opt: Options,
+    maps: Vec<Mapping>,
+    binaries: AHashMap<PathBuf, Option<Binary>>,
+    cache: AHashMap<u64, Option<Vec<String>>>,
+    /// The first process addresses were resolved for, when [`Options::pid`] isn't set.
+    first_pid: Option<u32>,
+    warned_about_pids: bool,
+}
+
+impl std::fmt::Debug for Symbolizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Symbolizer")
+            .field("opt", &self.opt)
+            .finish()
+    }
+}
+
+impl Symbolizer {
+    /// Create a symbolizer, reading the [`maps`](Options::maps) file if there is one.
+    ///
+    /// Returns an error if the maps file can't be read, or one of the
+    /// [`binaries`](Options::binaries) doesn't exist.
+    pub fn new(opt: Options) -> io::Result<Self> {
+        let with_path = |path: &Path, e: io::Error| {
+            io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+        };
+        let maps = match opt.maps {
+            Some(ref path) => {
+                parse_maps(&fs::read_to_string(path).map_err(|e| with_path(path, e))?)
+            }
+            None => Vec::new(),
+        };
+        for path in &opt.binaries {
+            fs::metadata(path).map_err(|e| with_path(path, e))?;
+        }
+        Ok(Symbolizer {
+            opt,
+            maps,
+            binaries: AHashMap::default(),
+            cache: AHashMap::default(),
+            first_pid: None,
+            warned_about_pids: false,
+        })
+    }
+
+    /// Resolve `addr` to the frames of a folded stack, outermost first.
+    ///
+    /// There is more than one frame if [`inlines`](Options::inlines) is set and functions were
+    /// inlined at `addr`. Returns `None` if the address isn't in any of the binaries, or no
+    /// function covers it.
+    pub fn symbolize(&mut self, addr: u64) -> Option<Vec<String>> {
+        if let Some(frames) = self.cache.get(&addr) {
+            return frames.clone();
+        }
+        let frames = self.lookup(addr);
+        self.cache.insert(addr, frames.clone());
+        frames
+    }
+
+    /// Like [`symbolize`](Symbolizer::symbolize), for an address of the process `pid`, or of an
+    /// unknown process if it's `None`.
+    ///
+    /// Returns `None` for the addresses of any process but [`pid`](Options::pid), if it's set.
+    pub fn symbolize_in(&mut self, pid: Option<u32>, addr: u64) -> Option<Vec<String>> {
+        match (self.opt.pid, pid) {
+            (Some(expected), Some(pid)) if expected == pid => {}
+            (Some(_), _) => return None,
+            (None, Some(pid)) => match self.first_pid {
+                None => self.first_pid = Some(pid),
+                Some(first) if first != pid && !self.warned_about_pids => {
+                    warn!(
+                        "Symbolizing the stacks of processes {} and {} with the same binaries; \
+                         set the pid of the maps file to only symbolize one of them",
+                        first, pid
+                    );
+                    self.warned_about_pids = true;
+                }
+                Some(_) => {}
+            },
+            (None, None) => {}
+        }
+        self.symbolize(addr)
+    }
+
+    fn lookup(&mut self, addr: u64) -> Option<Vec<String>> {
+        let with_dwarf = self.opt.lines || self.opt.inlines;
+        if let Some(mapping) = self
+            .maps
+            .iter()
+            .find(|mapping| addr >= mapping.start && addr < mapping.end)
+        {
+            let path = match self.opt.symfs {
+                Some(ref symfs) => symfs.join(mapping.filename.trim_start_matches('/')),
+                None => PathBuf::from(&mapping.filename),
+            };
+            let offset = addr - mapping.start + mapping.offset;
+            let binary = load_binary(&mut self.binaries, &path, with_dwarf)?;
+            return binary.frames(&self.opt, binary.vaddr(offset)?);
+        }
+
+        for path in &self.opt.binaries {
+            if let Some(binary) = load_binary(&mut self.binaries, path, with_dwarf) {
+                if binary.contains(addr) {
+                    return binary.frames(&self.opt, addr);
+                }
+            }
+        }
+        None
+    }
+}
+
+fn load_binary<'a>(
+    binaries: &'a mut AHashMap<PathBuf, Option<Binary>>,
+    path: &Path,
+    with_dwarf: bool,
+) -> Option<&'a Binary> {
+    binaries
+        .entry(path.to_path_buf())
+        .or_insert_with(|| Binary::load(path, with_dwarf))
+        .as_ref()
+}
+
+/// Symbolize the raw addresses in the folded stack lines of `reader`.
+///
+/// Frames are symbolized if they are a hex address (`0x7f0012345678`), a hex address after a
+/// module (``libfoo.so`0x7f0012345678``), or the `[module <7f0012345678>]` frames that
+/// `inferno-collapse-perf --addrs` writes where `perf` couldn't find a symbol. Other frames, and
+/// addresses that can't be resolved, are left as they are.
+///
+/// Stacks that end up with the same frames are merged, and the output is sorted like the output
+/// of the [collapsers](crate::collapse).
+pub fn from_reader<R, W>(symbolizer: &mut Symbolizer, reader: R, writer: W) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    let collapsed = FoldedStacks::from_reader(reader)?;
+    let mut stacks = FoldedStacks::new();
+    for (stack, count) in &collapsed {
+        let mut frames = Vec::new();
+        for frame in stack.frames() {
+            let symbolized = parse_address(frame)
+                .and_then(|(module, addr)| Some((module, symbolizer.symbolize(addr)?)));
+            match symbolized {
+                Some(("", symbolized)) => frames.extend(symbolized.into_iter().map(Cow::Owned)),
+                Some((module, symbolized)) => frames.extend(
+                    symbolized
+                        .into_iter()
+                        .map(|symbolized| Cow::Owned(format!("{}{}", module, symbolized))),
+                ),
+                None => frames.push(Cow::Borrowed(frame)),
+            }
+        }
+        stacks.add_frames(frames, count);
+    }
+    stacks.write_to(writer)
+}
+
+/// Symbolize the raw addresses in the folded stack lines of `path`, or of STDIN if `path` is
+/// `None`.
+///
+/// See [`from_reader`] for details.
+pub fn from_file<P, W>(symbolizer: &mut Symbolizer, path: Option<P>, writer: W) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    match path {
+        Some(path) => {
+            let reader = io::BufReader::new(fs::File::open(path)?);
+            from_reader(symbolizer, reader, writer)
+        }
+        None => {
+            let stdin = io::stdin();
+            let reader = stdin.lock();
+            from_reader(symbolizer, reader, writer)
+        }
+    }
+}
+
+// Finds the address of a frame that wasn't symbolized, along with its module prefix (as in
+// "libfoo.so`"), which is kept.
+fn parse_address(frame: &str) -> Option<(&str, u64)> {
+    let (module, hex) = if frame.starts_with('[') && frame.ends_with(">]") {
+        let start = frame.rfind(" <")? + 2;
+        ("", &frame[start..frame.len() - 2])
+    } else {
+        let start = frame.rfind('`').map_or(0, |i| i + 1);
+        let hex = &frame[start..];
+        if !hex.starts_with("0x") {
+            return None;
+        }
+        (&frame[..start], &hex[2..])
+    };
+    if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some((module, u64::from_str_radix(hex, 16).ok()?))
+}
+
+/// A file mapped into memory, as listed in `/proc/<pid>/maps`.
+#[derive(Debug, Clone)]
+struct Mapping {
+    start: u64,
+    end: u64,
+    offset: u64,
+    filename: String,
+}
+
+// Each line of a maps file is `START-END PERMS OFFSET DEV INODE PATH`, with the addresses and the
+// offset in hex. Anonymous mappings and pseudo-files like `[heap]` can't be symbolized.
+fn parse_maps(data: &str) -> Vec<Mapping> {
+    data.lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let mut range = parts.next()?.splitn(2, '-');
+            let start = u64::from_str_radix(range.next()?, 16).ok()?;
+            let end = u64::from_str_radix(range.next()?, 16).ok()?;
+            let _perms = parts.next()?;
+            let offset = u64::from_str_radix(parts.next()?, 16).ok()?;
+            let _dev = parts.next()?;
+            let _inode = parts.next()?;
+            let filename = parts.collect::<Vec<_>>().join(" ");
+            if filename.starts_with('/') {
+                Some(Mapping {
+                    start,
+                    end,
+                    offset,
+                    filename,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Symbols sorted by start address.
+#[derive(Default)]
+pub(crate) struct Symbols {
+    symbols: Vec<(u64, u64, String)>,
+}
+
+impl Symbols {
+    pub(crate) fn from_unsorted(mut symbols: Vec<(u64, u64, String)>) -> Self {
+        symbols.sort_by_key(|s| s.0);
+        Self { symbols }
+    }
+
+    // Finds the symbol that contains `addr`. Symbols without a size are assumed to extend up to
+    // the next symbol.
+    pub(crate) fn lookup(&self, addr: u64) -> Option<&str> {
+        let i = match self.symbols.binary_search_by(|s| s.0.cmp(&addr)) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let (start, size, ref name) = self.symbols[i];
+        if size == 0 || addr < start + size {
+            Some(name)
+        } else {
+            None
+        }
+    }
+}
+
+/// The symbols and debug info of an ELF file.
+struct Binary {
+    /// The file offset, file size, virtual address and memory size of each loadable segment.
+    segments: Vec<(u64, u64, u64, u64)>,
+    symbols: Symbols,
+    dwarf: Option<Context<EndianArcSlice<RunTimeEndian>>>,
+}
+
+impl Binary {
+    fn load(path: &Path, with_dwarf: bool) -> Option<Self> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Unable to read {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        let file = match object::File::parse(&data) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Unable to parse {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        let segments = file
+            .segments()
+            .map(|segment| {
+                let (offset, size) = segment.file_range();
+                (offset, size, segment.address(), segment.size())
+            })
+            .collect();
+        let symbols = file
+            .symbols()
+            .chain(file.dynamic_symbols())
+            .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.address() != 0)
+            .filter_map(|symbol| {
+                let name = symbol.name().ok()?;
+                Some((symbol.address(), symbol.size(), name.to_string()))
+            })
+            .collect();
+        let dwarf = if with_dwarf {
+            load_dwarf(&file).unwrap_or_else(|e| {
+                debug!("Unable to read the debug info of {}: {}", path.display(), e);
+                None
+            })
+        } else {
+            None
+        };
+
+        Some(Binary {
+            segments,
+            symbols: Symbols::from_unsorted(symbols),
+            dwarf,
+        })
+    }
+
+    // Translates a file offset into a virtual address through the loadable segments.
+    fn vaddr(&self, offset: u64) -> Option<u64> {
+        self.segments
+            .iter()
+            .find(|&&(start, size, _, _)| offset >= start && offset < start + size)
+            .map(|&(start, _, vaddr, _)| vaddr + (offset - start))
+    }
+
+    fn contains(&self, vaddr: u64) -> bool {
+        self.segments
+            .iter()
+            .any(|&(_, _, start, size)| vaddr >= start && vaddr < start + size)
+    }
+
+    fn frames(&self, opt: &Options, vaddr: u64) -> Option<Vec<String>> {
+        let symbol = self.symbols.lookup(vaddr);
+        let dwarf = match self.dwarf {
+            Some(ref dwarf) => dwarf,
+            None => return symbol.map(|symbol| vec![name(opt, symbol)]),
+        };
+
+        // The functions at the address, innermost first, each with the location it was at: the
+        // line of the address for the innermost one, and the call site of the function it inlined
+        // for the others.
+        let mut frames = Vec::new();
+        if let Ok(mut iter) = dwarf.find_frames(vaddr) {
+            while let Ok(Some(frame)) = iter.next() {
+                let function = frame
+                    .function
+                    .as_ref()
+                    .and_then(|function| function.raw_name().ok())
+                    .map(|function| function.into_owned());
+                let location = frame
+                    .location
+                    .and_then(|location| Some((location.file?.to_string(), location.line?)));
+                frames.push((function, location));
+            }
+        }
+        if !opt.inlines && frames.len() > 1 {
+            // Keep the outermost function at the line of the address.
+            let (function, _) = frames.pop().unwrap();
+            let (_, location) = frames.swap_remove(0);
+            frames = vec![(function, location)];
+        }
+
+        let outermost = frames.len().checked_sub(1);
+        let mut symbolized = Vec::with_capacity(frames.len());
+        for (i, (function, location)) in frames.into_iter().enumerate().rev() {
+            let mut frame = match function.as_deref().or(symbol) {
+                Some(function) => name(opt, function),
+                None => continue,
+            };
+            if Some(i) != outermost {
+                frame.push_str("_[i]");
+            }
+            if let (true, Some((file, line))) = (opt.lines, location) {
+                Location { file: &file, line }.annotate(&mut frame);
+            }
+            symbolized.push(frame);
+        }
+        if symbolized.is_empty() {
+            symbol.map(|symbol| vec![name(opt, symbol)])
+        } else {
+            Some(symbolized)
+        }
+    }
+}
+
+fn load_dwarf(
+    file: &object::File<'_>,
+) -> Result<Option<Context<EndianArcSlice<RunTimeEndian>>>, gimli::Error> {
+    if file.section_by_name(".debug_info").is_none() {
+        return Ok(None);
+    }
+    let endian = if file.is_little_endian() {
+        RunTimeEndian::Little
+    } else {
+        RunTimeEndian::Big
+    };
+    let load_section = |id: gimli::SectionId| -> Result<_, gimli::Error> {
+        let data = file
+            .section_by_name(id.name())
+            .and_then(|section| section.uncompressed_data().ok())
+            .unwrap_or(Cow::Borrowed(&[]));
+        Ok(EndianArcSlice::new(Arc::from(&*data), endian))
+    };
+    let no_sup = |_| Ok(EndianArcSlice::new(Arc::from(&[][..]), endian));
+    let dwarf = gimli::Dwarf::load(load_section, no_sup)?;
+    Context::from_dwarf(dwarf).map(Some)
+}
+
+// Demangles a function name the way the collapsers would.
+fn name(opt: &Options, function: &str) -> String {
+    demangle::demangle_frame(&opt.demangle, function).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_address_of_unsymbolized_frames() {
+        assert_eq!(
+            parse_address("0x7f0012345678"),
+            Some(("", 0x7f00_1234_5678))
+        );
+        assert_eq!(
+            parse_address("libfoo.so`0x1a2b"),
+            Some(("libfoo.so`", 0x1a2b))
+        );
+        assert_eq!(parse_address("[app <401000>]"), Some(("", 0x40_1000)));
+        assert_eq!(
+            parse_address("[unknown <7f53389994d0>]"),
+            Some(("", 0x7f53_3899_94d0))
+        );
+        assert_eq!(parse_address("[unknown]"), None);
+        assert_eq!(parse_address("0xcafe_main"), None);
+        assert_eq!(parse_address("main"), None);
+    }
+
+    #[test]
+    fn name_uses_the_demangle_options() {
+        let mut opt = Options::default();
+        let symbol = "_ZN3app3run17h0123456789abcdefE";
+        assert_eq!(name(&opt, symbol), "app::run");
+
+        opt.demangle.keep_rust_hash = true;
+        assert_eq!(name(&opt, symbol), "app::run::h0123456789abcdef");
+    }
+
+    #[test]
+    fn parse_maps_skips_anonymous_mappings() {
+        let maps = parse_maps(
+            "00400000-00401000 r--p 00000000 08:01 1234 /usr/bin/app\n\
+             00401000-00402000 r-xp 00001000 08:01 1234 /usr/bin/app\n\
+             01b4e000-01b6f000 rw-p 00000000 00:00 0    [heap]\n\
+             7ffd5e1ff000-7ffd5e201000 rw-p 00000000 00:00 0\n\
+             7f2a1b000000-7f2a1b010000 r-xp 00002000 08:01 99 /opt/my lib/libengine.so\n",
+        );
+        assert_eq!(maps.len(), 3);
+        assert_eq!(maps[1].start, 0x40_1000);
+        assert_eq!(maps[1].offset, 0x1000);
+        assert_eq!(maps[2].filename, "/opt/my lib/libengine.so");
+    }
+
+    #[test]
+    fn test_symbols_lookup() {
+        let symbols = Symbols::from_unsorted(vec![
+            (0x2000, 0x10, "bar".to_string()),
+            (0x1000, 0x100, "foo".to_string()),
+            (0x3000, 0, "baz".to_string()),
+        ]);
+        assert_eq!(symbols.lookup(0xfff), None);
+        assert_eq!(symbols.lookup(0x1000), Some("foo"));
+        assert_eq!(symbols.lookup(0x10ff), Some("foo"));
+        assert_eq!(symbols.lookup(0x1100), None);
+        assert_eq!(symbols.lookup(0x200f), Some("bar"));
+        assert_eq!(symbols.lookup(0x2010), None);
+        assert_eq!(symbols.lookup(0x4000), Some("baz"));
+    }
+}
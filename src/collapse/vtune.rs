@@ -2,8 +2,9 @@ use std::io::{self, BufRead};
 
 use log::warn;
 
-use crate::collapse::common::Occurrences;
+use crate::collapse::common::{CollapseLines, Occurrences};
 use crate::collapse::Collapse;
+use crate::folded::FoldedStacks;
 
 // The call graph begins after this line.
 static HEADER: &str = "Function Stack,CPU Time:Self,Module";
@@ -33,6 +34,9 @@ pub struct Folder {
     /// Function on the stack in this entry thus far.
     stack: Vec<String>,
 
+    /// Whether the header has been found yet, when streaming.
+    header_done: bool,
+
     opt: Options,
 }
 
@@ -40,52 +44,28 @@ impl Default for Folder {
     fn default() -> Self {
         Self {
             stack: Vec::default(),
+            header_done: false,
             opt: Options::default(),
         }
     }
 }
 
 impl Collapse for Folder {
-    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
     where
         R: io::BufRead,
         W: io::Write,
     {
-        // Consume the header...
-        let mut line = Vec::new();
-        loop {
-            line.clear();
-            if reader.read_until(0x0A, &mut line)? == 0 {
-                warn!("File ended before header");
-                return Ok(());
-            };
-            let l = String::from_utf8_lossy(&line);
-            if l.starts_with(HEADER) {
-                break;
-            }
-        }
-
-        // Process the data...
-        let mut occurrences = Occurrences::new(1);
-        loop {
-            line.clear();
-            if reader.read_until(0x0A, &mut line)? == 0 {
-                break;
-            }
-            let l = String::from_utf8_lossy(&line);
-            let line = l.trim_end();
-            if line.is_empty() {
-                continue;
-            } else {
-                self.on_line(line, &mut occurrences)?;
-            }
-        }
-
-        // Write the results...
-        occurrences.write_and_clear(writer)?;
+        self.collapse_to_occurrences(reader)?
+            .write_and_clear(writer)
+    }
 
-        // Reset the state...
-        self.stack.clear();
+    fn collapse_into<R>(&mut self, reader: R, stacks: &mut FoldedStacks) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        self.collapse_to_occurrences(reader)?
+            .add_to_and_clear(stacks);
         Ok(())
     }
 
@@ -111,6 +91,31 @@ impl Collapse for Folder {
     }
 }
 
+impl CollapseLines for Folder {
+    fn collapse_line(&mut self, line: &[u8], occurrences: &mut Occurrences) -> io::Result<()> {
+        let l = String::from_utf8_lossy(line);
+        if !self.header_done {
+            self.header_done = l.starts_with(HEADER);
+            return Ok(());
+        }
+        let line = l.trim_end();
+        if line.is_empty() {
+            Ok(())
+        } else {
+            self.on_line(line, occurrences)
+        }
+    }
+
+    fn end_of_input(&mut self, _: &mut Occurrences) -> io::Result<()> {
+        if !self.header_done {
+            warn!("File ended before header");
+        }
+        self.header_done = false;
+        self.stack.clear();
+        Ok(())
+    }
+}
+
 impl From<Options> for Folder {
     fn from(opt: Options) -> Self {
         Folder {
@@ -121,6 +126,45 @@ impl From<Options> for Folder {
 }
 
 impl Folder {
+    fn collapse_to_occurrences<R>(&mut self, mut reader: R) -> io::Result<Occurrences>
+    where
+        R: io::BufRead,
+    {
+        // Consume the header...
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                warn!("File ended before header");
+                return Ok(Occurrences::new(1));
+            };
+            let l = String::from_utf8_lossy(&line);
+            if l.starts_with(HEADER) {
+                break;
+            }
+        }
+
+        // Process the data...
+        let mut occurrences = Occurrences::new(1);
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let line = l.trim_end();
+            if line.is_empty() {
+                continue;
+            } else {
+                self.on_line(line, &mut occurrences)?;
+            }
+        }
+
+        // Reset the state...
+        self.stack.clear();
+        Ok(occurrences)
+    }
+
     fn line_parts<'a>(&self, line: &'a str) -> Option<(&'a str, &'a str, &'a str)> {
         let mut line = if line.starts_with('"') {
             // The function name will be in quotes if it contains spaces.
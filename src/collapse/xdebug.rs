@@ -0,0 +1,276 @@
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::FoldedStacks;
+
+// The trace begins after this line.
+static TRACE_START: &str = "TRACE START";
+
+// Only present in computer-readable (format 1) traces.
+static FILE_FORMAT: &str = "File format:";
+
+/// `xdebug` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Weight each stack by the inclusive time of its leaf function, rather than by the time spent
+    /// in the leaf function itself.
+    ///
+    /// Note that with this option, a function's width in the flame graph includes the time of its
+    /// callees twice: once through its own stack, and once through theirs.
+    ///
+    /// Default is `false`.
+    pub inclusive: bool,
+}
+
+/// A function call that has not returned yet.
+#[derive(Clone)]
+struct Call {
+    name: String,
+    entry_time: f64,
+}
+
+/// A stack collapser for Xdebug function traces.
+///
+/// This reads traces written with `xdebug.trace_format=1` (computer-readable). Counts are in
+/// microseconds, and stacks are weighted by the time spent in the leaf function (exclusive time)
+/// unless [`Options::inclusive`] is set.
+///
+/// To construct one, either use `xdebug::Folder::default()` or create an [`Options`] and use
+/// `xdebug::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    /// Calls on the stack, outermost first.
+    stack: Vec<Call>,
+
+    /// The time index of the last entry or exit.
+    last_time: f64,
+
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        self.collapse_to_occurrences(reader)?
+            .write_and_clear(writer)
+    }
+
+    fn collapse_into<R>(&mut self, reader: R, stacks: &mut FoldedStacks) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        self.collapse_to_occurrences(reader)?
+            .add_to_and_clear(stacks);
+        Ok(())
+    }
+
+    /// Check for the header of a computer-readable trace.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut found_file_format = false;
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            let line = line.trim();
+            if line.starts_with(TRACE_START) {
+                return Some(found_file_format);
+            } else if line.starts_with(FILE_FORMAT) {
+                found_file_format = true;
+            } else if !line.is_empty() && !line.starts_with("Version:") {
+                return Some(false);
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder {
+            opt,
+            ..Default::default()
+        }
+    }
+}
+
+impl Folder {
+    fn collapse_to_occurrences<R>(&mut self, mut reader: R) -> io::Result<Occurrences>
+    where
+        R: io::BufRead,
+    {
+        // Consume the header...
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                warn!("File ended before start of trace");
+                return Ok(Occurrences::new(1));
+            };
+            let l = String::from_utf8_lossy(&line);
+            if l.starts_with(TRACE_START) {
+                break;
+            }
+        }
+
+        // Process the data...
+        let mut occurrences = Occurrences::new(1);
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let line = l.trim_end_matches(&['\n', '\r'][..]);
+            if line.trim().is_empty() {
+                continue;
+            } else if line.starts_with("TRACE END") {
+                break;
+            } else if let Err(e) = self.on_line(line, &mut occurrences) {
+                self.reset();
+                return Err(e);
+            }
+        }
+
+        // Calls that never returned end with the trace.
+        while !self.stack.is_empty() {
+            self.on_exit(self.last_time, &mut occurrences);
+        }
+
+        // Reset the state...
+        self.reset();
+        Ok(occurrences)
+    }
+
+    fn reset(&mut self) {
+        self.stack.clear();
+        self.last_time = 0.0;
+    }
+
+    // Lines are tab-separated records of one of the following kinds:
+    //
+    //     level  function#  0  time  memory  name  user-defined  include-file  file  line  #params  params...
+    //     level  function#  1  time  memory
+    //     level  function#  R                 return-value
+    //                          time  memory
+    //
+    // for function entry, function exit, return values, and the end of the trace respectively.
+    fn on_line(&mut self, line: &str, occurrences: &mut Occurrences) -> io::Result<()> {
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() < 4 {
+            return invalid_data_error!("Unable to parse trace line:\n{}", line);
+        }
+
+        match parts[2] {
+            "0" | "1" => {}
+            // Return values don't change the stack.
+            "R" => return Ok(()),
+            // The last line of a trace only has the time and memory at the end of the script.
+            "" if parts[0].is_empty() => {
+                let time = parse_time(parts[3], line)?;
+                self.advance(time, occurrences);
+                return Ok(());
+            }
+            _ => return invalid_data_error!("Invalid record type in trace line:\n{}", line),
+        }
+
+        let level = match parts[0].parse::<usize>() {
+            Ok(level) if level > 0 => level,
+            _ => return invalid_data_error!("Invalid level in trace line:\n{}", line),
+        };
+        let time = parse_time(parts[3], line)?;
+
+        if parts[2] == "0" {
+            if parts.len() < 6 {
+                return invalid_data_error!("Missing function name in trace line:\n{}", line);
+            }
+            // Calls at shallower levels that we never saw return from have returned by now.
+            while self.stack.len() >= level {
+                self.on_exit(time, occurrences);
+            }
+            self.advance(time, occurrences);
+
+            let mut name = parts[5].replace(';', ":");
+            // include and require calls list the included file.
+            if let Some(file) = parts.get(7).filter(|f| !f.is_empty()) {
+                name = format!("{}({})", name, file.replace(';', ":"));
+            }
+            self.stack.push(Call {
+                name,
+                entry_time: time,
+            });
+        } else {
+            if self.stack.len() < level {
+                warn!("Exit from a function that was never entered:\n{}", line);
+                return Ok(());
+            }
+            while self.stack.len() >= level {
+                self.on_exit(time, occurrences);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Attributes the time since the last event to the current stack, when weighting by exclusive
+    // time.
+    fn advance(&mut self, time: f64, occurrences: &mut Occurrences) {
+        if !self.opt.inclusive && !self.stack.is_empty() {
+            self.write_stack(occurrences, time - self.last_time);
+        }
+        if time > self.last_time {
+            self.last_time = time;
+        }
+    }
+
+    fn on_exit(&mut self, time: f64, occurrences: &mut Occurrences) {
+        if self.opt.inclusive {
+            let entry_time = self
+                .stack
+                .last()
+                .map(|call| call.entry_time)
+                .unwrap_or(time);
+            self.write_stack(occurrences, time - entry_time);
+        } else {
+            self.advance(time, occurrences);
+        }
+        self.stack.pop();
+    }
+
+    fn write_stack(&self, occurrences: &mut Occurrences, seconds: f64) {
+        let micros = (seconds * 1_000_000.0).round();
+        if micros < 1.0 {
+            return;
+        }
+
+        let mut stack_str = String::new();
+        for call in &self.stack {
+            if !stack_str.is_empty() {
+                stack_str.push(';');
+            }
+            stack_str.push_str(&call.name);
+        }
+        occurrences.insert_or_add(stack_str, micros as usize);
+    }
+}
+
+fn parse_time(time: &str, line: &str) -> io::Result<f64> {
+    match time.trim().parse::<f64>() {
+        Ok(time) => Ok(time),
+        Err(_) => invalid_data_error!("Invalid time index in trace line:\n{}", line),
+    }
+}
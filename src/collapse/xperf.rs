@@ -0,0 +1,483 @@
+use std::io::{self, prelude::*};
+
+use log::warn;
+
+use crate::collapse::common::{self, CollapsePrivate, Occurrences};
+
+// The frame that WPA puts at the base of every stack.
+static ROOT_FRAME: &str = "[Root]";
+
+/// `xperf` folder configuration options.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Options {
+    /// Include the process ID with the process name (e.g., `app.exe-1234`).
+    ///
+    /// Default is `false`.
+    pub include_pid: bool,
+
+    /// Include the process and thread ID with the process name (e.g., `app.exe-1234/5678`).
+    ///
+    /// Default is `false`.
+    pub include_tid: bool,
+
+    /// Don't include modules with function names.
+    ///
+    /// Default is `false`.
+    pub no_modules: bool,
+
+    /// The number of threads to use.
+    ///
+    /// Default is the number of logical cores on your machine.
+    pub nthreads: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            include_pid: false,
+            include_tid: false,
+            no_modules: false,
+            nthreads: *common::DEFAULT_NTHREADS,
+        }
+    }
+}
+
+/// The positions of the columns we use within a row.
+#[derive(Clone, Debug)]
+struct Columns {
+    process: Option<usize>,
+    thread: Option<usize>,
+    weight: usize,
+    /// Whether the weight column holds time (in ms) rather than a plain count.
+    weight_is_time: bool,
+    stack: usize,
+}
+
+/// A stack collapser for stacks exported as CSV from Windows Performance Analyzer (WPA) or
+/// `xperf`.
+///
+/// The first line must be a header that names the columns. The `Stack` column and either a
+/// `Weight` or a `Count` column are required, and the `Process` (or `Process Name`) and
+/// `Thread ID` columns are used when present. Each row is one stack, such as
+///
+/// ```text
+/// Process,Thread ID,Stack,Weight
+/// app.exe (1234),5678,[Root]/ntdll.dll!RtlUserThreadStart/app.exe!main/app.exe!compute,12.5
+/// ```
+///
+/// with frames written as `module!function` and separated by `/`, starting from the root. A
+/// `Weight` is taken to be in milliseconds, and counts are written in microseconds; a `Count` is
+/// used as is. The process becomes the root frame of each stack.
+///
+/// To construct one, either use `xperf::Folder::default()` or create an [`Options`] and use
+/// `xperf::Folder::from(options)`.
+pub struct Folder {
+    /// Found in the header by `pre_process`.
+    columns: Option<Columns>,
+
+    /// The number of stacks per job to send to the threadpool.
+    nstacks_per_job: usize,
+
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(mut opt: Options) -> Self {
+        if opt.nthreads == 0 {
+            opt.nthreads = 1;
+        }
+        Self {
+            columns: None,
+            nstacks_per_job: common::DEFAULT_NSTACKS_PER_JOB,
+            opt,
+        }
+    }
+}
+
+impl Default for Folder {
+    fn default() -> Self {
+        Options::default().into()
+    }
+}
+
+impl CollapsePrivate for Folder {
+    fn pre_process<R>(&mut self, reader: &mut R, _: &mut Occurrences) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                warn!("File ended before header");
+                return Ok(());
+            }
+            let l = String::from_utf8_lossy(&line);
+            // Excel-friendly exports start with a byte order mark.
+            let l = l.trim_start_matches('\u{feff}').trim();
+            if l.is_empty() {
+                continue;
+            }
+            return match parse_header(l) {
+                Some(columns) => {
+                    self.columns = Some(columns);
+                    Ok(())
+                }
+                None => invalid_data_error!(
+                    "Expected a header with Stack and Weight or Count columns, but found:\n{}",
+                    l
+                ),
+            };
+        }
+    }
+
+    fn collapse_single_threaded<R>(
+        &mut self,
+        mut reader: R,
+        occurrences: &mut Occurrences,
+    ) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        let columns = match self.columns.clone() {
+            Some(columns) => columns,
+            None => return Ok(()),
+        };
+
+        let mut line = Vec::new();
+        let mut fields = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let l = l.trim_end();
+            if l.is_empty() {
+                continue;
+            }
+            fields.clear();
+            split_csv(l, &mut fields);
+            self.on_row(l, &fields, &columns, occurrences)?;
+        }
+        Ok(())
+    }
+
+    // Every row is a stack of its own.
+    fn would_end_stack(&mut self, line: &[u8]) -> bool {
+        line.iter().any(|b| !b.is_ascii_whitespace())
+    }
+
+    fn clone_and_reset_stack_context(&self) -> Self {
+        Self {
+            columns: self.columns.clone(),
+            nstacks_per_job: self.nstacks_per_job,
+            opt: self.opt.clone(),
+        }
+    }
+
+    /// Check for a header with the columns we need.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            let line = line.trim_start_matches('\u{feff}').trim();
+            if !line.is_empty() {
+                return Some(parse_header(line).is_some());
+            }
+        }
+        None
+    }
+
+    fn nstacks_per_job(&self) -> usize {
+        self.nstacks_per_job
+    }
+
+    fn set_nstacks_per_job(&mut self, n: usize) {
+        self.nstacks_per_job = n;
+    }
+
+    fn nthreads(&self) -> usize {
+        self.opt.nthreads
+    }
+
+    fn set_nthreads(&mut self, n: usize) {
+        self.opt.nthreads = n;
+    }
+}
+
+impl Folder {
+    fn on_row(
+        &self,
+        line: &str,
+        fields: &[String],
+        columns: &Columns,
+        occurrences: &mut Occurrences,
+    ) -> io::Result<()> {
+        let (weight, stack) = match (fields.get(columns.weight), fields.get(columns.stack)) {
+            (Some(weight), Some(stack)) => (weight, stack),
+            _ => return invalid_data_error!("Missing columns in line:\n{}", line),
+        };
+        // Numbers may be written with thousands separators.
+        let weight = weight.replace(',', "");
+        let count = match weight.trim().parse::<f64>() {
+            Ok(count) if columns.weight_is_time => (count * 1000.0).round(),
+            Ok(count) => count.round(),
+            Err(_) => return invalid_data_error!("Invalid weight in line:\n{}", line),
+        };
+        if count < 1.0 {
+            return Ok(());
+        }
+
+        let mut stack_str = String::with_capacity(stack.len());
+        if let Some(process) = columns.process.and_then(|i| fields.get(i)) {
+            let thread = columns.thread.and_then(|i| fields.get(i));
+            stack_str.push_str(&self.process_frame(process, thread));
+        }
+        for frame in stack.split('/') {
+            let frame = frame.trim();
+            if frame.is_empty() || frame == ROOT_FRAME {
+                continue;
+            }
+            if !stack_str.is_empty() {
+                stack_str.push(';');
+            }
+            let frame = match frame.find('!') {
+                Some(bang) if self.opt.no_modules => &frame[bang + 1..],
+                _ => frame,
+            };
+            stack_str.push_str(&frame.replace(';', ":"));
+        }
+        if !stack_str.is_empty() {
+            occurrences.insert_or_add(stack_str, count as usize);
+        }
+        Ok(())
+    }
+
+    // Processes are written as `name (pid)`.
+    fn process_frame(&self, process: &str, thread: Option<&String>) -> String {
+        let process = process.trim();
+        let (name, pid) = match process.rfind(" (") {
+            Some(open) if process.ends_with(')') => {
+                (&process[..open], &process[open + 2..process.len() - 1])
+            }
+            _ => (process, ""),
+        };
+        let name = name.replace(';', ":").replace(' ', "_");
+        match thread {
+            Some(tid) if self.opt.include_tid => format!("{}-{}/{}", name, pid, tid.trim()),
+            _ if self.opt.include_pid || self.opt.include_tid => format!("{}-{}", name, pid),
+            _ => name,
+        }
+    }
+}
+
+fn parse_header(line: &str) -> Option<Columns> {
+    let mut fields = Vec::new();
+    split_csv(line, &mut fields);
+    let find = |names: &[&str]| {
+        fields.iter().position(|field| {
+            names
+                .iter()
+                .any(|name| field.trim().eq_ignore_ascii_case(name))
+        })
+    };
+
+    let stack = find(&["Stack"])?;
+    let (weight, weight_is_time) = match find(&["Weight", "Weight (in view)"]) {
+        Some(weight) => (weight, true),
+        None => (find(&["Count", "Sample Count"])?, false),
+    };
+    Some(Columns {
+        process: find(&["Process", "Process Name"]),
+        thread: find(&["Thread ID", "ThreadID", "TID"]),
+        weight,
+        weight_is_time,
+        stack,
+    })
+}
+
+// Splits a CSV row into `fields`, handling quoted fields with `""` escapes.
+fn split_csv(line: &str, fields: &mut Vec<String>) {
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use lazy_static::lazy_static;
+    use pretty_assertions::assert_eq;
+    use rand::prelude::*;
+
+    use super::*;
+    use crate::collapse::common;
+    use crate::collapse::Collapse;
+
+    lazy_static! {
+        static ref INPUT: Vec<PathBuf> = {
+            [
+                "./tests/data/collapse-xperf/sample-count.csv",
+                "./tests/data/collapse-xperf/wpa-cpu.csv",
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        };
+    }
+
+    #[test]
+    fn test_would_end_stack() {
+        let mut folder = Folder::default();
+        assert!(folder.would_end_stack(b"app.exe (4312),7720,[Root]/app.exe!main,12.5\n"));
+        assert!(!folder.would_end_stack(b"\r\n"));
+        assert!(!folder.would_end_stack(b"\n"));
+    }
+
+    #[test]
+    fn test_split_csv() {
+        let mut fields = Vec::new();
+        split_csv(r#"a,"b,c",,"say ""hi""""#, &mut fields);
+        assert_eq!(fields, vec!["a", "b,c", "", r#"say "hi""#]);
+    }
+
+    #[test]
+    fn test_process_frame() {
+        let folder = Folder::default();
+        let tid = "7720".to_string();
+        assert_eq!(
+            folder.process_frame("app.exe (4312)", Some(&tid)),
+            "app.exe"
+        );
+        assert_eq!(folder.process_frame("Unknown", None), "Unknown");
+
+        let folder = Folder::from(Options {
+            include_pid: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            folder.process_frame("svc host.exe (1180)", Some(&tid)),
+            "svc_host.exe-1180"
+        );
+
+        let folder = Folder::from(Options {
+            include_tid: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            folder.process_frame("app.exe (4312)", Some(&tid)),
+            "app.exe-4312/7720"
+        );
+    }
+
+    #[test]
+    fn test_collapse_multi_xperf() -> io::Result<()> {
+        let mut folder = Folder::default();
+        common::testing::test_collapse_multi(&mut folder, &INPUT)
+    }
+
+    #[test]
+    fn test_collapse_multi_xperf_simple() -> io::Result<()> {
+        let path = "./tests/data/collapse-xperf/wpa-cpu.csv";
+        let mut file = fs::File::open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let mut folder = Folder::default();
+        <Folder as Collapse>::collapse(&mut folder, &bytes[..], io::sink())
+    }
+
+    /// Varies the nstacks_per_job parameter and outputs the 10 fastests configurations by file.
+    ///
+    /// Command: `cargo test bench_nstacks_xperf --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_nstacks_xperf() -> io::Result<()> {
+        let mut folder = Folder::default();
+        common::testing::bench_nstacks(&mut folder, &INPUT)
+    }
+
+    #[test]
+    #[ignore]
+    /// Fuzz test the multithreaded collapser.
+    ///
+    /// Command: `cargo test fuzz_collapse_xperf --release -- --ignored --nocapture`
+    fn fuzz_collapse_xperf() -> io::Result<()> {
+        let seed = thread_rng().gen::<u64>();
+        println!("Random seed: {}", seed);
+        let mut rng = SmallRng::seed_from_u64(seed);
+
+        let mut buf_actual = Vec::new();
+        let mut buf_expected = Vec::new();
+        let mut count = 0;
+
+        let inputs = common::testing::read_inputs(&INPUT)?;
+
+        loop {
+            let nstacks_per_job = rng.gen_range(1, 500 + 1);
+            let options = Options {
+                include_pid: rng.gen(),
+                include_tid: rng.gen(),
+                no_modules: rng.gen(),
+                nthreads: rng.gen_range(2, 32 + 1),
+            };
+
+            for (path, input) in inputs.iter() {
+                buf_actual.clear();
+                buf_expected.clear();
+
+                let mut folder = {
+                    let mut options = options.clone();
+                    options.nthreads = 1;
+                    Folder::from(options)
+                };
+                folder.nstacks_per_job = nstacks_per_job;
+                <Folder as Collapse>::collapse(&mut folder, &input[..], &mut buf_expected)?;
+                let expected = std::str::from_utf8(&buf_expected[..]).unwrap();
+
+                let mut folder = Folder::from(options.clone());
+                folder.nstacks_per_job = nstacks_per_job;
+                <Folder as Collapse>::collapse(&mut folder, &input[..], &mut buf_actual)?;
+                let actual = std::str::from_utf8(&buf_actual[..]).unwrap();
+
+                if actual != expected {
+                    eprintln!(
+                        "Failed on file: {}\noptions: {:#?}\n",
+                        path.display(),
+                        options
+                    );
+                    assert_eq!(actual, expected);
+                }
+            }
+
+            count += 1;
+            if count % 10 == 0 {
+                println!("Successfully ran {} fuzz tests.", count);
+            }
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::Path;
+
+use ahash::AHashMap;
+use regex::Regex;
+
+use crate::collapse::demangle::{strip_generics, strip_rust_hash};
+use crate::report::write_table;
+
+use super::{diff_counts, write_stacks, Counts, Options, READER_CAPACITY};
+
+/// Configure how frames are rewritten before the stacks of two profiles are lined up.
+///
+/// Stacks are compared frame by frame after these rules are applied, in the order of the fields
+/// below, so that a renamed function or a new wrapper frame doesn't turn every stack that goes
+/// through it into a deleted and an added one.
+///
+/// All options default to off.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct AlignOptions {
+    /// Strip the hashes off of Rust symbols, as in `foo::bar::h0123456789abcdef`.
+    pub strip_rust_hashes: bool,
+
+    /// Strip generic and template parameters, so that `Vec<u8>::push` and `Vec<String>::push`
+    /// become `Vec::push`.
+    pub strip_generics: bool,
+
+    /// Regular expressions and their replacements, which are applied to every frame in turn.
+    ///
+    /// The replacements can refer to capture groups, as in `$1` or `${name}`.
+    pub rename: Vec<(Regex, String)>,
+
+    /// Drop the frames that match one of these expressions, such as wrappers that were added or
+    /// removed between the profiles. Their samples count towards their caller.
+    pub drop: Vec<Regex>,
+}
+
+impl AlignOptions {
+    // Rewrites the frames of a stack. Returns `None` if no frames are left.
+    pub(super) fn align(&self, stack: &str) -> Option<String> {
+        let mut aligned = String::with_capacity(stack.len());
+        for frame in stack.split(';') {
+            let mut frame = Cow::Borrowed(frame);
+            if self.strip_rust_hashes {
+                frame = match frame {
+                    Cow::Borrowed(frame) => Cow::Borrowed(strip_rust_hash(frame)),
+                    Cow::Owned(frame) => Cow::Owned(strip_rust_hash(&frame).to_string()),
+                };
+            }
+            if self.strip_generics {
+                if let Cow::Owned(stripped) = strip_generics(&frame) {
+                    frame = Cow::Owned(stripped);
+                }
+            }
+            for (re, replacement) in &self.rename {
+                if let Cow::Owned(renamed) = re.replace_all(&frame, replacement.as_str()) {
+                    frame = Cow::Owned(renamed);
+                }
+            }
+            if frame.is_empty() || self.drop.iter().any(|re| re.is_match(&frame)) {
+                continue;
+            }
+
+            if !aligned.is_empty() {
+                aligned.push(';');
+            }
+            aligned.push_str(&frame);
+        }
+
+        if aligned.is_empty() {
+            None
+        } else {
+            Some(aligned)
+        }
+    }
+}
+
+/// Like [`from_readers`], but the stacks of the profiles are lined up after their frames are
+/// rewritten by `align`.
+///
+/// Stacks that end up the same have their sample counts added up.
+///
+///   [`from_readers`]: super::from_readers
+pub fn from_readers_aligned<R1, R2, W>(
+    opt: Options,
+    align: &AlignOptions,
+    before: R1,
+    after: R2,
+    writer: W,
+) -> io::Result<()>
+where
+    R1: BufRead,
+    R2: BufRead,
+    W: Write,
+{
+    let stack_counts = diff_counts(opt, Some(align), before, after)?;
+    write_stacks(&stack_counts, writer)
+}
+
+/// Like [`from_files`], but the stacks of the profiles are lined up after their frames are
+/// rewritten by `align`.
+///
+/// See [`from_readers_aligned`] for details.
+///
+///   [`from_files`]: super::from_files
+pub fn from_files_aligned<P1, P2, W>(
+    opt: Options,
+    align: &AlignOptions,
+    file_before: P1,
+    file_after: P2,
+    writer: W,
+) -> io::Result<()>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+    W: Write,
+{
+    let (reader1, reader2) = open(file_before, file_after)?;
+    from_readers_aligned(opt, align, reader1, reader2, writer)
+}
+
+/// The samples of a function in the before and after profiles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FunctionDelta {
+    /// The name of the function, after its frames were rewritten.
+    pub function: String,
+
+    /// The samples of stacks that end in the function in the before profile.
+    pub self_before: usize,
+
+    /// The samples of stacks that end in the function in the after profile.
+    pub self_after: usize,
+
+    /// The samples of stacks that go through the function in the before profile.
+    pub total_before: usize,
+
+    /// The samples of stacks that go through the function in the after profile.
+    pub total_after: usize,
+}
+
+impl FunctionDelta {
+    /// The change in the self samples of the function.
+    pub fn self_delta(&self) -> isize {
+        self.self_after as isize - self.self_before as isize
+    }
+
+    /// The change in the total samples of the function.
+    pub fn total_delta(&self) -> isize {
+        self.total_after as isize - self.total_before as isize
+    }
+}
+
+/// Compute the change in the self and total samples of each function between a before and an
+/// after profile, whose stacks are lined up as in [`from_readers_aligned`].
+///
+/// Samples of recursive functions are only counted once towards their total. The functions are
+/// sorted by the size of their change in self samples, then in total samples, largest first.
+pub fn function_deltas<R1, R2>(
+    opt: Options,
+    align: &AlignOptions,
+    before: R1,
+    after: R2,
+) -> io::Result<Vec<FunctionDelta>>
+where
+    R1: BufRead,
+    R2: BufRead,
+{
+    let stack_counts = diff_counts(opt, Some(align), before, after)?;
+
+    let mut functions: AHashMap<&str, FunctionDelta> = AHashMap::default();
+    let mut seen = Vec::new();
+    for (stack, &Counts { first, second }) in &stack_counts {
+        seen.clear();
+        let mut frames = stack.split(';').peekable();
+        while let Some(frame) = frames.next() {
+            let function = functions.entry(frame).or_insert_with(|| FunctionDelta {
+                function: frame.to_string(),
+                self_before: 0,
+                self_after: 0,
+                total_before: 0,
+                total_after: 0,
+            });
+            if frames.peek().is_none() {
+                function.self_before += first;
+                function.self_after += second;
+            }
+            if !seen.contains(&frame) {
+                seen.push(frame);
+                function.total_before += first;
+                function.total_after += second;
+            }
+        }
+    }
+
+    let mut functions: Vec<_> = functions.into_iter().map(|(_, delta)| delta).collect();
+    functions.sort_unstable_by(|a, b| {
+        b.self_delta()
+            .abs()
+            .cmp(&a.self_delta().abs())
+            .then_with(|| b.total_delta().abs().cmp(&a.total_delta().abs()))
+            .then_with(|| a.function.cmp(&b.function))
+    });
+    Ok(functions)
+}
+
+/// Compute the change in the samples of each function between a before and an after profile.
+///
+/// See [`function_deltas`] for details.
+pub fn function_deltas_from_files<P1, P2>(
+    opt: Options,
+    align: &AlignOptions,
+    file_before: P1,
+    file_after: P2,
+) -> io::Result<Vec<FunctionDelta>>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let (reader1, reader2) = open(file_before, file_after)?;
+    function_deltas(opt, align, reader1, reader2)
+}
+
+/// Write the [`function_deltas`] as a table, with a column for the self and total samples
+/// before, after, and the change between them.
+pub fn write_function_deltas<W>(functions: &[FunctionDelta], writer: W) -> io::Result<()>
+where
+    W: Write,
+{
+    let rows: Vec<_> = functions
+        .iter()
+        .map(|function| {
+            let columns = vec![
+                function.self_before.to_string(),
+                function.self_after.to_string(),
+                format!("{:+}", function.self_delta()),
+                function.total_before.to_string(),
+                function.total_after.to_string(),
+                format!("{:+}", function.total_delta()),
+            ];
+            (columns, function.function.as_str())
+        })
+        .collect();
+    write_table(
+        writer,
+        &["Self1", "Self2", "Delta", "Total1", "Total2", "Delta"],
+        &rows,
+    )
+}
+
+fn open<P1, P2>(
+    file_before: P1,
+    file_after: P2,
+) -> io::Result<(io::BufReader<File>, io::BufReader<File>)>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let file1 = File::open(file_before)?;
+    let file2 = File::open(file_after)?;
+    Ok((
+        io::BufReader::with_capacity(READER_CAPACITY, file1),
+        io::BufReader::with_capacity(READER_CAPACITY, file2),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn align_applies_the_rules_in_order() {
+        let align = AlignOptions {
+            strip_rust_hashes: true,
+            strip_generics: true,
+            rename: vec![(Regex::new("^app::").unwrap(), String::new())],
+            drop: vec![Regex::new("^with_retry$").unwrap()],
+        };
+        assert_eq!(
+            align.align("main;app::with_retry::h0123456789abcdef;app::Cache<K>::get"),
+            Some("main;Cache::get".to_string())
+        );
+        assert_eq!(align.align("app::with_retry"), None);
+    }
+}
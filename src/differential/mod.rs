@@ -1,10 +1,18 @@
+mod align;
+
 use std::fs::File;
 use std::io::{self, prelude::*};
 use std::path::Path;
+use std::str::FromStr;
 
 use ahash::AHashMap;
 use log::warn;
 
+pub use self::align::{
+    from_files_aligned, from_readers_aligned, function_deltas, function_deltas_from_files,
+    write_function_deltas, AlignOptions, FunctionDelta,
+};
+
 const READER_CAPACITY: usize = 128 * 1024;
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -30,6 +38,59 @@ pub struct Options {
     pub strip_hex: bool,
 }
 
+/// How the difference between the before and after runs of a stack is scored by
+/// [`from_multiple_readers`].
+///
+/// Both scores are the difference of the mean sample counts divided by its standard error, so a
+/// score of about 2 or more (in either direction) is unlikely to be noise. The default is
+/// [`Welch`](Score::Welch).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Score {
+    /// Welch's t-statistic, which estimates the noise from the spread of the sample counts
+    /// across runs.
+    ///
+    /// It falls back to the Poisson z-score when either profile has a single run, or when the
+    /// counts don't vary between runs at all.
+    #[default]
+    Welch,
+
+    /// A z-score that treats the sample counts as Poisson distributed, so that their variance is
+    /// their mean. This works with a single run of each profile.
+    Poisson,
+}
+
+impl FromStr for Score {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "welch" => Ok(Score::Welch),
+            "poisson" => Ok(Score::Poisson),
+            _ => Err(format!("unknown score: {}", s)),
+        }
+    }
+}
+
+impl Score {
+    // Scores the difference between the sample counts of a stack in each before and after run.
+    fn score(self, before: &[f64], after: &[f64]) -> f64 {
+        let (nbefore, nafter) = (before.len() as f64, after.len() as f64);
+        let (mean_before, var_before) = mean_and_variance(before);
+        let (mean_after, var_after) = mean_and_variance(after);
+
+        let welch = var_before / nbefore + var_after / nafter;
+        let variance = match self {
+            Score::Welch if before.len() > 1 && after.len() > 1 && welch > 0.0 => welch,
+            _ => mean_before / nbefore + mean_after / nafter,
+        };
+        if variance > 0.0 {
+            (mean_after - mean_before) / variance.sqrt()
+        } else {
+            0.0
+        }
+    }
+}
+
 /// Produce an output that can be used to generate a differential flame graph.
 ///
 /// The readers are expected to contain folded stack lines of before and after profiles with
@@ -46,14 +107,7 @@ where
     R2: BufRead,
     W: Write,
 {
-    let mut stack_counts = AHashMap::default();
-    let total1 = parse_stack_counts(opt, &mut stack_counts, before, true)?;
-    let total2 = parse_stack_counts(opt, &mut stack_counts, after, false)?;
-    if opt.normalize && total1 != total2 {
-        for counts in stack_counts.values_mut() {
-            counts.first = (counts.first as f64 * total2 as f64 / total1 as f64) as usize;
-        }
-    }
+    let stack_counts = diff_counts(opt, None, before, after)?;
     write_stacks(&stack_counts, writer)
 }
 
@@ -79,9 +133,192 @@ where
     from_readers(opt, reader1, reader2, writer)
 }
 
+/// Produce an output that can be used to generate a differential flame graph from several runs
+/// of a before and an after profile, such as repeated benchmarks of two builds.
+///
+/// Each reader is expected to contain the folded stack lines of one run, as in [`from_readers`].
+/// A stack that is missing from a run has no samples in it. If [`normalize`](Options::normalize)
+/// is set, every run is scaled to the mean total of the after runs.
+///
+/// The output written to the `writer` has four whitespace-separated fields per stack:
+///
+///  - The semicolon-separated list of frame names.
+///  - The mean sample count of the before runs, rounded to a whole sample.
+///  - The mean sample count of the after runs, rounded to a whole sample.
+///  - The `score` of the difference between the runs, with a sign and two decimals (e.g.,
+///    `+2.35`).
+///
+/// The lines are sorted. [`flamegraph`] reads the score, and only colors the stacks with
+/// significant changes if its [`significance_threshold`] is set.
+///
+///   [`flamegraph`]: crate::flamegraph
+///   [`significance_threshold`]: crate::flamegraph::Options::significance_threshold
+pub fn from_multiple_readers<I1, I2, R1, R2, W>(
+    opt: Options,
+    score: Score,
+    before: I1,
+    after: I2,
+    mut writer: W,
+) -> io::Result<()>
+where
+    I1: IntoIterator<Item = R1>,
+    I2: IntoIterator<Item = R2>,
+    R1: BufRead,
+    R2: BufRead,
+    W: Write,
+{
+    let mut before = parse_runs(opt, before)?;
+    let mut after = parse_runs(opt, after)?;
+    if before.is_empty() || after.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "at least one before and one after profile are needed",
+        ));
+    }
+
+    if opt.normalize {
+        let target = after.iter().map(|run| run.total).sum::<usize>() as f64 / after.len() as f64;
+        for run in before.iter_mut().chain(after.iter_mut()) {
+            run.scale = if run.total == 0 {
+                0.0
+            } else {
+                target / run.total as f64
+            };
+        }
+    }
+
+    let mut stacks: Vec<&str> = before
+        .iter()
+        .chain(after.iter())
+        .flat_map(|run| run.counts.keys().map(String::as_str))
+        .collect();
+    stacks.sort_unstable();
+    stacks.dedup();
+
+    let mut before_samples = Vec::with_capacity(before.len());
+    let mut after_samples = Vec::with_capacity(after.len());
+    for stack in stacks {
+        before_samples.clear();
+        before_samples.extend(before.iter().map(|run| run.samples(stack)));
+        after_samples.clear();
+        after_samples.extend(after.iter().map(|run| run.samples(stack)));
+
+        let (mean_before, _) = mean_and_variance(&before_samples);
+        let (mean_after, _) = mean_and_variance(&after_samples);
+        writeln!(
+            writer,
+            "{} {} {} {:+.2}",
+            stack,
+            mean_before.round() as usize,
+            mean_after.round() as usize,
+            score.score(&before_samples, &after_samples)
+        )?;
+    }
+    Ok(())
+}
+
+/// Produce an output that can be used to generate a differential flame graph from several runs
+/// of a before and an after profile.
+///
+/// See [`from_multiple_readers`] for the input and output formats.
+pub fn from_multiple_files<P, W>(
+    opt: Options,
+    score: Score,
+    files_before: &[P],
+    files_after: &[P],
+    writer: W,
+) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    let open = |path: &P| -> io::Result<_> {
+        let file = File::open(path)?;
+        Ok(io::BufReader::with_capacity(READER_CAPACITY, file))
+    };
+    let before = files_before
+        .iter()
+        .map(open)
+        .collect::<io::Result<Vec<_>>>()?;
+    let after = files_after
+        .iter()
+        .map(open)
+        .collect::<io::Result<Vec<_>>>()?;
+    from_multiple_readers(opt, score, before, after, writer)
+}
+
+// The sample counts of one run of a profile.
+struct Run {
+    counts: AHashMap<String, usize>,
+    total: usize,
+    scale: f64,
+}
+
+impl Run {
+    fn samples(&self, stack: &str) -> f64 {
+        self.counts.get(stack).copied().unwrap_or(0) as f64 * self.scale
+    }
+}
+
+fn parse_runs<I, R>(opt: Options, readers: I) -> io::Result<Vec<Run>>
+where
+    I: IntoIterator<Item = R>,
+    R: BufRead,
+{
+    let mut runs = Vec::new();
+    for reader in readers {
+        let mut stack_counts = AHashMap::default();
+        let total = parse_stack_counts(opt, None, &mut stack_counts, reader, true)?;
+        runs.push(Run {
+            counts: stack_counts
+                .into_iter()
+                .map(|(stack, counts)| (stack, counts.first))
+                .collect(),
+            total,
+            scale: 1.0,
+        });
+    }
+    Ok(runs)
+}
+
+// Returns the mean and the sample variance of the values.
+fn mean_and_variance(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    if values.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, variance)
+}
+
+// Count the samples of each stack in the before and after profiles, after aligning the stacks if
+// `align` is given.
+fn diff_counts<R1, R2>(
+    opt: Options,
+    align: Option<&AlignOptions>,
+    before: R1,
+    after: R2,
+) -> io::Result<AHashMap<String, Counts>>
+where
+    R1: BufRead,
+    R2: BufRead,
+{
+    let mut stack_counts = AHashMap::default();
+    let total1 = parse_stack_counts(opt, align, &mut stack_counts, before, true)?;
+    let total2 = parse_stack_counts(opt, align, &mut stack_counts, after, false)?;
+    if opt.normalize && total1 != total2 {
+        for counts in stack_counts.values_mut() {
+            counts.first = (counts.first as f64 * total2 as f64 / total1 as f64) as usize;
+        }
+    }
+    Ok(stack_counts)
+}
+
 // Populate stack_counts based on lines from the reader and returns the sum of the sample counts.
 fn parse_stack_counts<R>(
     opt: Options,
+    align: Option<&AlignOptions>,
     stack_counts: &mut AHashMap<String, Counts>,
     mut reader: R,
     is_first: bool,
@@ -103,6 +340,14 @@ where
         if let Some((stack, count)) =
             parse_line(&l, opt.strip_hex, &mut stripped_fractional_samples)
         {
+            let stack = match align {
+                Some(align) => match align.align(&stack) {
+                    Some(stack) => stack,
+                    // Every frame of the stack was dropped.
+                    None => continue,
+                },
+                None => stack,
+            };
             let mut counts = stack_counts.entry(stack).or_default();
             if is_first {
                 counts.first += count;
@@ -186,3 +431,26 @@ fn strip_hex_address(mut stack: &str) -> String {
     stripped.push_str(stack);
     stripped
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn welch_uses_the_spread_between_runs() {
+        let score = Score::Welch.score(&[10.0, 12.0, 8.0], &[20.0, 22.0, 18.0]);
+        assert!((score - 10.0 / (8.0f64 / 3.0).sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn welch_falls_back_to_poisson() {
+        for (before, after) in &[(&[10.0][..], &[20.0][..]), (&[10.0, 10.0], &[20.0, 20.0])] {
+            assert_eq!(
+                Score::Welch.score(before, after),
+                Score::Poisson.score(before, after)
+            );
+        }
+        assert!((Score::Poisson.score(&[10.0], &[20.0]) - 10.0 / 30.0f64.sqrt()).abs() < 1e-9);
+        assert_eq!(Score::Poisson.score(&[0.0], &[0.0]), 0.0);
+    }
+}
@@ -0,0 +1,270 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::PathBuf;
+
+use log::warn;
+use regex::Regex;
+
+use crate::flamegraph::{rfind_samples, rfind_score};
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+/// Configure how stacks are filtered and rewritten.
+///
+/// The steps are applied to each stack in the order of the fields below: frames are renamed
+/// first, so that the other steps see the new names, and the depth is capped last.
+///
+/// All options default to off.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Regular expressions and their replacements, which are applied to every frame in turn.
+    ///
+    /// The replacements can refer to capture groups, as in `$1` or `${name}`. For example,
+    /// `(::h[0-9a-f]{16})$` replaced with nothing strips the hashes off of Rust symbols.
+    pub rename: Vec<(Regex, String)>,
+
+    /// Collapse frames that directly call themselves into a single frame.
+    pub fold_recursion: bool,
+
+    /// Only keep the stacks with a frame that matches one of these expressions.
+    pub grep: Vec<Regex>,
+
+    /// Drop the stacks with a frame that matches one of these expressions.
+    pub exclude: Vec<Regex>,
+
+    /// Drop the callers of the first frame that matches this expression, so that it becomes the
+    /// root of the stack, like pprof's `-focus`. Stacks without a matching frame are dropped.
+    pub focus: Option<Regex>,
+
+    /// Drop the callees of the first frame that matches this expression, so that their samples
+    /// count towards it, like pprof's `-prune_from`.
+    pub prune: Option<Regex>,
+
+    /// Only keep this many frames from the root of each stack.
+    pub max_depth: Option<usize>,
+}
+
+/// Filter and rewrite the folded stack lines of `reader`, writing each line that is kept to
+/// `writer` as soon as it is read.
+///
+/// Lines are expected to have a semicolon-separated list of frame names followed by one or two
+/// sample counts, as written by the [collapsers] and [`differential`]. The sample counts are
+/// copied over unchanged.
+///
+/// Stacks aren't merged, so a stack may appear on several lines once it has been rewritten. The
+/// lines are still valid input for [`flamegraph`], which adds up their samples.
+///
+///   [collapsers]: crate::collapse
+///   [`differential`]: crate::differential
+///   [`flamegraph`]: crate::flamegraph
+pub fn from_reader<R, W>(opt: &Options, mut reader: R, mut writer: W) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut line = Vec::new();
+    let mut ignored = 0;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+
+        let l = String::from_utf8_lossy(&line);
+        let l = l.trim();
+        if l.is_empty() {
+            continue;
+        }
+        let (stack, counts) = match split_counts(l) {
+            Some(parts) => parts,
+            None => {
+                ignored += 1;
+                continue;
+            }
+        };
+        if let Some(frames) = filter_stack(opt, stack) {
+            for (i, frame) in frames.iter().enumerate() {
+                if i != 0 {
+                    writer.write_all(b";")?;
+                }
+                writer.write_all(frame.as_bytes())?;
+            }
+            writer.write_all(b" ")?;
+            writer.write_all(counts.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+    }
+    if ignored != 0 {
+        warn!("Ignored {} lines with invalid format", ignored);
+    }
+    Ok(())
+}
+
+/// Filter and rewrite the folded stack lines of each of `files` in turn.
+///
+/// If files is empty, or is just `-`, STDIN will be used as input.
+///
+/// See [`from_reader`] for details.
+pub fn from_files<W>(opt: &Options, files: &[PathBuf], mut writer: W) -> io::Result<()>
+where
+    W: Write,
+{
+    let stdin = io::stdin();
+    if files.is_empty() {
+        return from_reader(opt, stdin.lock(), writer);
+    }
+    for infile in files {
+        if infile.to_str() == Some("-") {
+            from_reader(opt, stdin.lock(), &mut writer)?;
+        } else {
+            let file = File::open(infile)?;
+            let reader = io::BufReader::with_capacity(READER_CAPACITY, file);
+            from_reader(opt, reader, &mut writer)?;
+        }
+    }
+    Ok(())
+}
+
+// Splits a folded stack line into its stack and its one or two sample counts, along with the
+// significance score of a differential of several runs.
+fn split_counts(line: &str) -> Option<(&str, &str)> {
+    let end = rfind_score(line).map_or(line.len(), |scorei| line[..scorei].trim_end().len());
+    let (mut samplesi, _) = rfind_samples(&line[..end])?;
+    // The line of a differential has a second sample count.
+    if let Some((firsti, _)) = rfind_samples(line[..samplesi].trim_end()) {
+        samplesi = firsti;
+    }
+    let stack = line[..samplesi].trim_end();
+    if stack.is_empty() {
+        None
+    } else {
+        Some((stack, &line[samplesi..]))
+    }
+}
+
+// Applies the steps of `opt` to the frames of `stack`.
+//
+// Returns `None` if the stack is dropped.
+fn filter_stack<'a>(opt: &Options, stack: &'a str) -> Option<Vec<Cow<'a, str>>> {
+    let mut frames: Vec<_> = stack.split(';').map(|frame| rename(opt, frame)).collect();
+
+    if opt.fold_recursion {
+        frames.dedup();
+    }
+
+    let matches_any = |res: &[Regex], frames: &[Cow<'_, str>]| {
+        frames
+            .iter()
+            .any(|frame| res.iter().any(|re| re.is_match(frame)))
+    };
+    if !opt.grep.is_empty() && !matches_any(&opt.grep, &frames) {
+        return None;
+    }
+    if matches_any(&opt.exclude, &frames) {
+        return None;
+    }
+
+    if let Some(ref focus) = opt.focus {
+        match frames.iter().position(|frame| focus.is_match(frame)) {
+            Some(i) => {
+                frames.drain(..i);
+            }
+            None => return None,
+        }
+    }
+    if let Some(ref prune) = opt.prune {
+        if let Some(i) = frames.iter().position(|frame| prune.is_match(frame)) {
+            frames.truncate(i + 1);
+        }
+    }
+    if let Some(max_depth) = opt.max_depth {
+        frames.truncate(max_depth);
+    }
+
+    if frames.is_empty() {
+        None
+    } else {
+        Some(frames)
+    }
+}
+
+fn rename<'a>(opt: &Options, frame: &'a str) -> Cow<'a, str> {
+    let mut frame = Cow::Borrowed(frame);
+    for (re, replacement) in &opt.rename {
+        if let Cow::Owned(renamed) = re.replace_all(&frame, replacement.as_str()) {
+            frame = Cow::Owned(renamed);
+        }
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(opt: &Options, stack: &str) -> Option<String> {
+        filter_stack(opt, stack).map(|frames| frames.join(";"))
+    }
+
+    #[test]
+    fn split_counts_keeps_differential_counts() {
+        assert_eq!(split_counts("main;foo 12"), Some(("main;foo", "12")));
+        assert_eq!(split_counts("main;foo 3 5"), Some(("main;foo", "3 5")));
+        assert_eq!(
+            split_counts("main;foo 3 5 +2.10"),
+            Some(("main;foo", "3 5 +2.10"))
+        );
+        assert_eq!(split_counts("main;foo 1.5"), Some(("main;foo", "1.5")));
+        assert_eq!(split_counts("main;foo bar 5"), Some(("main;foo bar", "5")));
+        assert_eq!(split_counts("main;foo"), None);
+    }
+
+    #[test]
+    fn focus_and_prune_cut_the_stack() {
+        let mut opt = Options {
+            focus: Some(Regex::new("^run$").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter(&opt, "main;run;compute;run;hash"),
+            Some("run;compute;run;hash".to_string())
+        );
+        assert_eq!(filter(&opt, "main;parse"), None);
+
+        opt.prune = Some(Regex::new("^compute$").unwrap());
+        assert_eq!(
+            filter(&opt, "main;run;compute;run;hash"),
+            Some("run;compute".to_string())
+        );
+    }
+
+    #[test]
+    fn rename_happens_before_folding_recursion() {
+        let opt = Options {
+            rename: vec![(Regex::new("::h[0-9a-f]{16}$").unwrap(), String::new())],
+            fold_recursion: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            filter(
+                &opt,
+                "main;fib::h0123456789abcdef;fib::hfedcba9876543210;fib;add"
+            ),
+            Some("main;fib;add".to_string())
+        );
+    }
+
+    #[test]
+    fn grep_and_exclude() {
+        let opt = Options {
+            grep: vec![Regex::new("^std::").unwrap()],
+            exclude: vec![Regex::new("alloc").unwrap()],
+            ..Default::default()
+        };
+        assert!(filter(&opt, "main;std::vec::Vec::push").is_some());
+        assert!(filter(&opt, "main;std::vec::Vec::push;alloc").is_none());
+        assert!(filter(&opt, "main;compute").is_none());
+    }
+}
@@ -6,6 +6,8 @@ use ahash::AHashMap;
 use indexmap::map::Entry;
 use log::warn;
 
+use crate::folded::Location;
+
 type AttrMap<K, V> = indexmap::IndexMap<K, V, ahash::RandomState>;
 
 macro_rules! unwrap_or_continue {
@@ -19,8 +21,14 @@ macro_rules! unwrap_or_continue {
 }
 
 /// Provides a way to customize the attributes on the SVG elements for a frame.
+///
+/// Frames with a source [`Location`] are looked up by their full name first, and then by their
+/// name without the location, so that the attributes of a function apply to all its lines.
 #[derive(PartialEq, Eq, Debug, Default)]
-pub struct FuncFrameAttrsMap(AHashMap<String, FrameAttrs>);
+pub struct FuncFrameAttrsMap {
+    attrs: AHashMap<String, FrameAttrs>,
+    source_url: Option<String>,
+}
 
 impl FuncFrameAttrsMap {
     /// Parse frame attributes from a file.
@@ -52,7 +60,7 @@ impl FuncFrameAttrsMap {
             if func.is_empty() {
                 continue;
             }
-            let funcattrs = funcattr_map.0.entry(func.to_string()).or_default();
+            let funcattrs = funcattr_map.attrs.entry(func.to_string()).or_default();
             let namevals = unwrap_or_continue!(line.next());
             for nameval in namevals.split('\t') {
                 let mut nameval = nameval.splitn(2, '=');
@@ -91,9 +99,35 @@ impl FuncFrameAttrsMap {
         Ok(funcattr_map)
     }
 
+    /// Link the frames that have a source [`Location`] to a source browser.
+    ///
+    /// The link is made by replacing `{file}` and `{line}` in `template`, as in
+    /// `https://github.com/user/repo/blob/main/{file}#L{line}`. Frames whose attributes already
+    /// have an `href` keep it.
+    pub fn set_source_url(&mut self, template: String) {
+        self.source_url = Some(template);
+    }
+
     /// Return FrameAttrs for the given function name if it exists
     pub(super) fn frameattrs_for_func(&self, func: &str) -> Option<&FrameAttrs> {
-        self.0.get(func)
+        self.attrs
+            .get(func)
+            .or_else(|| match Location::split(func) {
+                (name, Some(_)) => self.attrs.get(name),
+                (_, None) => None,
+            })
+    }
+
+    /// Return the link to the source of the given function, if it has a location and a source
+    /// URL was set.
+    pub(super) fn source_href_for_func(&self, func: &str) -> Option<String> {
+        let template = self.source_url.as_ref()?;
+        let location = Location::split(func).1?;
+        Some(
+            template
+                .replace("{file}", location.file)
+                .replace("{line}", &location.line.to_string()),
+        )
     }
 }
 
@@ -254,8 +288,30 @@ mod test {
         );
 
         let result = FuncFrameAttrsMap::from_reader(r).unwrap();
-        let expected = FuncFrameAttrsMap(expected_inner);
+        let expected = FuncFrameAttrsMap {
+            attrs: expected_inner,
+            source_url: None,
+        };
 
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn func_frame_attrs_map_source_href() {
+        let r = "compute\thref=https://example.com/compute".as_bytes();
+        let mut attrs = FuncFrameAttrsMap::from_reader(r).unwrap();
+        attrs.set_source_url("https://example.com/src/{file}#L{line}".to_string());
+
+        assert_eq!(
+            attrs.source_href_for_func("parse [app/parse.c:12]"),
+            Some("https://example.com/src/app/parse.c#L12".to_string())
+        );
+        assert_eq!(attrs.source_href_for_func("parse"), None);
+        assert!(attrs
+            .frameattrs_for_func("compute [app/compute.c:3]")
+            .is_some());
+        assert!(attrs
+            .frameattrs_for_func("parse [app/parse.c:12]")
+            .is_none());
+    }
 }
@@ -0,0 +1,375 @@
+"use strict";
+(function () {
+    var profile = JSON.parse(document.getElementById("profile").textContent);
+    var names = profile.names;
+    var frames = profile.frames;
+    // indices into each frame
+    var NAME = 0, DEPTH = 1, START = 2, END = 3, COLOR = 4;
+    var xpad = 10;
+    var fh = profile.frame_height;
+
+    var canvas = document.getElementById("canvas");
+    var ctx = canvas.getContext("2d");
+    var graph = document.getElementById("graph");
+    var tooltip = document.getElementById("tooltip");
+    var details = document.getElementById("details");
+    var unzoombtn = document.getElementById("unzoom");
+    var searchbox = document.getElementById("search");
+    var matchedtxt = document.getElementById("matched");
+    var table = document.getElementById("functions");
+
+    var attrs = {};
+    profile.attrs.forEach(function (a) { attrs[a[0]] = a[1]; });
+    var locations = {};
+    (profile.locations || []).forEach(function (l) { locations[l[0]] = l[1]; });
+
+    // frames are sorted by depth, so each depth is a contiguous range of frames
+    var depthmax = 0;
+    var depthstart = [0];
+    frames.forEach(function (f, i) {
+        while (f[DEPTH] > depthmax) {
+            depthmax += 1;
+            depthstart[depthmax] = i;
+        }
+    });
+    depthstart.push(frames.length);
+
+    var width = 0;          // width of the graph, without padding
+    var zoomed = null;      // index of the frame that is zoomed into
+    var viewstart = 0;
+    var viewend = profile.total;
+    var matches = null;     // whether each name matches the search
+    var hovered = null;
+    var sortcolumn = 1;     // 1 = self, 2 = total
+
+    document.body.style.fontFamily = profile.font_type;
+    document.body.style.fontSize = profile.font_size + "px";
+    graph.style.background = "linear-gradient(" + profile.background[0] + " 5%, " + profile.background[1] + " 95%)";
+
+    function samples(n) {
+        return Math.round(n * profile.factor);
+    }
+
+    function percent(n) {
+        return (100 * n / profile.total).toFixed(2);
+    }
+
+    // The amount of `n` samples, in the unit of the profile.
+    function amount(n) {
+        if (profile.unit === "percent") {
+            return percent(n) + "%";
+        } else if (profile.unit) {
+            return format_unit(n * profile.factor);
+        }
+        return samples(n).toLocaleString("en-US") + " " + profile.count_name;
+    }
+
+    function format_unit(n) {
+        var step = 1000;
+        var units = ["ns", "\u00b5s", "ms", "s"];
+        if (profile.unit === "bytes") {
+            step = 1024;
+            units = ["B", "KiB", "MiB", "GiB", "TiB"];
+        } else if (profile.unit === "us") {
+            n *= 1e3;
+        } else if (profile.unit === "ms") {
+            n *= 1e6;
+        }
+        var i = 0;
+        while (n >= step && i < units.length - 1) {
+            n /= step;
+            i++;
+        }
+        return (i === 0 ? Math.round(n) : n.toFixed(2)) + " " + units[i];
+    }
+
+    function label(f) {
+        return f[DEPTH] === 0 && names[f[NAME]] === "" ? "all" : names[f[NAME]];
+    }
+
+    function info(i) {
+        var f = frames[i];
+        var text = label(f);
+        if (locations[f[NAME]]) {
+            text += " at " + locations[f[NAME]];
+        }
+        text += " (";
+        if (profile.unit !== "percent") {
+            text += amount(f[END] - f[START]) + ", ";
+        }
+        if (f[DEPTH] === 0 && names[f[NAME]] === "") {
+            return text + "100%)";
+        }
+        text += percent(samples(f[END] - f[START]) / profile.factor) + "%";
+        if (profile.deltas) {
+            var delta = profile.deltas[i];
+            text += "; " + (delta > 0 ? "+" : "") + percent(delta / profile.factor) + "%";
+        }
+        return text + ")";
+    }
+
+    function title(i) {
+        var a = attrs[frames[i][NAME]];
+        var text = a && a.title ? a.title : info(i);
+        if (a && a.href) {
+            text += "\nCtrl+click to open " + a.href;
+        }
+        return text;
+    }
+
+    function isancestor(f) {
+        if (zoomed === null) {
+            return false;
+        }
+        var z = frames[zoomed];
+        return f[DEPTH] < z[DEPTH] && f[START] <= z[START] && f[END] >= z[END];
+    }
+
+    // horizontal extent of a frame in the current view, or null if it is hidden
+    function extent(f) {
+        if (isancestor(f)) {
+            return [xpad, xpad + width];
+        }
+        if (zoomed !== null && f[DEPTH] < frames[zoomed][DEPTH]) {
+            return null;
+        }
+        if (f[END] <= viewstart || f[START] >= viewend) {
+            return null;
+        }
+        var scale = width / (viewend - viewstart);
+        return [
+            xpad + (Math.max(f[START], viewstart) - viewstart) * scale,
+            xpad + (Math.min(f[END], viewend) - viewstart) * scale
+        ];
+    }
+
+    function top(depth) {
+        return profile.inverted ? depth * fh : (depthmax - depth) * fh;
+    }
+
+    function fittext(text, w) {
+        var fitchars = Math.floor((w - 3) / (profile.font_size * profile.font_width));
+        if (fitchars < 3) {
+            // room for one char plus two dots
+            return "";
+        }
+        if (text.length < fitchars) {
+            return text;
+        }
+        if (profile.truncate_text_right) {
+            return text.substring(0, fitchars - 2) + "..";
+        }
+        return ".." + text.substring(text.length - fitchars + 2);
+    }
+
+    function draw() {
+        var height = (depthmax + 1) * fh;
+        ctx.clearRect(0, 0, width + 2 * xpad, height);
+        ctx.font = profile.font_size + "px " + profile.font_type;
+        ctx.textBaseline = "middle";
+        for (var i = 0; i < frames.length; i++) {
+            var f = frames[i];
+            var x = extent(f);
+            if (x === null || x[1] - x[0] < 0.1) {
+                continue;
+            }
+            var y = top(f[DEPTH]);
+            ctx.globalAlpha = isancestor(f) ? 0.5 : 1;
+            ctx.fillStyle = matches && matches[f[NAME]] ? profile.search_color : profile.colors[f[COLOR]];
+            ctx.fillRect(x[0], y, x[1] - x[0], fh - 1);
+            if (i === hovered) {
+                ctx.strokeStyle = "black";
+                ctx.lineWidth = 0.5;
+                ctx.strokeRect(x[0], y, x[1] - x[0], fh - 1);
+            }
+            var text = fittext(label(f), x[1] - x[0]);
+            if (text) {
+                ctx.fillStyle = "black";
+                ctx.fillText(text, x[0] + 3, y + fh / 2);
+            }
+        }
+        ctx.globalAlpha = 1;
+    }
+
+    function resize() {
+        var fullwidth = profile.image_width || graph.clientWidth;
+        var height = (depthmax + 1) * fh;
+        var ratio = window.devicePixelRatio || 1;
+        width = fullwidth - 2 * xpad;
+        canvas.width = fullwidth * ratio;
+        canvas.height = height * ratio;
+        canvas.style.width = fullwidth + "px";
+        canvas.style.height = height + "px";
+        ctx.setTransform(ratio, 0, 0, ratio, 0, 0);
+        draw();
+    }
+
+    function frameat(x, y) {
+        var row = Math.floor(y / fh);
+        var depth = profile.inverted ? row : depthmax - row;
+        if (depth < 0 || depth > depthmax) {
+            return null;
+        }
+        for (var i = depthstart[depth]; i < depthstart[depth + 1]; i++) {
+            var e = extent(frames[i]);
+            if (e !== null && x >= e[0] && x < e[1]) {
+                return i;
+            }
+        }
+        return null;
+    }
+
+    function zoom(i) {
+        var f = frames[i];
+        if (f[DEPTH] === 0) {
+            unzoom();
+            return;
+        }
+        zoomed = i;
+        viewstart = f[START];
+        viewend = f[END];
+        unzoombtn.classList.remove("hide");
+        draw();
+    }
+
+    function unzoom() {
+        zoomed = null;
+        viewstart = 0;
+        viewend = profile.total;
+        unzoombtn.classList.add("hide");
+        draw();
+    }
+
+    function search(term) {
+        if (!term) {
+            matches = null;
+            matchedtxt.textContent = "";
+            draw();
+            return;
+        }
+        var re;
+        try {
+            re = new RegExp(term);
+        } catch (e) {
+            return;
+        }
+        matches = names.map(function (name) { return re.test(name); });
+
+        // add up the matched samples, without counting frames inside other matched frames twice
+        var spans = [];
+        frames.forEach(function (f) {
+            if (matches[f[NAME]]) {
+                spans.push([f[START], f[END]]);
+            }
+        });
+        spans.sort(function (a, b) { return a[0] - b[0] || b[1] - a[1]; });
+        var count = 0;
+        var end = 0;
+        spans.forEach(function (s) {
+            if (s[1] > end) {
+                count += s[1] - Math.max(s[0], end);
+                end = s[1];
+            }
+        });
+        var pct = (100 * count / profile.total).toFixed(1) + "%";
+        if (profile.unit && profile.unit !== "percent") {
+            matchedtxt.textContent = "Matched: " + format_unit(count * profile.factor) + " (" + pct + ")";
+        } else {
+            matchedtxt.textContent = "Matched: " + pct;
+        }
+        draw();
+    }
+
+    function filltable() {
+        var rows = profile.functions.slice().sort(function (a, b) {
+            return b[sortcolumn] - a[sortcolumn] || a[0] - b[0];
+        }).slice(0, profile.top_functions);
+        var tbody = table.tBodies[0];
+        tbody.textContent = "";
+        rows.forEach(function (row) {
+            var tr = document.createElement("tr");
+            [names[row[0]], percent(row[1]) + "%", percent(row[2]) + "%"].forEach(function (text) {
+                var td = document.createElement("td");
+                td.textContent = text;
+                tr.appendChild(td);
+            });
+            if (profile.unit) {
+                tr.title = amount(row[1]) + " self, " + amount(row[2]) + " total";
+            } else {
+                tr.title = samples(row[1]).toLocaleString("en-US") + " self, " + samples(row[2]).toLocaleString("en-US") + " total " + profile.count_name;
+            }
+            tr.addEventListener("click", function () {
+                searchbox.value = "^" + names[row[0]].replace(/[.*+?^${}()|[\]\\]/g, "\\$&") + "$";
+                search(searchbox.value);
+                window.scrollTo(0, 0);
+            });
+            tbody.appendChild(tr);
+        });
+        Array.prototype.forEach.call(table.tHead.rows[0].cells, function (th) {
+            th.classList.toggle("sorted", Number(th.dataset.sort) === sortcolumn);
+        });
+    }
+
+    canvas.addEventListener("mousemove", function (e) {
+        var rect = canvas.getBoundingClientRect();
+        var i = frameat(e.clientX - rect.left, e.clientY - rect.top);
+        if (i !== hovered) {
+            hovered = i;
+            draw();
+        }
+        if (i === null) {
+            tooltip.classList.add("hide");
+            details.innerHTML = "&nbsp;";
+            return;
+        }
+        details.textContent = profile.name_type + " " + info(i);
+        tooltip.textContent = title(i);
+        tooltip.classList.remove("hide");
+        var x = e.clientX - graph.getBoundingClientRect().left + graph.scrollLeft + 12;
+        if (x + tooltip.offsetWidth > graph.scrollLeft + graph.clientWidth) {
+            x = Math.max(0, x - tooltip.offsetWidth - 24);
+        }
+        tooltip.style.left = x + "px";
+        tooltip.style.top = (e.clientY - rect.top + 16) + "px";
+    });
+    canvas.addEventListener("mouseleave", function () {
+        hovered = null;
+        tooltip.classList.add("hide");
+        details.innerHTML = "&nbsp;";
+        draw();
+    });
+    canvas.addEventListener("click", function (e) {
+        if (hovered === null) {
+            return;
+        }
+        var a = attrs[frames[hovered][NAME]];
+        if (a && a.href && (e.ctrlKey || e.metaKey)) {
+            window.open(a.href, a.target || "_top");
+            return;
+        }
+        zoom(hovered);
+    });
+    unzoombtn.addEventListener("click", unzoom);
+    searchbox.addEventListener("input", function () { search(searchbox.value); });
+    table.tHead.addEventListener("click", function (e) {
+        if (e.target.dataset.sort) {
+            sortcolumn = Number(e.target.dataset.sort);
+            filltable();
+        }
+    });
+    window.addEventListener("keydown", function (e) {
+        if ((e.key === "/" && e.target !== searchbox) || (e.key === "f" && (e.ctrlKey || e.metaKey))) {
+            e.preventDefault();
+            searchbox.focus();
+            searchbox.select();
+        } else if (e.key === "Escape") {
+            searchbox.value = "";
+            search("");
+            unzoom();
+        }
+    });
+    window.addEventListener("resize", resize);
+
+    filltable();
+    resize();
+})();
@@ -150,7 +150,7 @@ macro_rules! u8_from_hex_iter {
     };
 }
 
-fn parse_flat_bgcolor(s: &str) -> Option<Color> {
+pub(super) fn parse_flat_bgcolor(s: &str) -> Option<Color> {
     if !s.starts_with('#') || (s.len() != 7) {
         None
     } else {
@@ -390,6 +390,17 @@ pub(super) fn color_scale(value: isize, max: usize) -> Color {
     }
 }
 
+/// Like [`color_scale`], but leaves the changes whose significance `score` falls short of
+/// `threshold` uncolored, so that only the differences that stand out from the noise between runs
+/// are highlighted.
+pub(super) fn significance_scale(value: isize, max: usize, score: f64, threshold: f64) -> Color {
+    if score.abs() < threshold {
+        color_scale(0, max)
+    } else {
+        color_scale(value, max)
+    }
+}
+
 fn default_bg_color_for(palette: Palette) -> BackgroundColor {
     match palette {
         Palette::Basic(BasicPalette::Mem) => BackgroundColor::Green,
@@ -438,6 +449,7 @@ mod tests {
     use super::namehash;
     use super::parse_flat_bgcolor;
     use super::Color;
+    use super::{color_scale, significance_scale};
     use pretty_assertions::assert_eq;
 
     #[test]
@@ -492,4 +504,11 @@ mod tests {
         test_hash!("``0xfffffffffb8001d6", 0.418_131_17);
         test_hash!("", 1.0);
     }
+
+    #[test]
+    fn significance_scale_test() {
+        assert_eq!(significance_scale(5, 10, 1.5, 2.0), color_scale(0, 10));
+        assert_eq!(significance_scale(5, 10, 2.5, 2.0), color_scale(5, 10));
+        assert_eq!(significance_scale(-5, 10, -2.5, 2.0), color_scale(-5, 10));
+    }
 }
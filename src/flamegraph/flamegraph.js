@@ -394,7 +394,29 @@ function search(term) {
     matchedtxt.classList.remove("hide");
     var pct = 100 * count / maxwidth;
     if (pct != 100) pct = pct.toFixed(1);
-    matchedtxt.firstChild.nodeValue = "Matched: " + pct + "%";
+    if (typeof unit == "undefined") {
+        matchedtxt.firstChild.nodeValue = "Matched: " + pct + "%";
+    } else {
+        matchedtxt.firstChild.nodeValue = "Matched: " + format_unit(unittotal * count / maxwidth) + " (" + pct + "%)";
+    }
+}
+function format_unit(n) {
+    var step = 1000;
+    var units = ["ns", "\u00b5s", "ms", "s"];
+    if (unit == "bytes") {
+        step = 1024;
+        units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    } else if (unit == "us") {
+        n *= 1e3;
+    } else if (unit == "ms") {
+        n *= 1e6;
+    }
+    var i = 0;
+    while (n >= step && i < units.length - 1) {
+        n /= step;
+        i++;
+    }
+    return (i == 0 ? Math.round(n) : n.toFixed(2)) + " " + units[i];
 }
 function format_percent(n) {
     return n.toFixed(4) + "%";
@@ -0,0 +1,364 @@
+use std::borrow::Cow;
+use std::io::{self, prelude::*};
+
+use ahash::AHashMap;
+use log::error;
+use serde::Serialize;
+
+use super::merge::TimedFrame;
+use super::{color, Direction, Options, TextTruncateDirection, Unit};
+use crate::folded::Location;
+
+/// The number of functions listed in the table below the flame graph.
+const TOP_FUNCTIONS: usize = 100;
+
+/// Everything the page needs to draw the flame graph, embedded into it as JSON.
+#[derive(Serialize)]
+struct Profile<'a> {
+    title: Cow<'a, str>,
+    subtitle: Option<&'a str>,
+    notes: &'a str,
+    name_type: &'a str,
+    count_name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<&'static str>,
+    factor: f64,
+    total: usize,
+    inverted: bool,
+    truncate_text_right: bool,
+    image_width: Option<usize>,
+    frame_height: usize,
+    font_type: &'a str,
+    font_size: usize,
+    font_width: f64,
+    search_color: String,
+    background: (Cow<'a, str>, Cow<'a, str>),
+    top_functions: usize,
+
+    /// Function names with their annotations removed, indexed by name id.
+    names: Vec<&'a str>,
+
+    /// Frame colors, indexed by color id.
+    colors: Vec<String>,
+
+    /// `[name id, depth, start, end, color id]` for each frame, sorted by depth and then start.
+    frames: Vec<(usize, usize, usize, usize, usize)>,
+
+    /// The difference in samples of each frame, in differential flame graphs.
+    deltas: Option<Vec<isize>>,
+
+    /// `[name id, self samples, total samples]` for each function.
+    functions: Vec<(usize, usize, usize)>,
+
+    /// `[name id, attributes]` for the functions that have attributes.
+    attrs: Vec<(usize, Attrs<'a>)>,
+
+    /// `[name id, file:line]` for the functions that have a source location.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    locations: Vec<(usize, String)>,
+}
+
+/// The attributes of a function that carry over from [`FuncFrameAttrsMap`](super::FuncFrameAttrsMap).
+#[derive(Serialize)]
+struct Attrs<'a> {
+    title: Option<&'a str>,
+    href: Option<Cow<'a, str>>,
+    target: Option<&'a str>,
+}
+
+/// The samples of a function, summed over all the frames it appears in.
+#[derive(Default)]
+struct FunctionTime {
+    self_time: usize,
+
+    /// Only counts the outermost frame of recursive calls.
+    total_time: usize,
+}
+
+pub(super) fn write_html<W>(
+    opt: &mut Options<'_>,
+    mut frames: Vec<TimedFrame<'_>>,
+    time: usize,
+    delta_max: usize,
+    mut writer: W,
+) -> quick_xml::Result<()>
+where
+    W: Write,
+{
+    if time == 0 {
+        error!("No stack counts found");
+        // emit an error message page, for tools automating flamegraph use
+        write_head(&mut writer, opt)?;
+        writer.write_all(
+            b"<p>ERROR: No valid input provided to flamegraph</p>\n</body>\n</html>\n",
+        )?;
+        return Err(quick_xml::Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No stack counts found",
+        )));
+    }
+
+    // The table covers all the frames, including those too narrow to draw.
+    let function_times = function_times(&frames);
+
+    // prune blocks that are too narrow
+    let widthpertime_pct = 100.0 / time as f64;
+    let minwidth_time = opt.min_width / widthpertime_pct;
+    frames.retain(|frame| ((frame.end_time - frame.start_time) as f64) >= minwidth_time);
+    frames.sort_by_key(|frame| (frame.location.depth, frame.start_time));
+
+    let mut name_ids = AHashMap::default();
+    let mut names = Vec::new();
+    let mut color_ids = AHashMap::default();
+    let mut colors = Vec::new();
+    let mut frame_data = Vec::with_capacity(frames.len());
+    let mut thread_rng = super::rand::thread_rng();
+    for frame in &frames {
+        let function = frame.location.function;
+        let name_id = *name_ids.entry(function).or_insert_with(|| {
+            names.push(super::deannotate(function));
+            names.len() - 1
+        });
+
+        let width_pct = (frame.end_time - frame.start_time) as f64 * widthpertime_pct;
+        let color = super::frame_color(opt, frame, width_pct, delta_max, &mut thread_rng);
+        let color_id = *color_ids.entry(color).or_insert_with(|| {
+            colors.push(format!("rgb({},{},{})", color.r, color.g, color.b));
+            colors.len() - 1
+        });
+
+        frame_data.push((
+            name_id,
+            frame.location.depth,
+            frame.start_time,
+            frame.end_time,
+            color_id,
+        ));
+    }
+
+    let deltas = if frames.iter().any(|frame| frame.delta.is_some()) {
+        let sign = if opt.negate_differentials { -1 } else { 1 };
+        Some(
+            frames
+                .iter()
+                .map(|frame| sign * frame.delta.unwrap_or(0))
+                .collect(),
+        )
+    } else {
+        None
+    };
+
+    // Functions whose frames were all pruned are still listed in the table.
+    let mut functions = Vec::with_capacity(function_times.len());
+    for (function, times) in function_times {
+        let name_id = *name_ids.entry(function).or_insert_with(|| {
+            names.push(super::deannotate(function));
+            names.len() - 1
+        });
+        functions.push((name_id, times.self_time, times.total_time));
+    }
+    functions.sort_unstable();
+
+    let mut name_attrs = Vec::new();
+    #[cfg(feature = "nameattr")]
+    for (&function, &name_id) in &name_ids {
+        let frame_attrs = opt.func_frameattrs.frameattrs_for_func(function);
+        let mut attrs = Attrs {
+            title: frame_attrs.and_then(|frame_attrs| frame_attrs.title.as_deref()),
+            href: frame_attrs
+                .and_then(|frame_attrs| frame_attrs.attrs.get("xlink:href"))
+                .map(|href| Cow::Borrowed(href.as_str())),
+            target: frame_attrs
+                .and_then(|frame_attrs| frame_attrs.attrs.get("target"))
+                .map(String::as_str),
+        };
+        if attrs.href.is_none() {
+            attrs.href = opt
+                .func_frameattrs
+                .source_href_for_func(function)
+                .map(Cow::Owned);
+        }
+        // Other attributes only make sense on SVG elements.
+        if attrs.title.is_some() || attrs.href.is_some() {
+            name_attrs.push((name_id, attrs));
+        }
+    }
+    name_attrs.sort_unstable_by_key(|&(name_id, _)| name_id);
+
+    let mut locations: Vec<_> = name_ids
+        .iter()
+        .filter_map(|(&function, &name_id)| {
+            let (_, location) = Location::split(function);
+            location.map(|location| (name_id, location.to_string()))
+        })
+        .collect();
+    locations.sort_unstable();
+
+    let profile = Profile {
+        title: super::title(opt, time),
+        subtitle: opt.subtitle.as_deref(),
+        notes: &opt.notes,
+        name_type: &opt.name_type,
+        count_name: &opt.count_name,
+        unit: match opt.unit {
+            Unit::Samples => None,
+            unit => Some(unit.name()),
+        },
+        factor: opt.factor,
+        total: time,
+        inverted: opt.direction == Direction::Inverted,
+        truncate_text_right: opt.text_truncate_direction == TextTruncateDirection::Right,
+        image_width: opt.image_width,
+        frame_height: opt.frame_height,
+        font_type: &opt.font_type,
+        font_size: opt.font_size,
+        font_width: opt.font_width,
+        search_color: opt.search_color.to_string(),
+        background: color::bgcolor_for(opt.bgcolors, opt.colors),
+        top_functions: TOP_FUNCTIONS,
+        names,
+        colors,
+        frames: frame_data,
+        deltas,
+        functions,
+        attrs: name_attrs,
+        locations,
+    };
+    let json = if opt.pretty_xml {
+        serde_json::to_string_pretty(&profile)
+    } else {
+        serde_json::to_string(&profile)
+    }
+    .map_err(io::Error::from)?;
+
+    write_head(&mut writer, opt)?;
+    writer.write_all(
+        br#"<div id="controls">
+<button id="unzoom" class="hide">Reset Zoom</button>
+<span class="spacer"></span>
+<span id="matched"></span>
+<input id="search" type="search" placeholder="Search (regex)" title="Search (/ or Ctrl+F)">
+</div>
+<div id="graph"><canvas id="canvas"></canvas><div id="tooltip" class="hide"></div></div>
+<div id="details">&nbsp;</div>
+<table id="functions">
+<thead><tr><th>"#,
+    )?;
+    writer.write_all(&escape(opt.name_type.trim_end_matches(':')))?;
+    writer.write_all(
+        br#"</th><th data-sort="1">Self</th><th data-sort="2">Total</th></tr></thead>
+<tbody></tbody>
+</table>
+<script type="application/json" id="profile">
+"#,
+    )?;
+    // `<` can only appear inside JSON strings, where it can be escaped so that the JSON never
+    // closes the script element.
+    writer.write_all(json.replace('<', "\\u003c").as_bytes())?;
+    writer.write_all(b"\n</script>\n")?;
+    if !opt.no_javascript {
+        writer.write_all(b"<script>\n")?;
+        writer.write_all(include_str!("canvas.js").as_bytes())?;
+        writer.write_all(b"</script>\n")?;
+    }
+    writer.write_all(b"</body>\n</html>\n")?;
+
+    Ok(())
+}
+
+/// Writes everything up to and including the title and subtitle.
+fn write_head<W>(writer: &mut W, opt: &Options<'_>) -> io::Result<()>
+where
+    W: Write,
+{
+    let title = escape(&opt.title);
+    writer.write_all(b"<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n")?;
+    writer.write_all(b"<title>")?;
+    writer.write_all(&title)?;
+    writer.write_all(b"</title>\n<style>\n")?;
+    writer.write_all(include_str!("canvas.css").as_bytes())?;
+    writer.write_all(b"</style>\n</head>\n<body>\n<h1 id=\"title\">")?;
+    writer.write_all(&title)?;
+    writer.write_all(b"</h1>\n")?;
+    if let Some(ref subtitle) = opt.subtitle {
+        writer.write_all(b"<h2 id=\"subtitle\">")?;
+        writer.write_all(&escape(subtitle))?;
+        writer.write_all(b"</h2>\n")?;
+    }
+    Ok(())
+}
+
+fn escape(s: &str) -> Cow<'_, [u8]> {
+    quick_xml::escape::escape(s.as_bytes())
+}
+
+/// Sums the self and total samples of each function over all of its frames.
+fn function_times<'a>(frames: &[TimedFrame<'a>]) -> AHashMap<&'a str, FunctionTime> {
+    // Visiting frames by start time, and parents before children, means the frames that enclose
+    // a frame are the last ones visited at each lower depth.
+    let mut order: Vec<_> = frames.iter().collect();
+    order.sort_by_key(|frame| (frame.start_time, frame.location.depth));
+
+    let mut times: AHashMap<_, FunctionTime> = AHashMap::default();
+    let mut ancestors: Vec<&TimedFrame<'a>> = Vec::new();
+    for frame in order {
+        let function = frame.location.function;
+        let samples = frame.end_time - frame.start_time;
+        ancestors.truncate(frame.location.depth);
+        if let Some(parent) = ancestors.last() {
+            if let Some(parent_time) = times.get_mut(parent.location.function) {
+                parent_time.self_time -= samples;
+            }
+        }
+
+        let recursive = ancestors
+            .iter()
+            .any(|ancestor| ancestor.location.function == function);
+        let time = times.entry(function).or_default();
+        time.self_time += samples;
+        if !recursive {
+            time.total_time += samples;
+        }
+        ancestors.push(frame);
+    }
+
+    // The root frame isn't a function.
+    times.remove("");
+    times
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::merge::{Frame, TimedFrame};
+    use super::function_times;
+
+    fn frame(function: &str, depth: usize, start_time: usize, end_time: usize) -> TimedFrame<'_> {
+        TimedFrame {
+            location: Frame { function, depth },
+            start_time,
+            end_time,
+            delta: None,
+            score: None,
+        }
+    }
+
+    #[test]
+    fn function_times_skips_recursion() {
+        // main;a;b;a 3
+        // main;b 2
+        // main 1
+        let frames = vec![
+            frame("a", 4, 0, 3),
+            frame("b", 3, 0, 3),
+            frame("a", 2, 0, 3),
+            frame("b", 2, 3, 5),
+            frame("main", 1, 0, 6),
+            frame("", 0, 0, 6),
+        ];
+        let times = function_times(&frames);
+        assert_eq!(times.len(), 3);
+        assert_eq!((times["main"].self_time, times["main"].total_time), (1, 6));
+        assert_eq!((times["a"].self_time, times["a"].total_time), (3, 3));
+        assert_eq!((times["b"].self_time, times["b"].total_time), (2, 5));
+    }
+}
@@ -0,0 +1,333 @@
+use std::io::{self, prelude::*};
+
+use ahash::AHashMap;
+use log::error;
+use serde::ser::{Serialize, Serializer};
+
+use super::merge::TimedFrame;
+use super::{deannotate, Options, OutputFormat};
+
+/// A node of the tree read by [d3-flame-graph](https://github.com/spiermar/d3-flame-graph).
+#[derive(serde::Serialize)]
+struct Node<'a> {
+    name: &'a str,
+    value: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta: Option<Value>,
+    children: Vec<Node<'a>>,
+}
+
+/// A [speedscope](https://www.speedscope.app) file with a single sampled profile.
+#[derive(serde::Serialize)]
+struct Speedscope<'a> {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    name: &'a str,
+    exporter: &'static str,
+    #[serde(rename = "activeProfileIndex")]
+    active_profile_index: usize,
+    shared: Shared<'a>,
+    profiles: [SampledProfile<'a>; 1],
+}
+
+#[derive(serde::Serialize)]
+struct Shared<'a> {
+    frames: Vec<SpeedscopeFrame<'a>>,
+}
+
+#[derive(serde::Serialize)]
+struct SpeedscopeFrame<'a> {
+    name: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct SampledProfile<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    name: &'a str,
+    unit: &'static str,
+    #[serde(rename = "startValue")]
+    start_value: Value,
+    #[serde(rename = "endValue")]
+    end_value: Value,
+    /// The indices into the shared frame table of each stack, root first.
+    samples: Vec<Vec<usize>>,
+    weights: Vec<Value>,
+}
+
+/// A sample count scaled by [`Options::factor`], written as an integer when it is one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Value(f64);
+
+impl Value {
+    fn scaled(samples: isize, opt: &Options<'_>) -> Self {
+        // Rounding hides the error of fractional factors (e.g., 3 * 0.1 = 0.30000000000000004).
+        Value((samples as f64 * opt.factor * 1e9).round() / 1e9)
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if self.0.fract() == 0.0 && self.0.abs() < (1u64 << 53) as f64 {
+            serializer.serialize_i64(self.0 as i64)
+        } else {
+            serializer.serialize_f64(self.0)
+        }
+    }
+}
+
+pub(super) fn write_json<W>(
+    opt: &Options<'_>,
+    mut frames: Vec<TimedFrame<'_>>,
+    time: usize,
+    writer: W,
+) -> quick_xml::Result<()>
+where
+    W: Write,
+{
+    if time == 0 {
+        error!("No stack counts found");
+        return Err(quick_xml::Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No stack counts found",
+        )));
+    }
+
+    // Visit the frames by start time, and parents before children, which is the order they
+    // appear in the output.
+    frames.sort_by_key(|frame| (frame.start_time, frame.location.depth));
+
+    let result = match opt.format {
+        OutputFormat::Speedscope => write_value(opt, &speedscope(opt, &frames, time), writer),
+        _ => write_value(opt, &d3_tree(opt, &frames), writer),
+    };
+    result.map_err(|e| quick_xml::Error::Io(e.into()))
+}
+
+fn write_value<T, W>(opt: &Options<'_>, value: &T, writer: W) -> serde_json::Result<()>
+where
+    T: Serialize,
+    W: Write,
+{
+    if opt.pretty_xml {
+        serde_json::to_writer_pretty(writer, value)
+    } else {
+        serde_json::to_writer(writer, value)
+    }
+}
+
+/// Nests the frames, which must be sorted by start time and then depth.
+fn d3_tree<'a>(opt: &Options<'_>, frames: &[TimedFrame<'a>]) -> Node<'a> {
+    fn pop_into_parent(stack: &mut Vec<Node<'_>>) {
+        let node = stack.pop().expect("only popped below the root");
+        stack
+            .last_mut()
+            .expect("only popped below the root")
+            .children
+            .push(node);
+    }
+
+    // The nodes enclosing the current frame, by depth.
+    let mut stack: Vec<Node<'a>> = Vec::new();
+    for frame in frames {
+        while stack.len() > frame.location.depth {
+            pop_into_parent(&mut stack);
+        }
+
+        let samples = (frame.end_time - frame.start_time) as isize;
+        let delta = frame.delta.map(|delta| {
+            let delta = if opt.negate_differentials {
+                -delta
+            } else {
+                delta
+            };
+            Value::scaled(delta, opt)
+        });
+        let node = Node {
+            name: if frame.location.depth == 0 {
+                "all"
+            } else {
+                deannotate(frame.location.function)
+            },
+            value: Value::scaled(samples, opt),
+            delta,
+            children: Vec::new(),
+        };
+        stack.push(node);
+    }
+    while stack.len() > 1 {
+        pop_into_parent(&mut stack);
+    }
+
+    stack.pop().expect("there is a root frame when time > 0")
+}
+
+/// Turns the frames, which must be sorted by start time and then depth, back into the stacks
+/// they were merged from.
+fn speedscope<'a>(opt: &'a Options<'_>, frames: &[TimedFrame<'a>], time: usize) -> Speedscope<'a> {
+    // The root frame is not in the frame table.
+    const ROOT: usize = usize::MAX;
+
+    let mut frame_ids = AHashMap::default();
+    let mut shared_frames = Vec::new();
+    let mut samples = Vec::new();
+    let mut weights = Vec::new();
+
+    // The frames enclosing the current one, by depth, as their frame id, end time, and the time
+    // up to which their samples have been written out.
+    let mut stack: Vec<(usize, usize, usize)> = Vec::new();
+
+    // Writes out the samples of the top frame of `stack` between its cursor and `until`, which
+    // are the samples that aren't in any of its children.
+    let mut write_self_time = |stack: &mut Vec<(usize, usize, usize)>, until: usize| {
+        let cursor = match stack.last() {
+            Some(&(_, _, cursor)) if until > cursor => cursor,
+            _ => return,
+        };
+        // The root frame isn't part of the stacks.
+        if stack.len() > 1 {
+            samples.push(stack[1..].iter().map(|&(id, _, _)| id).collect());
+            weights.push(Value::scaled((until - cursor) as isize, opt));
+        }
+        stack.last_mut().expect("stack is not empty").2 = until;
+    };
+
+    for frame in frames {
+        while stack.len() > frame.location.depth {
+            let end = stack[stack.len() - 1].1;
+            write_self_time(&mut stack, end);
+            stack.pop();
+        }
+        write_self_time(&mut stack, frame.start_time);
+        if let Some(parent) = stack.last_mut() {
+            parent.2 = frame.end_time;
+        }
+
+        let function = frame.location.function;
+        let id = if frame.location.depth == 0 {
+            ROOT
+        } else {
+            *frame_ids.entry(function).or_insert_with(|| {
+                shared_frames.push(SpeedscopeFrame {
+                    name: deannotate(function),
+                });
+                shared_frames.len() - 1
+            })
+        };
+        stack.push((id, frame.end_time, frame.start_time));
+    }
+    while !stack.is_empty() {
+        let end = stack[stack.len() - 1].1;
+        write_self_time(&mut stack, end);
+        stack.pop();
+    }
+
+    Speedscope {
+        schema: "https://www.speedscope.app/file-format-schema.json",
+        name: &opt.title,
+        exporter: "inferno",
+        active_profile_index: 0,
+        shared: Shared {
+            frames: shared_frames,
+        },
+        profiles: [SampledProfile {
+            kind: "sampled",
+            name: &opt.title,
+            unit: "none",
+            start_value: Value(0.0),
+            end_value: Value::scaled(time as isize, opt),
+            samples,
+            weights,
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::super::{merge, Options};
+    use super::*;
+
+    fn frames(lines: &[&'static str]) -> (Vec<TimedFrame<'static>>, usize) {
+        let (mut frames, time, _, _) = merge::frames(lines.iter().copied(), false).unwrap();
+        frames.sort_by_key(|frame| (frame.start_time, frame.location.depth));
+        (frames, time)
+    }
+
+    #[test]
+    fn d3_tree_nests_frames() {
+        let (frames, _) = frames(&["main;a 1", "main;a;b 2", "main;c 3"]);
+        let tree = serde_json::to_value(d3_tree(&Options::default(), &frames)).unwrap();
+        assert_eq!(
+            tree,
+            serde_json::json!({
+                "name": "all",
+                "value": 6,
+                "children": [{
+                    "name": "main",
+                    "value": 6,
+                    "children": [
+                        {
+                            "name": "a",
+                            "value": 3,
+                            "children": [{ "name": "b", "value": 2, "children": [] }],
+                        },
+                        { "name": "c", "value": 3, "children": [] },
+                    ],
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn speedscope_samples_are_the_merged_stacks() {
+        let lines = [
+            "main 1",
+            "main;a 2",
+            "main;a;b 3",
+            "main;b;a 4",
+            "other_[k] 5",
+        ];
+        let (frames, time) = frames(&lines);
+        let opt = Options {
+            factor: 0.5,
+            ..Default::default()
+        };
+        let profile = speedscope(&opt, &frames, time);
+
+        let names: Vec<_> = profile.shared.frames.iter().map(|f| f.name).collect();
+        assert_eq!(names, vec!["main", "a", "b", "other"]);
+
+        let stacks: Vec<_> = profile.profiles[0]
+            .samples
+            .iter()
+            .zip(&profile.profiles[0].weights)
+            .map(|(sample, weight)| {
+                let names: Vec<_> = sample.iter().map(|&id| names[id]).collect();
+                (names.join(";"), weight.0)
+            })
+            .collect();
+        assert_eq!(
+            stacks,
+            vec![
+                ("main".to_string(), 0.5),
+                ("main;a".to_string(), 1.0),
+                ("main;a;b".to_string(), 1.5),
+                ("main;b;a".to_string(), 2.0),
+                ("other".to_string(), 2.5),
+            ]
+        );
+        assert_eq!(profile.profiles[0].end_value, Value(7.5));
+    }
+
+    #[test]
+    fn value_is_written_as_integer_when_possible() {
+        assert_eq!(serde_json::to_string(&Value(3.0)).unwrap(), "3");
+        assert_eq!(serde_json::to_string(&Value(-2.0)).unwrap(), "-2");
+        assert_eq!(serde_json::to_string(&Value(2.5)).unwrap(), "2.5");
+    }
+}
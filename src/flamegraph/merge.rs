@@ -16,12 +16,14 @@ pub(super) struct TimedFrame<'a> {
     pub(super) start_time: usize,
     pub(super) end_time: usize,
     pub(super) delta: Option<isize>,
+    pub(super) score: Option<f64>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub(super) struct FrameTime {
     pub(super) start_time: usize,
     pub(super) delta: Option<isize>,
+    pub(super) score: Option<f64>,
 }
 
 fn flow<'a, LI, TI>(
@@ -31,6 +33,7 @@ fn flow<'a, LI, TI>(
     this: TI,
     time: usize,
     delta: Option<isize>,
+    score: Option<f64>,
 ) where
     LI: IntoIterator<Item = &'a str>,
     TI: IntoIterator<Item = &'a str>,
@@ -70,6 +73,7 @@ fn flow<'a, LI, TI>(
             start_time: frame_time.start_time,
             end_time: time,
             delta: frame_time.delta,
+            score: frame_time.score,
         };
         frames.push(frame);
     }
@@ -87,11 +91,13 @@ fn flow<'a, LI, TI>(
             Some(_) if !is_last => Some(0),
             d => d,
         };
+        let score = if is_last { score } else { None };
         let frame_time = FrameTime {
             start_time: time,
             // For some reason the Perl version does a `+=` for `delta`, but I can't figure out why.
             // See https://github.com/brendangregg/FlameGraph/blob/1b1c6deede9c33c5134c920bdb7a44cc5528e9a7/flamegraph.pl#L588
             delta,
+            score,
         };
 
         //eprintln!("stored tmp for time {}: {:?}", time, key);
@@ -139,6 +145,9 @@ where
             }
         }
 
+        // Differentials of several runs per profile end with a significance score.
+        let score = parse_score(&mut line);
+
         // Parse the number of samples for the purpose of computing overall time passed.
         // Usually there will only be one samples column at the end of a line,
         // but for differentials there will be two. When there are two we compute the
@@ -169,7 +178,7 @@ where
         if last.is_empty() {
             // need to special-case this, because otherwise iter("") + "".split(';') == ["", ""]
             //eprintln!("flow(_, {}, {})", stack, time);
-            flow(&mut tmp, &mut frames, None, this, time, delta);
+            flow(&mut tmp, &mut frames, None, this, time, delta, score);
         } else {
             //eprintln!("flow({}, {}, {})", last, stack, time);
             flow(
@@ -179,6 +188,7 @@ where
                 this,
                 time,
                 delta,
+                score,
             );
         }
 
@@ -196,12 +206,56 @@ where
             None,
             time,
             delta,
+            None,
         );
     }
 
     Ok((frames, time, ignored, delta_max))
 }
 
+/// Like `frames`, but for stacks that are already split into frames, sorted, and free of
+/// duplicates.
+pub(super) fn frames_from_stacks<'a>(
+    stacks: &[(Vec<&'a str>, usize)],
+) -> (Vec<TimedFrame<'a>>, usize) {
+    let mut time = 0;
+    let mut tmp = Default::default();
+    let mut frames = Default::default();
+    let mut last: Option<&[&'a str]> = None;
+    for (stack, nsamples) in stacks {
+        // inject empty first-level stack frame to capture "all"
+        let this = iter::once("").chain(stack.iter().copied());
+        match last {
+            None => flow(&mut tmp, &mut frames, None, this, time, None, None),
+            Some(last) => flow(
+                &mut tmp,
+                &mut frames,
+                iter::once("").chain(last.iter().copied()),
+                this,
+                time,
+                None,
+                None,
+            ),
+        }
+        last = Some(stack);
+        time += nsamples;
+    }
+
+    if let Some(last) = last {
+        flow(
+            &mut tmp,
+            &mut frames,
+            iter::once("").chain(last.iter().copied()),
+            None,
+            time,
+            None,
+            None,
+        );
+    }
+
+    (frames, time)
+}
+
 // Parse and remove the number of samples from the end of a line.
 fn parse_nsamples(line: &mut &str, stripped_fractional_samples: &mut bool) -> Option<usize> {
     if let Some((samplesi, doti)) = rfind_samples(line) {
@@ -235,6 +289,31 @@ fn parse_nsamples(line: &mut &str, stripped_fractional_samples: &mut bool) -> Op
     }
 }
 
+// Parse and remove the significance score from the end of a line.
+fn parse_score(line: &mut &str) -> Option<f64> {
+    let scorei = rfind_score(line)?;
+    let score = line[scorei..].parse().ok()?;
+    *line = line[..scorei].trim_end();
+    Some(score)
+}
+
+// Tries to find a significance score, as written by `differential::from_multiple_readers`, at
+// the end of a line.
+//
+// Scores always carry a sign, which sets them apart from sample counts. On success, the index
+// to the score is returned.
+pub(crate) fn rfind_score(line: &str) -> Option<usize> {
+    let scorei = line.rfind(' ')? + 1;
+    let score = &line[scorei..];
+    if !score.starts_with(&['+', '-'][..]) {
+        return None;
+    }
+    match score.parse::<f64>() {
+        Ok(score) if score.is_finite() => Some(scorei),
+        _ => None,
+    }
+}
+
 // Tries to find a sample count at the end of a line.
 //
 // On success, the first value of the returned tuple will be the index to the sample count.
@@ -244,7 +323,7 @@ fn parse_nsamples(line: &mut &str, stripped_fractional_samples: &mut bool) -> Op
 // to the last digit in the sample count.
 //
 // If no sample count is found, `None` will be returned.
-pub(super) fn rfind_samples(line: &str) -> Option<(usize, usize)> {
+pub(crate) fn rfind_samples(line: &str) -> Option<(usize, usize)> {
     let samplesi = line.rfind(' ')? + 1;
     let samples = &line[samplesi..];
     if let Some(doti) = samples.find('.') {
@@ -8,10 +8,17 @@ macro_rules! args {
 mod attrs;
 
 pub mod color;
+mod html;
+mod json;
 mod merge;
 mod rand;
+#[cfg(feature = "raster")]
+mod raster;
 mod svg;
+mod text;
+mod unit;
 
+use std::borrow::Cow;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::{self, BufReader};
@@ -33,7 +40,10 @@ pub use self::attrs::FuncFrameAttrsMap;
 
 pub use self::color::Palette;
 use self::color::{Color, SearchColor};
+pub(crate) use self::merge::{rfind_samples, rfind_score};
 use self::svg::{Dimension, StyleOptions};
+pub use self::unit::Unit;
+use crate::folded::{self, FoldedStacks, Location};
 
 const XPAD: usize = 10; // pad left and right
 const FRAMEPAD: usize = 1; // vertical padding for frames
@@ -150,7 +160,8 @@ pub struct Options<'a> {
 
     /// Width of the flame graph
     ///
-    /// Defaults to None, which means the width will be "fluid".
+    /// Defaults to None, which means the width will be "fluid". [Text](OutputFormat::Text) output
+    /// is measured in columns instead, and is 80 columns wide by default.
     pub image_width: Option<usize>,
 
     /// Height of each frame.
@@ -186,6 +197,13 @@ pub struct Options<'a> {
     /// [Default value](defaults::COUNT_NAME).
     pub count_name: String,
 
+    /// The unit of the sample counts.
+    ///
+    /// Other than [samples](Unit::Samples), which is the default, the counts are shown scaled to
+    /// a readable unit in the tooltips and in the text of search matches, and the total is added
+    /// to the title.
+    pub unit: Unit,
+
     /// Name type label for the flame graph.
     ///
     /// [Default value](defaults::NAME_TYPE).
@@ -201,6 +219,16 @@ pub struct Options<'a> {
     /// [differential]: http://www.brendangregg.com/blog/2014-11-09/differential-flame-graphs.html
     pub negate_differentials: bool,
 
+    /// Only color the differences of stacks whose significance score is at least this large,
+    /// in either direction. Other stacks are drawn as if they hadn't changed.
+    ///
+    /// Scores are written by [`differential::from_multiple_readers`] when comparing several runs
+    /// of each profile. Stacks without a score are colored as usual, and so is everything if this
+    /// is `None`, which is the default.
+    ///
+    /// [`differential::from_multiple_readers`]: crate::differential::from_multiple_readers
+    pub significance_threshold: Option<f64>,
+
     /// Factor to scale sample counts by in the flame graph.
     ///
     /// This option can be useful if the sample data has fractional sample counts since the fractional
@@ -214,6 +242,8 @@ pub struct Options<'a> {
     pub factor: f64,
 
     /// Pretty print XML with newlines and indentation.
+    ///
+    /// In [HTML](OutputFormat::Html) output, this pretty prints the embedded JSON instead.
     pub pretty_xml: bool,
 
     /// Don't sort the input lines.
@@ -249,6 +279,10 @@ pub struct Options<'a> {
     ///
     /// Note that stack is not sorted and will be reversed
     pub flame_chart: bool,
+    /// The kind of document to produce.
+    ///
+    /// Defaults to an [SVG](OutputFormat::Svg) image.
+    pub format: OutputFormat,
 }
 
 impl<'a> Options<'a> {
@@ -294,6 +328,7 @@ impl<'a> Default for Options<'a> {
             font_width: defaults::FONT_WIDTH,
             text_truncate_direction: Default::default(),
             count_name: defaults::COUNT_NAME.to_string(),
+            unit: Default::default(),
             name_type: defaults::NAME_TYPE.to_string(),
             factor: defaults::FACTOR,
             image_width: Default::default(),
@@ -305,12 +340,14 @@ impl<'a> Default for Options<'a> {
             palette_map: Default::default(),
             direction: Default::default(),
             negate_differentials: Default::default(),
+            significance_threshold: Default::default(),
             pretty_xml: Default::default(),
             no_sort: Default::default(),
             reverse_stack_order: Default::default(),
             no_javascript: Default::default(),
             color_diffusion: Default::default(),
             flame_chart: Default::default(),
+            format: Default::default(),
 
             #[cfg(feature = "nameattr")]
             func_frameattrs: Default::default(),
@@ -354,6 +391,70 @@ impl Default for TextTruncateDirection {
     }
 }
 
+/// The kind of document a flame graph is written as.
+///
+/// More formats may be added, and some are only available with a feature, such as
+/// `Png` with `raster`, so matches on this need a catch-all arm.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[non_exhaustive]
+pub enum OutputFormat {
+    /// An SVG image with embedded JavaScript for zooming and searching.
+    #[default]
+    Svg,
+
+    /// A self-contained HTML page that draws the flame graph on a `<canvas>`.
+    ///
+    /// The merged frames are embedded as JSON, and the page adds a tooltip and a table of the
+    /// functions with the most samples to the zooming and searching of the SVG. This stays
+    /// responsive for graphs that have too many frames for an SVG.
+    Html,
+
+    /// The tree of frames as nested `{"name", "value", "children"}` JSON objects, as read by
+    /// [d3-flame-graph](https://github.com/spiermar/d3-flame-graph).
+    ///
+    /// The root object is the `all` frame. Each `value` is the number of samples of the frame,
+    /// including those of its children. In differential flame graphs, objects also have a
+    /// `delta`.
+    Json,
+
+    /// A [speedscope](https://www.speedscope.app) file with a single sampled profile that has
+    /// one sample for each of the stacks the flame graph was merged from.
+    ///
+    /// Speedscope has no notion of differentials, so only the second sample counts are used.
+    Speedscope,
+
+    /// Text for a terminal, with one line for each depth and frames colored with ANSI escape
+    /// codes (using 24-bit colors).
+    ///
+    /// The [width](Options::image_width) is a number of columns.
+    Text,
+
+    /// A PNG image of the frames, titles, and labels that the SVG would draw.
+    ///
+    /// This is rendered in Rust, without a browser, using a bundled copy of the Noto Sans font
+    /// instead of the [font type](Options::font_type). There is no JavaScript, so no tooltips,
+    /// zooming, or searching. Only available with the `raster` feature.
+    #[cfg(feature = "raster")]
+    Png,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "svg" => Ok(OutputFormat::Svg),
+            "html" => Ok(OutputFormat::Html),
+            "json" => Ok(OutputFormat::Json),
+            "speedscope" => Ok(OutputFormat::Speedscope),
+            "text" => Ok(OutputFormat::Text),
+            #[cfg(feature = "raster")]
+            "png" => Ok(OutputFormat::Png),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
+}
+
 struct Rectangle {
     x1_pct: f64,
     y1: usize,
@@ -382,17 +483,17 @@ impl Rectangle {
 /// flame graph uses the difference between the two sample counts to show how the sample counts for
 /// each stack has changed between the first and second profiling.
 ///
-/// The resulting flame graph will be written out to `writer` in SVG format.
+/// The resulting flame graph will be written out to `writer` in the format given by
+/// [`Options::format`], which is SVG by default.
 ///
 /// [differential flame graph]: http://www.brendangregg.com/blog/2014-11-09/differential-flame-graphs.html
-#[allow(clippy::cognitive_complexity)]
 pub fn from_lines<'a, I, W>(opt: &mut Options<'_>, lines: I, writer: W) -> quick_xml::Result<()>
 where
     I: IntoIterator<Item = &'a str>,
     W: Write,
 {
     let mut reversed = StrStack::new();
-    let (mut frames, time, ignored, delta_max) = if opt.reverse_stack_order {
+    let (frames, time, ignored, delta_max) = if opt.reverse_stack_order {
         if opt.no_sort {
             warn!(
                 "Input lines are always sorted when `reverse_stack_order` is `true`. \
@@ -403,9 +504,12 @@ where
         let mut stack = String::new();
         for line in lines {
             stack.clear();
-            let samples_idx = merge::rfind_samples(line)
-                .map(|(i, _)| i)
+            let line_end = merge::rfind_score(line)
+                .map(|i| line[..i].trim_end().len())
                 .unwrap_or_else(|| line.len());
+            let samples_idx = merge::rfind_samples(&line[..line_end])
+                .map(|(i, _)| i)
+                .unwrap_or(line_end);
             let samples_idx = merge::rfind_samples(&line[..samples_idx - 1])
                 .map(|(i, _)| i)
                 .unwrap_or(samples_idx);
@@ -441,6 +545,74 @@ where
         warn!("Ignored {} lines with invalid format", ignored);
     }
 
+    write_frames(opt, frames, time, delta_max, writer)
+}
+
+/// Produce a flame graph from stacks held in memory.
+///
+/// This draws the same flame graph as [`from_lines`] would for the folded stack lines of
+/// `stacks`, without having to format and parse them. Stacks are always sorted, so the
+/// `flame_chart` and `no_sort` options have no effect.
+///
+/// The resulting flame graph will be written out to `writer` in the format given by
+/// [`Options::format`].
+pub fn from_folded<W>(
+    opt: &mut Options<'_>,
+    stacks: &FoldedStacks,
+    writer: W,
+) -> quick_xml::Result<()>
+where
+    W: Write,
+{
+    if opt.flame_chart {
+        warn!(
+            "Stacks are always sorted when drawing `FoldedStacks`. \
+             The `flame_chart` option is being ignored."
+        );
+    }
+
+    let mut stacks: Vec<(Vec<&str>, usize)> = stacks
+        .iter()
+        .map(|(stack, count)| {
+            let frames = if opt.reverse_stack_order {
+                stack.frames().rev().collect()
+            } else {
+                stack.frames().collect()
+            };
+            (frames, count)
+        })
+        .collect();
+    // Sort the stacks just like their lines would be sorted.
+    stacks.sort_unstable_by(|(a, _), (b, _)| {
+        folded::folded_bytes(a.iter().copied()).cmp(folded::folded_bytes(b.iter().copied()))
+    });
+
+    let (frames, time) = merge::frames_from_stacks(&stacks);
+    write_frames(opt, frames, time, 1, writer)
+}
+
+#[allow(clippy::cognitive_complexity)]
+fn write_frames<W>(
+    opt: &mut Options<'_>,
+    mut frames: Vec<merge::TimedFrame<'_>>,
+    time: usize,
+    delta_max: usize,
+    writer: W,
+) -> quick_xml::Result<()>
+where
+    W: Write,
+{
+    match opt.format {
+        OutputFormat::Svg => {}
+        OutputFormat::Html => return html::write_html(opt, frames, time, delta_max, writer),
+        OutputFormat::Json | OutputFormat::Speedscope => {
+            return json::write_json(opt, frames, time, writer)
+        }
+        OutputFormat::Text => return text::write_text(opt, frames, time, delta_max, writer),
+        #[cfg(feature = "raster")]
+        OutputFormat::Png => return raster::write_png(opt, frames, time, delta_max, writer),
+    }
+
     let mut buffer = StrStack::new();
 
     // let's start writing the svg!
@@ -498,6 +670,8 @@ where
         imageheight,
         bgcolor1,
         bgcolor2,
+        title: title(opt, timemax),
+        total: timemax as f64 * opt.factor,
     };
 
     svg::write_prelude(&mut svg, &style_options, &opt)?;
@@ -527,6 +701,7 @@ where
 
     // draw frames
     let mut samples_txt_buffer = num_format::Buffer::default();
+    let mut amount_txt = String::new();
     for frame in frames {
         let x1_pct = frame.start_time as f64 * widthpertime_pct;
         let x2_pct = frame.end_time as f64 * widthpertime_pct;
@@ -560,27 +735,38 @@ where
         //     `sprintf "%.0f", 3.5` produces "4"
         let samples = ((frame.end_time - frame.start_time) as f64 * opt.factor).round() as usize;
 
-        // add thousands separators to `samples`
-        let _ = samples_txt_buffer.write_formatted(&samples, &Locale::en);
-        let samples_txt = samples_txt_buffer.as_str();
+        // The amount of samples in the frame, followed by a separator, or nothing when only the
+        // percentage is shown.
+        amount_txt.clear();
+        match opt.unit {
+            Unit::Samples => {
+                // add thousands separators to `samples`
+                let _ = samples_txt_buffer.write_formatted(&samples, &Locale::en);
+                amount_txt.push_str(samples_txt_buffer.as_str());
+                amount_txt.push(' ');
+                amount_txt.push_str(&opt.count_name);
+                amount_txt.push_str(", ");
+            }
+            unit => {
+                let value = (frame.end_time - frame.start_time) as f64 * opt.factor;
+                if let Some(value_txt) = unit.format(value) {
+                    amount_txt.push_str(&value_txt);
+                    amount_txt.push_str(", ");
+                }
+            }
+        }
 
         let info = if frame.location.function.is_empty() && frame.location.depth == 0 {
-            write!(buffer, "all ({} {}, 100%)", samples_txt, opt.count_name)
+            write!(buffer, "all ({}100%)", amount_txt)
         } else {
             let pct = (100 * samples) as f64 / (timemax as f64 * opt.factor);
-            let function = deannotate(&frame.location.function);
+            let function = tooltip_name(frame.location.function);
             match frame.delta {
-                None => write!(
-                    buffer,
-                    "{} ({} {}, {:.2}%)",
-                    function, samples_txt, opt.count_name, pct
-                ),
+                None => write!(buffer, "{} ({}{:.2}%)", function, amount_txt, pct),
                 // Special case delta == 0 so we don't format percentage with a + sign.
-                Some(delta) if delta == 0 => write!(
-                    buffer,
-                    "{} ({} {}, {:.2}%; 0.00%)",
-                    function, samples_txt, opt.count_name, pct,
-                ),
+                Some(delta) if delta == 0 => {
+                    write!(buffer, "{} ({}{:.2}%; 0.00%)", function, amount_txt, pct,)
+                }
                 Some(mut delta) => {
                     if opt.negate_differentials {
                         delta = -delta;
@@ -588,8 +774,8 @@ where
                     let delta_pct = (100 * delta) as f64 / (timemax as f64 * opt.factor);
                     write!(
                         buffer,
-                        "{} ({} {}, {:.2}%; {:+.2}%)",
-                        function, samples_txt, opt.count_name, pct, delta_pct
+                        "{} ({}{:.2}%; {:+.2}%)",
+                        function, amount_txt, pct, delta_pct
                     )
                 }
             }
@@ -609,38 +795,7 @@ where
         svg.write_event(Event::End(BytesEnd::borrowed(b"title")))?;
 
         // select the color of the rectangle
-        let color = if frame.location.function == "--" {
-            color::VDGREY
-        } else if frame.location.function == "-" {
-            color::DGREY
-        } else if opt.color_diffusion {
-            // We want to visually highlight high priority regions for
-            // optimization: wider frames are redder. Typically when optimizing,
-            // a frame that is 50% of width is high priority, so it seems wrong
-            // to give it half the saturation of 100%. So we use sqrt to make
-            // the red dropoff less linear.
-            color::color_scale((((x2_pct - x1_pct) / 100.0).sqrt() * 2000.0) as isize, 2000)
-        } else if let Some(mut delta) = frame.delta {
-            if opt.negate_differentials {
-                delta = -delta;
-            }
-            color::color_scale(delta, delta_max)
-        } else if let Some(ref mut palette_map) = opt.palette_map {
-            let colors = opt.colors;
-            let hash = opt.hash;
-            let deterministic = opt.deterministic;
-            palette_map.find_color_for(&frame.location.function, |name| {
-                color::color(colors, hash, deterministic, name, &mut thread_rng)
-            })
-        } else {
-            color::color(
-                opt.colors,
-                opt.hash,
-                opt.deterministic,
-                frame.location.function,
-                &mut thread_rng,
-            )
-        };
+        let color = frame_color(opt, &frame, x2_pct - x1_pct, delta_max, &mut thread_rng);
         filled_rectangle(&mut svg, &mut buffer, &rect, color, &mut cache_rect)?;
 
         let fitchars = (rect.width_pct() as f64
@@ -696,6 +851,49 @@ where
     Ok(())
 }
 
+/// Selects the color of a frame that is `width_pct` percent of the full width of the graph.
+fn frame_color(
+    opt: &mut Options<'_>,
+    frame: &merge::TimedFrame<'_>,
+    width_pct: f64,
+    delta_max: usize,
+    mut rng: impl FnMut() -> f32,
+) -> Color {
+    // Frames of the same function have the same color, whatever line they are at.
+    let (function, _) = Location::split(frame.location.function);
+    if function == "--" {
+        color::VDGREY
+    } else if function == "-" {
+        color::DGREY
+    } else if opt.color_diffusion {
+        // We want to visually highlight high priority regions for
+        // optimization: wider frames are redder. Typically when optimizing,
+        // a frame that is 50% of width is high priority, so it seems wrong
+        // to give it half the saturation of 100%. So we use sqrt to make
+        // the red dropoff less linear.
+        color::color_scale(((width_pct / 100.0).sqrt() * 2000.0) as isize, 2000)
+    } else if let Some(mut delta) = frame.delta {
+        if opt.negate_differentials {
+            delta = -delta;
+        }
+        match (opt.significance_threshold, frame.score) {
+            (Some(threshold), Some(score)) => {
+                color::significance_scale(delta, delta_max, score, threshold)
+            }
+            _ => color::color_scale(delta, delta_max),
+        }
+    } else if let Some(ref mut palette_map) = opt.palette_map {
+        let colors = opt.colors;
+        let hash = opt.hash;
+        let deterministic = opt.deterministic;
+        palette_map.find_color_for(function, |name| {
+            color::color(colors, hash, deterministic, name, &mut rng)
+        })
+    } else {
+        color::color(opt.colors, opt.hash, opt.deterministic, function, &mut rng)
+    }
+}
+
 #[cfg(feature = "nameattr")]
 fn write_container_start<'a, W: Write>(
     opt: &'a Options<'a>,
@@ -708,9 +906,39 @@ fn write_container_start<'a, W: Write>(
     let frame_attributes = opt
         .func_frameattrs
         .frameattrs_for_func(frame.location.function);
+    let source_href = match frame_attributes {
+        Some(frame_attributes) if frame_attributes.attrs.contains_key("xlink:href") => None,
+        _ => opt
+            .func_frameattrs
+            .source_href_for_func(frame.location.function),
+    };
 
     let mut has_href = false;
-    if let Some(frame_attributes) = frame_attributes {
+    if let Some(href) = source_href {
+        // link the frame to its source
+        if let Event::Start(ref mut c) = cache_a {
+            c.clear_attributes();
+            let mut has_target = false;
+            if let Some(frame_attributes) = frame_attributes {
+                has_target = frame_attributes.attrs.contains_key("target");
+                c.extend_attributes(
+                    frame_attributes
+                        .attrs
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), v.as_str())),
+                );
+            }
+            c.push_attribute(("xlink:href", href.as_str()));
+            if !has_target {
+                c.push_attribute(("target", "_top"));
+            }
+        }
+        svg.write_event(&cache_a)?;
+        has_href = true;
+        if let Some(t) = frame_attributes.and_then(|f| f.title.as_ref()) {
+            title = t.as_str();
+        }
+    } else if let Some(frame_attributes) = frame_attributes {
         if frame_attributes.attrs.contains_key("xlink:href") {
             write_container_attributes(cache_a, &frame_attributes);
             svg.write_event(&cache_a)?;
@@ -767,7 +995,8 @@ fn write_container_attributes(event: &mut Event<'_>, frame_attributes: &FrameAtt
 ///
 /// See [`from_sorted_lines`] for the expected format of each line.
 ///
-/// The resulting flame graph will be written out to `writer` in SVG format.
+/// The resulting flame graph will be written out to `writer` in the format given by
+/// [`Options::format`].
 pub fn from_reader<R, W>(opt: &mut Options<'_>, reader: R, writer: W) -> quick_xml::Result<()>
 where
     R: Read,
@@ -780,7 +1009,8 @@ where
 ///
 /// See [`from_sorted_lines`] for the expected format of each line.
 ///
-/// The resulting flame graph will be written out to `writer` in SVG format.
+/// The resulting flame graph will be written out to `writer` in the format given by
+/// [`Options::format`].
 pub fn from_readers<R, W>(opt: &mut Options<'_>, readers: R, writer: W) -> quick_xml::Result<()>
 where
     R: IntoIterator,
@@ -833,7 +1063,25 @@ pub fn from_files<W: Write>(
     }
 }
 
+/// The title of the flame graph, with the total added for [units](Unit) that are shown scaled.
+fn title<'a>(opt: &'a Options<'_>, total: usize) -> Cow<'a, str> {
+    match opt.unit.format(total as f64 * opt.factor) {
+        Some(total) => Cow::Owned(format!("{} ({})", opt.title, total)),
+        None => Cow::Borrowed(&opt.title),
+    }
+}
+
+/// The name of a function for its tooltip, with its source location if it has one.
+fn tooltip_name(f: &str) -> Cow<'_, str> {
+    match Location::split(f) {
+        (_, Some(location)) => Cow::Owned(format!("{} at {}", deannotate(f), location)),
+        (_, None) => Cow::Borrowed(deannotate(f)),
+    }
+}
+
+/// The name of a function for its label, without its annotation or source location.
 fn deannotate(f: &str) -> &str {
+    let (f, _) = Location::split(f);
     if f.ends_with(']') {
         if let Some(ai) = f.rfind("_[") {
             if f[ai..].len() == 4 && "kwij".contains(&f[ai + 2..ai + 3]) {
@@ -0,0 +1,301 @@
+use std::borrow::Cow;
+use std::io::{self, prelude::*};
+
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use log::error;
+
+use super::color::{self, Color};
+use super::merge::TimedFrame;
+use super::{deannotate, frame_color, title, Direction, Options};
+use super::{DEFAULT_IMAGE_WIDTH, FRAMEPAD, XPAD};
+
+const BLACK: Color = Color { r: 0, g: 0, b: 0 };
+
+pub(super) fn write_png<W>(
+    opt: &mut Options<'_>,
+    mut frames: Vec<TimedFrame<'_>>,
+    time: usize,
+    delta_max: usize,
+    writer: W,
+) -> quick_xml::Result<()>
+where
+    W: Write,
+{
+    let font = FontRef::try_from_slice(notosans::REGULAR_TTF).expect("bundled font is valid");
+    let image_width = opt.image_width.unwrap_or(DEFAULT_IMAGE_WIDTH);
+    let (bgcolor1, bgcolor2) = color::bgcolor_for(opt.bgcolors, opt.colors);
+    let background = (parse_color(&bgcolor1), parse_color(&bgcolor2));
+
+    if time == 0 {
+        error!("No stack counts found");
+        // emit an error message image, for tools automating flamegraph use
+        let mut canvas = Canvas::new(image_width, opt.font_size * 5, background);
+        canvas.draw_text_centered(
+            &font,
+            "ERROR: No valid input provided to flamegraph",
+            (opt.font_size * 2) as f32,
+            opt.font_size as f32,
+        );
+        canvas.write_png(writer)?;
+        return Err(quick_xml::Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No stack counts found",
+        )));
+    }
+
+    // prune blocks that are too narrow
+    let widthpertime_pct = 100.0 / time as f64;
+    let minwidth_time = opt.min_width / widthpertime_pct;
+    frames.retain(|frame| ((frame.end_time - frame.start_time) as f64) >= minwidth_time);
+    let depthmax = frames
+        .iter()
+        .map(|frame| frame.location.depth)
+        .max()
+        .unwrap_or(0);
+
+    let imageheight = ((depthmax + 1) * opt.frame_height) + opt.ypad1() + opt.ypad2();
+    let mut canvas = Canvas::new(image_width, imageheight, background);
+    canvas.draw_text_centered(
+        &font,
+        &title(opt, time),
+        (opt.font_size * 2) as f32,
+        (opt.font_size + 5) as f32,
+    );
+    if let Some(ref subtitle) = opt.subtitle {
+        canvas.draw_text_centered(
+            &font,
+            subtitle,
+            (opt.font_size * 4) as f32,
+            opt.font_size as f32,
+        );
+    }
+
+    // Frames are laid out in percent of the frames container, just like in the SVG.
+    let container_width = (image_width - XPAD - XPAD) as f64;
+    let x_for_pct = |pct: f64| XPAD as f64 + pct * container_width / 100.0;
+
+    let mut thread_rng = super::rand::thread_rng();
+    for frame in &frames {
+        let x1_pct = frame.start_time as f64 * widthpertime_pct;
+        let x2_pct = frame.end_time as f64 * widthpertime_pct;
+        let (y1, y2) = match opt.direction {
+            Direction::Straight => {
+                let y1 = imageheight - opt.ypad2() - (frame.location.depth + 1) * opt.frame_height
+                    + FRAMEPAD;
+                let y2 = imageheight - opt.ypad2() - frame.location.depth * opt.frame_height;
+                (y1, y2)
+            }
+            Direction::Inverted => {
+                let y1 = opt.ypad1() + frame.location.depth * opt.frame_height;
+                let y2 = opt.ypad1() + (frame.location.depth + 1) * opt.frame_height - FRAMEPAD;
+                (y1, y2)
+            }
+        };
+
+        let color = frame_color(opt, frame, x2_pct - x1_pct, delta_max, &mut thread_rng);
+        canvas.fill_rect(x_for_pct(x1_pct), x_for_pct(x2_pct), y1, y2, color);
+
+        let fitchars = ((x2_pct - x1_pct)
+            / (100.0 * opt.font_size as f64 * opt.font_width / image_width as f64))
+            .trunc() as usize;
+        let text = fit(deannotate(frame.location.function), fitchars);
+        if !text.is_empty() {
+            canvas.draw_text(
+                &font,
+                &text,
+                x_for_pct(x1_pct + 100.0 * 3.0 / image_width as f64) as f32,
+                3.0 + (y1 + y2) as f32 / 2.0,
+                opt.font_size as f32,
+            );
+        }
+    }
+
+    canvas.write_png(writer)?;
+    Ok(())
+}
+
+/// Truncates `name` to `fitchars` characters the way the SVG labels are truncated.
+fn fit(name: &str, fitchars: usize) -> Cow<'_, str> {
+    if fitchars < 3 {
+        // room for one char plus two dots
+        "".into()
+    } else if name.len() < fitchars {
+        name.into()
+    } else {
+        let mut truncated: String = name.chars().take(fitchars - 2).collect();
+        truncated.push_str("..");
+        truncated.into()
+    }
+}
+
+fn parse_color(s: &str) -> Color {
+    color::parse_flat_bgcolor(s).expect("background colors are written as #rrggbb")
+}
+
+/// An RGB image that frames and text are drawn onto.
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    /// Creates an image filled with a vertical gradient between two colors, which is the
+    /// gradient the SVG background goes through between 5% and 95% of its height.
+    fn new(width: usize, height: usize, (top, bottom): (Color, Color)) -> Self {
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            let t = (((y as f32 + 0.5) / height as f32 - 0.05) / 0.9).clamp(0.0, 1.0);
+            let color = mix(top, bottom, t);
+            for _ in 0..width {
+                pixels.extend_from_slice(&[color.r, color.g, color.b]);
+            }
+        }
+        Canvas {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Blends `color` into the pixel at `(x, y)`, with `alpha` between 0 and 1.
+    fn blend(&mut self, x: usize, y: usize, color: Color, alpha: f32) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let i = (y * self.width + x) * 3;
+        let pixel = Color {
+            r: self.pixels[i],
+            g: self.pixels[i + 1],
+            b: self.pixels[i + 2],
+        };
+        let blended = mix(pixel, color, alpha);
+        self.pixels[i..i + 3].copy_from_slice(&[blended.r, blended.g, blended.b]);
+    }
+
+    /// Fills the rows `y1..y2` between the horizontal positions `x1` and `x2`, blending in the
+    /// columns that are only partially covered.
+    fn fill_rect(&mut self, x1: f64, x2: f64, y1: usize, y2: usize, color: Color) {
+        let first = x1.floor().max(0.0) as usize;
+        let last = (x2.ceil().max(0.0) as usize).min(self.width);
+        for x in first..last {
+            let coverage = x2.min(x as f64 + 1.0) - x1.max(x as f64);
+            if coverage <= 0.0 {
+                continue;
+            }
+            for y in y1..y2.min(self.height) {
+                self.blend(x, y, color, coverage.min(1.0) as f32);
+            }
+        }
+    }
+
+    /// Draws black `text` with its baseline starting at `(x, y)`, with a font size of `size`
+    /// pixels.
+    fn draw_text(&mut self, font: &FontRef<'_>, text: &str, x: f32, y: f32, size: f32) {
+        let font = font.as_scaled(px_scale(font, size));
+        let mut caret = x;
+        let mut previous = None;
+        for c in text.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += font.kern(previous, id);
+            }
+            let glyph = id.with_scale_and_position(font.scale(), point(caret, y));
+            caret += font.h_advance(id);
+            previous = Some(id);
+
+            if let Some(outline) = font.outline_glyph(glyph) {
+                let bounds = outline.px_bounds();
+                outline.draw(|gx, gy, coverage| {
+                    let px = bounds.min.x + gx as f32;
+                    let py = bounds.min.y + gy as f32;
+                    if px >= 0.0 && py >= 0.0 {
+                        self.blend(px as usize, py as usize, BLACK, coverage.min(1.0));
+                    }
+                });
+            }
+        }
+    }
+
+    /// Draws `text` centered horizontally, like the SVG title.
+    fn draw_text_centered(&mut self, font: &FontRef<'_>, text: &str, y: f32, size: f32) {
+        let scaled = font.as_scaled(px_scale(font, size));
+        let mut width = 0.0;
+        let mut previous = None;
+        for c in text.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                width += scaled.kern(previous, id);
+            }
+            width += scaled.h_advance(id);
+            previous = Some(id);
+        }
+        let x = (self.width as f32 - width) / 2.0;
+        self.draw_text(font, text, x, y, size);
+    }
+
+    fn write_png<W>(&self, writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+}
+
+/// The scale at which an em of `font` is `size` pixels, like a CSS `font-size`.
+fn px_scale(font: &FontRef<'_>, size: f32) -> PxScale {
+    let units_per_em = font.units_per_em().unwrap_or(1000.0);
+    PxScale::from(size * font.height_unscaled() / units_per_em)
+}
+
+fn mix(from: Color, to: Color, t: f32) -> Color {
+    let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+    Color {
+        r: channel(from.r, to.r),
+        g: channel(from.g, to.g),
+        b: channel(from.b, to.b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fit, mix, Canvas, Color};
+
+    #[test]
+    fn fit_truncates_like_svg_labels() {
+        assert_eq!(fit("main", 2), "");
+        assert_eq!(fit("main", 5), "main");
+        assert_eq!(fit("main", 4), "ma..");
+        assert_eq!(fit("std::vec::Vec::push", 8), "std::v..");
+    }
+
+    #[test]
+    fn fill_rect_blends_partially_covered_columns() {
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let black = Color { r: 0, g: 0, b: 0 };
+        let mut canvas = Canvas::new(4, 2, (white, white));
+        canvas.fill_rect(0.5, 2.0, 0, 1, black);
+        assert_eq!(
+            &canvas.pixels[..12],
+            &[128, 128, 128, 0, 0, 0, 255, 255, 255, 255, 255, 255]
+        );
+        assert!(canvas.pixels[12..].iter().all(|&c| c == 255));
+        assert_eq!(
+            mix(black, white, 0.5),
+            Color {
+                r: 128,
+                g: 128,
+                b: 128
+            }
+        );
+    }
+}
@@ -48,6 +48,8 @@ pub(super) struct StyleOptions<'a> {
     pub(super) imageheight: usize,
     pub(super) bgcolor1: Cow<'a, str>,
     pub(super) bgcolor2: Cow<'a, str>,
+    pub(super) title: Cow<'a, str>,
+    pub(super) total: f64,
 }
 
 pub fn write_header<W>(
@@ -151,6 +153,15 @@ var truncate_text_right = {};",
         opt.image_width.is_none(),
         opt.text_truncate_direction == TextTruncateDirection::Right
     ))))?;
+    if opt.unit.format(style_options.total).is_some() {
+        svg.write_event(Event::CData(BytesText::from_escaped_str(format!(
+            "
+var unit = '{}';
+var unittotal = {};",
+            opt.unit.name(),
+            style_options.total
+        ))))?;
+    }
     if !opt.no_javascript {
         svg.write_event(Event::CData(BytesText::from_escaped_str(include_str!(
             "flamegraph.js"
@@ -176,7 +187,7 @@ var truncate_text_right = {};",
         TextItem {
             x: Dimension::Percent(50.0),
             y: (opt.font_size * 2) as f64,
-            text: (&*opt.title).into(),
+            text: (&*style_options.title).into(),
             extra: vec![("id", "title")],
         },
     )?;
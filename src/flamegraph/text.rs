@@ -0,0 +1,177 @@
+use std::fmt::Write as _;
+use std::io::{self, prelude::*};
+
+use log::error;
+use unicode_width::UnicodeWidthChar;
+
+use super::merge::TimedFrame;
+use super::{deannotate, frame_color, title, Direction, Options, TextTruncateDirection};
+
+/// The number of columns drawn when no width is given.
+const DEFAULT_COLUMNS: usize = 80;
+
+const RESET: &str = "\x1b[0m";
+
+pub(super) fn write_text<W>(
+    opt: &mut Options<'_>,
+    mut frames: Vec<TimedFrame<'_>>,
+    time: usize,
+    delta_max: usize,
+    mut writer: W,
+) -> quick_xml::Result<()>
+where
+    W: Write,
+{
+    if time == 0 {
+        error!("No stack counts found");
+        writer.write_all(b"ERROR: No valid input provided to flamegraph\n")?;
+        return Err(quick_xml::Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No stack counts found",
+        )));
+    }
+
+    let columns = opt.image_width.unwrap_or(DEFAULT_COLUMNS).max(1);
+    let column = |time_: usize| (time_ as f64 * columns as f64 / time as f64).round() as usize;
+
+    // prune blocks that are too narrow, including those narrower than a column
+    let widthpertime_pct = 100.0 / time as f64;
+    let minwidth_time = opt.min_width / widthpertime_pct;
+    frames.retain(|frame| {
+        ((frame.end_time - frame.start_time) as f64) >= minwidth_time
+            && column(frame.end_time) > column(frame.start_time)
+    });
+    frames.sort_by_key(|frame| (frame.location.depth, frame.start_time));
+
+    let depthmax = frames.last().map_or(0, |frame| frame.location.depth);
+    let mut rows = vec![String::new(); depthmax + 1];
+    let mut cursors = vec![0; depthmax + 1];
+    let mut thread_rng = super::rand::thread_rng();
+    for frame in &frames {
+        let depth = frame.location.depth;
+        let row = &mut rows[depth];
+        let (x1, x2) = (column(frame.start_time), column(frame.end_time));
+        if x1 > cursors[depth] {
+            row.push_str(RESET);
+            push_spaces(row, x1 - cursors[depth]);
+        }
+        cursors[depth] = x2;
+
+        let width_pct = (frame.end_time - frame.start_time) as f64 * widthpertime_pct;
+        let color = frame_color(opt, frame, width_pct, delta_max, &mut thread_rng);
+        let _ = write!(
+            row,
+            "\x1b[38;2;0;0;0;48;2;{};{};{}m",
+            color.r, color.g, color.b
+        );
+
+        let name = if frame.location.function.is_empty() && depth == 0 {
+            "all"
+        } else {
+            deannotate(frame.location.function)
+        };
+        // leave the last column blank to tell neighbouring frames apart
+        let fitted = fit(name, x2 - x1 - 1, opt.text_truncate_direction);
+        row.push_str(&fitted);
+        push_spaces(row, x2 - x1 - display_width(&fitted));
+    }
+
+    write_centered(&mut writer, &title(opt, time), columns)?;
+    if let Some(ref subtitle) = opt.subtitle {
+        write_centered(&mut writer, subtitle, columns)?;
+    }
+    let mut write_row = |row: &str| writeln!(writer, "{}{}", row, RESET);
+    match opt.direction {
+        Direction::Straight => rows.iter().rev().try_for_each(|row| write_row(row))?,
+        Direction::Inverted => rows.iter().try_for_each(|row| write_row(row))?,
+    }
+
+    Ok(())
+}
+
+/// Fits `name` into `width` terminal columns, replacing what doesn't fit with `..`.
+///
+/// Wide characters, such as those of CJK names, take two columns and combining marks none.
+/// Names are left out entirely when there isn't room for one character plus the two dots.
+/// Control characters are replaced so that names can't send escape sequences to the terminal.
+fn fit(name: &str, width: usize, direction: TextTruncateDirection) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_control() { '?' } else { c })
+        .collect();
+    if display_width(&name) <= width {
+        return name;
+    } else if width < 3 {
+        return String::new();
+    }
+
+    let mut room = width - 2;
+    let fits = |c: &char| {
+        let w = char_width(*c);
+        if w <= room {
+            room -= w;
+            true
+        } else {
+            false
+        }
+    };
+    match direction {
+        TextTruncateDirection::Left => {
+            let tail: Vec<_> = name.chars().rev().take_while(fits).collect();
+            "..".chars().chain(tail.into_iter().rev()).collect()
+        }
+        TextTruncateDirection::Right => name.chars().take_while(fits).chain("..".chars()).collect(),
+    }
+}
+
+/// The number of terminal columns `text` takes.
+fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+fn push_spaces(row: &mut String, n: usize) {
+    row.push_str(&" ".repeat(n));
+}
+
+fn write_centered<W>(writer: &mut W, text: &str, columns: usize) -> io::Result<()>
+where
+    W: Write,
+{
+    let text = fit(text, columns, TextTruncateDirection::Right);
+    let indent = (columns - display_width(&text)) / 2;
+    writeln!(writer, "{:indent$}{}", "", text, indent = indent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::TextTruncateDirection::{Left, Right};
+    use super::{display_width, fit};
+
+    #[test]
+    fn fit_truncates_in_the_given_direction() {
+        assert_eq!(fit("main", 4, Left), "main");
+        assert_eq!(fit("main", 10, Right), "main");
+        assert_eq!(fit("std::vec::Vec::push", 8, Left), "..::push");
+        assert_eq!(fit("std::vec::Vec::push", 8, Right), "std::v..");
+        assert_eq!(fit("main", 2, Left), "");
+        assert_eq!(fit("main", 3, Right), "m..");
+    }
+
+    #[test]
+    fn fit_measures_display_width() {
+        assert_eq!(fit("日本語", 6, Left), "日本語");
+        assert_eq!(fit("日本語の関数", 6, Right), "日本..");
+        assert_eq!(fit("日本語の関数", 5, Left), "..数");
+        assert_eq!(display_width(&fit("日本語の関数", 5, Left)), 4);
+        assert_eq!(fit("e\u{301}te\u{301}", 3, Right), "e\u{301}te\u{301}");
+    }
+
+    #[test]
+    fn fit_replaces_control_characters() {
+        assert_eq!(fit("a\x1b[2Jb", 10, Left), "a?[2Jb");
+    }
+}
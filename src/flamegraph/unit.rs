@@ -0,0 +1,107 @@
+use std::str::FromStr;
+
+/// The unit of the sample counts, which decides how they are shown in tooltips, in the text of
+/// search matches and next to the title.
+///
+/// The units apply to the sample counts after they are scaled by
+/// [`factor`](super::Options::factor).
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Unit {
+    /// Plain counts with thousands separators, followed by the
+    /// [`count_name`](super::Options::count_name). This is the default.
+    #[default]
+    Samples,
+
+    /// Bytes, shown in B, KiB, MiB, GiB or TiB, such as for heap profiles.
+    ///
+    /// `inferno-flamegraph` uses the [`Mem`](super::color::BasicPalette::Mem) palette for bytes
+    /// unless `--colors` is given.
+    Bytes,
+
+    /// Nanoseconds, shown in ns, µs, ms or s.
+    Nanoseconds,
+
+    /// Microseconds, shown in ns, µs, ms or s.
+    Microseconds,
+
+    /// Milliseconds, shown in ns, µs, ms or s.
+    Milliseconds,
+
+    /// Only show the share of the total, for profiles whose absolute counts don't mean much, such
+    /// as ones that were normalized and merged.
+    Percent,
+}
+
+impl FromStr for Unit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "samples" => Ok(Unit::Samples),
+            "bytes" => Ok(Unit::Bytes),
+            "ns" => Ok(Unit::Nanoseconds),
+            "us" => Ok(Unit::Microseconds),
+            "ms" => Ok(Unit::Milliseconds),
+            "percent" => Ok(Unit::Percent),
+            _ => Err(format!("unknown unit: {}", s)),
+        }
+    }
+}
+
+impl Unit {
+    /// The name of the unit, as accepted by `from_str`.
+    pub(super) fn name(self) -> &'static str {
+        match self {
+            Unit::Samples => "samples",
+            Unit::Bytes => "bytes",
+            Unit::Nanoseconds => "ns",
+            Unit::Microseconds => "us",
+            Unit::Milliseconds => "ms",
+            Unit::Percent => "percent",
+        }
+    }
+
+    /// Formats `value` in the largest unit that it is at least one of, as in `1.50 MiB`.
+    ///
+    /// Returns `None` for the units that aren't scaled, which are shown as before.
+    pub(super) fn format(self, value: f64) -> Option<String> {
+        let (mut value, step, units): (f64, f64, &[&str]) = match self {
+            Unit::Samples | Unit::Percent => return None,
+            Unit::Bytes => (value, 1024.0, &["B", "KiB", "MiB", "GiB", "TiB"]),
+            Unit::Nanoseconds => (value, 1000.0, &["ns", "µs", "ms", "s"]),
+            Unit::Microseconds => (value * 1e3, 1000.0, &["ns", "µs", "ms", "s"]),
+            Unit::Milliseconds => (value * 1e6, 1000.0, &["ns", "µs", "ms", "s"]),
+        };
+        let mut i = 0;
+        while value >= step && i < units.len() - 1 {
+            value /= step;
+            i += 1;
+        }
+        Some(if i == 0 {
+            format!("{:.0} {}", value, units[i])
+        } else {
+            format!("{:.2} {}", value, units[i])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Unit;
+
+    #[test]
+    fn format_scales_to_the_largest_unit() {
+        assert_eq!(Unit::Bytes.format(512.0).unwrap(), "512 B");
+        assert_eq!(Unit::Bytes.format(1536.0).unwrap(), "1.50 KiB");
+        assert_eq!(
+            Unit::Bytes.format(3.0 * 1024.0 * 1024.0 * 1024.0).unwrap(),
+            "3.00 GiB"
+        );
+        assert_eq!(Unit::Nanoseconds.format(999.0).unwrap(), "999 ns");
+        assert_eq!(Unit::Nanoseconds.format(1500.0).unwrap(), "1.50 µs");
+        assert_eq!(Unit::Microseconds.format(2500.0).unwrap(), "2.50 ms");
+        assert_eq!(Unit::Milliseconds.format(90_000.0).unwrap(), "90.00 s");
+        assert_eq!(Unit::Samples.format(10.0), None);
+        assert_eq!(Unit::Percent.format(10.0), None);
+    }
+}
@@ -0,0 +1,549 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+
+use ahash::AHashMap;
+use log::warn;
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+/// Folded stacks held in memory: a map from stacks to their sample counts.
+///
+/// This is the same data as the `stack count` lines written by the [collapsers] and read by
+/// [`flamegraph`], without having to format and parse it as text. A collapser can fill one with
+/// [`Collapse::collapse_into`], and [`flamegraph::from_folded`] draws one.
+///
+/// Frame names are interned, so each distinct name is only stored once no matter how many stacks
+/// it appears in.
+///
+/// ```
+/// use inferno::folded::FoldedStacks;
+///
+/// let mut stacks = FoldedStacks::new();
+/// stacks.add("main;compute;hash", 3);
+/// stacks.add_frames(&["main", "compute"], 1);
+/// stacks.add("main;compute;hash", 2);
+///
+/// assert_eq!(stacks.get("main;compute;hash"), Some(5));
+/// assert_eq!(stacks.total(), 6);
+///
+/// let mut text = Vec::new();
+/// stacks.write_to(&mut text)?;
+/// assert_eq!(text, b"main;compute 1\nmain;compute;hash 5\n");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+///   [collapsers]: crate::collapse
+///   [`flamegraph`]: crate::flamegraph
+///   [`Collapse::collapse_into`]: crate::collapse::Collapse::collapse_into
+///   [`flamegraph::from_folded`]: crate::flamegraph::from_folded
+#[derive(Clone, Debug, Default)]
+pub struct FoldedStacks {
+    /// Frame names, indexed by frame id.
+    names: Vec<Box<str>>,
+
+    /// Frame ids, indexed by frame name.
+    ids: AHashMap<Box<str>, u32>,
+
+    /// Sample counts, indexed by the ids of the frames of each stack (root first).
+    stacks: AHashMap<Box<[u32]>, usize>,
+
+    /// Reused when looking up stacks, to avoid an allocation for stacks that are already known.
+    scratch: Vec<u32>,
+}
+
+impl FoldedStacks {
+    /// Creates an empty set of stacks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `count` samples to a stack given as semicolon-separated frames, root first (e.g.,
+    /// `main;foo;bar`).
+    pub fn add(&mut self, stack: &str, count: usize) {
+        self.add_frames(stack.split(';'), count);
+    }
+
+    /// Adds `count` samples to a stack given as its frames, root first.
+    ///
+    /// Stacks without any frames are ignored.
+    pub fn add_frames<I, S>(&mut self, frames: I, count: usize)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.clear();
+        scratch.extend(frames.into_iter().map(|frame| self.intern(frame.as_ref())));
+        if !scratch.is_empty() {
+            match self.stacks.get_mut(&scratch[..]) {
+                Some(total) => *total += count,
+                None => {
+                    self.stacks.insert(scratch[..].into(), count);
+                }
+            }
+        }
+        self.scratch = scratch;
+    }
+
+    /// Adds the stack and count of a folded stack line (e.g., `main;foo;bar 12`).
+    ///
+    /// Returns `false`, without adding anything, if the line isn't a valid folded stack line.
+    pub fn add_line(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        let (stack, count) = match line.rfind(' ') {
+            Some(space) => (line[..space].trim_end(), &line[space + 1..]),
+            None => return false,
+        };
+        match count.parse::<usize>() {
+            Ok(count) if !stack.is_empty() => {
+                self.add(stack, count);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Reads folded stack lines, as written by the collapsers.
+    ///
+    /// Lines that aren't valid folded stack lines are skipped, with a warning.
+    pub fn from_reader<R>(reader: R) -> io::Result<Self>
+    where
+        R: io::BufRead,
+    {
+        let mut stacks = Self::new();
+        let mut ignored = 0;
+        for line in reader.lines() {
+            let line = line?;
+            if !line.trim().is_empty() && !stacks.add_line(&line) {
+                ignored += 1;
+            }
+        }
+        if ignored != 0 {
+            warn!("Ignored {} lines with invalid format", ignored);
+        }
+        Ok(stacks)
+    }
+
+    /// Splits the stacks by their root frame, which is removed from them.
+    ///
+    /// This undoes adding a root frame to tell groups of stacks apart, such as the processes of
+    /// [`perf::Options::group_by`]. Stacks that are only a root frame are dropped.
+    ///
+    ///   [`perf::Options::group_by`]: crate::collapse::perf::Options::group_by
+    pub fn split_roots(&self) -> BTreeMap<String, FoldedStacks> {
+        let mut roots: BTreeMap<String, FoldedStacks> = BTreeMap::new();
+        for (stack, count) in self.iter() {
+            let mut frames = stack.frames();
+            if let Some(root) = frames.next() {
+                if frames.len() == 0 {
+                    continue;
+                }
+                if !roots.contains_key(root) {
+                    roots.insert(root.to_string(), FoldedStacks::new());
+                }
+                roots.get_mut(root).unwrap().add_frames(frames, count);
+            }
+        }
+        roots
+    }
+
+    /// Adds all the samples of `other` to these stacks.
+    pub fn merge(&mut self, other: &FoldedStacks) {
+        for (stack, count) in other {
+            self.add_frames(stack.frames(), count);
+        }
+    }
+
+    /// Returns the sample count of a stack given as semicolon-separated frames, or `None` if the
+    /// stack was never added.
+    pub fn get(&self, stack: &str) -> Option<usize> {
+        let frames = stack
+            .split(';')
+            .map(|frame| self.ids.get(frame).copied())
+            .collect::<Option<Vec<_>>>()?;
+        self.stacks.get(&frames[..]).copied()
+    }
+
+    /// Returns the number of distinct stacks.
+    pub fn len(&self) -> usize {
+        self.stacks.len()
+    }
+
+    /// Returns `true` if there are no stacks.
+    pub fn is_empty(&self) -> bool {
+        self.stacks.is_empty()
+    }
+
+    /// Returns the sum of the sample counts of all stacks.
+    pub fn total(&self) -> usize {
+        self.stacks.values().sum()
+    }
+
+    /// Iterates over the stacks and their sample counts, in no particular order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            names: &self.names,
+            stacks: self.stacks.iter(),
+        }
+    }
+
+    /// Returns the stacks and their sample counts in the order of their folded stack lines, which
+    /// is the order the collapsers write them in.
+    pub fn sorted(&self) -> Vec<(Stack<'_>, usize)> {
+        let mut stacks: Vec<_> = self.iter().collect();
+        stacks.sort_by(|(a, _), (b, _)| a.cmp_folded(b));
+        stacks
+    }
+
+    /// Writes the stacks as sorted folded stack lines, just like the collapsers do.
+    pub fn write_to<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        for (stack, count) in self.sorted() {
+            writeln!(writer, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+
+    fn intern(&mut self, frame: &str) -> u32 {
+        if let Some(&id) = self.ids.get(frame) {
+            return id;
+        }
+        let id = self.names.len() as u32;
+        self.names.push(frame.into());
+        self.ids.insert(frame.into(), id);
+        id
+    }
+}
+
+impl<'a> IntoIterator for &'a FoldedStacks {
+    type Item = (Stack<'a>, usize);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<S> Extend<(S, usize)> for FoldedStacks
+where
+    S: AsRef<str>,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (S, usize)>,
+    {
+        for (stack, count) in iter {
+            self.add(stack.as_ref(), count);
+        }
+    }
+}
+
+impl<S> std::iter::FromIterator<(S, usize)> for FoldedStacks
+where
+    S: AsRef<str>,
+{
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (S, usize)>,
+    {
+        let mut stacks = Self::new();
+        stacks.extend(iter);
+        stacks
+    }
+}
+
+/// Serializes to a map from semicolon-separated stacks to sample counts, such as
+/// `{"main;foo": 3}`.
+impl Serialize for FoldedStacks {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(
+            self.sorted()
+                .into_iter()
+                .map(|(stack, count)| (stack.to_string(), count)),
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for FoldedStacks {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let stacks = HashMap::<String, usize>::deserialize(deserializer)?;
+        Ok(stacks.into_iter().collect())
+    }
+}
+
+/// An iterator over the stacks of a [`FoldedStacks`] and their sample counts.
+pub struct Iter<'a> {
+    names: &'a [Box<str>],
+    stacks: std::collections::hash_map::Iter<'a, Box<[u32]>, usize>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (Stack<'a>, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (frames, &count) = self.stacks.next()?;
+        let stack = Stack {
+            names: self.names,
+            frames,
+        };
+        Some((stack, count))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stacks.size_hint()
+    }
+}
+
+/// A stack of a [`FoldedStacks`].
+///
+/// It displays as semicolon-separated frames, like in folded stack lines.
+#[derive(Clone, Copy)]
+pub struct Stack<'a> {
+    names: &'a [Box<str>],
+    frames: &'a [u32],
+}
+
+impl<'a> Stack<'a> {
+    /// Iterates over the names of the frames of the stack, root first.
+    pub fn frames(&self) -> impl DoubleEndedIterator<Item = &'a str> + ExactSizeIterator + 'a {
+        let names = self.names;
+        self.frames.iter().map(move |&id| &*names[id as usize])
+    }
+
+    /// Returns the number of frames of the stack.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns `true` if the stack has no frames, which is never the case for the stacks of a
+    /// [`FoldedStacks`].
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Compares stacks the way their folded stack lines compare.
+    fn cmp_folded(&self, other: &Stack<'_>) -> Ordering {
+        folded_bytes(self.frames()).cmp(folded_bytes(other.frames()))
+    }
+}
+
+impl fmt::Display for Stack<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, frame) in self.frames().enumerate() {
+            if i != 0 {
+                f.write_str(";")?;
+            }
+            f.write_str(frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Stack<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+/// The source location of a frame: the file and line it was at.
+///
+/// Collapsers that know where a frame was, such as from the `srcline` field of `perf script` or
+/// from debug info, put it at the end of the frame after any `_[x]` annotation:
+///
+/// ```text
+/// main [src/main.c:20];compute [src/main.c:12];square_[i] [src/math.h:3] 5
+/// ```
+///
+/// Frames at different lines of the same function are different frames. The
+/// [`flamegraph`](crate::flamegraph) shows the location in the tooltip of a frame rather than in
+/// its label, and can link to it.
+///
+/// ```
+/// use inferno::folded::Location;
+///
+/// let (name, location) = Location::split("square_[i] [src/math.h:3]");
+/// assert_eq!(name, "square_[i]");
+/// assert_eq!(location, Some(Location { file: "src/math.h", line: 3 }));
+///
+/// let mut frame = String::from("compute");
+/// Location { file: "src/main.c", line: 12 }.annotate(&mut frame);
+/// assert_eq!(frame, "compute [src/main.c:12]");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Location<'a> {
+    /// The source file, as recorded by the profiler or the debug info.
+    pub file: &'a str,
+
+    /// The line in `file`, starting at 1.
+    pub line: u32,
+}
+
+impl<'a> Location<'a> {
+    /// Parses a location written as `file:line`, like the `srcline` field of `perf script`.
+    ///
+    /// Returns `None` for unknown locations, such as `??:0`.
+    pub fn parse(s: &'a str) -> Option<Self> {
+        let colon = s.rfind(':')?;
+        let file = s[..colon].trim();
+        let line = s[colon + 1..].trim().parse().ok()?;
+        if file.is_empty() || file == "??" || line == 0 {
+            return None;
+        }
+        Some(Location { file, line })
+    }
+
+    /// Splits the location annotation off of a frame, returning the rest of the frame and the
+    /// location, if there is one.
+    pub fn split(frame: &'a str) -> (&'a str, Option<Self>) {
+        if frame.ends_with(']') {
+            if let Some(open) = frame.rfind(" [") {
+                if let Some(location) = Self::parse(&frame[open + 2..frame.len() - 1]) {
+                    return (&frame[..open], Some(location));
+                }
+            }
+        }
+        (frame, None)
+    }
+
+    /// Appends the location annotation to a frame.
+    pub fn annotate(&self, frame: &mut String) {
+        frame.push_str(" [");
+        frame.push_str(self.file);
+        frame.push(':');
+        frame.push_str(&self.line.to_string());
+        frame.push(']');
+    }
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Returns the bytes of the semicolon-separated frames, without joining them into a string.
+pub(crate) fn folded_bytes<'a, I>(frames: I) -> impl Iterator<Item = u8> + 'a
+where
+    I: IntoIterator<Item = &'a str>,
+    I::IntoIter: 'a,
+{
+    frames.into_iter().enumerate().flat_map(|(i, frame)| {
+        let separator: &[u8] = if i == 0 { b"" } else { b";" };
+        separator.iter().chain(frame.as_bytes()).copied()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_sorted_like_folded_lines() {
+        let stacks: FoldedStacks = vec![
+            ("main;foo-bar;baz", 1),
+            ("main;foo;baz", 2),
+            ("main;foo", 3),
+            ("main", 4),
+        ]
+        .into_iter()
+        .collect();
+        let sorted: Vec<_> = stacks
+            .sorted()
+            .into_iter()
+            .map(|(stack, count)| format!("{} {}", stack, count))
+            .collect();
+        let mut lines = vec![
+            "main;foo-bar;baz 1",
+            "main;foo;baz 2",
+            "main;foo 3",
+            "main 4",
+        ];
+        lines.sort();
+        assert_eq!(sorted, lines);
+    }
+
+    #[test]
+    fn test_add_line() {
+        let mut stacks = FoldedStacks::new();
+        assert!(stacks.add_line("main;foo 3\n"));
+        assert!(stacks.add_line("  main;foo   2"));
+        assert!(stacks.add_line("with space;frame 1"));
+        assert!(!stacks.add_line("main;foo"));
+        assert!(!stacks.add_line("main;foo bar"));
+        assert!(!stacks.add_line(" 3"));
+        assert_eq!(stacks.get("main;foo"), Some(5));
+        assert_eq!(stacks.get("with space;frame"), Some(1));
+        assert_eq!(stacks.get("main"), None);
+        assert_eq!(stacks.len(), 2);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a: FoldedStacks = vec![("main;foo", 1), ("main;bar", 2)].into_iter().collect();
+        let b: FoldedStacks = vec![("main;bar", 3), ("other", 4)].into_iter().collect();
+        a.merge(&b);
+        let mut text = Vec::new();
+        a.write_to(&mut text).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "main;bar 5\nmain;foo 1\nother 4\n"
+        );
+    }
+
+    #[test]
+    fn test_location_split() {
+        assert_eq!(
+            Location::split("compute [/src/app/main.c:12]"),
+            (
+                "compute",
+                Some(Location {
+                    file: "/src/app/main.c",
+                    line: 12
+                })
+            )
+        );
+        assert_eq!(Location::split("[unknown]"), ("[unknown]", None));
+        assert_eq!(Location::split("foo [bar]"), ("foo [bar]", None));
+        assert_eq!(Location::split("foo [??:0]"), ("foo [??:0]", None));
+        assert_eq!(Location::split("foo_[i]"), ("foo_[i]", None));
+    }
+
+    #[test]
+    fn test_split_roots() {
+        let stacks: FoldedStacks = vec![
+            ("tid-2;app;main;foo", 1),
+            ("tid-1;app;main", 2),
+            ("tid-2;app;main;foo", 3),
+            ("tid-3", 4),
+        ]
+        .into_iter()
+        .collect();
+        let roots = stacks.split_roots();
+        assert_eq!(roots.keys().collect::<Vec<_>>(), vec!["tid-1", "tid-2"]);
+        assert_eq!(roots["tid-1"].get("app;main"), Some(2));
+        assert_eq!(roots["tid-2"].get("app;main;foo"), Some(4));
+        assert_eq!(roots["tid-2"].total(), 4);
+    }
+
+    #[test]
+    fn test_serde_roundtrip() {
+        let stacks: FoldedStacks = vec![("main;foo", 1), ("main;bar", 2)].into_iter().collect();
+        let json = serde_json::to_string(&stacks).unwrap();
+        assert_eq!(json, r#"{"main;bar":2,"main;foo":1}"#);
+        let stacks: FoldedStacks = serde_json::from_str(&json).unwrap();
+        assert_eq!(stacks.get("main;bar"), Some(2));
+        assert_eq!(stacks.total(), 3);
+    }
+}
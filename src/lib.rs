@@ -29,9 +29,12 @@
 //! Since profiling tools produce stack traces in a myriad of different formats, and the flame
 //! graph plotter expects input in a particular folded stack trace format, each profiler needs a
 //! separate collapse implementation. While the original Perl implementation supports _lots_ of
-//! profilers, Inferno currently only supports four: the widely used [`perf`] tool (specifically
-//! the output from `perf script`), [DTrace], [sample], and [VTune]. Support for xdebug is
-//! [hopefully coming soon], and [`bpftrace`] should get [native support] before too long.
+//! profilers, Inferno currently only supports eleven: the widely used [`perf`] tool (either the
+//! output from `perf script` or the `perf.data` file itself), [DTrace], [sample], [VTune],
+//! [`bpftrace`] (along with the folded output of [BCC] tools), PHP's [Xdebug], Valgrind's
+//! [callgrind] and cachegrind, [pprof] profiles such as those written by Go, the `.cpuprofile`
+//! files of [Chrome DevTools] and Node.js, Java's [JDK Flight Recorder], and stacks exported as
+//! CSV from [xperf] and Windows Performance Analyzer.
 //!
 //! Inferno supports profiles from applications written in any language, but we'll walk through an
 //! example with a Rust program. To profile a Rust application, you would first set
@@ -51,7 +54,29 @@
 //! $ perf script | inferno-collapse-perf > stacks.folded
 //! ```
 //!
-//! For more advanced uses, see Brendan Gregg's excellent [perf examples] page.
+//! If you recorded with frame pointers (`perf record -g`), you can also skip `perf script` and
+//! have Inferno read `perf.data` directly with `inferno-collapse-perf --input-format=data
+//! perf.data`. For more advanced uses, see Brendan Gregg's excellent [perf examples] page.
+//!
+//! To break a profile down by process, thread, CPU or thread name, `--group-by` puts the stacks
+//! of each group under a root frame of their own, and `--output-dir` writes each group to a
+//! folded file of its own instead. `--group-pattern` lumps threads together by name, such as the
+//! workers of a thread pool:
+//!
+//! ```console
+//! $ perf script -F+pid,tid,cpu | inferno-collapse-perf --group-by thread-name --group-pattern '^(worker)-[0-9]+$' --output-dir groups
+//! ```
+//!
+//! By default, only the samples of the first event type are collapsed. If you recorded several
+//! events at once, `--all-events` collapses all of them in one go, each under a root frame named
+//! after the event, and `--output-dir` then writes a folded file per event. `--ratio` compares two
+//! events by their periods instead, as differential folded stacks whose flame graph is red where
+//! the instructions per cycle are lower than overall and blue where they are higher:
+//!
+//! ```console
+//! $ perf record -e cycles,instructions -g -- ./my_program
+//! $ perf script | inferno-collapse-perf --ratio instructions cycles | inferno-flamegraph > ipc.svg
+//! ```
 //!
 //! ### DTrace (macOS)
 //!
@@ -65,6 +90,15 @@
 //! For more advanced uses, see also upstream FlameGraph's [DTrace examples].
 //! You may also be interested in something like [NodeJS's ustack helper].
 //!
+//! ### bpftrace (Linux)
+//!
+//! ```console
+//! # bpftrace -e 'profile:hz:99 /comm == "mybin"/ { @[kstack, ustack, comm] = count(); }' -o out.stacks
+//! $ cat out.stacks | inferno-collapse-bpftrace > stacks.folded
+//! ```
+//!
+//! The folded output of BCC's `profile -f` can be passed to `inferno-collapse-bpftrace` as well.
+//!
 //! ### sample (macOS)
 //!
 //! ```console
@@ -82,6 +116,99 @@
 //! $ inferno-collapse-vtune result.csv > stacks.folded
 //! ```
 //!
+//! ### Xdebug (PHP)
+//!
+//! ```console
+//! $ php -d xdebug.mode=trace -d xdebug.trace_format=1 -d xdebug.start_with_request=yes -d xdebug.trace_output_name=trace script.php
+//! $ inferno-collapse-xdebug /tmp/trace.xt > stacks.folded
+//! ```
+//!
+//! ### callgrind (Valgrind)
+//!
+//! ```console
+//! $ valgrind --tool=callgrind --callgrind-out-file=callgrind.out target/release/mybin
+//! $ inferno-collapse-callgrind --event Ir callgrind.out > stacks.folded
+//! ```
+//!
+//! Since callgrind only records the cost of calls between pairs of functions, the stacks are
+//! reconstructed from the call graph, and are an approximation for functions called from more than
+//! one place. Output from `valgrind --tool=cachegrind` is also accepted, with one frame per stack.
+//!
+//! ### pprof (Go)
+//!
+//! ```console
+//! $ curl -o cpu.pb.gz http://localhost:6060/debug/pprof/profile?seconds=30
+//! $ inferno-collapse-pprof cpu.pb.gz > stacks.folded
+//! ```
+//!
+//! Use `--sample-index` to pick another sample type, such as `alloc_space` in a heap profile. Going
+//! the other way, `inferno-collapse-pprof --export stacks.folded > profile.pb.gz` turns folded
+//! stacks into a profile that `go tool pprof` can load.
+//!
+//! ### Chrome DevTools and Node.js
+//!
+//! ```console
+//! $ node --cpu-prof --cpu-prof-name=app.cpuprofile app.js
+//! $ inferno-collapse-chrome app.cpuprofile > stacks.folded
+//! ```
+//!
+//! Profiles saved from the DevTools Performance panel work as well. Pass `--flamechart` to keep
+//! the samples in time order for `inferno-flamegraph --flamechart`.
+//!
+//! ### JDK Flight Recorder (Java)
+//!
+//! ```console
+//! $ java -XX:StartFlightRecording=filename=recording.jfr,settings=profile -jar app.jar
+//! $ inferno-collapse-jfr recording.jfr > stacks.folded
+//! $ inferno-flamegraph --colors java stacks.folded > profile.svg
+//! ```
+//!
+//! Recordings made by async-profiler (`-f recording.jfr`) work too. Use `--weight alloc` to see
+//! where memory is allocated rather than where CPU time is spent.
+//!
+//! ### xperf (Windows)
+//!
+//! ```console
+//! $ xperf -on base -stackwalk profile
+//! $ xperf -d trace.etl
+//! $ wpaexporter trace.etl -profile ExportStacks.wpaProfile
+//! $ inferno-collapse-xperf CPU_Usage_Sampled_Stacks.csv > stacks.folded
+//! $ inferno-flamegraph stacks.folded > profile.svg
+//! ```
+//!
+//! The exported table needs a `Stack` column and either a `Weight` or a `Count` column. Weights
+//! are in milliseconds and become microseconds in the folded output.
+//!
+//! ### Demangling
+//!
+//! When symbolization doesn't go all the way, frames can be left as mangled `_ZN...E` or `_R...`
+//! Rust symbols or `_Z...` C++ symbols. Every `inferno-collapse-*` binary takes `--demangle` to
+//! demangle them (see [`collapse::demangle`]):
+//!
+//! ```console
+//! $ perf script --no-demangle | inferno-collapse-perf --demangle --simplify-templates > stacks.folded
+//! ```
+//!
+//! Rust hashes are stripped so that the frames of different builds line up, unless
+//! `--keep-rust-hash` is given. `--simplify-templates` strips the arguments of C++ templates and
+//! Rust generics, which otherwise make for very long frames.
+//!
+//! ### Symbolizing raw addresses
+//!
+//! Stacks recorded without symbols, such as from a stripped binary or a JIT that has since
+//! exited, are left with frames like `[unknown]` or `0x401036`. `inferno-symbolize` resolves them
+//! offline from the symbol tables and DWARF debug info of the binaries on disk (see
+//! [`collapse::symbolize`]):
+//!
+//! ```console
+//! $ inferno-symbolize --binary ./app --lines --inlines stacks.folded > symbolized.folded
+//! ```
+//!
+//! Addresses from a process with ASLR can be mapped back to their binary with a copy of its
+//! `/proc/<pid>/maps` given as `--maps`. `inferno-collapse-perf` takes the same `--binary`,
+//! `--maps`, `--lines` and `--inlines` options to symbolize the `[unknown]` frames of `perf
+//! script` output as it collapses them.
+//!
 //! ## Producing a flame graph
 //!
 //! Once you have a folded stack file, you're ready to produce the flame graph SVG image. To do so,
@@ -94,6 +221,53 @@
 //!
 //! And then open `profile.svg` in your viewer of choice.
 //!
+//! Flame graphs with a lot of frames can be slow to browse as SVG. For those, `--format html`
+//! produces a single HTML page instead, which draws the flame graph on a canvas and adds a table
+//! of the functions with the most samples:
+//!
+//! ```console
+//! $ cat stacks.folded | inferno-flamegraph --format html > profile.html
+//! ```
+//!
+//! The merged frames can also be exported for other viewers, either as a JSON tree for
+//! [d3-flame-graph] with `--format json`, or as a [speedscope] profile with `--format speedscope`.
+//!
+//! When there's no browser at hand, such as on a server you're logged into over SSH,
+//! `--format text` draws the flame graph right in the terminal, one line per stack depth:
+//!
+//! ```console
+//! $ cat stacks.folded | inferno-flamegraph --format text --min-width 1
+//! ```
+//!
+//! For reports, chat messages and CI artifacts that need an image rather than a document, the
+//! optional `raster` feature adds `--format png`, which draws the same frames and labels as the
+//! SVG into a PNG without needing a browser:
+//!
+//! ```console
+//! $ cargo install inferno --features raster
+//! $ cat stacks.folded | inferno-flamegraph --format png > profile.png
+//! ```
+//!
+//! Not every profile counts samples. For heap profiles whose counts are bytes, `--unit bytes`
+//! shows them as KiB, MiB or GiB in the tooltips, the text of search matches and the title, and
+//! picks the `mem` palette unless `--colors` is given. `--unit ns`, `us` and `ms` do the same
+//! for durations, and `--unit percent` only shows each frame's share of the total:
+//!
+//! ```console
+//! $ cat allocations.folded | inferno-flamegraph --unit bytes > heap.svg
+//! ```
+//!
+//! Frames can carry the source file and line they were at, written after the frame as in
+//! `compute [src/compute.c:12]` (see [`folded::Location`]). `inferno-collapse-perf --lines`
+//! takes them from the `srcline` field of `perf script -F+srcline`, and
+//! `inferno-collapse-dtrace --lines` from frames symbolicated like `compute (compute.c:12)`. The
+//! flame graph shows the location in the tooltip, and `--source-url` turns it into a link:
+//!
+//! ```console
+//! $ perf script -F+srcline | inferno-collapse-perf --lines > stacks.folded
+//! $ cat stacks.folded | inferno-flamegraph --source-url 'https://github.com/user/repo/blob/main/{file}#L{line}' > profile.svg
+//! ```
+//!
 //! ## Differential flame graphs
 //!
 //! You can debug CPU performance regressions with the help of differential flame graphs.
@@ -116,6 +290,64 @@
 //! $ inferno-diff-folded folded2 folded1 | inferno-flamegraph --negate > diff1.svg
 //! ```
 //!
+//! Profiles are noisy, so a single before and after pair can show changes that aren't really
+//! there. If you have several runs of each, such as repeated benchmarks of two builds, pass them
+//! with `--before` and `--after` instead. Each stack then gets the mean of its sample counts
+//! across the runs, along with a score of how significant the change is: Welch's t-statistic by
+//! default, or a Poisson z-score with `--score poisson`. With `--significance`,
+//! `inferno-flamegraph` only colors the stacks whose score is at least that large:
+//!
+//! ```console
+//! $ inferno-diff-folded -b old1 -b old2 -b old3 -a new1 -a new2 -a new3 \
+//!     | inferno-flamegraph --significance 2 > diff.svg
+//! ```
+//!
+//! Stacks are compared as they are, so a function that was renamed or wrapped in a new frame
+//! makes every stack through it look deleted and added. `--strip-generics`, `--strip-rust-hash`,
+//! `--rename` and `--drop-frame` rewrite the frames of both profiles before their stacks are
+//! lined up, and `--functions` lists the change in the self and total samples of each function
+//! instead of the stacks:
+//!
+//! ```console
+//! $ inferno-diff-folded --strip-rust-hash --drop-frame '^with_retry$' --functions folded1 folded2
+//! ```
+//!
+//! ## Filtering stacks
+//!
+//! `inferno-filter` sits between the collapsers and `inferno-flamegraph` to rewrite the folded
+//! stacks as they stream through. It renames frames with regular expression substitutions, folds
+//! directly recursive frames into one, keeps (`--grep`) or drops (`--exclude`) stacks with
+//! matching frames, drops the callers (`--focus`) or callees (`--prune`) of a frame, and caps the
+//! stack depth:
+//!
+//! ```console
+//! $ inferno-collapse-perf perf.txt | inferno-filter --focus '^handle_request$' | inferno-flamegraph > handler.svg
+//! ```
+//!
+//! ## Merging profiles
+//!
+//! `inferno-merge` adds up the samples of each stack across many folded stack files, such as the
+//! profiles of a fleet of hosts. Each file can be given a weight, the files can be normalized to
+//! a common total first, and `--prefix-root` adds the name of each file as the root frame of its
+//! stacks. The output is sorted, so merged profiles diff cleanly:
+//!
+//! ```console
+//! $ inferno-merge --normalize --prefix-root web1.folded web2.folded | inferno-flamegraph > web.svg
+//! ```
+//!
+//! ## Reports
+//!
+//! Sometimes a table says more than a picture. `inferno-report` reads the same folded stacks as
+//! `inferno-flamegraph` and lists the functions with the most samples: the samples in which they
+//! are the leaf frame (self), the samples of the stacks they appear in (total), both as a
+//! percentage of all samples, and the number of distinct stacks they appear in. The table can be
+//! written as text, CSV or JSON, stacks can be cut off at a maximum depth, and functions can be
+//! grouped together with regular expressions, for example to see the samples of each crate:
+//!
+//! ```console
+//! $ inferno-report --top 10 --sort total --group '^([^:]+)::' folded.txt
+//! ```
+//!
 //! # Development
 //!
 //! This crate was initially developed through [a series of live coding sessions]. If you want to
@@ -130,14 +362,21 @@
 //!   [stack traces]: https://en.wikipedia.org/wiki/Stack_trace
 //!   [`perf`]: https://perf.wiki.kernel.org/index.php/Main_Page
 //!   [DTrace]: https://www.joyent.com/dtrace
-//!   [hopefully coming soon]: https://twitter.com/DanielLockyer/status/1094605231155900416
-//!   [native support]: https://github.com/jonhoo/inferno/issues/51#issuecomment-466732304
+//!   [Xdebug]: https://xdebug.org/docs/trace
 //!   [`bpftrace`]: https://github.com/iovisor/bpftrace
+//!   [BCC]: https://github.com/iovisor/bcc
+//!   [callgrind]: https://valgrind.org/docs/manual/cl-manual.html
+//!   [pprof]: https://github.com/google/pprof
+//!   [Chrome DevTools]: https://developer.chrome.com/docs/devtools/
+//!   [JDK Flight Recorder]: https://docs.oracle.com/en/java/java-components/jdk-mission-control/8/user-guide/using-jdk-flight-recorder.html
+//!   [xperf]: https://docs.microsoft.com/en-us/windows-hardware/test/wpt/xperf-command-line-reference
 //!   [perf examples]: http://www.brendangregg.com/perf.html
 //!   [DTrace examples]: http://www.brendangregg.com/FlameGraphs/cpuflamegraphs.html#DTrace
 //!   [NodeJS's ustack helper]: http://dtrace.org/blogs/dap/2012/01/05/where-does-your-node-program-spend-its-time/
 //!   [a series of live coding sessions]: https://www.youtube.com/watch?v=jTpK-bNZiA4&list=PLqbS7AVVErFimAvMW-kIJUwxpPvcPBCsz
 //!   [differential flame graphs]: http://www.brendangregg.com/blog/2014-11-09/differential-flame-graphs.html
+//!   [d3-flame-graph]: https://github.com/spiermar/d3-flame-graph
+//!   [speedscope]: https://www.speedscope.app
 //!   [sample]: https://gist.github.com/loderunner/36724cc9ee8db66db305#profiling-with-sample
 //!   [VTune]: https://software.intel.com/en-us/vtune-amplifier-help-command-line-interface
 
@@ -161,9 +400,33 @@ pub mod collapse;
 ///   [crate-level documentation]: ../index.html
 pub mod differential;
 
+/// Filtering and rewriting of folded stack traces.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../index.html
+pub mod filter;
+
+/// An in-memory representation of folded stack traces.
+pub mod folded;
+
 /// Tools for producing flame graphs from folded stack traces.
 ///
 /// See the [crate-level documentation] for details.
 ///
 ///   [crate-level documentation]: ../index.html
 pub mod flamegraph;
+
+/// Merging and scaling of folded stack files.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../index.html
+pub mod merge;
+
+/// Tables of the functions with the most samples in folded stack traces.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../index.html
+pub mod report;
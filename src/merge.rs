@@ -0,0 +1,226 @@
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::PathBuf;
+
+use ahash::AHashMap;
+use log::warn;
+
+use crate::folded::FoldedStacks;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+/// How the inputs are scaled to a common total before they are weighted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalize {
+    /// Scale each input up to the total samples of the input with the most samples.
+    Largest,
+
+    /// Scale each input to this many samples.
+    Total(usize),
+}
+
+/// Configure how inputs are merged.
+///
+/// All options default to off.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Scale the inputs to a common total, so that each contributes the same number of samples no
+    /// matter how long it was recorded for.
+    ///
+    /// Normalization happens before the [weight](Input::weight) of each input is applied.
+    pub normalize: Option<Normalize>,
+}
+
+/// How the samples of one input are counted.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Input {
+    /// The factor the sample counts of this input are multiplied by, which must be a finite number
+    /// that isn't negative.
+    ///
+    /// Defaults to 1.
+    pub weight: f64,
+
+    /// A frame added to the root of every stack of this input, such as the name of the host the
+    /// profile was taken on.
+    ///
+    /// Defaults to `None`.
+    pub root: Option<String>,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Input {
+            weight: 1.0,
+            root: None,
+        }
+    }
+}
+
+/// Sum the sample counts of each stack across several inputs of folded stack lines.
+///
+/// Each input is first [normalized](Options::normalize), then multiplied by its
+/// [weight](Input::weight), and the counts are only rounded to whole samples once they have been
+/// added up. Stacks that end up with no samples are left out.
+///
+/// The output is written as folded stack lines, sorted like the output of the [collapsers], so
+/// that merged profiles diff cleanly.
+///
+///   [collapsers]: crate::collapse
+pub fn from_readers<I, R, W>(opt: &Options, inputs: I, mut writer: W) -> io::Result<()>
+where
+    I: IntoIterator<Item = (R, Input)>,
+    R: BufRead,
+    W: Write,
+{
+    let mut parsed = Vec::new();
+    for (reader, input) in inputs {
+        if !input.weight.is_finite() || input.weight < 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid weight: {}", input.weight),
+            ));
+        }
+        parsed.push((FoldedStacks::from_reader(reader)?, input));
+    }
+
+    let target = match opt.normalize {
+        Some(Normalize::Largest) => parsed.iter().map(|(stacks, _)| stacks.total()).max(),
+        Some(Normalize::Total(total)) => Some(total),
+        None => None,
+    };
+
+    let mut counts: AHashMap<String, f64> = AHashMap::default();
+    let mut stack = String::new();
+    for (stacks, input) in &parsed {
+        let total = stacks.total();
+        if total == 0 {
+            warn!("Skipping an input without any samples");
+            continue;
+        }
+        let scale = match target {
+            Some(target) => target as f64 / total as f64,
+            None => 1.0,
+        } * input.weight;
+
+        for (frames, count) in stacks {
+            stack.clear();
+            if let Some(ref root) = input.root {
+                stack.push_str(root);
+                stack.push(';');
+            }
+            stack.push_str(&frames.to_string());
+            match counts.get_mut(&stack) {
+                Some(sum) => *sum += count as f64 * scale,
+                None => {
+                    counts.insert(stack.clone(), count as f64 * scale);
+                }
+            }
+        }
+    }
+
+    let mut counts: Vec<_> = counts
+        .into_iter()
+        .map(|(stack, count)| (stack, count.round() as usize))
+        .filter(|&(_, count)| count != 0)
+        .collect();
+    counts.sort_unstable();
+    for (stack, count) in counts {
+        writeln!(writer, "{} {}", stack, count)?;
+    }
+    Ok(())
+}
+
+/// Sum the sample counts of each stack across several files of folded stack lines.
+///
+/// A path of `-` reads STDIN, which can only be given once. See [`from_readers`] for details.
+pub fn from_files<W>(opt: &Options, inputs: &[(PathBuf, Input)], writer: W) -> io::Result<()>
+where
+    W: Write,
+{
+    let stdin = io::stdin();
+    let mut stdin_added = false;
+    let mut readers: Vec<(Box<dyn BufRead>, Input)> = Vec::with_capacity(inputs.len());
+    for (path, input) in inputs {
+        let reader: Box<dyn BufRead> = if path.to_str() == Some("-") {
+            if stdin_added {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "STDIN (-) can only be merged once",
+                ));
+            }
+            stdin_added = true;
+            Box::new(stdin.lock())
+        } else {
+            Box::new(io::BufReader::with_capacity(
+                READER_CAPACITY,
+                File::open(path)?,
+            ))
+        };
+        readers.push((reader, input.clone()));
+    }
+    from_readers(opt, readers, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(opt: &Options, inputs: Vec<(&'static str, Input)>) -> String {
+        let mut result = Vec::new();
+        let inputs = inputs
+            .into_iter()
+            .map(|(text, input)| (text.as_bytes(), input));
+        from_readers(opt, inputs, &mut result).unwrap();
+        String::from_utf8(result).unwrap()
+    }
+
+    #[test]
+    fn counts_are_weighted_before_rounding() {
+        let half = Input {
+            weight: 0.5,
+            ..Default::default()
+        };
+        let result = merge(
+            &Options::default(),
+            vec![("main;a 1\nmain;b 2\n", half.clone()), ("main;a 1\n", half)],
+        );
+        assert_eq!(result, "main;a 1\nmain;b 1\n");
+    }
+
+    #[test]
+    fn negative_and_nan_weights_are_rejected() {
+        for &weight in &[-1.0, f64::NAN] {
+            let input = Input {
+                weight,
+                ..Default::default()
+            };
+            let inputs = vec![(&b"main;a 1\n"[..], input)];
+            let error = from_readers(&Options::default(), inputs, io::sink()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn normalize_to_largest_input() {
+        let opt = Options {
+            normalize: Some(Normalize::Largest),
+        };
+        let root = |name: &str| Input {
+            root: Some(name.to_string()),
+            ..Default::default()
+        };
+        let result = merge(
+            &opt,
+            vec![
+                ("main;a 10\nmain;b 30\n", root("host1")),
+                ("main;a 2\nmain;b 2\n", root("host2")),
+            ],
+        );
+        assert_eq!(
+            result,
+            "host1;main;a 10\nhost1;main;b 30\nhost2;main;a 20\nhost2;main;b 20\n"
+        );
+    }
+}
@@ -0,0 +1,456 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use ahash::AHashMap;
+use log::{error, warn};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::flamegraph::{rfind_samples, rfind_score};
+use crate::folded::FoldedStacks;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+/// The format a report is written in.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum ReportFormat {
+    /// A table with aligned columns, preceded by the total number of samples and stacks.
+    #[default]
+    Text,
+
+    /// Comma-separated values with a header row, and percentages without a `%` sign.
+    Csv,
+
+    /// A JSON object with the `total` number of samples, the number of distinct `stacks`, and the
+    /// rows of the table as `functions`.
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ReportFormat::Text),
+            "csv" => Ok(ReportFormat::Csv),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!("unknown report format: {}", s)),
+        }
+    }
+}
+
+/// The column a report is sorted by, in decreasing order.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum SortBy {
+    /// The samples in which a function is the leaf frame.
+    #[default]
+    SelfSamples,
+
+    /// The samples in which a function appears anywhere on the stack.
+    TotalSamples,
+
+    /// The number of distinct stacks a function appears in.
+    Stacks,
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "self" => Ok(SortBy::SelfSamples),
+            "total" => Ok(SortBy::TotalSamples),
+            "stacks" => Ok(SortBy::Stacks),
+            _ => Err(format!("unknown sort column: {}", s)),
+        }
+    }
+}
+
+/// Configure the generated report.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// The number of functions to list, or all of them if `None`.
+    ///
+    /// Defaults to `None`.
+    pub top: Option<usize>,
+
+    /// The column to sort the functions by. Ties are broken by the total samples (or by the self
+    /// samples when sorting by total samples), and then by function name.
+    ///
+    /// Defaults to the self samples.
+    pub sort_by: SortBy,
+
+    /// The format of the report.
+    ///
+    /// Defaults to a text table.
+    pub format: ReportFormat,
+
+    /// Only look at this many frames from the root of each stack.
+    ///
+    /// The samples of the frames further from the root count as self samples of the last frame
+    /// that is kept, just as if the stacks had been collected with a lower stack depth.
+    ///
+    /// Defaults to `None`, which keeps all frames.
+    pub max_depth: Option<usize>,
+
+    /// Report the functions that match one of these expressions as a single function.
+    ///
+    /// The group is named after the first capture group of the first expression that matches,
+    /// or after the expression itself when it has no capture groups. For example, `^std::` lumps
+    /// together everything in the standard library, and `^([^:]+)::` reports each crate as a
+    /// whole. Functions that don't match any of the expressions are reported on their own.
+    ///
+    /// Defaults to no groups.
+    pub groups: Vec<Regex>,
+}
+
+/// The samples of a function (or group of functions), summed over all stacks.
+#[derive(Debug, Default)]
+struct FunctionStats {
+    self_samples: usize,
+
+    /// Only counts each stack once, even when the function appears in it several times.
+    total_samples: usize,
+
+    stacks: usize,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    total: usize,
+    stacks: usize,
+    functions: Vec<Row<'a>>,
+}
+
+#[derive(Serialize)]
+struct Row<'a> {
+    name: &'a str,
+    #[serde(rename = "self")]
+    self_samples: usize,
+    self_pct: f64,
+    total: usize,
+    total_pct: f64,
+    stacks: usize,
+}
+
+/// Produce a report of the functions with the most samples from folded stack lines.
+///
+/// Each line has a semicolon-separated list of frame names followed by a sample count (e.g.,
+/// `main;foo;bar 12`), just like the lines read by [`flamegraph::from_lines`]. Lines with two
+/// sample counts, as written by [`differential`], are counted with the second one.
+///
+/// The report lists each function with the samples in which it is the leaf frame (self), the
+/// samples of the stacks it appears in (total), both as a percentage of all samples, and the
+/// number of distinct stacks it appears in.
+///
+///   [`flamegraph::from_lines`]: crate::flamegraph::from_lines
+///   [`differential`]: crate::differential
+pub fn from_lines<'a, I, W>(opt: &Options, lines: I, mut writer: W) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    let mut stacks = FoldedStacks::new();
+    let mut ignored = 0;
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match parse_line(line) {
+            Some((stack, count)) => stacks.add(stack, count),
+            None => ignored += 1,
+        }
+    }
+    if ignored != 0 {
+        warn!("Ignored {} lines with invalid format", ignored);
+    }
+
+    let total = stacks.total();
+    if total == 0 {
+        error!("No stack counts found");
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No stack counts found",
+        ));
+    }
+
+    let mut functions: AHashMap<Cow<'_, str>, FunctionStats> = AHashMap::default();
+    let mut seen = Vec::new();
+    let mut nstacks = 0;
+    for (stack, count) in &stacks {
+        if count == 0 {
+            continue;
+        }
+        nstacks += 1;
+
+        let depth = opt.max_depth.unwrap_or(usize::MAX);
+        seen.clear();
+        seen.extend(stack.frames().take(depth).map(|frame| group(opt, frame)));
+        if let Some(leaf) = seen.last() {
+            functions.entry(leaf.clone()).or_default().self_samples += count;
+        }
+        seen.sort_unstable();
+        seen.dedup();
+        for function in seen.drain(..) {
+            let stats = functions.entry(function).or_default();
+            stats.total_samples += count;
+            stats.stacks += 1;
+        }
+    }
+
+    let mut functions: Vec<_> = functions.into_iter().collect();
+    functions.sort_unstable_by(|(a_name, a), (b_name, b)| {
+        let key = |stats: &FunctionStats| match opt.sort_by {
+            SortBy::SelfSamples => (stats.self_samples, stats.total_samples),
+            SortBy::TotalSamples => (stats.total_samples, stats.self_samples),
+            SortBy::Stacks => (stats.stacks, stats.total_samples),
+        };
+        key(b).cmp(&key(a)).then_with(|| a_name.cmp(b_name))
+    });
+    functions.truncate(opt.top.unwrap_or(usize::MAX));
+
+    let pct = |samples: usize| (samples as f64 * 10000.0 / total as f64).round() / 100.0;
+    let report = Report {
+        total,
+        stacks: nstacks,
+        functions: functions
+            .iter()
+            .map(|(name, stats)| Row {
+                name,
+                self_samples: stats.self_samples,
+                self_pct: pct(stats.self_samples),
+                total: stats.total_samples,
+                total_pct: pct(stats.total_samples),
+                stacks: stats.stacks,
+            })
+            .collect(),
+    };
+
+    match opt.format {
+        ReportFormat::Text => write_text(&report, writer),
+        ReportFormat::Csv => write_csv(&report, writer),
+        ReportFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &report)?;
+            writeln!(writer)
+        }
+    }
+}
+
+/// Produce a report from a set of readers that contain folded stack lines.
+///
+/// See [`from_lines`] for the expected format of each line.
+pub fn from_readers<R, W>(opt: &Options, readers: R, writer: W) -> io::Result<()>
+where
+    R: IntoIterator,
+    R::Item: Read,
+    W: Write,
+{
+    let mut input = String::new();
+    for mut reader in readers {
+        reader.read_to_string(&mut input)?;
+    }
+    from_lines(opt, input.lines(), writer)
+}
+
+/// Produce a report from files that contain folded stack lines and write it to `writer`.
+///
+/// If files is empty, or is just `-`, STDIN will be used as input.
+pub fn from_files<W>(opt: &Options, files: &[PathBuf], writer: W) -> io::Result<()>
+where
+    W: Write,
+{
+    let stdin = io::stdin();
+    let mut stdin_added = false;
+    let mut readers: Vec<Box<dyn Read>> = Vec::with_capacity(files.len().max(1));
+    for infile in files.iter() {
+        if infile.to_str() == Some("-") {
+            if !stdin_added {
+                readers.push(Box::new(io::BufReader::with_capacity(
+                    READER_CAPACITY,
+                    stdin.lock(),
+                )));
+                stdin_added = true;
+            }
+        } else {
+            readers.push(Box::new(File::open(infile)?));
+        }
+    }
+    if readers.is_empty() {
+        readers.push(Box::new(io::BufReader::with_capacity(
+            READER_CAPACITY,
+            stdin.lock(),
+        )));
+    }
+    from_readers(opt, readers, writer)
+}
+
+// Splits a folded stack line into its stack and its sample count. The fractional part of the
+// sample count, if any, is dropped.
+fn parse_line(line: &str) -> Option<(&str, usize)> {
+    // Differentials of several runs end with a significance score, which isn't needed here.
+    let line = match rfind_score(line) {
+        Some(scorei) => line[..scorei].trim_end(),
+        None => line,
+    };
+    let (samplesi, doti) = rfind_samples(line)?;
+    let count = line[samplesi..samplesi + doti].parse().ok()?;
+    let mut stack = line[..samplesi].trim_end();
+    // The first of two sample counts is the one from before, in differential input.
+    if let Some((firsti, _)) = rfind_samples(stack) {
+        stack = stack[..firsti].trim_end();
+    }
+    if stack.is_empty() {
+        None
+    } else {
+        Some((stack, count))
+    }
+}
+
+fn group<'a>(opt: &Options, function: &'a str) -> Cow<'a, str> {
+    for re in &opt.groups {
+        if let Some(captures) = re.captures(function) {
+            return match captures.get(1) {
+                Some(name) => Cow::Borrowed(name.as_str()),
+                None => Cow::Owned(re.as_str().to_string()),
+            };
+        }
+    }
+    Cow::Borrowed(function)
+}
+
+fn write_text<W>(report: &Report<'_>, mut writer: W) -> io::Result<()>
+where
+    W: Write,
+{
+    writeln!(
+        writer,
+        "Total: {} samples in {} stacks\n",
+        report.total, report.stacks
+    )?;
+
+    let rows: Vec<_> = report
+        .functions
+        .iter()
+        .map(|row| {
+            let columns = vec![
+                row.self_samples.to_string(),
+                format!("{:.2}%", row.self_pct),
+                row.total.to_string(),
+                format!("{:.2}%", row.total_pct),
+                row.stacks.to_string(),
+            ];
+            (columns, row.name)
+        })
+        .collect();
+    write_table(
+        writer,
+        &["Self", "Self%", "Total", "Total%", "Stacks"],
+        &rows,
+    )
+}
+
+/// Writes a table of right-aligned columns under `headers`, followed by the function of each row
+/// in a last, left-aligned column.
+pub(crate) fn write_table<W>(
+    mut writer: W,
+    headers: &[&str],
+    rows: &[(Vec<String>, &str)],
+) -> io::Result<()>
+where
+    W: Write,
+{
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            rows.iter()
+                .map(|(columns, _)| columns[i].len())
+                .chain(Some(header.len()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    for (header, &width) in headers.iter().zip(&widths) {
+        write!(writer, "{:>width$}  ", header, width = width)?;
+    }
+    writeln!(writer, "Function")?;
+    for (columns, function) in rows {
+        for (column, &width) in columns.iter().zip(&widths) {
+            write!(writer, "{:>width$}  ", column, width = width)?;
+        }
+        writeln!(writer, "{}", function)?;
+    }
+    Ok(())
+}
+
+fn write_csv<W>(report: &Report<'_>, mut writer: W) -> io::Result<()>
+where
+    W: Write,
+{
+    writeln!(writer, "function,self,self_pct,total,total_pct,stacks")?;
+    for row in &report.functions {
+        writeln!(
+            writer,
+            "{},{},{:.2},{},{:.2},{}",
+            csv_field(row.name),
+            row.self_samples,
+            row.self_pct,
+            row.total,
+            row.total_pct,
+            row.stacks
+        )?;
+    }
+    Ok(())
+}
+
+// Quotes a CSV field if needed, as described in RFC 4180.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_line_takes_the_last_sample_count() {
+        assert_eq!(parse_line("main;foo 12"), Some(("main;foo", 12)));
+        assert_eq!(parse_line("main;foo 1.5"), Some(("main;foo", 1)));
+        assert_eq!(parse_line("main;foo 3 5"), Some(("main;foo", 5)));
+        assert_eq!(parse_line("main;foo 3 5 -1.25"), Some(("main;foo", 5)));
+        assert_eq!(parse_line("main;foo bar 5"), Some(("main;foo bar", 5)));
+        assert_eq!(parse_line("main;foo"), None);
+        assert_eq!(parse_line(" 5"), None);
+    }
+
+    #[test]
+    fn group_uses_the_first_matching_expression() {
+        let opt = Options {
+            groups: vec![
+                Regex::new("^std::").unwrap(),
+                Regex::new("^([^:]+)::").unwrap(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(group(&opt, "std::vec::Vec::push"), "^std::");
+        assert_eq!(group(&opt, "serde_json::de::parse"), "serde_json");
+        assert_eq!(group(&opt, "main"), "main");
+    }
+
+    #[test]
+    fn csv_field_is_quoted_when_needed() {
+        assert_eq!(csv_field("main"), "main");
+        assert_eq!(csv_field("HashMap<K, V>::get"), "\"HashMap<K, V>::get\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
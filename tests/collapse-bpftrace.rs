@@ -0,0 +1,151 @@
+mod common;
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor};
+use std::process::{Command, Stdio};
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::collapse::bpftrace::{Folder, Options};
+use log::Level;
+use pretty_assertions::assert_eq;
+use testing_logger::CapturedLog;
+
+fn test_collapse_bpftrace(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    for &n in &[1, 2] {
+        let mut options = options.clone();
+        options.nthreads = n;
+        common::test_collapse(Folder::from(options), test_file, expected_file, false)?;
+    }
+    Ok(())
+}
+
+fn test_collapse_bpftrace_logs<F>(input_file: &str, asserter: F)
+where
+    F: Fn(&Vec<CapturedLog>),
+{
+    // We must run log tests in a single thread to play nicely with `testing_logger`.
+    let mut options = Options::default();
+    options.nthreads = 1;
+    common::test_collapse_logs(Folder::from(options), input_file, asserter);
+}
+
+#[test]
+fn collapse_bpftrace_kstack_ustack_comm() {
+    let test_file = "./tests/data/collapse-bpftrace/kstack-ustack-comm.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/kstack-ustack-comm-default.txt";
+    test_collapse_bpftrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_bpftrace_kstack_ustack_comm_kernel() {
+    let test_file = "./tests/data/collapse-bpftrace/kstack-ustack-comm.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/kstack-ustack-comm-kernel.txt";
+    let mut options = Options::default();
+    options.annotate_kernel = true;
+    test_collapse_bpftrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_bpftrace_kstack_ustack_comm_addrs() {
+    let test_file = "./tests/data/collapse-bpftrace/kstack-ustack-comm.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/kstack-ustack-comm-addrs.txt";
+    let mut options = Options::default();
+    options.include_addrs = true;
+    test_collapse_bpftrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_bpftrace_kstack() {
+    let test_file = "./tests/data/collapse-bpftrace/kstack.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/kstack-default.txt";
+    test_collapse_bpftrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_bpftrace_perf_mode() {
+    let test_file = "./tests/data/collapse-bpftrace/perf-mode.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/perf-mode-default.txt";
+    test_collapse_bpftrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_bpftrace_bcc_profile() {
+    let test_file = "./tests/data/collapse-bpftrace/bcc-profile.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/bcc-profile-default.txt";
+    test_collapse_bpftrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_bpftrace_bcc_profile_kernel() {
+    let test_file = "./tests/data/collapse-bpftrace/bcc-profile.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/bcc-profile-kernel.txt";
+    let mut options = Options::default();
+    options.annotate_kernel = true;
+    test_collapse_bpftrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_bpftrace_should_warn_about_weird_input_lines() {
+    test_collapse_bpftrace_logs(
+        "./tests/data/collapse-bpftrace/weird-line.txt",
+        |captured_logs| {
+            let nwarnings = captured_logs
+                .iter()
+                .filter(|log| {
+                    log.body.starts_with("Weird folded stack line: ") && log.level == Level::Warn
+                })
+                .count();
+            assert_eq!(
+                nwarnings, 1,
+                "bad lines warning logged {} times, but should be logged exactly once",
+                nwarnings
+            );
+        },
+    );
+}
+
+#[test]
+fn collapse_bpftrace_should_error_on_truncated_input() {
+    let mut options = Options::default();
+    options.nthreads = 1;
+    let error = common::test_collapse_error(
+        Folder::from(options),
+        "./tests/data/collapse-bpftrace/truncated.txt",
+    );
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn collapse_bpftrace_cli() {
+    let input_file = "./tests/data/collapse-bpftrace/kstack-ustack-comm.txt";
+    let expected_file = "./tests/data/collapse-bpftrace/results/kstack-ustack-comm-kernel.txt";
+
+    // Test with file passed in
+    let output = Command::cargo_bin("inferno-collapse-bpftrace")
+        .unwrap()
+        .arg("--kernel")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+
+    // Test with STDIN
+    let mut child = Command::cargo_bin("inferno-collapse-bpftrace")
+        .unwrap()
+        .arg("--kernel")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let mut input = BufReader::new(File::open(input_file).unwrap());
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::copy(&mut input, stdin).unwrap();
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
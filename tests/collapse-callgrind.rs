@@ -0,0 +1,145 @@
+mod common;
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor};
+use std::process::{Command, Stdio};
+
+use assert_cmd::prelude::*;
+use inferno::collapse::callgrind::{Folder, Options};
+use log::Level;
+use pretty_assertions::assert_eq;
+use testing_logger::CapturedLog;
+
+fn test_collapse_callgrind(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+fn test_collapse_callgrind_error(test_file: &str, options: Options) -> io::Error {
+    common::test_collapse_error(Folder::from(options), test_file)
+}
+
+fn test_collapse_callgrind_logs<F>(input_file: &str, asserter: F)
+where
+    F: Fn(&Vec<CapturedLog>),
+{
+    common::test_collapse_logs(Folder::default(), input_file, asserter);
+}
+
+#[test]
+fn collapse_callgrind_default() {
+    let test_file = "./tests/data/collapse-callgrind/callgrind.out";
+    let result_file = "./tests/data/collapse-callgrind/results/callgrind-default.txt";
+    test_collapse_callgrind(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_callgrind_into() {
+    let test_file = "./tests/data/collapse-callgrind/callgrind.out";
+    let result_file = "./tests/data/collapse-callgrind/results/callgrind-default.txt";
+    common::test_collapse_into(Folder::default(), test_file, result_file).unwrap()
+}
+
+#[test]
+fn collapse_callgrind_event() {
+    let test_file = "./tests/data/collapse-callgrind/callgrind.out";
+    let result_file = "./tests/data/collapse-callgrind/results/callgrind-dr.txt";
+
+    let mut options = Options::default();
+    options.event = Some("Dr".to_string());
+
+    test_collapse_callgrind(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_callgrind_cachegrind() {
+    let test_file = "./tests/data/collapse-callgrind/cachegrind.out";
+    let result_file = "./tests/data/collapse-callgrind/results/cachegrind-d1mr.txt";
+
+    let mut options = Options::default();
+    options.event = Some("D1mr".to_string());
+
+    test_collapse_callgrind(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_callgrind_should_log_warning_for_ending_before_events() {
+    test_collapse_callgrind_logs(
+        "./tests/data/collapse-callgrind/end-before-events.out",
+        |captured_logs| {
+            let nwarnings = captured_logs
+                .iter()
+                .filter(|log| {
+                    log.body == "File ended before events line" && log.level == Level::Warn
+                })
+                .count();
+            assert_eq!(
+                nwarnings, 1,
+                "warning logged {} times, but should be logged exactly once",
+                nwarnings
+            );
+        },
+    );
+}
+
+#[test]
+fn collapse_callgrind_should_return_error_for_unknown_event() {
+    let test_file = "./tests/data/collapse-callgrind/callgrind.out";
+    let mut options = Options::default();
+    options.event = Some("Cycles".to_string());
+    let error = test_collapse_callgrind_error(test_file, options);
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("Event Cycles not found"));
+}
+
+#[test]
+fn collapse_callgrind_should_return_error_for_invalid_cost() {
+    let test_file = "./tests/data/collapse-callgrind/invalid-cost.out";
+    let error = test_collapse_callgrind_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("Invalid cost"));
+}
+
+#[test]
+fn collapse_callgrind_should_return_error_for_unknown_compressed_name() {
+    let test_file = "./tests/data/collapse-callgrind/unknown-compressed-name.out";
+    let error = test_collapse_callgrind_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("Unknown compressed name"));
+}
+
+#[test]
+fn collapse_callgrind_cli() {
+    let input_file = "./tests/data/collapse-callgrind/callgrind.out";
+    let expected_file = "./tests/data/collapse-callgrind/results/callgrind-dr.txt";
+
+    // Test with file passed in
+    let output = Command::cargo_bin("inferno-collapse-callgrind")
+        .unwrap()
+        .arg("--event")
+        .arg("Dr")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+
+    // Test with STDIN
+    let mut child = Command::cargo_bin("inferno-collapse-callgrind")
+        .unwrap()
+        .arg("--event")
+        .arg("Dr")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let mut input = BufReader::new(File::open(input_file).unwrap());
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::copy(&mut input, stdin).unwrap();
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
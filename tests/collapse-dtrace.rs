@@ -5,6 +5,7 @@ use std::io::{self, BufReader, Cursor};
 use std::process::{Command, Stdio};
 
 use assert_cmd::cargo::CommandCargoExt;
+use inferno::collapse::demangle::{self, Demangle};
 use inferno::collapse::dtrace::{Folder, Options};
 use log::Level;
 use pretty_assertions::assert_eq;
@@ -60,6 +61,29 @@ fn collapse_dtrace_compare_to_upstream_java() {
     test_collapse_dtrace(test_file, result_file, Options::default()).unwrap()
 }
 
+#[test]
+fn collapse_dtrace_lines() {
+    let test_file = "./tests/data/collapse-dtrace/locations.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/locations.txt";
+
+    let mut options = Options::default();
+    options.include_lines = true;
+
+    test_collapse_dtrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_dtrace_lines_with_offsets() {
+    let test_file = "./tests/data/collapse-dtrace/locations.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/locations-offsets.txt";
+
+    let mut options = Options::default();
+    options.include_lines = true;
+    options.includeoffset = true;
+
+    test_collapse_dtrace(test_file, result_file, options).unwrap()
+}
+
 #[test]
 fn collapse_dtrace_hex_addresses() {
     let test_file = "./tests/data/collapse-dtrace/hex-addresses.txt";
@@ -117,6 +141,44 @@ fn collapse_dtrace_rust_names() {
     test_collapse_dtrace(test_file, result_file, Options::default()).unwrap()
 }
 
+#[test]
+fn collapse_dtrace_cpp_templates() {
+    let test_file = "./tests/data/collapse-dtrace/cpp-templates.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/cpp-templates.txt";
+    test_collapse_dtrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_dtrace_cpp_templates_demangled() {
+    let test_file = "./tests/data/collapse-dtrace/cpp-templates.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/cpp-templates-demangled.txt";
+    let mut options = Options::default();
+    options.keep_cpp_signatures = true;
+    common::test_collapse(
+        Demangle::new(Folder::from(options), demangle::Options::default()),
+        test_file,
+        result_file,
+        false,
+    )
+    .unwrap()
+}
+
+#[test]
+fn collapse_dtrace_demangle_cli() {
+    let input_file = "./tests/data/collapse-dtrace/cpp-templates.txt";
+    let expected_file = "./tests/data/collapse-dtrace/results/cpp-templates-simplify-templates.txt";
+
+    let output = Command::cargo_bin("inferno-collapse-dtrace")
+        .unwrap()
+        .arg("--demangle")
+        .arg("--simplify-templates")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
+
 #[test]
 fn collapse_dtrace_cli() {
     let input_file = "./flamegraph/example-dtrace-stacks.txt";
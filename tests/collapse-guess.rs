@@ -85,6 +85,55 @@ fn collapse_guess_vtune() {
     test_collapse_guess(test_file, result_file, false).unwrap()
 }
 
+#[test]
+fn collapse_guess_bpftrace() {
+    let test_file = "./tests/data/collapse-bpftrace/kstack-ustack-comm.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/kstack-ustack-comm-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_bcc() {
+    let test_file = "./tests/data/collapse-bpftrace/bcc-profile.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/bcc-profile-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_xdebug() {
+    let test_file = "./tests/data/collapse-xdebug/trace.xt";
+    let result_file = "./tests/data/collapse-xdebug/results/trace-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_chrome() {
+    let test_file = "./tests/data/collapse-chrome/node.cpuprofile";
+    let result_file = "./tests/data/collapse-chrome/results/node-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_chrome_pretty_printed() {
+    let test_file = "./tests/data/collapse-chrome/hit-counts.cpuprofile";
+    let result_file = "./tests/data/collapse-chrome/results/hit-counts-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_xperf() {
+    let test_file = "./tests/data/collapse-xperf/wpa-cpu.csv";
+    let result_file = "./tests/data/collapse-xperf/results/wpa-cpu-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_into() {
+    let test_file = "./tests/data/collapse-xperf/wpa-cpu.csv";
+    let result_file = "./tests/data/collapse-xperf/results/wpa-cpu-default.txt";
+    common::test_collapse_into(Folder::default(), test_file, result_file).unwrap()
+}
+
 #[test]
 fn collapse_guess_unknown_format_should_log_error() {
     test_collapse_guess_logs(
@@ -106,6 +155,27 @@ fn collapse_guess_unknown_format_should_log_error() {
     );
 }
 
+#[test]
+fn collapse_guess_folded_should_log_error() {
+    test_collapse_guess_logs(
+        "./tests/data/collapse-perf/results/cpp-stacks-std-function-collapsed.txt",
+        |captured_logs| {
+            let nerrors = captured_logs
+                .iter()
+                .filter(|log| {
+                    log.level == Level::Error
+                        && log.body == "No applicable collapse implementation found for input"
+                })
+                .count();
+            assert_eq!(
+                nerrors, 1,
+                "bad lines error logged {} times, but should be logged exactly once",
+                nerrors
+            );
+        },
+    );
+}
+
 #[test]
 fn collapse_guess_invalid_perf_should_log_error() {
     test_collapse_guess_logs(
@@ -0,0 +1,115 @@
+mod common;
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor};
+use std::process::{Command, Stdio};
+
+use assert_cmd::prelude::*;
+use inferno::collapse::jfr::{Folder, Options, Weight};
+use log::Level;
+use pretty_assertions::assert_eq;
+use testing_logger::CapturedLog;
+
+fn test_collapse_jfr(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+fn test_collapse_jfr_error(test_file: &str, options: Options) -> io::Error {
+    common::test_collapse_error(Folder::from(options), test_file)
+}
+
+fn test_collapse_jfr_logs<F>(input_file: &str, asserter: F)
+where
+    F: Fn(&Vec<CapturedLog>),
+{
+    common::test_collapse_logs(Folder::default(), input_file, asserter);
+}
+
+#[test]
+fn collapse_jfr_cpu() {
+    let test_file = "./tests/data/collapse-jfr/recording.jfr";
+    let result_file = "./tests/data/collapse-jfr/results/recording-cpu.txt";
+    test_collapse_jfr(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_jfr_into() {
+    let test_file = "./tests/data/collapse-jfr/recording.jfr";
+    let result_file = "./tests/data/collapse-jfr/results/recording-cpu.txt";
+    common::test_collapse_into(Folder::default(), test_file, result_file).unwrap()
+}
+
+#[test]
+fn collapse_jfr_allocation() {
+    let test_file = "./tests/data/collapse-jfr/recording.jfr";
+    let result_file = "./tests/data/collapse-jfr/results/recording-alloc.txt";
+
+    let mut options = Options::default();
+    options.weight = Weight::Allocation;
+
+    test_collapse_jfr(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_jfr_should_log_warning_for_empty_file() {
+    test_collapse_jfr_logs("./tests/data/collapse-jfr/empty.jfr", |captured_logs| {
+        let nwarnings = captured_logs
+            .iter()
+            .filter(|log| log.body == "File is empty" && log.level == Level::Warn)
+            .count();
+        assert_eq!(
+            nwarnings, 1,
+            "warning logged {} times, but should be logged exactly once",
+            nwarnings
+        );
+    });
+}
+
+#[test]
+fn collapse_jfr_should_return_error_for_truncated_recording() {
+    let test_file = "./tests/data/collapse-jfr/truncated.jfr";
+    let error = test_collapse_jfr_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("JFR chunk is truncated"));
+}
+
+#[test]
+fn collapse_jfr_should_return_error_for_old_version() {
+    let test_file = "./tests/data/collapse-jfr/version1.jfr";
+    let error = test_collapse_jfr_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("Unsupported JFR version 1"));
+}
+
+#[test]
+fn collapse_jfr_cli() {
+    let input_file = "./tests/data/collapse-jfr/recording.jfr";
+    let expected_file = "./tests/data/collapse-jfr/results/recording-alloc.txt";
+
+    // Test with file passed in
+    let output = Command::cargo_bin("inferno-collapse-jfr")
+        .unwrap()
+        .arg("--weight")
+        .arg("alloc")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+
+    // Test with STDIN
+    let mut child = Command::cargo_bin("inferno-collapse-jfr")
+        .unwrap()
+        .arg("--weight")
+        .arg("alloc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let mut input = BufReader::new(File::open(input_file).unwrap());
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::copy(&mut input, stdin).unwrap();
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
@@ -1,14 +1,18 @@
 mod common;
 
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, BufReader, Cursor};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
 use assert_cmd::cargo::CommandCargoExt;
-use inferno::collapse::perf::{Folder, Options};
+use inferno::collapse::demangle::{self, Demangle};
+use inferno::collapse::perf::{self, Folder, GroupBy, Options};
+use inferno::collapse::symbolize;
+use inferno::collapse::Collapse;
 use log::Level;
 use pretty_assertions::assert_eq;
+use regex::Regex;
 use testing_logger::CapturedLog;
 
 fn test_collapse_perf(
@@ -30,6 +34,34 @@ fn test_collapse_perf(
     Ok(())
 }
 
+fn test_collapse_perf_data(test_file: &str, expected_file: &str, mut options: Options) {
+    options.symfs = Some(PathBuf::from("./tests/data/collapse-perf/perf-data"));
+    options.kallsyms = Some(PathBuf::from(
+        "./tests/data/collapse-perf/perf-data/kallsyms",
+    ));
+    common::test_collapse(
+        perf::data::Folder::from(options),
+        test_file,
+        expected_file,
+        false,
+    )
+    .unwrap();
+}
+
+fn test_collapse_perf_demangled(test_file: &str, expected_file: &str, demangle: demangle::Options) {
+    for &n in &[1, 2] {
+        let mut options = Options::default();
+        options.nthreads = n;
+        common::test_collapse(
+            Demangle::new(Folder::from(options), demangle),
+            test_file,
+            expected_file,
+            false,
+        )
+        .unwrap();
+    }
+}
+
 fn test_collapse_perf_logs_with_options<F>(input_file: &str, asserter: F, mut options: Options)
 where
     F: Fn(&Vec<CapturedLog>),
@@ -247,6 +279,65 @@ fn collapse_perf_example_perf_stacks() {
     .unwrap();
 }
 
+#[test]
+fn collapse_perf_data() {
+    test_collapse_perf_data(
+        "./tests/data/collapse-perf/perf-data/hello.data",
+        "./tests/data/collapse-perf/results/perf-data-hello-collapsed.txt",
+        Default::default(),
+    );
+}
+
+#[test]
+fn collapse_perf_data_into() {
+    let mut options = Options::default();
+    options.symfs = Some(PathBuf::from("./tests/data/collapse-perf/perf-data"));
+    options.kallsyms = Some(PathBuf::from(
+        "./tests/data/collapse-perf/perf-data/kallsyms",
+    ));
+    common::test_collapse_into(
+        perf::data::Folder::from(options),
+        "./tests/data/collapse-perf/perf-data/hello.data",
+        "./tests/data/collapse-perf/results/perf-data-hello-collapsed.txt",
+    )
+    .unwrap();
+}
+
+#[test]
+fn collapse_perf_data_all_tid() {
+    test_collapse_perf_data(
+        "./tests/data/collapse-perf/perf-data/hello.data",
+        "./tests/data/collapse-perf/results/perf-data-hello-collapsed-all-tid.txt",
+        options_from_vec(vec!["all", "tid"]),
+    );
+}
+
+#[test]
+fn collapse_perf_data_multiple_events() {
+    test_collapse_perf_data(
+        "./tests/data/collapse-perf/perf-data/multi-event.data",
+        "./tests/data/collapse-perf/results/perf-data-multi-event-collapsed.txt",
+        Default::default(),
+    );
+
+    let mut options = Options::default();
+    options.event_filter = Some("instructions".to_string());
+    test_collapse_perf_data(
+        "./tests/data/collapse-perf/perf-data/multi-event.data",
+        "./tests/data/collapse-perf/results/perf-data-multi-event-collapsed-instructions.txt",
+        options,
+    );
+}
+
+#[test]
+fn collapse_perf_data_rejects_script_output() {
+    let mut folder = perf::data::Folder::default();
+    let err = folder
+        .collapse_file(Some("./tests/data/collapse-perf/go-stacks.txt"), io::sink())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
 #[test]
 fn collapse_perf_should_warn_about_empty_input_lines() {
     test_collapse_perf_logs(
@@ -317,3 +408,325 @@ fn collapse_perf_cli() {
     let expected = BufReader::new(File::open(expected_file).unwrap());
     common::compare_results(Cursor::new(output.stdout), expected, expected_file, true);
 }
+
+#[test]
+fn collapse_perf_data_cli() {
+    let input_file = "./tests/data/collapse-perf/perf-data/hello.data";
+    let expected_file = "./tests/data/collapse-perf/results/perf-data-hello-collapsed-all-tid.txt";
+
+    let output = Command::cargo_bin("inferno-collapse-perf")
+        .unwrap()
+        .arg("--input-format=data")
+        .arg("--symfs=./tests/data/collapse-perf/perf-data")
+        .arg("--kallsyms=./tests/data/collapse-perf/perf-data/kallsyms")
+        .arg("--all")
+        .arg("--tid")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
+
+#[test]
+fn collapse_perf_demangle() {
+    let test_file = "./tests/data/collapse-perf/mangled-stacks.txt";
+    let result_file = "./tests/data/collapse-perf/results/mangled-stacks-demangled.txt";
+    test_collapse_perf_demangled(test_file, result_file, demangle::Options::default());
+}
+
+#[test]
+fn collapse_perf_demangle_keep_rust_hash() {
+    let test_file = "./tests/data/collapse-perf/mangled-stacks.txt";
+    let result_file =
+        "./tests/data/collapse-perf/results/mangled-stacks-demangled-keep-rust-hash.txt";
+    let mut options = demangle::Options::default();
+    options.keep_rust_hash = true;
+    test_collapse_perf_demangled(test_file, result_file, options);
+}
+
+#[test]
+fn collapse_perf_demangle_cli() {
+    let input_file = "./tests/data/collapse-perf/mangled-stacks.txt";
+    let expected_file =
+        "./tests/data/collapse-perf/results/mangled-stacks-demangled-simplify-templates.txt";
+
+    let output = Command::cargo_bin("inferno-collapse-perf")
+        .unwrap()
+        .arg("--demangle")
+        .arg("--simplify-templates")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
+
+#[test]
+fn collapse_perf_srclines() {
+    let test_file = "./tests/data/collapse-perf/srcline.txt";
+    let result_file = "./tests/data/collapse-perf/results/srcline.txt";
+    test_collapse_perf(test_file, result_file, Options::default(), false).unwrap();
+}
+
+#[test]
+fn collapse_perf_srclines_with_lines() {
+    let test_file = "./tests/data/collapse-perf/srcline.txt";
+    let result_file = "./tests/data/collapse-perf/results/srcline-lines.txt";
+
+    let mut options = Options::default();
+    options.include_lines = true;
+    test_collapse_perf(test_file, result_file, options, false).unwrap();
+}
+
+#[test]
+fn collapse_perf_srclines_should_not_warn() {
+    test_collapse_perf_logs("./tests/data/collapse-perf/srcline.txt", |captured_logs| {
+        let nwarnings = captured_logs
+            .iter()
+            .filter(|log| log.level == Level::Warn)
+            .count();
+        assert_eq!(
+            nwarnings, 0,
+            "srcline lines were logged as weird stack lines"
+        );
+    });
+}
+
+fn test_collapse_perf_grouped(result_file: &str, group_by: GroupBy) {
+    let test_file = "./tests/data/collapse-perf/workers.txt";
+    let mut options = Options::default();
+    options.group_by = Some(group_by);
+    test_collapse_perf(test_file, result_file, options, false).unwrap();
+}
+
+#[test]
+fn collapse_perf_group_by_process() {
+    let result_file = "./tests/data/collapse-perf/results/workers-process.txt";
+    test_collapse_perf_grouped(result_file, GroupBy::Process);
+}
+
+#[test]
+fn collapse_perf_group_by_thread() {
+    let result_file = "./tests/data/collapse-perf/results/workers-thread.txt";
+    test_collapse_perf_grouped(result_file, GroupBy::Thread);
+}
+
+#[test]
+fn collapse_perf_group_by_cpu() {
+    let result_file = "./tests/data/collapse-perf/results/workers-cpu.txt";
+    test_collapse_perf_grouped(result_file, GroupBy::Cpu);
+}
+
+#[test]
+fn collapse_perf_group_by_thread_name() {
+    let result_file = "./tests/data/collapse-perf/results/workers-thread-name.txt";
+    let patterns = vec![Regex::new("^(worker)-[0-9]+$").unwrap()];
+    test_collapse_perf_grouped(result_file, GroupBy::ThreadName(patterns));
+}
+
+#[test]
+fn collapse_perf_group_by_thread_name_without_capture_group() {
+    let result_file = "./tests/data/collapse-perf/results/workers-thread-name-semicolon.txt";
+    let patterns = vec![Regex::new("^worker-[0-9]+$|^pool;worker$").unwrap()];
+    test_collapse_perf_grouped(result_file, GroupBy::ThreadName(patterns));
+}
+
+#[test]
+fn collapse_perf_group_pattern_without_thread_name_cli() {
+    let output = Command::cargo_bin("inferno-collapse-perf")
+        .unwrap()
+        .arg("--group-by")
+        .arg("thread")
+        .arg("--group-pattern")
+        .arg("^(worker)-[0-9]+$")
+        .arg("./tests/data/collapse-perf/workers.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("requires --group-by thread-name"));
+}
+
+#[test]
+fn collapse_perf_group_by_output_dir_cli() {
+    let input_file = "./tests/data/collapse-perf/workers.txt";
+    let expected_dir = Path::new("./tests/data/collapse-perf/results/workers-split");
+    let output_dir = std::env::temp_dir().join(format!("workers-{}", rand::random::<u64>()));
+
+    let output = Command::cargo_bin("inferno-collapse-perf")
+        .unwrap()
+        .arg("--group-by")
+        .arg("thread-name")
+        .arg("--group-pattern")
+        .arg("^(worker)-[0-9]+$")
+        .arg("--output-dir")
+        .arg(&output_dir)
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let mut files: Vec<_> = fs::read_dir(&output_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        vec!["batch_job.folded", "server.folded", "worker.folded"]
+    );
+    for file in files {
+        let expected_file = expected_dir.join(&file);
+        let expected = BufReader::new(File::open(&expected_file).unwrap());
+        let result = BufReader::new(File::open(output_dir.join(&file)).unwrap());
+        common::compare_results(result, expected, expected_file.to_str().unwrap(), false);
+    }
+    fs::remove_dir_all(&output_dir).unwrap();
+}
+
+#[test]
+fn collapse_perf_all_events() {
+    let test_file = "./tests/data/collapse-perf/events.txt";
+    let result_file = "./tests/data/collapse-perf/results/events-all.txt";
+
+    let mut options = Options::default();
+    options.all_events = true;
+    test_collapse_perf(test_file, result_file, options, false).unwrap();
+}
+
+#[test]
+fn collapse_perf_all_events_with_periods() {
+    let test_file = "./tests/data/collapse-perf/events.txt";
+    let result_file = "./tests/data/collapse-perf/results/events-periods.txt";
+
+    let mut options = Options::default();
+    options.all_events = true;
+    options.count_periods = true;
+    test_collapse_perf(test_file, result_file, options, false).unwrap();
+}
+
+#[test]
+fn collapse_perf_all_events_output_dir_cli() {
+    let input_file = "./tests/data/collapse-perf/events.txt";
+    let expected_dir = Path::new("./tests/data/collapse-perf/results/events-split");
+    let output_dir = std::env::temp_dir().join(format!("events-{}", rand::random::<u64>()));
+
+    let output = Command::cargo_bin("inferno-collapse-perf")
+        .unwrap()
+        .arg("--all-events")
+        .arg("--output-dir")
+        .arg(&output_dir)
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+
+    let mut files: Vec<_> = fs::read_dir(&output_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            "cache-misses.folded",
+            "cycles.folded",
+            "instructions.folded"
+        ]
+    );
+    for file in files {
+        let expected_file = expected_dir.join(&file);
+        let expected = BufReader::new(File::open(&expected_file).unwrap());
+        let result = BufReader::new(File::open(output_dir.join(&file)).unwrap());
+        common::compare_results(result, expected, expected_file.to_str().unwrap(), false);
+    }
+    fs::remove_dir_all(&output_dir).unwrap();
+}
+
+#[test]
+fn collapse_perf_output_dir_requires_grouping_cli() {
+    let output = Command::cargo_bin("inferno-collapse-perf")
+        .unwrap()
+        .arg("--output-dir")
+        .arg(std::env::temp_dir())
+        .arg("./tests/data/collapse-perf/events.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn collapse_perf_ratio_cli() {
+    let input_file = "./tests/data/collapse-perf/events.txt";
+    let expected_file = "./tests/data/collapse-perf/results/events-ratio.txt";
+
+    let output = Command::cargo_bin("inferno-collapse-perf")
+        .unwrap()
+        .arg("--ratio")
+        .arg("instructions")
+        .arg("cycles")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
+
+#[test]
+fn collapse_perf_ratio_of_missing_event_cli() {
+    let output = Command::cargo_bin("inferno-collapse-perf")
+        .unwrap()
+        .arg("--ratio")
+        .arg("instructionz")
+        .arg("cycles")
+        .arg("./tests/data/collapse-perf/events.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("no stacks of the event instructionz"));
+}
+
+#[test]
+fn collapse_perf_symbolize_unknown_addresses() {
+    let test_file = "./tests/data/collapse-perf/unknown-addresses.txt";
+    let result_file = "./tests/data/collapse-perf/results/unknown-addresses-symbolized.txt";
+
+    let mut symbolize = symbolize::Options::default();
+    symbolize.maps = Some(PathBuf::from("./tests/data/symbolize/app.maps"));
+    symbolize.symfs = Some(PathBuf::from("./tests/data/symbolize"));
+    symbolize.inlines = true;
+    let mut options = Options::default();
+    options.symbolize = Some(symbolize);
+    test_collapse_perf(test_file, result_file, options, false).unwrap();
+}
+
+#[test]
+fn collapse_perf_symbolize_one_process() {
+    let test_file = "./tests/data/collapse-perf/unknown-addresses-pids.txt";
+    let result_file = "./tests/data/collapse-perf/results/unknown-addresses-pids-symbolized.txt";
+
+    let mut symbolize = symbolize::Options::default();
+    symbolize.maps = Some(PathBuf::from("./tests/data/symbolize/app.maps"));
+    symbolize.symfs = Some(PathBuf::from("./tests/data/symbolize"));
+    symbolize.pid = Some(4242);
+    let mut options = Options::default();
+    options.symbolize = Some(symbolize);
+    test_collapse_perf(test_file, result_file, options, false).unwrap();
+}
+
+#[test]
+fn collapse_perf_symbolize_missing_maps() {
+    let mut symbolize = symbolize::Options::default();
+    symbolize.maps = Some(PathBuf::from("./tests/data/symbolize/missing.maps"));
+    let mut options = Options::default();
+    options.symbolize = Some(symbolize);
+    let error = Folder::new(options).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+}
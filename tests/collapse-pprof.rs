@@ -0,0 +1,149 @@
+mod common;
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor};
+use std::process::{Command, Stdio};
+
+use assert_cmd::prelude::*;
+use inferno::collapse::pprof::{self, ExportOptions, Folder, Options};
+use inferno::collapse::Collapse;
+use log::Level;
+use pretty_assertions::assert_eq;
+use testing_logger::CapturedLog;
+
+fn test_collapse_pprof(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+fn test_collapse_pprof_error(test_file: &str, options: Options) -> io::Error {
+    common::test_collapse_error(Folder::from(options), test_file)
+}
+
+fn test_collapse_pprof_logs<F>(input_file: &str, asserter: F)
+where
+    F: Fn(&Vec<CapturedLog>),
+{
+    common::test_collapse_logs(Folder::default(), input_file, asserter);
+}
+
+#[test]
+fn collapse_pprof_default() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let result_file = "./tests/data/collapse-pprof/results/cpu-default.txt";
+    test_collapse_pprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_pprof_into() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let result_file = "./tests/data/collapse-pprof/results/cpu-default.txt";
+    common::test_collapse_into(Folder::default(), test_file, result_file).unwrap()
+}
+
+#[test]
+fn collapse_pprof_uncompressed() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb";
+    let result_file = "./tests/data/collapse-pprof/results/cpu-default.txt";
+    test_collapse_pprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_pprof_sample_index() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let result_file = "./tests/data/collapse-pprof/results/cpu-samples.txt";
+
+    let mut options = Options::default();
+    options.sample_index = Some(0);
+
+    test_collapse_pprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_pprof_should_log_warning_for_empty_file() {
+    test_collapse_pprof_logs("./tests/data/collapse-pprof/empty.pb", |captured_logs| {
+        let nwarnings = captured_logs
+            .iter()
+            .filter(|log| log.body == "File is empty" && log.level == Level::Warn)
+            .count();
+        assert_eq!(
+            nwarnings, 1,
+            "warning logged {} times, but should be logged exactly once",
+            nwarnings
+        );
+    });
+}
+
+#[test]
+fn collapse_pprof_should_return_error_for_invalid_sample_index() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let mut options = Options::default();
+    options.sample_index = Some(2);
+    let error = test_collapse_pprof_error(test_file, options);
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error
+        .to_string()
+        .starts_with("Sample index 2 is out of range"));
+}
+
+#[test]
+fn collapse_pprof_should_return_error_for_invalid_profile() {
+    let test_file = "./tests/data/collapse-pprof/not-a-profile.pb.gz";
+    let error = test_collapse_pprof_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("Unsupported wire type"));
+}
+
+#[test]
+fn collapse_pprof_export_roundtrip() {
+    let folded_file = "./tests/data/collapse-pprof/results/cpu-samples.txt";
+    let mut profile = Vec::new();
+    pprof::export(
+        &ExportOptions::default(),
+        BufReader::new(File::open(folded_file).unwrap()),
+        &mut profile,
+    )
+    .unwrap();
+
+    let mut collapsed = Vec::new();
+    Folder::default()
+        .collapse(&profile[..], &mut collapsed)
+        .unwrap();
+    let expected = BufReader::new(File::open(folded_file).unwrap());
+    common::compare_results(Cursor::new(collapsed), expected, folded_file, false);
+}
+
+#[test]
+fn collapse_pprof_cli() {
+    let input_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let expected_file = "./tests/data/collapse-pprof/results/cpu-samples.txt";
+
+    // Test with file passed in
+    let output = Command::cargo_bin("inferno-collapse-pprof")
+        .unwrap()
+        .arg("--sample-index")
+        .arg("0")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+
+    // Test exporting with STDIN, and collapsing the exported profile again
+    let mut child = Command::cargo_bin("inferno-collapse-pprof")
+        .unwrap()
+        .arg("--export")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let mut input = BufReader::new(File::open(expected_file).unwrap());
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::copy(&mut input, stdin).unwrap();
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    let mut collapsed = Vec::new();
+    Folder::default()
+        .collapse(&output.stdout[..], &mut collapsed)
+        .unwrap();
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(collapsed), expected, expected_file, false);
+}
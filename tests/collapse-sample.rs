@@ -39,6 +39,13 @@ fn collapse_sample_default() {
     test_collapse_sample(test_file, result_file, Options::default()).unwrap()
 }
 
+#[test]
+fn collapse_sample_into() {
+    let test_file = "./tests/data/collapse-sample/sample.txt";
+    let result_file = "./tests/data/collapse-sample/results/sample-default.txt";
+    common::test_collapse_into(Folder::default(), test_file, result_file).unwrap()
+}
+
 #[test]
 fn collapse_sample_no_modules() {
     let test_file = "./tests/data/collapse-sample/sample.txt";
@@ -39,6 +39,13 @@ fn collapse_vtune_default() {
     test_collapse_vtune(test_file, result_file, Options::default()).unwrap()
 }
 
+#[test]
+fn collapse_vtune_into() {
+    let test_file = "./tests/data/collapse-vtune/vtune.csv";
+    let result_file = "./tests/data/collapse-vtune/results/vtune-default.txt";
+    common::test_collapse_into(Folder::default(), test_file, result_file).unwrap()
+}
+
 #[test]
 fn collapse_vtune_no_modules() {
     let test_file = "./tests/data/collapse-vtune/vtune.csv";
@@ -0,0 +1,140 @@
+mod common;
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor};
+use std::process::{Command, Stdio};
+
+use assert_cmd::prelude::*;
+use inferno::collapse::xdebug::{Folder, Options};
+use inferno::collapse::Collapse;
+use log::Level;
+use pretty_assertions::assert_eq;
+use testing_logger::CapturedLog;
+
+fn test_collapse_xdebug(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+fn test_collapse_xdebug_error(test_file: &str, options: Options) -> io::Error {
+    common::test_collapse_error(Folder::from(options), test_file)
+}
+
+fn test_collapse_xdebug_logs<F>(input_file: &str, asserter: F)
+where
+    F: Fn(&Vec<CapturedLog>),
+{
+    common::test_collapse_logs(Folder::default(), input_file, asserter);
+}
+
+#[test]
+fn collapse_xdebug_default() {
+    let test_file = "./tests/data/collapse-xdebug/trace.xt";
+    let result_file = "./tests/data/collapse-xdebug/results/trace-default.txt";
+    test_collapse_xdebug(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_xdebug_into() {
+    let test_file = "./tests/data/collapse-xdebug/trace.xt";
+    let result_file = "./tests/data/collapse-xdebug/results/trace-default.txt";
+    common::test_collapse_into(Folder::default(), test_file, result_file).unwrap()
+}
+
+#[test]
+fn collapse_xdebug_inclusive() {
+    let test_file = "./tests/data/collapse-xdebug/trace.xt";
+    let result_file = "./tests/data/collapse-xdebug/results/trace-inclusive.txt";
+
+    let mut options = Options::default();
+    options.inclusive = true;
+
+    test_collapse_xdebug(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_xdebug_unfinished() {
+    let test_file = "./tests/data/collapse-xdebug/unfinished.xt";
+    let result_file = "./tests/data/collapse-xdebug/results/unfinished-default.txt";
+    test_collapse_xdebug(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_xdebug_should_log_warning_for_ending_before_trace() {
+    test_collapse_xdebug_logs(
+        "./tests/data/collapse-xdebug/end-before-trace.xt",
+        |captured_logs| {
+            let nwarnings = captured_logs
+                .iter()
+                .filter(|log| {
+                    log.body == "File ended before start of trace" && log.level == Level::Warn
+                })
+                .count();
+            assert_eq!(
+                nwarnings, 1,
+                "warning logged {} times, but should be logged exactly once",
+                nwarnings
+            );
+        },
+    );
+}
+
+#[test]
+fn collapse_xdebug_should_return_error_for_invalid_time() {
+    let test_file = "./tests/data/collapse-xdebug/invalid-time.xt";
+    let error = test_collapse_xdebug_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("Invalid time index"));
+}
+
+#[test]
+fn collapse_xdebug_after_error() {
+    // The calls of a trace that failed don't end up in the next one.
+    let mut folder = Folder::default();
+    let invalid = "TRACE START [2020-12-01 10:00:00.000001]\n\
+                   1\t0\t0\t0.000100\t393216\tstale\t1\t\t/var/www/app/stale.php\t0\t0\n\
+                   2\t1\t0\tsoon\t393216\tfoo\t1\t\t/var/www/app/stale.php\t3\t0\n";
+    assert!(folder.collapse(invalid.as_bytes(), io::sink()).is_err());
+
+    let test_file = "./tests/data/collapse-xdebug/trace.xt";
+    let result_file = "./tests/data/collapse-xdebug/results/trace-default.txt";
+    common::test_collapse(folder, test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_xdebug_should_return_error_for_human_readable_trace() {
+    let test_file = "./tests/data/collapse-xdebug/human-readable.xt";
+    let error = test_collapse_xdebug_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("Unable to parse trace line"));
+}
+
+#[test]
+fn collapse_xdebug_cli() {
+    let input_file = "./tests/data/collapse-xdebug/trace.xt";
+    let expected_file = "./tests/data/collapse-xdebug/results/trace-inclusive.txt";
+
+    // Test with file passed in
+    let output = Command::cargo_bin("inferno-collapse-xdebug")
+        .unwrap()
+        .arg("--inclusive")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+
+    // Test with STDIN
+    let mut child = Command::cargo_bin("inferno-collapse-xdebug")
+        .unwrap()
+        .arg("--inclusive")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let mut input = BufReader::new(File::open(input_file).unwrap());
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::copy(&mut input, stdin).unwrap();
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
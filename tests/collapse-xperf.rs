@@ -0,0 +1,132 @@
+mod common;
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor};
+use std::process::{Command, Stdio};
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::collapse::xperf::{Folder, Options};
+use log::Level;
+use pretty_assertions::assert_eq;
+use testing_logger::CapturedLog;
+
+fn test_collapse_xperf(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    for &n in &[1, 2] {
+        let mut options = options.clone();
+        options.nthreads = n;
+        common::test_collapse(Folder::from(options), test_file, expected_file, false)?;
+    }
+    Ok(())
+}
+
+fn test_collapse_xperf_logs<F>(input_file: &str, asserter: F)
+where
+    F: Fn(&Vec<CapturedLog>),
+{
+    // We must run log tests in a single thread to play nicely with `testing_logger`.
+    let mut options = Options::default();
+    options.nthreads = 1;
+    common::test_collapse_logs(Folder::from(options), input_file, asserter);
+}
+
+#[test]
+fn collapse_xperf_wpa_cpu() {
+    let test_file = "./tests/data/collapse-xperf/wpa-cpu.csv";
+    let result_file = "./tests/data/collapse-xperf/results/wpa-cpu-default.txt";
+    test_collapse_xperf(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_xperf_wpa_cpu_tid() {
+    let test_file = "./tests/data/collapse-xperf/wpa-cpu.csv";
+    let result_file = "./tests/data/collapse-xperf/results/wpa-cpu-tid.txt";
+    let mut options = Options::default();
+    options.include_tid = true;
+    test_collapse_xperf(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_xperf_wpa_cpu_no_modules() {
+    let test_file = "./tests/data/collapse-xperf/wpa-cpu.csv";
+    let result_file = "./tests/data/collapse-xperf/results/wpa-cpu-no-modules.txt";
+    let mut options = Options::default();
+    options.no_modules = true;
+    test_collapse_xperf(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_xperf_sample_count_pid() {
+    let test_file = "./tests/data/collapse-xperf/sample-count.csv";
+    let result_file = "./tests/data/collapse-xperf/results/sample-count-pid.txt";
+    let mut options = Options::default();
+    options.include_pid = true;
+    test_collapse_xperf(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_xperf_should_warn_about_empty_input() {
+    test_collapse_xperf_logs("./tests/data/collapse-xperf/empty.csv", |captured_logs| {
+        let nwarnings = captured_logs
+            .iter()
+            .filter(|log| log.body == "File ended before header" && log.level == Level::Warn)
+            .count();
+        assert_eq!(
+            nwarnings, 1,
+            "empty file warning logged {} times, but should be logged exactly once",
+            nwarnings
+        );
+    });
+}
+
+#[test]
+fn collapse_xperf_should_error_on_missing_header() {
+    let mut options = Options::default();
+    options.nthreads = 1;
+    let error = common::test_collapse_error(
+        Folder::from(options),
+        "./tests/data/collapse-xperf/no-header.csv",
+    );
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn collapse_xperf_should_error_on_invalid_weight() {
+    let mut options = Options::default();
+    options.nthreads = 1;
+    let error = common::test_collapse_error(
+        Folder::from(options),
+        "./tests/data/collapse-xperf/invalid-weight.csv",
+    );
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn collapse_xperf_cli() {
+    let input_file = "./tests/data/collapse-xperf/wpa-cpu.csv";
+    let expected_file = "./tests/data/collapse-xperf/results/wpa-cpu-tid.txt";
+
+    // Test with file passed in
+    let output = Command::cargo_bin("inferno-collapse-xperf")
+        .unwrap()
+        .arg("--tid")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+
+    // Test with STDIN
+    let mut child = Command::cargo_bin("inferno-collapse-xperf")
+        .unwrap()
+        .arg("--tid")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let mut input = BufReader::new(File::open(input_file).unwrap());
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::copy(&mut input, stdin).unwrap();
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
@@ -2,6 +2,7 @@ use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader, Cursor};
 
 use inferno::collapse::Collapse;
+use inferno::folded::FoldedStacks;
 use libflate::gzip::Decoder;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
@@ -93,6 +94,32 @@ where
     Ok(())
 }
 
+/// Checks that `collapse_into` adds the same stacks that `collapse` writes to `expected_filename`.
+pub fn test_collapse_into<C>(
+    mut collapser: C,
+    test_filename: &str,
+    expected_filename: &str,
+) -> io::Result<()>
+where
+    C: Collapse,
+{
+    let mut stacks = FoldedStacks::new();
+    let test_file = File::open(test_filename)?;
+    if test_filename.ends_with(".gz") {
+        let r = BufReader::new(Decoder::new(test_file).unwrap());
+        collapser.collapse_into(r, &mut stacks)?;
+    } else {
+        collapser.collapse_into(BufReader::new(test_file), &mut stacks)?;
+    }
+
+    let mut result = Cursor::new(Vec::new());
+    stacks.write_to(&mut result)?;
+    result.set_position(0);
+    let expected = BufReader::new(File::open(expected_filename)?);
+    compare_results(result, expected, expected_filename, false);
+    Ok(())
+}
+
 pub fn test_collapse_logs<C, F>(mut collapser: C, input_file: &str, asserter: F)
 where
     C: Collapse,
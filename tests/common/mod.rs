@@ -2,4 +2,6 @@
 
 mod collapse;
 
-pub use self::collapse::{compare_results, test_collapse, test_collapse_error, test_collapse_logs};
+pub use self::collapse::{
+    compare_results, test_collapse, test_collapse_error, test_collapse_into, test_collapse_logs,
+};
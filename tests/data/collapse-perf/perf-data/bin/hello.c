@@ -0,0 +1,5 @@
+__attribute__((noinline)) int bar(int x) { return x * 3 + 1; }
+__attribute__((noinline)) int foo(int x) { return bar(x) + bar(x + 1); }
+__attribute__((noinline)) int baz(int x) { return x ^ 0x55; }
+int main(void) { return foo(1) + baz(2); }
+void _start(void) { main(); for (;;) {} }
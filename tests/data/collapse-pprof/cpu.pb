@@ -0,0 +1,12 @@
+
+
+(	* 
+* 
+* 
+* 
+* 
+""""""(""	""
+
+	
+
+2 2samples2count2cpu2nanoseconds2	main.main2main.compute2	main.hash2runtime.main2/tmp/app2main.go2runtime/proc.go2main.(*cache).getZ`P
\ No newline at end of file
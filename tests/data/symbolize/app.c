@@ -0,0 +1,17 @@
+static inline __attribute__((always_inline)) int square(int x) {
+    return x * x;
+}
+
+__attribute__((noinline)) int compute(int x) {
+    int total = 0;
+    for (int i = 0; i < x; i++) {
+        total += square(i + total);
+    }
+    return total;
+}
+
+__attribute__((noinline)) int parse(int x) { return x ^ 0x55; }
+
+int main(void) { return compute(7) + parse(2); }
+
+void _start(void) { main(); for (;;) {} }
@@ -5,9 +5,10 @@ use std::io::{self, BufRead, BufReader, Cursor};
 use std::process::Command;
 
 use assert_cmd::cargo::CommandCargoExt;
-use inferno::differential::{self, Options};
+use inferno::differential::{self, AlignOptions, Options, Score};
 use log::Level;
 use pretty_assertions::assert_eq;
+use regex::Regex;
 use testing_logger::CapturedLog;
 
 fn test_diff_folded(
@@ -47,6 +48,53 @@ fn test_diff_folded(
     Ok(())
 }
 
+fn test_diff_folded_runs(
+    before: &[&str],
+    after: &[&str],
+    expected_result_file: &str,
+    options: Options,
+    score: Score,
+) -> io::Result<()> {
+    if let Err(e) = fs::metadata(expected_result_file) {
+        if e.kind() == io::ErrorKind::NotFound {
+            // be nice to the dev and make the file
+            let f = File::create(expected_result_file).unwrap();
+            differential::from_multiple_files(options, score, before, after, f)?;
+        } else {
+            return Err(e);
+        }
+    }
+
+    let mut result = Vec::new();
+    differential::from_multiple_files(options, score, before, after, &mut result)?;
+    let expected = fs::read_to_string(expected_result_file).unwrap();
+    assert_eq!(String::from_utf8(result).unwrap(), expected);
+    Ok(())
+}
+
+fn test_diff_folded_aligned(
+    infile1: &str,
+    infile2: &str,
+    expected_result_file: &str,
+    align: &AlignOptions,
+) -> io::Result<()> {
+    if let Err(e) = fs::metadata(expected_result_file) {
+        if e.kind() == io::ErrorKind::NotFound {
+            // be nice to the dev and make the file
+            let f = File::create(expected_result_file).unwrap();
+            differential::from_files_aligned(Default::default(), align, infile1, infile2, f)?;
+        } else {
+            return Err(e);
+        }
+    }
+
+    let mut result = Vec::new();
+    differential::from_files_aligned(Default::default(), align, infile1, infile2, &mut result)?;
+    let expected = BufReader::new(File::open(expected_result_file).unwrap());
+    compare_results(Cursor::new(result), expected, expected_result_file);
+    Ok(())
+}
+
 fn compare_results<R, E>(result: R, expected: E, expected_file: &str)
 where
     R: BufRead,
@@ -148,6 +196,121 @@ fn diff_folded_fractional_samples() {
     test_diff_folded(infile1, infile2, expected_result_file, Default::default()).unwrap();
 }
 
+const BEFORE_RUNS: [&str; 3] = [
+    "./tests/data/diff-folded/runs/before1.txt",
+    "./tests/data/diff-folded/runs/before2.txt",
+    "./tests/data/diff-folded/runs/before3.txt",
+];
+
+const AFTER_RUNS: [&str; 3] = [
+    "./tests/data/diff-folded/runs/after1.txt",
+    "./tests/data/diff-folded/runs/after2.txt",
+    "./tests/data/diff-folded/runs/after3.txt",
+];
+
+#[test]
+fn diff_folded_multiple_runs() {
+    let expected_result_file = "./tests/data/diff-folded/results/runs_welch.txt";
+
+    test_diff_folded_runs(
+        &BEFORE_RUNS,
+        &AFTER_RUNS,
+        expected_result_file,
+        Default::default(),
+        Score::default(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn diff_folded_multiple_runs_poisson() {
+    let expected_result_file = "./tests/data/diff-folded/results/runs_poisson.txt";
+
+    test_diff_folded_runs(
+        &BEFORE_RUNS,
+        &AFTER_RUNS,
+        expected_result_file,
+        Default::default(),
+        Score::Poisson,
+    )
+    .unwrap();
+}
+
+#[test]
+fn diff_folded_multiple_runs_normalize() {
+    let expected_result_file = "./tests/data/diff-folded/results/runs_normalize.txt";
+
+    let opt = Options {
+        normalize: true,
+        ..Default::default()
+    };
+    test_diff_folded_runs(
+        &BEFORE_RUNS,
+        &AFTER_RUNS,
+        expected_result_file,
+        opt,
+        Score::default(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn diff_folded_multiple_runs_needs_both_profiles() {
+    let result = differential::from_multiple_files(
+        Default::default(),
+        Score::default(),
+        &BEFORE_RUNS,
+        &[],
+        io::sink(),
+    );
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+fn align_options() -> AlignOptions {
+    let mut align = AlignOptions::default();
+    align.strip_rust_hashes = true;
+    align.strip_generics = true;
+    align.rename = vec![(Regex::new("^app::").unwrap(), String::new())];
+    align.drop = vec![Regex::new("^with_retry$").unwrap()];
+    align
+}
+
+#[test]
+fn diff_folded_aligned() {
+    let infile1 = "./tests/data/diff-folded/align/before.txt";
+    let infile2 = "./tests/data/diff-folded/align/after.txt";
+    let expected_result_file = "./tests/data/diff-folded/results/aligned.txt";
+
+    test_diff_folded_aligned(infile1, infile2, expected_result_file, &align_options()).unwrap();
+}
+
+#[test]
+fn diff_folded_function_deltas() {
+    let infile1 = "./tests/data/diff-folded/align/before.txt";
+    let infile2 = "./tests/data/diff-folded/align/after.txt";
+    let expected_result_file = "./tests/data/diff-folded/results/functions.txt";
+
+    let functions = differential::function_deltas_from_files(
+        Default::default(),
+        &align_options(),
+        infile1,
+        infile2,
+    )
+    .unwrap();
+    let hash = functions.iter().find(|f| f.function == "hash").unwrap();
+    assert_eq!((hash.self_delta(), hash.total_delta()), (30, 30));
+
+    if fs::metadata(expected_result_file).is_err() {
+        // be nice to the dev and make the file
+        let f = File::create(expected_result_file).unwrap();
+        differential::write_function_deltas(&functions, f).unwrap();
+    }
+    let mut result = Vec::new();
+    differential::write_function_deltas(&functions, &mut result).unwrap();
+    let expected = fs::read_to_string(expected_result_file).unwrap();
+    assert_eq!(String::from_utf8(result).unwrap(), expected);
+}
+
 #[test]
 fn diff_folded_should_log_warning_on_bad_input_line() {
     test_diff_folded_logs(
@@ -206,3 +369,53 @@ fn diff_folded_cli() {
     let expected = BufReader::new(File::open(expected_file).unwrap());
     compare_results(Cursor::new(output.stdout), expected, expected_file);
 }
+
+#[test]
+fn diff_folded_multiple_runs_cli() {
+    let expected_file = "./tests/data/diff-folded/results/runs_poisson.txt";
+
+    let output = Command::cargo_bin("inferno-diff-folded")
+        .unwrap()
+        .arg("--score")
+        .arg("poisson")
+        .arg("-b")
+        .arg(BEFORE_RUNS[0])
+        .arg("-b")
+        .arg(BEFORE_RUNS[1])
+        .arg("-b")
+        .arg(BEFORE_RUNS[2])
+        .arg("-a")
+        .arg(AFTER_RUNS[0])
+        .arg("-a")
+        .arg(AFTER_RUNS[1])
+        .arg("-a")
+        .arg(AFTER_RUNS[2])
+        .output()
+        .expect("failed to execute process");
+    let expected = fs::read_to_string(expected_file).unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
+
+#[test]
+fn diff_folded_functions_cli() {
+    let infile1 = "./tests/data/diff-folded/align/before.txt";
+    let infile2 = "./tests/data/diff-folded/align/after.txt";
+    let expected_file = "./tests/data/diff-folded/results/functions.txt";
+
+    let output = Command::cargo_bin("inferno-diff-folded")
+        .unwrap()
+        .arg("--strip-rust-hash")
+        .arg("--strip-generics")
+        .arg("--rename")
+        .arg("^app::")
+        .arg("")
+        .arg("--drop-frame")
+        .arg("^with_retry$")
+        .arg("--functions")
+        .arg(infile1)
+        .arg(infile2)
+        .output()
+        .expect("failed to execute process");
+    let expected = fs::read_to_string(expected_file).unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
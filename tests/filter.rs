@@ -0,0 +1,121 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::filter::{self, Options};
+use log::Level;
+use pretty_assertions::assert_eq;
+use regex::Regex;
+
+fn test_filter(infile: &str, expected_result_file: &str, options: Options) -> io::Result<()> {
+    let infiles = [PathBuf::from(infile)];
+    if let Err(e) = fs::metadata(expected_result_file) {
+        if e.kind() == io::ErrorKind::NotFound {
+            // be nice to the dev and make the file
+            let f = File::create(expected_result_file).unwrap();
+            filter::from_files(&options, &infiles, f)?;
+        } else {
+            return Err(e);
+        }
+    }
+
+    let mut result = Vec::new();
+    filter::from_files(&options, &infiles, &mut result)?;
+    let expected = fs::read_to_string(expected_result_file).unwrap();
+    assert_eq!(String::from_utf8(result).unwrap(), expected);
+    Ok(())
+}
+
+#[test]
+fn filter_default_keeps_valid_lines() {
+    let input_file = "./tests/data/filter/stacks.txt";
+    let expected_result_file = "./tests/data/filter/results/default.txt";
+
+    test_filter(input_file, expected_result_file, Default::default()).unwrap();
+}
+
+#[test]
+fn filter_rename_and_fold_recursion() {
+    let input_file = "./tests/data/filter/stacks.txt";
+    let expected_result_file = "./tests/data/filter/results/rename.txt";
+
+    let mut options = Options::default();
+    options.rename = vec![
+        (Regex::new("::h[0-9a-f]{16}$").unwrap(), String::new()),
+        (Regex::new("^app::").unwrap(), String::new()),
+    ];
+    options.fold_recursion = true;
+    test_filter(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn filter_grep_and_exclude() {
+    let input_file = "./tests/data/filter/stacks.txt";
+    let expected_result_file = "./tests/data/filter/results/grep.txt";
+
+    let mut options = Options::default();
+    options.grep = vec![Regex::new("^app::run$").unwrap()];
+    options.exclude = vec![Regex::new("^std::thread::").unwrap()];
+    test_filter(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn filter_focus_prune_and_max_depth() {
+    let input_file = "./tests/data/filter/stacks.txt";
+    let expected_result_file = "./tests/data/filter/results/focus.txt";
+
+    let mut options = Options::default();
+    options.focus = Some(Regex::new("^app::run$").unwrap());
+    options.prune = Some(Regex::new("^app::parse$").unwrap());
+    options.max_depth = Some(3);
+    test_filter(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn filter_differential_keeps_both_counts() {
+    let input_file = "./tests/data/filter/differential.txt";
+    let expected_result_file = "./tests/data/filter/results/differential.txt";
+
+    let mut options = Options::default();
+    options.fold_recursion = true;
+    test_filter(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn filter_should_warn_about_bad_lines() {
+    testing_logger::setup();
+    let input = &b"main;foo 1\nbad line\n"[..];
+    filter::from_reader(&Options::default(), input, io::sink()).unwrap();
+    testing_logger::validate(|captured_logs| {
+        let nwarnings = captured_logs
+            .iter()
+            .filter(|log| {
+                log.body == "Ignored 1 lines with invalid format" && log.level == Level::Warn
+            })
+            .count();
+        assert_eq!(nwarnings, 1);
+    });
+}
+
+#[test]
+fn filter_cli() {
+    let input_file = "./tests/data/filter/stacks.txt";
+    let expected_file = "./tests/data/filter/results/rename.txt";
+
+    let output = Command::cargo_bin("inferno-filter")
+        .unwrap()
+        .arg("--rename")
+        .arg("::h[0-9a-f]{16}$")
+        .arg("")
+        .arg("--rename")
+        .arg("^app::")
+        .arg("")
+        .arg("--fold-recursion")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = fs::read_to_string(expected_file).unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
@@ -7,8 +7,12 @@ use std::process::{Command, Stdio};
 use std::str::FromStr;
 
 use assert_cmd::cargo::CommandCargoExt;
+use inferno::collapse::{perf, Collapse};
 use inferno::flamegraph::color::{BackgroundColor, PaletteMap};
-use inferno::flamegraph::{self, Direction, Options, Palette, TextTruncateDirection};
+use inferno::flamegraph::{
+    self, Direction, Options, OutputFormat, Palette, TextTruncateDirection, Unit,
+};
+use inferno::folded::FoldedStacks;
 use log::Level;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
@@ -187,6 +191,15 @@ fn flamegraph_differential_negated() {
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
+#[test]
+fn flamegraph_differential_significance() {
+    let input_file = "./tests/data/flamegraph/differential/significance.txt";
+    let expected_result_file = "./tests/data/flamegraph/differential/significance.svg";
+    let mut options = flamegraph::Options::default();
+    options.significance_threshold = Some(2.0);
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
 #[test]
 fn flamegraph_collor_diffusion() {
     let input_file = "./flamegraph/test/results/perf-vertx-stacks-01-collapsed-all.txt";
@@ -883,3 +896,423 @@ fn flamegraph_flamechart() {
 
     test_flamegraph(input_file, expected_result_file, opts).unwrap();
 }
+
+#[test]
+fn flamegraph_from_folded_matches_from_lines() {
+    let input_files = [
+        "./tests/data/collapse-perf/java-inline.txt",
+        "./tests/data/collapse-perf/go-stacks.txt",
+        "./tests/data/collapse-perf/cpp-stacks-std-function.txt",
+    ];
+
+    for input_file in &input_files {
+        let mut folder = perf::Folder::default();
+        let mut collapsed = Vec::new();
+        folder
+            .collapse_file(Some(input_file), &mut collapsed)
+            .unwrap();
+        let mut stacks = FoldedStacks::new();
+        folder
+            .collapse_into(BufReader::new(File::open(input_file).unwrap()), &mut stacks)
+            .unwrap();
+        let mut written = Vec::new();
+        stacks.write_to(&mut written).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            String::from_utf8(collapsed.clone()).unwrap()
+        );
+
+        for &reverse_stack_order in &[false, true] {
+            let mut opts = flamegraph::Options::default();
+            opts.hash = true;
+            opts.reverse_stack_order = reverse_stack_order;
+
+            let mut expected = Vec::new();
+            let lines = std::str::from_utf8(&collapsed).unwrap().lines();
+            flamegraph::from_lines(&mut opts, lines, &mut expected).unwrap();
+
+            let mut result = Vec::new();
+            flamegraph::from_folded(&mut opts, &stacks, &mut result).unwrap();
+            assert_eq!(
+                String::from_utf8(result).unwrap(),
+                String::from_utf8(expected).unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn flamegraph_unit_bytes() {
+    let input_file = "./tests/data/flamegraph/units/alloc.txt";
+    let expected_result_file = "./tests/data/flamegraph/units/bytes.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.unit = Unit::Bytes;
+    options.colors = Palette::from_str("mem").unwrap();
+    options.hash = true;
+    options.count_name = "bytes".to_string();
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_unit_percent() {
+    let input_file = "./tests/data/flamegraph/units/alloc.txt";
+    let expected_result_file = "./tests/data/flamegraph/units/percent.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.unit = Unit::Percent;
+    options.hash = true;
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_unit_nanoseconds_html() {
+    let input_file = "./tests/data/flamegraph/html/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/units/nanoseconds.html";
+
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Html;
+    options.unit = Unit::Nanoseconds;
+    options.factor = 1000.0;
+    options.hash = true;
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_unit_cli() {
+    let input_file = "./tests/data/flamegraph/units/alloc.txt";
+    let expected_file = "./tests/data/flamegraph/units/bytes.svg";
+
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--pretty-xml")
+        .arg("--no-javascript")
+        .arg("--hash")
+        .arg("--unit")
+        .arg("bytes")
+        .arg("--countname")
+        .arg("bytes")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(Cursor::new(output.stdout), expected, expected_file);
+}
+
+#[test]
+fn flamegraph_locations() {
+    let input_file = "./tests/data/flamegraph/locations/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/locations/locations.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.colors = Palette::from_str("java").unwrap();
+    options.hash = true;
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+#[cfg(feature = "nameattr")]
+fn flamegraph_locations_source_url() {
+    let input_file = "./tests/data/flamegraph/locations/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/locations/source-url.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options
+        .func_frameattrs
+        .set_source_url("https://example.com/app/blob/main/{file}#L{line}".to_string());
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+#[cfg(feature = "nameattr")]
+fn flamegraph_locations_source_url_html() {
+    let input_file = "./tests/data/flamegraph/locations/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/locations/source-url.html";
+
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Html;
+    options.hash = true;
+    options
+        .func_frameattrs
+        .set_source_url("https://example.com/app/blob/main/{file}#L{line}".to_string());
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+#[cfg(feature = "nameattr")]
+fn flamegraph_locations_source_url_cli() {
+    let input_file = "./tests/data/flamegraph/locations/stacks.txt";
+    let expected_file = "./tests/data/flamegraph/locations/source-url.svg";
+
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--pretty-xml")
+        .arg("--no-javascript")
+        .arg("--hash")
+        .arg("--source-url")
+        .arg("https://example.com/app/blob/main/{file}#L{line}")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(Cursor::new(output.stdout), expected, expected_file);
+}
+
+#[test]
+fn flamegraph_html() {
+    let input_file = "./tests/data/flamegraph/html/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/html/html.html";
+
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Html;
+    options.hash = true;
+    options.title = "<Title> & more".to_string();
+    options.subtitle = Some("Subtitle".to_string());
+    options.direction = Direction::Inverted;
+    options.colors = Palette::from_str("java").unwrap();
+    options.min_width = 5.0;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_html_cli() {
+    let input_file = "./tests/data/flamegraph/html/stacks.txt";
+    let expected_file = "./tests/data/flamegraph/html/html.html";
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--format")
+        .arg("html")
+        .arg("--pretty-xml")
+        .arg("--no-javascript")
+        .arg("--hash")
+        .arg("--title")
+        .arg("<Title> & more")
+        .arg("--subtitle")
+        .arg("Subtitle")
+        .arg("--inverted")
+        .arg("--colors")
+        .arg("java")
+        .arg("--minwidth")
+        .arg("5")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(Cursor::new(output.stdout), expected, expected_file);
+}
+
+#[test]
+#[cfg(feature = "nameattr")]
+fn flamegraph_html_differential_nameattr() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/html/diff-nameattr.html";
+    let nameattr_file = "./tests/data/flamegraph/nameattr/nameattr.txt";
+
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Html;
+    options.negate_differentials = true;
+    options.func_frameattrs =
+        flamegraph::FuncFrameAttrsMap::from_file(&PathBuf::from(nameattr_file)).unwrap();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_html_no_stacks() {
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Html;
+
+    let mut result = Vec::new();
+    let error = flamegraph::from_lines(&mut options, vec!["bad line"], &mut result).unwrap_err();
+    assert!(matches!(error, quick_xml::Error::Io(_)));
+    let result = String::from_utf8(result).unwrap();
+    assert!(result.starts_with("<!DOCTYPE html>"));
+    assert!(result.contains("ERROR: No valid input provided to flamegraph"));
+}
+
+#[test]
+fn flamegraph_json() {
+    let input_file = "./tests/data/flamegraph/html/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/json/d3.json";
+
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Json;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_json_differential() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/json/d3-diff.json";
+
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Json;
+    options.factor = 0.1;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_speedscope() {
+    let input_file = "./tests/data/flamegraph/html/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/json/speedscope.json";
+
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Speedscope;
+    options.title = "stacks".to_string();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_speedscope_flamechart() {
+    let input_file = "./tests/data/flamegraph/json/flamechart.txt";
+    let expected_result_file = "./tests/data/flamegraph/json/speedscope-flamechart.json";
+
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Speedscope;
+    options.title = flamegraph::defaults::CHART_TITLE.to_owned();
+    options.flame_chart = true;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_json_cli() {
+    let input_file = "./tests/data/flamegraph/html/stacks.txt";
+    for &(format, expected_file) in &[
+        ("json", "./tests/data/flamegraph/json/d3.json"),
+        ("speedscope", "./tests/data/flamegraph/json/speedscope.json"),
+    ] {
+        let output = Command::cargo_bin("inferno-flamegraph")
+            .unwrap()
+            .arg("--format")
+            .arg(format)
+            .arg("--pretty-xml")
+            .arg("--title")
+            .arg("stacks")
+            .arg(input_file)
+            .output()
+            .expect("failed to execute process");
+        let expected = BufReader::new(File::open(expected_file).unwrap());
+        compare_results(Cursor::new(output.stdout), expected, expected_file);
+    }
+}
+
+#[test]
+fn flamegraph_text() {
+    let input_file = "./tests/data/flamegraph/html/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/text/text.txt";
+
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Text;
+    options.hash = true;
+    options.image_width = Some(60);
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_text_inverted() {
+    let input_file = "./tests/data/flamegraph/html/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/text/inverted.txt";
+
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Text;
+    options.hash = true;
+    options.colors = Palette::from_str("java").unwrap();
+    options.direction = Direction::Inverted;
+    options.title = "Icicle Graph".to_string();
+    options.subtitle = Some("Subtitle".to_string());
+    options.text_truncate_direction = TextTruncateDirection::Right;
+    options.image_width = Some(40);
+    options.min_width = 10.0;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[cfg(feature = "raster")]
+fn decode_png(png: &[u8]) -> (png::OutputInfo, Vec<u8>) {
+    let decoder = png::Decoder::new(png);
+    let (info, mut reader) = decoder.read_info().unwrap();
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    (info, pixels)
+}
+
+#[test]
+#[cfg(feature = "raster")]
+fn flamegraph_png() {
+    let stacks = fs::read_to_string("./tests/data/flamegraph/html/stacks.txt").unwrap();
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Png;
+    options.hash = true;
+    options.image_width = Some(600);
+
+    let mut result = Vec::new();
+    flamegraph::from_lines(&mut options, stacks.lines(), &mut result).unwrap();
+    let (info, pixels) = decode_png(&result);
+    assert_eq!(info.color_type, png::ColorType::RGB);
+    assert_eq!(info.width, 600);
+
+    // Five frames high, plus the room for the title and details, as in the SVG.
+    let (frame_height, font_size) = (options.frame_height, options.font_size);
+    let height = 5 * frame_height + font_size * 3 + font_size * 2 + 10;
+    assert_eq!(info.height as usize, height);
+
+    let pixel = |x: usize, y: usize| {
+        let i = (y * 600 + x) * 3;
+        (pixels[i], pixels[i + 1], pixels[i + 2])
+    };
+    // The top of the default yellow background.
+    assert_eq!(pixel(0, 0), (0xee, 0xee, 0xee));
+    // The root frame spans the whole width, except for the padding.
+    let root_y = height - font_size * 2 - 10 - frame_height / 2;
+    assert_eq!(pixel(5, root_y), pixel(3, root_y));
+    assert_ne!(pixel(15, root_y), pixel(5, root_y));
+    assert_eq!(pixel(15, root_y), pixel(580, root_y));
+}
+
+#[test]
+#[cfg(feature = "raster")]
+fn flamegraph_png_no_stacks() {
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Png;
+
+    let mut result = Vec::new();
+    let error = flamegraph::from_lines(&mut options, vec!["bad line"], &mut result).unwrap_err();
+    assert!(matches!(error, quick_xml::Error::Io(_)));
+    let (info, _) = decode_png(&result);
+    assert_eq!(info.width, 1200);
+    assert_eq!(info.height as usize, options.font_size * 5);
+}
+
+#[test]
+fn flamegraph_text_cli() {
+    let input_file = "./tests/data/flamegraph/html/stacks.txt";
+    let expected_file = "./tests/data/flamegraph/text/inverted.txt";
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--format")
+        .arg("text")
+        .arg("--hash")
+        .arg("--colors")
+        .arg("java")
+        .arg("--inverted")
+        .arg("--subtitle")
+        .arg("Subtitle")
+        .arg("--truncate-text-right")
+        .arg("--min-width")
+        .arg("10")
+        .arg(input_file)
+        .env("COLUMNS", "40")
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(Cursor::new(output.stdout), expected, expected_file);
+}
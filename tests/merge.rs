@@ -0,0 +1,135 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::merge::{self, Input, Normalize, Options};
+use pretty_assertions::assert_eq;
+
+fn test_merge(
+    inputs: &[(PathBuf, Input)],
+    expected_result_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    if let Err(e) = fs::metadata(expected_result_file) {
+        if e.kind() == io::ErrorKind::NotFound {
+            // be nice to the dev and make the file
+            let f = File::create(expected_result_file).unwrap();
+            merge::from_files(&options, inputs, f)?;
+        } else {
+            return Err(e);
+        }
+    }
+
+    let mut result = Vec::new();
+    merge::from_files(&options, inputs, &mut result)?;
+    let expected = fs::read_to_string(expected_result_file).unwrap();
+    assert_eq!(String::from_utf8(result).unwrap(), expected);
+    Ok(())
+}
+
+fn input(path: &str, weight: f64, root: Option<&str>) -> (PathBuf, Input) {
+    let mut input = Input::default();
+    input.weight = weight;
+    input.root = root.map(String::from);
+    (PathBuf::from(path), input)
+}
+
+#[test]
+fn merge_sums_counts() {
+    let inputs = [
+        input("./tests/data/merge/web1.folded", 1.0, None),
+        input("./tests/data/merge/web2.folded", 1.0, None),
+    ];
+    let expected_result_file = "./tests/data/merge/results/sum.txt";
+
+    test_merge(&inputs, expected_result_file, Default::default()).unwrap();
+}
+
+#[test]
+fn merge_weighted_with_roots() {
+    let inputs = [
+        input("./tests/data/merge/web1.folded", 2.0, Some("web1")),
+        input("./tests/data/merge/web2.folded", 0.5, Some("web2")),
+    ];
+    let expected_result_file = "./tests/data/merge/results/weighted.txt";
+
+    test_merge(&inputs, expected_result_file, Default::default()).unwrap();
+}
+
+#[test]
+fn merge_normalized() {
+    let inputs = [
+        input("./tests/data/merge/web1.folded", 1.0, None),
+        input("./tests/data/merge/web2.folded", 0.5, None),
+    ];
+    let expected_result_file = "./tests/data/merge/results/normalized.txt";
+
+    let mut options = Options::default();
+    options.normalize = Some(Normalize::Total(100));
+    test_merge(&inputs, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn merge_cli() {
+    let expected_file = "./tests/data/merge/results/weighted.txt";
+
+    let output = Command::cargo_bin("inferno-merge")
+        .unwrap()
+        .arg("--prefix-root")
+        .arg("-w")
+        .arg("2")
+        .arg("./tests/data/merge/web1.folded")
+        .arg("-w")
+        .arg("0.5")
+        .arg("./tests/data/merge/web2.folded")
+        .output()
+        .expect("failed to execute process");
+    let expected = fs::read_to_string(expected_file).unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
+
+#[test]
+fn merge_cli_requires_a_weight_per_file() {
+    let output = Command::cargo_bin("inferno-merge")
+        .unwrap()
+        .arg("-w")
+        .arg("2")
+        .arg("./tests/data/merge/web1.folded")
+        .arg("./tests/data/merge/web2.folded")
+        .output()
+        .expect("failed to execute process");
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: 1 weights were given for 2 files\n"
+    );
+}
+
+#[test]
+fn merge_cli_reads_stdin_once() {
+    let output = Command::cargo_bin("inferno-merge")
+        .unwrap()
+        .arg("-")
+        .arg("-")
+        .stdin(Stdio::null())
+        .output()
+        .expect("failed to execute process");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn merge_cli_rejects_negative_weights() {
+    let output = Command::cargo_bin("inferno-merge")
+        .unwrap()
+        .arg("--weight=-1")
+        .arg("./tests/data/merge/web1.folded")
+        .output()
+        .expect("failed to execute process");
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: invalid weight: -1\n"
+    );
+}
@@ -0,0 +1,127 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::report::{self, Options, ReportFormat, SortBy};
+use log::Level;
+use pretty_assertions::assert_eq;
+use regex::Regex;
+
+fn test_report(infile: &str, expected_result_file: &str, options: Options) -> io::Result<()> {
+    let infiles = [PathBuf::from(infile)];
+    if let Err(e) = fs::metadata(expected_result_file) {
+        if e.kind() == io::ErrorKind::NotFound {
+            // be nice to the dev and make the file
+            let f = File::create(expected_result_file).unwrap();
+            report::from_files(&options, &infiles, f)?;
+        } else {
+            return Err(e);
+        }
+    }
+
+    let mut result = Vec::new();
+    report::from_files(&options, &infiles, &mut result)?;
+    let expected = fs::read_to_string(expected_result_file).unwrap();
+    assert_eq!(String::from_utf8(result).unwrap(), expected);
+    Ok(())
+}
+
+#[test]
+fn report_default() {
+    let input_file = "./tests/data/report/stacks.txt";
+    let expected_result_file = "./tests/data/report/results/default.txt";
+
+    test_report(input_file, expected_result_file, Default::default()).unwrap();
+}
+
+#[test]
+fn report_csv_by_total() {
+    let input_file = "./tests/data/report/stacks.txt";
+    let expected_result_file = "./tests/data/report/results/total.csv";
+
+    let mut options = Options::default();
+    options.format = ReportFormat::Csv;
+    options.sort_by = SortBy::TotalSamples;
+    options.top = Some(5);
+    test_report(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn report_json_grouped() {
+    let input_file = "./tests/data/report/stacks.txt";
+    let expected_result_file = "./tests/data/report/results/grouped.json";
+
+    let mut options = Options::default();
+    options.format = ReportFormat::Json;
+    options.groups = vec![
+        Regex::new("^std::").unwrap(),
+        Regex::new("^([a-z_]+)::").unwrap(),
+    ];
+    test_report(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn report_max_depth() {
+    let input_file = "./tests/data/report/stacks.txt";
+    let expected_result_file = "./tests/data/report/results/max_depth.txt";
+
+    let mut options = Options::default();
+    options.max_depth = Some(2);
+    options.sort_by = SortBy::Stacks;
+    test_report(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn report_differential_uses_second_count() {
+    let input_file = "./tests/data/report/differential.txt";
+    let expected_result_file = "./tests/data/report/results/differential.txt";
+
+    test_report(input_file, expected_result_file, Default::default()).unwrap();
+}
+
+#[test]
+fn report_should_warn_about_bad_lines() {
+    testing_logger::setup();
+    let _ = report::from_lines(
+        &Options::default(),
+        vec!["main;foo 1", "bad line", "bad line"],
+        io::sink(),
+    );
+    testing_logger::validate(|captured_logs| {
+        let nwarnings = captured_logs
+            .iter()
+            .filter(|log| {
+                log.body == "Ignored 2 lines with invalid format" && log.level == Level::Warn
+            })
+            .count();
+        assert_eq!(nwarnings, 1);
+    });
+}
+
+#[test]
+fn report_no_stacks() {
+    let error = report::from_lines(&Options::default(), vec!["bad line"], io::sink()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn report_cli() {
+    let input_file = "./tests/data/report/stacks.txt";
+    let expected_file = "./tests/data/report/results/total.csv";
+
+    let output = Command::cargo_bin("inferno-report")
+        .unwrap()
+        .arg("--format")
+        .arg("csv")
+        .arg("--sort")
+        .arg("total")
+        .arg("--top")
+        .arg("5")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = fs::read_to_string(expected_file).unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
@@ -0,0 +1,96 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::collapse::symbolize::{self, Options, Symbolizer};
+use pretty_assertions::assert_eq;
+
+fn test_symbolize(
+    input_file: &str,
+    expected_result_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    if let Err(e) = fs::metadata(expected_result_file) {
+        if e.kind() == io::ErrorKind::NotFound {
+            // be nice to the dev and make the file
+            let f = File::create(expected_result_file).unwrap();
+            let mut symbolizer = Symbolizer::new(options.clone())?;
+            symbolize::from_file(&mut symbolizer, Some(input_file), f)?;
+        } else {
+            return Err(e);
+        }
+    }
+
+    let mut result = Vec::new();
+    let mut symbolizer = Symbolizer::new(options)?;
+    symbolize::from_file(&mut symbolizer, Some(input_file), &mut result)?;
+    let expected = fs::read_to_string(expected_result_file).unwrap();
+    assert_eq!(String::from_utf8(result).unwrap(), expected);
+    Ok(())
+}
+
+#[test]
+fn symbolize_binary() {
+    let mut options = Options::default();
+    options.binaries = vec![PathBuf::from("./tests/data/symbolize/app")];
+    test_symbolize(
+        "./tests/data/symbolize/folded.txt",
+        "./tests/data/symbolize/results/folded.txt",
+        options,
+    )
+    .unwrap();
+}
+
+#[test]
+fn symbolize_binary_lines_inlines() {
+    let mut options = Options::default();
+    options.binaries = vec![PathBuf::from("./tests/data/symbolize/app")];
+    options.lines = true;
+    options.inlines = true;
+    test_symbolize(
+        "./tests/data/symbolize/folded.txt",
+        "./tests/data/symbolize/results/folded-lines-inlines.txt",
+        options,
+    )
+    .unwrap();
+}
+
+#[test]
+fn symbolize_maps() {
+    let mut options = Options::default();
+    options.maps = Some(PathBuf::from("./tests/data/symbolize/app.maps"));
+    options.symfs = Some(PathBuf::from("./tests/data/symbolize"));
+    test_symbolize(
+        "./tests/data/symbolize/relocated.txt",
+        "./tests/data/symbolize/results/relocated.txt",
+        options,
+    )
+    .unwrap();
+}
+
+#[test]
+fn symbolize_missing_maps_file() {
+    let mut options = Options::default();
+    options.maps = Some(PathBuf::from("./tests/data/symbolize/missing.maps"));
+    let error = Symbolizer::new(options).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn symbolize_cli() {
+    let expected_file = "./tests/data/symbolize/results/folded-lines-inlines.txt";
+
+    let output = Command::cargo_bin("inferno-symbolize")
+        .unwrap()
+        .arg("--binary")
+        .arg("./tests/data/symbolize/app")
+        .arg("--lines")
+        .arg("--inlines")
+        .arg("./tests/data/symbolize/folded.txt")
+        .output()
+        .expect("failed to execute process");
+    let expected = fs::read_to_string(expected_file).unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}